@@ -0,0 +1,74 @@
+//! Throughput of the reading of a large feed, whose lines are folded as most clients fold them
+//!
+//! Run with `cargo bench -p postgres-ical-parser`, which prints the time to read it and the
+//! resulting speed.
+
+use postgres_ical_parser::EventsReader;
+use std::io::Cursor;
+use std::time::Instant;
+
+const EVENTS: usize = 50_000;
+const RUNS: usize = 5;
+
+/// A calendar of `EVENTS` events, with CRLF line endings and lines folded at 75 octets
+fn feed() -> Vec<u8> {
+    let mut feed = String::from("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//bench//EN\r\n");
+    for i in 0..EVENTS {
+        let description = "A rather long description, as feeds have, that spans several \
+                           lines once folded. "
+            .repeat(4);
+        let lines = [
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}@example.com", i),
+            "DTSTAMP:20020101T080000Z".to_string(),
+            "DTSTART;TZID=Europe/Paris:20020110T120000".to_string(),
+            "DTEND;TZID=Europe/Paris:20020110T130000".to_string(),
+            format!("SUMMARY:Event {}", i),
+            format!("DESCRIPTION:{}", description),
+            "LOCATION:Room 1\\, first floor".to_string(),
+            "CATEGORIES:MEETING,WORK".to_string(),
+            "END:VEVENT".to_string(),
+        ];
+        for line in &lines {
+            let mut rest = line.as_str();
+            let mut limit = 75;
+            while rest.len() > limit {
+                let (head, tail) = rest.split_at(limit);
+                feed.push_str(head);
+                feed.push_str("\r\n ");
+                rest = tail;
+                limit = 74;
+            }
+            feed.push_str(rest);
+            feed.push_str("\r\n");
+        }
+    }
+    feed.push_str("END:VCALENDAR\r\n");
+    feed.into_bytes()
+}
+
+fn main() {
+    let feed = feed();
+
+    let mut best = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let mut count = 0;
+        for event in EventsReader::new(Cursor::new(&feed)) {
+            event.unwrap();
+            count += 1;
+        }
+        let elapsed = start.elapsed();
+        assert_eq!(count, EVENTS);
+        best = Some(best.map_or(elapsed, |best: std::time::Duration| best.min(elapsed)));
+    }
+
+    let best = best.unwrap();
+    let megabytes = feed.len() as f64 / 1_000_000.0;
+    println!(
+        "read {:.1} MB in {:?}, {:.1} MB/s",
+        megabytes,
+        best,
+        megabytes / best.as_secs_f64(),
+    );
+}
@@ -0,0 +1,616 @@
+//! Zero-copy reading of calendars that are entirely in memory
+//!
+//! Properties borrow their name, parameters and value from the calendar. Only the lines that are
+//! folded are copied, and the values that have to be unescaped or decoded, once they are read.
+//! [`EventRef`]s become [`Event`]s with the same rules as those of an
+//! [`EventsReader`](crate::EventsReader), for the values that the typed fields need.
+
+use super::input::mentions_quoted_printable;
+use super::parser::{CalendarParseError, Event, ParserOptions};
+use super::types::{
+    decode_param_value, decode_quoted_printable, unescape, IcalDateTime, IcalType, TzCache,
+};
+use ical::parser::ParserError;
+use ical::property::{Property, PropertyError};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// Property whose parts are borrowed from the calendar, unless its line was folded
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PropertyRef<'a> {
+    pub name: Cow<'a, str>,
+    /// Parameters, with their values unquoted, but not decoded
+    pub params: Vec<(Cow<'a, str>, Vec<Cow<'a, str>>)>,
+    /// Value, neither unescaped nor decoded
+    pub value: Cow<'a, str>,
+}
+
+impl<'a> PropertyRef<'a> {
+    /// Reads a content line, as the property parser of the `ical` crate does
+    fn parse(line: &'a str, number: usize) -> Result<Self, PropertyError> {
+        let name_end = line
+            .find(&[';', ':'][..])
+            .filter(|&end| end != 0)
+            .ok_or(PropertyError::MissingName { line: number })?;
+        let (name, mut rest) = line.split_at(name_end);
+
+        let mut params = Vec::new();
+        while let Some(param) = rest.strip_prefix(';') {
+            let (key, after_key) =
+                param
+                    .split_once('=')
+                    .ok_or(PropertyError::MissingDelimiter {
+                        line: number,
+                        delimiter: '=',
+                    })?;
+            if key.is_empty() {
+                return Err(PropertyError::MissingParamKey { line: number });
+            }
+
+            let mut values = Vec::new();
+            rest = after_key;
+            loop {
+                let (value, after_value) = match rest.strip_prefix('"') {
+                    Some(quoted) => quoted
+                        .split_once('"')
+                        .ok_or(PropertyError::MissingClosingQuote { line: number })?,
+                    None => {
+                        let end = rest.find(&[',', ';', ':'][..]).ok_or(
+                            PropertyError::MissingContentAfter {
+                                line: number,
+                                letter: '=',
+                            },
+                        )?;
+                        rest.split_at(end)
+                    }
+                };
+                values.push(Cow::Borrowed(value));
+                rest = after_value;
+
+                match rest.strip_prefix(',') {
+                    Some(after_comma) => rest = after_comma,
+                    None => break,
+                }
+            }
+            params.push((Cow::Borrowed(key), values));
+        }
+
+        let value = rest.strip_prefix(':').unwrap_or(rest);
+        Ok(Self {
+            name: Cow::Borrowed(name),
+            params,
+            value: Cow::Borrowed(value),
+        })
+    }
+
+    /// Copies the parts that are borrowed
+    pub fn into_owned(self) -> PropertyRef<'static> {
+        let owned = |part: Cow<'a, str>| Cow::Owned(part.into_owned());
+
+        PropertyRef {
+            name: owned(self.name),
+            params: self
+                .params
+                .into_iter()
+                .map(|(name, values)| (owned(name), values.into_iter().map(owned).collect()))
+                .collect(),
+            value: owned(self.value),
+        }
+    }
+
+    /// Whether the property is named `name`, regardless of its case
+    pub fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+
+    /// Last value of the parameter `name`, decoded
+    pub fn param(&self, name: &str) -> Option<Cow<'a, str>> {
+        let value = self
+            .params
+            .iter()
+            .rfind(|(n, _)| n.eq_ignore_ascii_case(name))
+            .and_then(|(_, values)| values.last())?;
+
+        if value.contains('^') {
+            Some(Cow::Owned(decode_param_value(value)))
+        } else {
+            Some(value.clone())
+        }
+    }
+
+    /// The value as a TEXT, unescaped, and decoded if it's quoted-printable
+    ///
+    /// Unknown escape sequences are kept, as by the lenient readers.
+    pub fn text(&self) -> Cow<'a, str> {
+        let is_quoted_printable = self
+            .param("ENCODING")
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("QUOTED-PRINTABLE"));
+
+        let value = if is_quoted_printable {
+            let decoded = decode_quoted_printable(self.to_property()).value;
+            Cow::Owned(decoded.unwrap_or_default())
+        } else {
+            self.value.clone()
+        };
+
+        if value.contains('\\') {
+            // Unescaping is lenient, so it doesn't fail
+            Cow::Owned(unescape(value.into_owned(), false).unwrap_or_else(|value| value))
+        } else {
+            value
+        }
+    }
+
+    /// The value as a DATE-TIME, with its TZID, if it's a valid one
+    pub fn date_time(&self) -> Option<IcalDateTime> {
+        IcalDateTime::from_value(
+            &self.value,
+            self.param("TZID").as_deref(),
+            &mut TzCache::new(),
+        )
+    }
+
+    /// The owned property, as the property parser of the `ical` crate builds it
+    pub fn to_property(&self) -> Property {
+        let params = self
+            .params
+            .iter()
+            .map(|(name, values)| {
+                let values = values.iter().map(|value| value.to_string()).collect();
+                (name.to_uppercase(), values)
+            })
+            .collect::<Vec<_>>();
+
+        Property {
+            name: self.name.to_string(),
+            params: Some(params).filter(|params| !params.is_empty()),
+            value: Some(self.value.to_string()).filter(|value| !value.is_empty()),
+        }
+    }
+}
+
+/// Event whose properties are borrowed from the calendar
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EventRef<'a> {
+    /// Properties of the event, without those of its sub-components
+    pub properties: Vec<PropertyRef<'a>>,
+    /// Number of `VALARM`s of the event
+    pub alarm_count: usize,
+}
+
+impl<'a> EventRef<'a> {
+    /// Last property `name`
+    pub fn property(&self, name: &str) -> Option<&PropertyRef<'a>> {
+        self.properties.iter().rfind(|property| property.is(name))
+    }
+
+    /// Last property `name`, as a TEXT
+    pub fn text(&self, name: &str) -> Option<Cow<'a, str>> {
+        self.property(name).map(PropertyRef::text)
+    }
+
+    /// Last property `name`, as a DATE-TIME
+    pub fn date_time(
+        &self,
+        name: &'static str,
+    ) -> Result<Option<IcalDateTime>, CalendarParseError> {
+        let property = match self.property(name) {
+            Some(property) => property,
+            None => return Ok(None),
+        };
+
+        match property.date_time() {
+            Some(date_time) => Ok(Some(date_time)),
+            None => Err(CalendarParseError::InvalidPropertyValue {
+                property: name,
+                found: property.value.to_string(),
+                expected: IcalDateTime::TYPE_NAME,
+            }),
+        }
+    }
+
+    pub fn uid(&self) -> Option<Cow<'a, str>> {
+        self.text("UID")
+    }
+
+    pub fn summary(&self) -> Option<Cow<'a, str>> {
+        self.text("SUMMARY")
+    }
+
+    /// Copies the event into an [`Event`], which is read as by an
+    /// [`EventsReader`](crate::EventsReader) with the same options
+    pub fn to_event(&self, options: &ParserOptions) -> Result<Event, CalendarParseError> {
+        let properties = self
+            .properties
+            .iter()
+            .map(|property| Ok(decode_quoted_printable(property.to_property())));
+
+        Event::from_properties(properties, self.alarm_count, options, &mut TzCache::new())
+    }
+}
+
+/// Reader of the [`EventRef`]s of a calendar, in the order of their `VEVENT`s
+///
+/// Its input is unfolded as by an [`EventsReader`](crate::EventsReader), and may have any line
+/// endings.
+pub struct EventRefsReader<'a> {
+    lines: ContentLines<'a>,
+    calendar_seq: usize,
+    /// Whether the last `VCALENDAR` wasn't ended yet
+    in_calendar: bool,
+}
+
+impl<'a> EventRefsReader<'a> {
+    pub fn new(calendar: &'a str) -> Self {
+        Self {
+            lines: ContentLines::new(calendar),
+            calendar_seq: 0,
+            in_calendar: false,
+        }
+    }
+
+    /// Position, starting at 1, of the `VCALENDAR` of the last event read, for inputs that
+    /// concatenate several of them
+    pub fn calendar_seq(&self) -> usize {
+        self.calendar_seq
+    }
+
+    /// Reads the properties of an event up to its `END`, skipping its sub-components
+    fn read_event(&mut self) -> Result<EventRef<'a>, CalendarParseError> {
+        let mut properties = Vec::new();
+        let mut depth = 0;
+        let mut alarm_count = 0;
+
+        loop {
+            let property = match self.lines.next() {
+                None => {
+                    self.in_calendar = false;
+                    return Err(CalendarParseError::Truncated("VEVENT".into()));
+                }
+                Some(property) => property?,
+            };
+
+            if property.is("BEGIN") {
+                if depth == 0 && property.value.eq_ignore_ascii_case("VALARM") {
+                    alarm_count += 1;
+                }
+                depth += 1;
+            } else if property.is("END") && depth == 0 {
+                if !property.value.eq_ignore_ascii_case("VEVENT") {
+                    return Err(ParserError::InvalidComponent.into());
+                }
+                return Ok(EventRef {
+                    properties,
+                    alarm_count,
+                });
+            } else if property.is("END") {
+                depth -= 1;
+            } else if depth == 0 {
+                properties.push(property);
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for EventRefsReader<'a> {
+    type Item = Result<EventRef<'a>, CalendarParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let property = match self.lines.next() {
+                None if self.in_calendar => {
+                    self.in_calendar = false;
+                    return Some(Err(CalendarParseError::Truncated("VCALENDAR".into())));
+                }
+                None => return None,
+                Some(Err(err)) => return Some(Err(err)),
+                Some(Ok(property)) => property,
+            };
+
+            if property.is("BEGIN") {
+                if property.value.is_empty() {
+                    return Some(Err(ParserError::InvalidComponent.into()));
+                } else if property.value.eq_ignore_ascii_case("VEVENT") {
+                    return Some(self.read_event());
+                } else if property.value.eq_ignore_ascii_case("VCALENDAR") {
+                    self.calendar_seq += 1;
+                    self.in_calendar = true;
+                }
+            } else if property.is("END") && property.value.eq_ignore_ascii_case("VCALENDAR") {
+                self.in_calendar = false;
+            }
+        }
+    }
+}
+
+/// Counts the components named `name` of a calendar, at any depth, such as its `VEVENT`s
+///
+/// Only the `BEGIN` lines are read, so it's much faster than reading the components, but it doesn't
+/// check that they are balanced. Lines that aren't properties are skipped.
+pub fn count_components(calendar: &str, name: &str) -> usize {
+    ContentLines::new(calendar)
+        .filter_map(Result::ok)
+        .filter(|property| property.is("BEGIN") && property.value.eq_ignore_ascii_case(name))
+        .count()
+}
+
+/// A TZID of a calendar, see [`tz_ids`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TzIdUsage {
+    pub tz_id: String,
+    /// Number of properties whose TZID parameter it is
+    pub properties: usize,
+    /// Whether a `VTIMEZONE` of the calendar defines it
+    pub defined: bool,
+}
+
+/// The TZIDs that the properties of a calendar refer to, and those that its `VTIMEZONE`s define,
+/// by name
+///
+/// The properties of every component are read, not only those of events. Lines that aren't
+/// properties are skipped.
+pub fn tz_ids(calendar: &str) -> Vec<TzIdUsage> {
+    fn usage<'u>(usages: &'u mut BTreeMap<String, TzIdUsage>, tz_id: &str) -> &'u mut TzIdUsage {
+        usages
+            .entry(tz_id.to_string())
+            .or_insert_with(|| TzIdUsage {
+                tz_id: tz_id.to_string(),
+                properties: 0,
+                defined: false,
+            })
+    }
+
+    let mut usages = BTreeMap::new();
+    let mut in_timezone = false;
+
+    for property in ContentLines::new(calendar).filter_map(Result::ok) {
+        if property.is("BEGIN") && property.value.eq_ignore_ascii_case("VTIMEZONE") {
+            in_timezone = true;
+        } else if property.is("END") && property.value.eq_ignore_ascii_case("VTIMEZONE") {
+            in_timezone = false;
+        } else if in_timezone && property.is("TZID") {
+            usage(&mut usages, &property.text()).defined = true;
+        } else if let Some(tz_id) = property.param("TZID") {
+            usage(&mut usages, &tz_id).properties += 1;
+        }
+    }
+
+    usages.into_values().collect()
+}
+
+/// Unfolded content lines of a calendar, read as properties
+struct ContentLines<'a> {
+    rest: &'a str,
+    /// Number of the last physical line read
+    number: usize,
+}
+
+impl<'a> ContentLines<'a> {
+    fn new(calendar: &'a str) -> Self {
+        Self {
+            rest: calendar.strip_prefix('\u{feff}').unwrap_or(calendar),
+            number: 0,
+        }
+    }
+
+    /// Next physical line, without its line ending, which may be CRLF, LF or CR
+    fn physical_line(&mut self) -> &'a str {
+        let end = self.rest.find(&['\r', '\n'][..]).unwrap_or(self.rest.len());
+        let (line, rest) = self.rest.split_at(end);
+        let rest = rest.strip_prefix('\r').unwrap_or(rest);
+        self.rest = rest.strip_prefix('\n').unwrap_or(rest);
+        self.number += 1;
+        line
+    }
+}
+
+impl<'a> Iterator for ContentLines<'a> {
+    type Item = Result<PropertyRef<'a>, CalendarParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (mut line, number) = loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            let line = self.physical_line();
+            if !line.is_empty() {
+                break (Cow::Borrowed(line), self.number);
+            }
+        };
+
+        loop {
+            if self.rest.starts_with(&[' ', '\t'][..]) {
+                let continuation = self.physical_line();
+                line.to_mut().push_str(&continuation[1..]);
+            } else if line.ends_with('=') && !self.rest.is_empty() && is_quoted_printable(&line) {
+                // Soft line break
+                let continuation = self.physical_line();
+                let line = line.to_mut();
+                line.pop();
+                line.push_str(continuation);
+            } else {
+                break;
+            }
+        }
+
+        let property = match line {
+            Cow::Borrowed(line) => PropertyRef::parse(line.trim_end(), number),
+            Cow::Owned(line) => {
+                PropertyRef::parse(line.trim_end(), number).map(PropertyRef::into_owned)
+            }
+        };
+        Some(property.map_err(|err| ParserError::PropertyError(err).into()))
+    }
+}
+
+/// Whether the name and parameters of a line have `ENCODING=QUOTED-PRINTABLE`
+fn is_quoted_printable(line: &str) -> bool {
+    let head = line.split(':').next().unwrap_or_default();
+    mentions_quoted_printable(head.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventsReader;
+    use std::io::Cursor;
+
+    fn parse(line: &str) -> PropertyRef<'_> {
+        PropertyRef::parse(line, 1).unwrap()
+    }
+
+    #[test]
+    fn parse_property_refs() {
+        let property = parse("ATTENDEE;cn=\"Doe, Jo\";MEMBER=a,\"b:c\";X=:mailto:jo@example.com");
+        assert_eq!(property.name, "ATTENDEE");
+        assert_eq!(property.param("CN").as_deref(), Some("Doe, Jo"));
+        assert_eq!(property.params[1].1, ["a", "b:c"]);
+        assert_eq!(property.params[2].1, [""]);
+        assert_eq!(property.value, "mailto:jo@example.com");
+        assert!(matches!(property.param("CN"), Some(Cow::Borrowed(_))));
+
+        let property = parse("SUMMARY;X-NOTE=a^'b^':Lunch\\, maybe");
+        assert_eq!(property.param("X-NOTE").as_deref(), Some("a\"b\""));
+        assert_eq!(property.text(), "Lunch, maybe");
+        assert_eq!(parse("DTSTART:20020110T123045Z").text(), "20020110T123045Z");
+
+        assert!(PropertyRef::parse(":a", 1).is_err());
+        assert!(PropertyRef::parse("A;B:c", 1).is_err());
+        assert!(PropertyRef::parse("A;B=\"c:d", 1).is_err());
+        assert!(PropertyRef::parse("A;=b:c", 1).is_err());
+    }
+
+    #[test]
+    fn borrow_unfolded_lines() {
+        let mut lines = ContentLines::new(
+            "\u{feff}A:a\r\nB:b\r\n c\r\n\r\nC;ENCODING=QUOTED-PRINTABLE:c=\nc\rD:",
+        );
+        let lines = std::iter::from_fn(|| lines.next())
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 4);
+        assert!(matches!(lines[0].value, Cow::Borrowed("a")));
+        assert!(matches!(lines[1].value, Cow::Owned(_)));
+        assert_eq!(lines[1].value, "bc");
+        assert_eq!(lines[2].value, "cc");
+        assert_eq!(lines[3].value, "");
+        assert_eq!(lines[3].to_property().value, None);
+    }
+
+    #[test]
+    fn read_event_refs() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART;TZID=Europe/Paris:20020111T100000\r\n\
+                        SUMMARY;ENCODING=QUOTED-PRINTABLE:Caf=C3=A9\r\n\
+                        ATTENDEE;CN=Jo;ROLE=CHAIR:mailto:jo@example.com\r\n\
+                        X-COLOR:red\r\n\
+                        BEGIN:VALARM\r\n\
+                        ACTION:DISPLAY\r\n\
+                        TRIGGER:-PT15M\r\n\
+                        END:VALARM\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+
+        let events = EventRefsReader::new(calendar)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.alarm_count, 1);
+        assert_eq!(event.properties.len(), 5);
+        assert!(matches!(event.uid(), Some(Cow::Borrowed("first"))));
+        assert_eq!(event.summary().as_deref(), Some("Café"));
+
+        let owned = EventsReader::new(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .unwrap();
+        let converted = event.to_event(&ParserOptions::default()).unwrap();
+        assert_eq!(
+            event.date_time("DTSTART").unwrap(),
+            Some(owned.dt_start.clone())
+        );
+        assert_eq!(converted.dt_start, owned.dt_start);
+        assert_eq!(converted.summary, owned.summary);
+        assert_eq!(converted.attendees, owned.attendees);
+        assert_eq!(converted.alarm_count, owned.alarm_count);
+        assert_eq!(
+            format!("{:?}", converted.extra),
+            format!("{:?}", owned.extra),
+        );
+    }
+
+    #[test]
+    fn count_nested_components() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        DESCRIPTION:BEGIN:VEVENT\r\n\
+                        BEGIN:VALARM\r\n\
+                        END:VALARM\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VTODO\r\n\
+                        END:VTODO\r\n\
+                        begin:vevent\r\n\
+                        BEGIN:VEV\r\n ENT\r\n\
+                        END:VCALENDAR\r\n";
+
+        assert_eq!(count_components(calendar, "VEVENT"), 3);
+        assert_eq!(count_components(calendar, "valarm"), 1);
+        assert_eq!(count_components(calendar, "VJOURNAL"), 0);
+        assert_eq!(count_components("", "VEVENT"), 0);
+    }
+
+    #[test]
+    fn list_tz_ids() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VTIMEZONE\r\n\
+                        TZID:Eastern Standard Time\r\n\
+                        BEGIN:STANDARD\r\n\
+                        DTSTART:16010101T020000\r\n\
+                        END:STANDARD\r\n\
+                        END:VTIMEZONE\r\n\
+                        BEGIN:VEVENT\r\n\
+                        DTSTART;TZID=Eastern Standard Time:20020111T100000\r\n\
+                        DTEND;TZID=\"Europe/Paris\":20020111T110000\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VTODO\r\n\
+                        DUE;TZID=Europe/Paris:20020112T100000\r\n\
+                        END:VTODO\r\n\
+                        END:VCALENDAR\r\n";
+
+        let usage = |tz_id: &str, properties, defined| TzIdUsage {
+            tz_id: tz_id.into(),
+            properties,
+            defined,
+        };
+        assert_eq!(
+            tz_ids(calendar),
+            [
+                usage("Eastern Standard Time", 1, true),
+                usage("Europe/Paris", 2, false),
+            ],
+        );
+        assert!(tz_ids("BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n").is_empty());
+    }
+
+    #[test]
+    fn read_truncated_event_refs() {
+        let calendar = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:first\r\n";
+        let mut reader = EventRefsReader::new(calendar);
+        assert!(matches!(
+            reader.next(),
+            Some(Err(CalendarParseError::Truncated(component))) if component == "VEVENT",
+        ));
+        assert!(reader.next().is_none());
+
+        let mut reader = EventRefsReader::new("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n");
+        assert!(matches!(
+            reader.next(),
+            Some(Err(CalendarParseError::Truncated(_)))
+        ));
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,282 @@
+//! Untyped ical components, that keep all of their properties
+
+use super::input::PropertyReader;
+use super::types::decode_quoted_printable;
+use super::{CalendarParseError, ParserOptions};
+use ical::parser::ParserError;
+use ical::property::Property;
+use std::io::BufRead;
+
+/// A component as written in the calendar, such as a `VEVENT` and its `VALARM`s
+pub struct RawComponent {
+    /// Upper-case name of the component
+    pub name: String,
+
+    /// Properties, in their order of appearance, with upper-case names
+    pub properties: Vec<Property>,
+
+    pub components: Vec<RawComponent>,
+}
+
+impl RawComponent {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            properties: Vec::new(),
+            components: Vec::new(),
+        }
+    }
+}
+
+/// How deep the components of a [`RawComponent`] may be nested, the sub-components of a
+/// component of a `VCALENDAR` being 1 level deep
+///
+/// Calendars nest them 2 levels deep at most, as do the `STANDARD`s of a `VTIMEZONE`, but
+/// consumers of the tree, such as serializers, recurse over it.
+pub const MAX_COMPONENT_DEPTH: usize = 64;
+
+/// Reads the components of the `VCALENDAR`s of a calendar
+///
+/// Components nested deeper than [`MAX_COMPONENT_DEPTH`] levels are an error.
+pub struct ComponentsReader<R: BufRead> {
+    raw_reader: PropertyReader<R>,
+    /// How deep sub-components may be nested, at most [`MAX_COMPONENT_DEPTH`]
+    max_depth: usize,
+    max_components: Option<usize>,
+    /// Number of components that were read, whether or not they were valid
+    count: usize,
+}
+
+impl ParserOptions {
+    /// A [`ComponentsReader`], which the limits of the options apply to
+    ///
+    /// As every component is read, [`ParserOptions::max_events`] limits the number of components,
+    /// such as time zones, and not only that of events.
+    pub fn build_raw_components<R: BufRead>(&self, buf_read: R) -> ComponentsReader<R> {
+        ComponentsReader {
+            raw_reader: PropertyReader::with_max_line_length(buf_read, self.max_property_length),
+            max_depth: self.max_depth.map_or(MAX_COMPONENT_DEPTH, |max_depth| {
+                max_depth.min(MAX_COMPONENT_DEPTH)
+            }),
+            max_components: self.max_events,
+            count: 0,
+        }
+    }
+}
+
+impl<R: BufRead> ComponentsReader<R> {
+    /// A reader with the default [`ParserOptions`]
+    pub fn new(buf_read: R) -> Self {
+        ParserOptions::default().build_raw_components(buf_read)
+    }
+
+    /// Reads the properties and sub-components of a component, up to its `END`
+    fn read_component(&mut self, name: String) -> Result<RawComponent, CalendarParseError> {
+        // The components being read, each one a sub-component of the previous one, without
+        // recursion so that hostile calendars can't overflow the stack before `MAX_COMPONENT_DEPTH`
+        let mut stack = vec![RawComponent::new(name)];
+
+        loop {
+            let component = stack.last_mut().expect("a component is being read");
+            let mut property = match self.raw_reader.next() {
+                None => {
+                    let name = std::mem::take(&mut component.name);
+                    return Err(CalendarParseError::Truncated(name));
+                }
+                Some(property) => property?,
+            };
+            property.name.make_ascii_uppercase();
+
+            match property.name.as_str() {
+                "BEGIN" => {
+                    if stack.len() > self.max_depth {
+                        return Err(CalendarParseError::TooDeep(self.max_depth));
+                    }
+                    stack.push(RawComponent::new(begin_name(property)?));
+                }
+                "END" => {
+                    match property.value {
+                        Some(name) if name.eq_ignore_ascii_case(&component.name) => {}
+                        _ => return Err(ParserError::InvalidComponent.into()),
+                    }
+                    let component = stack.pop().expect("a component is being read");
+                    match stack.last_mut() {
+                        Some(parent) => parent.components.push(component),
+                        None => return Ok(component),
+                    }
+                }
+                _ => component.properties.push(decode_quoted_printable(property)),
+            }
+        }
+    }
+}
+
+fn begin_name(property: Property) -> Result<String, CalendarParseError> {
+    match property.value {
+        Some(mut name) => {
+            name.make_ascii_uppercase();
+            Ok(name)
+        }
+        None => Err(ParserError::InvalidComponent.into()),
+    }
+}
+
+impl<R: BufRead> Iterator for ComponentsReader<R> {
+    type Item = Result<RawComponent, CalendarParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Nothing is read past the components that are allowed
+            if self
+                .max_components
+                .is_some_and(|max_components| self.count > max_components)
+            {
+                return None;
+            }
+            let mut property = match self.raw_reader.next()? {
+                Ok(property) => property,
+                Err(err) => return Some(Err(err)),
+            };
+            property.name.make_ascii_uppercase();
+
+            // The properties of the VCALENDAR itself are skipped
+            if property.name == "BEGIN" {
+                let component = begin_name(property).and_then(|name| match name.as_str() {
+                    "VCALENDAR" => Ok(None),
+                    _ => self.read_component(name).map(Some),
+                });
+
+                let component = match component {
+                    Ok(None) => continue,
+                    Ok(Some(component)) => Ok(component),
+                    Err(err) => Err(err),
+                };
+                self.count += 1;
+                if let Some(max_components) = self.max_components {
+                    if self.count > max_components {
+                        return Some(Err(CalendarParseError::TooManyEvents(max_components)));
+                    }
+                }
+                return Some(component);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_raw_components() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        VERSION:2.0\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        X-CUSTOM;X-PARAM=a,b:value\r\n\
+                        BEGIN:VALARM\r\n\
+                        ACTION:DISPLAY\r\n\
+                        END:VALARM\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VTODO\r\n\
+                        UID:second\r\n\
+                        END:VTODO\r\n\
+                        END:VCALENDAR\r\n";
+
+        let components = ComponentsReader::new(Cursor::new(calendar))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0].name, "VEVENT");
+        assert_eq!(components[0].properties.len(), 2);
+        assert_eq!(components[0].properties[1].name, "X-CUSTOM");
+        assert_eq!(components[0].components[0].name, "VALARM");
+        assert_eq!(components[1].name, "VTODO");
+    }
+
+    #[test]
+    fn read_raw_components_with_limits() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        BEGIN:VALARM\r\n\
+                        ACTION:DISPLAY\r\n\
+                        END:VALARM\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VTODO\r\n\
+                        UID:second\r\n\
+                        SUMMARY:A summary that is rather\r\n  long\r\n\
+                        END:VTODO\r\n\
+                        END:VCALENDAR\r\n";
+
+        let mut components = ParserOptions::default()
+            .max_events(1)
+            .build_raw_components(Cursor::new(calendar));
+        assert_eq!(components.next().unwrap().unwrap().name, "VEVENT");
+        assert!(matches!(
+            components.next(),
+            Some(Err(CalendarParseError::TooManyEvents(1))),
+        ));
+        assert!(components.next().is_none());
+
+        let mut components = ParserOptions::default()
+            .max_depth(0)
+            .build_raw_components(Cursor::new(calendar));
+        assert!(matches!(
+            components.next(),
+            Some(Err(CalendarParseError::TooDeep(0))),
+        ));
+
+        let mut components = ParserOptions::default()
+            .max_property_length(32)
+            .build_raw_components(Cursor::new(calendar));
+        assert_eq!(components.next().unwrap().unwrap().name, "VEVENT");
+        assert!(components.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn read_raw_components_too_deep() {
+        let nested = |depth: usize| {
+            format!(
+                "BEGIN:VCALENDAR\r\n{}{}END:VCALENDAR\r\n",
+                "BEGIN:X\r\n".repeat(depth + 1),
+                "END:X\r\n".repeat(depth + 1),
+            )
+        };
+
+        let calendar = nested(MAX_COMPONENT_DEPTH);
+        let mut components = ComponentsReader::new(Cursor::new(calendar));
+        let mut component = components.next().unwrap().unwrap();
+        for _ in 0..MAX_COMPONENT_DEPTH {
+            component = component.components.pop().unwrap();
+        }
+        assert!(component.components.is_empty());
+
+        let calendar = nested(MAX_COMPONENT_DEPTH + 1);
+        let mut components = ComponentsReader::new(Cursor::new(calendar));
+        assert!(matches!(
+            components.next(),
+            Some(Err(CalendarParseError::TooDeep(MAX_COMPONENT_DEPTH))),
+        ));
+
+        // Deep enough to overflow the stack of a recursive reader
+        let calendar = nested(100_000);
+        let mut components = ComponentsReader::new(Cursor::new(calendar));
+        assert!(matches!(
+            components.next(),
+            Some(Err(CalendarParseError::TooDeep(MAX_COMPONENT_DEPTH))),
+        ));
+    }
+
+    #[test]
+    fn read_raw_components_incomplete() {
+        let calendar = "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:first\r\n";
+
+        assert!(ComponentsReader::new(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .is_err());
+    }
+}
@@ -0,0 +1,485 @@
+//! Normalization of the raw calendar, before it's split into content lines
+//!
+//! Lines are unfolded here, on bytes, as folding may split UTF-8 sequences, which the line reader
+//! of the `ical` crate would reject, or corrupt by trimming the physical lines.
+//!
+//! That line reader panics on the errors of its input, so they are never returned to it. Instead,
+//! the input ends early, and the error is kept aside for the readers of this crate to return.
+
+use super::CalendarParseError;
+use ical::parser::ParserError;
+use ical::property::{Property, PropertyError};
+use ical::{LineReader, PropertyParser};
+use std::cell::Cell;
+use std::io::{self, BufRead, Read};
+use std::rc::Rc;
+
+const BOM: &[u8] = "\u{feff}".as_bytes();
+
+/// Reader that skips a leading UTF-8 byte order mark, unfolds lines, and turns the CRLF and bare
+/// CR line endings into LF, which is the only one that the line reader of the `ical` crate
+/// recognizes
+///
+/// The soft line breaks of `ENCODING=QUOTED-PRINTABLE` values, which end with `=` and aren't
+/// followed by a space, are removed along with their `=`.
+pub struct NormalizedInput<R: BufRead> {
+    inner: R,
+    pos: usize,
+    output: Normalizer,
+    error: InputError,
+}
+
+/// Error that ended a [`NormalizedInput`], shared with the reader of its lines
+pub type InputError = Rc<Cell<Option<io::Error>>>;
+
+/// Normalizer of the chunks of an input, as they are read, shared by the readers of any input
+///
+/// It normalizes as [`NormalizedInput`] does, which is described there.
+pub(crate) struct Normalizer {
+    /// Complete lines, that are valid UTF-8
+    buf: Vec<u8>,
+    /// The first chunk was read, and a BOM can't start the next ones
+    started: bool,
+    line: Vec<u8>,
+    /// A line that isn't valid UTF-8 was read, and the following ones are skipped
+    invalid: bool,
+    after_cr: bool,
+    /// A line break was read, but is only written once the next line isn't a continuation
+    pending_break: bool,
+    /// A `=` of a quoted-printable value was read, but may start a soft line break
+    pending_equals: bool,
+    /// Name and parameters of the current line, up to the first `:`, while they are short
+    head: Vec<u8>,
+    /// The bytes that are read are those of `head`
+    in_head: bool,
+    quoted_printable: bool,
+    /// Longest unfolded line, above which the input ends
+    max_line_length: Option<usize>,
+    /// The line that ended the input was too long, rather than not UTF-8
+    too_long: bool,
+}
+
+/// Longest name and parameters that are checked for `ENCODING=QUOTED-PRINTABLE`
+const MAX_HEAD_LENGTH: usize = 256;
+
+impl<R: BufRead> NormalizedInput<R> {
+    /// An input that ends with an error at the first line that is longer than `max_line_length`
+    /// bytes once unfolded, if any
+    pub fn new(inner: R, max_line_length: Option<usize>) -> Self {
+        Self {
+            inner,
+            pos: 0,
+            output: Normalizer::new(max_line_length),
+            error: InputError::default(),
+        }
+    }
+
+    /// Where the error that ends the input, if any, is stored
+    pub fn error(&self) -> InputError {
+        self.error.clone()
+    }
+
+    fn fail(&mut self, error: io::Error) {
+        self.output.invalid = true;
+        self.error.set(Some(error));
+    }
+}
+
+impl Normalizer {
+    pub(crate) fn new(max_line_length: Option<usize>) -> Self {
+        Self {
+            buf: Vec::new(),
+            started: false,
+            line: Vec::new(),
+            invalid: false,
+            after_cr: false,
+            pending_break: false,
+            pending_equals: false,
+            head: Vec::new(),
+            in_head: true,
+            quoted_printable: false,
+            max_line_length,
+            too_long: false,
+        }
+    }
+
+    /// Normalizes the next chunk of the input
+    pub(crate) fn write(&mut self, mut bytes: &[u8]) {
+        if !self.started {
+            // A BOM that is split across the first chunks isn't recognized, but readers hand out
+            // more than 3 bytes at once
+            self.started = true;
+            bytes = bytes.strip_prefix(BOM).unwrap_or(bytes);
+        }
+
+        while let Some((&byte, rest)) = bytes.split_first() {
+            if self.invalid {
+                return;
+            }
+
+            // The bytes up to the next line break, or up to the `:` that ends the head of the
+            // line, or up to the next `=` of a quoted-printable value, are copied at once
+            if !self.pending_break && !self.pending_equals {
+                let special = if self.in_head {
+                    memchr::memchr3(b'\r', b'\n', b':', bytes)
+                } else if self.quoted_printable {
+                    memchr::memchr3(b'\r', b'\n', b'=', bytes)
+                } else {
+                    memchr::memchr2(b'\r', b'\n', bytes)
+                };
+                let (plain, after) = bytes.split_at(special.unwrap_or(bytes.len()));
+                if !plain.is_empty() {
+                    self.copy(plain);
+                    bytes = after;
+                    continue;
+                }
+            }
+
+            self.push(byte);
+            bytes = rest;
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    /// Whether a line isn't valid UTF-8, or is too long, which ends the input
+    pub(crate) fn is_invalid(&self) -> bool {
+        self.invalid
+    }
+
+    /// Error of the line that ended the input
+    pub(crate) fn error(&self) -> io::Error {
+        match self.max_line_length {
+            Some(max_line_length) if self.too_long => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("a line is longer than {} bytes", max_line_length),
+            ),
+            _ => invalid_utf8(),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    /// Takes the complete lines that were normalized
+    pub(crate) fn take_lines(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.invalid {
+            return;
+        }
+
+        let after_cr = std::mem::replace(&mut self.after_cr, byte == b'\r');
+        if byte == b'\n' && after_cr {
+            return;
+        }
+
+        if self.pending_break {
+            self.pending_break = false;
+            if byte == b' ' || byte == b'\t' {
+                return;
+            }
+            if self.pending_equals {
+                // Soft line break
+                self.pending_equals = false;
+            } else {
+                self.end_line(true);
+                self.head.clear();
+                self.in_head = true;
+                self.quoted_printable = false;
+            }
+        }
+
+        if byte == b'\r' || byte == b'\n' {
+            self.pending_break = true;
+            return;
+        }
+
+        if self.pending_equals {
+            self.pending_equals = false;
+            self.line.push(b'=');
+        }
+        if byte == b'=' && self.quoted_printable {
+            self.pending_equals = true;
+            return;
+        }
+
+        if self.in_head {
+            if byte == b':' {
+                self.quoted_printable = mentions_quoted_printable(&self.head);
+                self.in_head = false;
+            } else if self.head.len() < MAX_HEAD_LENGTH {
+                self.head.push(byte);
+            } else {
+                self.in_head = false;
+            }
+        }
+        self.line.push(byte);
+        self.check_line_length();
+    }
+
+    /// Copies bytes that are neither line breaks, nor `:` or `=` that may have to be handled
+    fn copy(&mut self, bytes: &[u8]) {
+        if self.in_head {
+            if self.head.len() + bytes.len() <= MAX_HEAD_LENGTH {
+                self.head.extend_from_slice(bytes);
+            } else {
+                self.in_head = false;
+            }
+        }
+        self.line.extend_from_slice(bytes);
+        self.check_line_length();
+    }
+
+    fn check_line_length(&mut self) {
+        if let Some(max_line_length) = self.max_line_length {
+            if self.line.len() > max_line_length {
+                self.invalid = true;
+                self.too_long = true;
+            }
+        }
+    }
+
+    fn end_line(&mut self, line_break: bool) {
+        if std::str::from_utf8(&self.line).is_err() {
+            self.invalid = true;
+            return;
+        }
+
+        self.buf.append(&mut self.line);
+        if line_break {
+            self.buf.push(b'\n');
+        }
+    }
+
+    /// Completes the last line, at the end of the input
+    pub(crate) fn finish(&mut self) {
+        if self.invalid {
+            return;
+        }
+        if std::mem::take(&mut self.pending_equals) {
+            self.line.push(b'=');
+        }
+        let line_break = std::mem::take(&mut self.pending_break);
+        self.end_line(line_break);
+    }
+}
+
+impl<R: BufRead> Read for NormalizedInput<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(out.len());
+        out[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for NormalizedInput<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.output.buf.len() {
+            self.output.buf.clear();
+            self.pos = 0;
+            if self.output.invalid {
+                break;
+            }
+
+            let chunk = match self.inner.fill_buf() {
+                Ok(chunk) => chunk,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    // The lines before the error are complete
+                    self.output.finish();
+                    self.fail(err);
+                    break;
+                }
+            };
+            let chunk_len = chunk.len();
+            if chunk_len == 0 {
+                self.output.finish();
+                if self.output.invalid {
+                    self.fail(self.output.error());
+                }
+                break;
+            }
+
+            self.output.write(chunk);
+            self.inner.consume(chunk_len);
+            if self.output.invalid {
+                self.fail(self.output.error());
+            }
+        }
+
+        Ok(&self.output.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.output.buf.len());
+    }
+}
+
+/// Properties of a [`NormalizedInput`], followed by the error that ended it, if any
+pub struct PropertyReader<R: BufRead> {
+    parser: PropertyParser<NormalizedInput<R>>,
+    error: InputError,
+    failed: bool,
+}
+
+impl<R: BufRead> PropertyReader<R> {
+    /// A reader whose input ends with an error at the first line that is longer than
+    /// `max_line_length` bytes once unfolded, if any
+    pub fn with_max_line_length(buf_read: R, max_line_length: Option<usize>) -> Self {
+        let input = NormalizedInput::new(buf_read, max_line_length);
+        let error = input.error();
+
+        Self {
+            parser: PropertyParser::new(LineReader::new(input)),
+            error,
+            failed: false,
+        }
+    }
+
+    /// Whether the input ended with an error, rather than at its end
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+}
+
+impl<R: BufRead> Iterator for PropertyReader<R> {
+    type Item = Result<Property, CalendarParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.parser.next() {
+            Some(property) => Some(property.map_err(property_error)),
+            None => {
+                let error = self.error.take()?;
+                self.failed = true;
+                Some(Err(CalendarParseError::Io(error)))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+/// Properties of complete lines that were normalized
+pub(crate) fn properties(
+    lines: &[u8],
+) -> impl Iterator<Item = Result<Property, CalendarParseError>> + '_ {
+    PropertyParser::new(LineReader::new(lines)).map(|property| property.map_err(property_error))
+}
+
+fn property_error(err: PropertyError) -> CalendarParseError {
+    ParserError::PropertyError(err).into()
+}
+
+/// Whether the name and parameters of a line have `ENCODING=QUOTED-PRINTABLE`, in any case
+pub(crate) fn mentions_quoted_printable(head: &[u8]) -> bool {
+    const ENCODING: &[u8] = b"ENCODING=QUOTED-PRINTABLE";
+    head.windows(ENCODING.len())
+        .any(|window| window.eq_ignore_ascii_case(ENCODING))
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "a line isn't valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(input: &[u8]) -> String {
+        let mut output = String::new();
+        NormalizedInput::new(input, None)
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn normalize_line_endings() {
+        assert_eq!(normalize(b"A\r\nB\nC\rD\r\r\nE"), "A\nB\nC\nD\n\nE");
+        assert_eq!(normalize(b"\xef\xbb\xbfA\r\n"), "A\n");
+        // Only a leading BOM is skipped
+        assert_eq!(normalize(b"A\xef\xbb\xbf"), "A\u{feff}");
+    }
+
+    #[test]
+    fn unfold_lines() {
+        assert_eq!(normalize(b"A\r\n B\r\n\tC\r\nD\r\n"), "ABC\nD\n");
+        assert_eq!(normalize(b"A\n  B\r C"), "A BC");
+        assert_eq!(normalize("\u{e9}".as_bytes()), "\u{e9}");
+        assert_eq!(normalize(b"\xc3\r\n \xa9"), "\u{e9}");
+    }
+
+    #[test]
+    fn join_soft_line_breaks() {
+        assert_eq!(
+            normalize(b"A;ENCODING=QUOTED-PRINTABLE:a=3D=\r\nb=\r\n c\r\nD:d=\r\ne\r\n"),
+            "A;ENCODING=QUOTED-PRINTABLE:a=3Db=c\nD:d=\ne\n",
+        );
+        assert_eq!(
+            normalize(b"A;encoding=quoted-printable:a=\r\nb=\r\n"),
+            "A;encoding=quoted-printable:ab=\n",
+        );
+
+        // Heads that are too long aren't checked
+        let long_head = format!(
+            "A;X={};ENCODING=QUOTED-PRINTABLE:a=\r\nb\r\n",
+            "x".repeat(300)
+        );
+        assert_eq!(
+            normalize(long_head.as_bytes()),
+            long_head.replace("\r\n", "\n"),
+        );
+    }
+
+    #[test]
+    fn stop_at_errors() {
+        let mut input = NormalizedInput::new(&b"A\r\nB\xff\r\nC\r\n"[..], None);
+        let error = input.error();
+        let mut output = String::new();
+        input.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "A\n");
+        assert_eq!(error.take().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("connection reset"))
+            }
+        }
+        let input = io::BufReader::new(&b"A\r\n"[..]).chain(io::BufReader::new(Failing));
+        let mut input = NormalizedInput::new(input, None);
+        let error = input.error();
+        let mut output = String::new();
+        input.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "A\n");
+        assert_eq!(error.take().unwrap().to_string(), "connection reset");
+    }
+
+    #[test]
+    fn stop_at_long_lines() {
+        let mut input = NormalizedInput::new(&b"A:a\r\n bc\r\nB:bcdef\r\nC:c\r\n"[..], Some(5));
+        let error = input.error();
+        let mut output = String::new();
+        input.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "A:abc\n");
+        assert_eq!(
+            error.take().unwrap().to_string(),
+            "a line is longer than 5 bytes"
+        );
+    }
+
+    #[test]
+    fn normalize_across_chunks() {
+        // Chunks of a single byte, so that CRLF and folds are split
+        let input = io::BufReader::with_capacity(
+            1,
+            &b"A\r\nB\rC\r\n D\r\nE;ENCODING=QUOTED-PRINTABLE:e=\r\ne\r\n"[..],
+        );
+        let mut output = String::new();
+        NormalizedInput::new(input, None)
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "A\nB\nCD\nE;ENCODING=QUOTED-PRINTABLE:ee\n");
+    }
+}
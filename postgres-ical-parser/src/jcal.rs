@@ -0,0 +1,242 @@
+//! [jCal][jcal] (the JSON representation of iCalendar) input
+//!
+//! The properties of jCal components are converted back into their iCalendar form, so that events
+//! are built the same way as parsed ones.
+//!
+//! [jcal]: https://datatracker.ietf.org/doc/html/rfc7265
+
+use super::types::TzCache;
+use super::{CalendarParseError, Event, ParserOptions};
+use ical::property::Property;
+use serde_json::Value;
+
+/// Reads the events of a jCal document, which is either a `vcalendar` component or an array of
+/// them
+///
+/// An invalid document results in a single error.
+pub fn jcal_events(calendar: &Value) -> Vec<Result<Event, CalendarParseError>> {
+    jcal_calendars(calendar).into_iter().flatten().collect()
+}
+
+/// Reads the events of a jCal document like [`jcal_events`], grouped by `vcalendar`
+///
+/// An invalid `vcalendar` results in a single error, at the end of its group, and the following
+/// ones aren't read.
+pub fn jcal_calendars(calendar: &Value) -> Vec<Vec<Result<Event, CalendarParseError>>> {
+    let calendars = match calendar {
+        Value::Array(array) if array.first().is_some_and(Value::is_string) => vec![calendar],
+        Value::Array(array) => array.iter().collect(),
+        _ => return vec![vec![Err(invalid("the calendar must be an array"))]],
+    };
+
+    let mut groups = Vec::new();
+    for calendar in calendars {
+        let mut events = Vec::new();
+
+        match collect_events(calendar, &mut events) {
+            Ok(()) => groups.push(events),
+            Err(err) => {
+                events.push(Err(err));
+                groups.push(events);
+                break;
+            }
+        }
+    }
+    groups
+}
+
+fn invalid(reason: &'static str) -> CalendarParseError {
+    CalendarParseError::InvalidJcal(reason)
+}
+
+/// Adds the events of a `[name, properties, components]` component, or the event itself
+fn collect_events(
+    component: &Value,
+    events: &mut Vec<Result<Event, CalendarParseError>>,
+) -> Result<(), CalendarParseError> {
+    let (name, properties, components) = match component.as_array().map(Vec::as_slice) {
+        Some([Value::String(name), Value::Array(properties), Value::Array(components)]) => {
+            (name, properties, components)
+        }
+        _ => {
+            return Err(invalid(
+                "a component must be [name, properties, components]",
+            ))
+        }
+    };
+
+    if name.eq_ignore_ascii_case("vevent") {
+        // Alarms are only counted, as in iCalendar files
+        let alarm_count = components
+            .iter()
+            .filter(|component| {
+                let name = component.get(0).and_then(Value::as_str);
+                name.is_some_and(|name| name.eq_ignore_ascii_case("valarm"))
+            })
+            .count();
+        let properties = properties
+            .iter()
+            .map(to_property)
+            .collect::<Result<Vec<_>, _>>()?;
+        events.push(Event::from_properties(
+            properties.into_iter().map(Ok),
+            alarm_count,
+            &ParserOptions::default(),
+            &mut TzCache::new(),
+        ));
+        Ok(())
+    } else {
+        components
+            .iter()
+            .try_for_each(|component| collect_events(component, events))
+    }
+}
+
+/// Converts a `[name, parameters, type, values…]` property into its iCalendar form
+fn to_property(property: &Value) -> Result<Property, CalendarParseError> {
+    let (name, params, value_type, values) = match property.as_array().map(Vec::as_slice) {
+        Some(
+            [Value::String(name), Value::Object(params), Value::String(value_type), values @ ..],
+        ) if !values.is_empty() => (name, params, value_type, values),
+        _ => {
+            return Err(invalid(
+                "a property must be [name, parameters, type, values…]",
+            ))
+        }
+    };
+
+    let mut params: Vec<_> = params
+        .iter()
+        .map(|(name, value)| {
+            let values = match value {
+                Value::Array(values) => values.iter().map(to_string).collect(),
+                value => vec![to_string(value)],
+            };
+            (name.to_ascii_uppercase(), values)
+        })
+        .collect();
+    // The type of the value is its VALUE parameter in iCalendar, which DATEs need
+    if value_type == "date" && !params.iter().any(|(name, _)| name == "VALUE") {
+        params.push(("VALUE".to_string(), vec!["DATE".to_string()]));
+    }
+
+    let value = values
+        .iter()
+        .map(|value| to_ical_value(value_type, value))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    Ok(Property {
+        name: name.to_ascii_uppercase(),
+        params: Some(params),
+        value: Some(value),
+    })
+}
+
+fn to_string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn to_ical_value(value_type: &str, value: &Value) -> String {
+    // Structured values, such as the latitude and longitude of GEO
+    if let Value::Array(parts) = value {
+        return parts
+            .iter()
+            .map(|part| to_ical_value(value_type, part))
+            .collect::<Vec<_>>()
+            .join(";");
+    }
+
+    let value = to_string(value);
+
+    match value_type {
+        // 2006-01-02T15:04:05Z
+        "date-time" => value.replace(&['-', ':'][..], ""),
+        "date" => value.replace('-', ""),
+        "text" => value
+            .replace('\\', "\\\\")
+            .replace(';', "\\;")
+            .replace(',', "\\,")
+            .replace('\n', "\\n"),
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::IcalDateTime;
+    use chrono::{NaiveDate, TimeZone};
+    use chrono_tz::Europe::Paris;
+    use serde_json::json;
+
+    #[test]
+    fn read_jcal_events() {
+        let calendar = json!(["vcalendar", [["version", {}, "text", "2.0"]], [
+            ["vevent", [
+                ["uid", {}, "text", "first"],
+                ["summary", {}, "text", "Lunch; then, coffee\\tea"],
+                ["dtstart", {"tzid": "Europe/Paris"}, "date-time", "2002-01-10T12:30:45"],
+                ["sequence", {}, "integer", 3],
+            ], [["valarm", [["action", {}, "text", "DISPLAY"]], []]]],
+            ["vevent", [
+                ["uid", {}, "text", "second"],
+                ["dtstart", {}, "date", "2002-01-11"],
+            ], []],
+        ]]);
+
+        let events = jcal_events(&calendar)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].uid, "first");
+        assert_eq!(
+            events[0].summary.as_deref(),
+            Some("Lunch; then, coffee\\tea")
+        );
+        assert_eq!(
+            events[0].dt_start,
+            IcalDateTime::from(Paris.ymd(2002, 1, 10).and_hms(12, 30, 45)),
+        );
+        assert_eq!(events[0].sequence, 3);
+        assert_eq!(events[0].alarm_count, 1);
+        assert_eq!(
+            events[1].dt_start,
+            IcalDateTime::Date(NaiveDate::from_ymd(2002, 1, 11)),
+        );
+    }
+
+    #[test]
+    fn read_jcal_calendars() {
+        let event = |uid: &str| {
+            json!([
+                "vevent",
+                [
+                    ["uid", {}, "text", uid],
+                    ["dtstart", {}, "date-time", "2002-01-11T10:00:00Z"],
+                ],
+                []
+            ])
+        };
+        let calendars = json!([
+            ["vcalendar", [], [event("first"), event("second")]],
+            ["vcalendar", [], [event("third")]],
+        ]);
+
+        let groups = jcal_calendars(&calendars);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].len(), 2);
+        assert_eq!(groups[1][0].as_ref().unwrap().uid, "third");
+    }
+
+    #[test]
+    fn read_jcal_invalid() {
+        assert!(jcal_events(&json!({"vcalendar": []}))[0].is_err());
+        assert!(jcal_events(&json!(["vcalendar", [], [["vevent", [["uid"]], []]]]))[0].is_err());
+    }
+}
@@ -1,4 +1,26 @@
+pub mod borrowed;
+mod component;
+mod input;
+mod jcal;
 mod parser;
+pub mod recurrence;
+#[cfg(feature = "serde")]
+mod serialization;
+#[cfg(feature = "tokio")]
+mod stream;
+mod timezone;
 pub mod types;
+pub mod writer;
 
+pub use component::*;
+pub use jcal::*;
 pub use parser::*;
+#[cfg(feature = "tokio")]
+pub use stream::*;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Release of the tz database that time zones come from, which is the one of chrono-tz
+///
+/// chrono-tz doesn't expose it, so it has to be updated along with chrono-tz.
+pub const TZDATA_VERSION: &str = "2021e";
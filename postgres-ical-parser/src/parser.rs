@@ -1,12 +1,25 @@
 //! Type-safe ical event representation
 
-use super::types::{IcalDateTime, IcalInt, IcalText, IcalType};
+use super::input::PropertyReader;
+use super::types::{
+    decode_quoted_printable, param, CalAddress, IcalDateTime, IcalGeo, IcalInt, IcalText, IcalType,
+    TzCache,
+};
+use super::writer::{write_calendar, IcalWriter};
+use super::ComponentsReader;
+use chrono_tz::Tz;
 use ical::parser::ParserError;
 use ical::property::{Property, PropertyError};
-use ical::PropertyParser;
-use std::io::BufRead;
+use std::cmp::Ordering;
+use std::io::{self, BufRead, Write};
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Event {
+    /// Number of `VALARM`s of the event
+    pub alarm_count: usize,
+
+    pub attendees: Vec<CalAddress>,
+
     pub created: Option<IcalDateTime>,
 
     pub description: Option<String>,
@@ -19,13 +32,40 @@ pub struct Event {
 
     pub last_modified: Option<IcalDateTime>,
 
+    /// Latitude and longitude
+    pub geo: Option<(f32, f32)>,
+
     pub location: Option<String>,
 
+    pub organizer: Option<CalAddress>,
+
+    /// From 0 to 100
+    pub percent_complete: Option<i32>,
+
+    /// From 0, undefined, to 9, the lowest
+    pub priority: Option<i32>,
+
+    pub recurrence_id: Option<IcalDateTime>,
+
+    /// Whether the `RECURRENCE-ID` has `RANGE=THISANDFUTURE`, so that the event also overrides
+    /// the occurrences that follow it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub this_and_future: bool,
+
     pub sequence: i32,
 
     pub summary: Option<String>,
 
     pub uid: String,
+
+    /// Properties that none of the other fields holds, such as `CATEGORIES` or `X-` ones, in
+    /// their order, with uppercase names and their values as they were read
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::properties"))]
+    pub extra: Vec<Property>,
+
+    /// Problems that were worked around, outside of strict mode
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub warnings: Vec<CalendarParseError>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -40,8 +80,34 @@ pub enum CalendarParseError {
         expected: &'static str,
     },
 
-    #[error("unknown property {0}")]
-    UnknownProperty(String),
+    #[error("duplicate property {0}")]
+    DuplicateProperty(&'static str),
+
+    #[error("event {0} ends before it starts")]
+    EndBeforeStart(String),
+
+    #[error("{property} must be between {min} and {max}, found {found}")]
+    OutOfRange {
+        property: &'static str,
+        found: i32,
+        min: i32,
+        max: i32,
+    },
+
+    #[error("calendar ends within a {0}")]
+    Truncated(String),
+
+    #[error("components are nested deeper than {0} levels")]
+    TooDeep(usize),
+
+    #[error("the calendar has more than {0} events")]
+    TooManyEvents(usize),
+
+    #[error("could not read the calendar: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("invalid jCal: {0}")]
+    InvalidJcal(&'static str),
 
     #[error("internal ical parser error: {0}")]
     ParserError(#[from] ParserError),
@@ -50,70 +116,428 @@ pub enum CalendarParseError {
 fn ical_parse<T: IcalType>(
     property_name: &'static str,
     property: Property,
+    strict: bool,
+    timezones: &mut TzCache,
 ) -> Result<T::Output, CalendarParseError> {
-    T::parse(property).map_err(|value| CalendarParseError::InvalidPropertyValue {
-        property: property_name,
-        found: value,
-        expected: T::TYPE_NAME,
+    T::parse_cached(property, strict, timezones).map_err(|value| {
+        CalendarParseError::InvalidPropertyValue {
+            property: property_name,
+            found: value,
+            expected: T::TYPE_NAME,
+        }
     })
 }
 
+/// What to do with the properties that are repeated although RFC 5545 allows them only once
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DuplicatePropertyPolicy {
+    /// Fail with [`CalendarParseError::DuplicateProperty`]
+    Error,
+    FirstWins,
+    #[default]
+    LastWins,
+}
+
 macro_rules! event_from_properties {
     {
-        for $property:ident in $properties:expr;
+        for $property:ident in $properties:expr, $policy:ident, $strict:ident, $timezones:ident, else $extra:ident;
         $($name:literal $(! $($dummy:literal)*)? => $var:ident: $ical_type:ty $(= $default:expr)?,)*
+        $(+ $repeated_name:literal => $repeated_var:ident: $repeated_type:ty,)*
+        $(use $field:ident,)*
     } => {
         $(let mut $var = event_from_properties!(@i $name; $property; $ical_type $(= $default)?);)*
+        $(let mut $repeated_var = Vec::new();)*
+        let mut $extra = Vec::new();
+        let mut seen: Vec<&'static str> = Vec::new();
 
         for $property in $properties {
-            let $property = $property.map_err(ParserError::PropertyError)?;
+            let mut $property = $property.map_err(ParserError::PropertyError)?;
+            // In place, as the name is kept by the properties of `$extra`
+            $property.name.make_ascii_uppercase();
 
-            match $property.name.to_ascii_uppercase().as_str() {
-                $($name => $var = event_from_properties!(@s $name; $property; $ical_type $(= $default)?),)*
-                name => return Err(CalendarParseError::UnknownProperty(name.into())),
+            match $property.name.as_str() {
+                $($name => {
+                    if seen.contains(&$name) {
+                        match $policy {
+                            DuplicatePropertyPolicy::Error => return Err(CalendarParseError::DuplicateProperty($name)),
+                            DuplicatePropertyPolicy::FirstWins => continue,
+                            DuplicatePropertyPolicy::LastWins => {}
+                        }
+                    } else {
+                        seen.push($name);
+                    }
+                    $var = event_from_properties!(@s $name; $property, $strict, $timezones; $ical_type $(= $default)?);
+                })*
+                $($repeated_name => $repeated_var.push(ical_parse::<$repeated_type>($repeated_name, $property, $strict, $timezones)?),)*
+                _ => $extra.push($property),
             }
         }
 
         Ok(Self {
             $($var $(: $var.ok_or(CalendarParseError::MissingProperty(event_from_properties!(@t $name @ $($dummy)*)))?)?,)*
+            $($repeated_var,)*
+            $extra,
+            $($field,)*
         })
     };
     (@i $name:literal; $property:ident; $ical_type:ty = $default:expr) => { $default };
-    (@s $name:literal; $property:ident; $ical_type:ty = $default:expr) => { ical_parse::<$ical_type>($name, $property)? };
+    (@s $name:literal; $property:ident, $strict:ident, $timezones:ident; $ical_type:ty = $default:expr) => { ical_parse::<$ical_type>($name, $property, $strict, $timezones)? };
     (@i $name:literal; $property:ident; $ical_type:ty) => { None };
-    (@s $name:literal; $property:ident; $ical_type:ty) => { Some(ical_parse::<$ical_type>($name, $property)?) };
+    (@s $name:literal; $property:ident, $strict:ident, $timezones:ident; $ical_type:ty) => { Some(ical_parse::<$ical_type>($name, $property, $strict, $timezones)?) };
     (@t $lit:literal @ $($tt:tt)*) => { $lit };
 }
 
 impl Event {
-    fn from_properties(
+    /// Builds an event from its properties, without those of its sub-components
+    ///
+    /// In strict mode, values that are out of their range are errors rather than clamped, and
+    /// so are unknown escape sequences rather than kept. The time zones of TZIDs are looked up in
+    /// `timezones`, which the reader keeps from an event to the next.
+    pub(crate) fn from_properties(
+        properties: impl Iterator<Item = Result<Property, PropertyError>>,
+        alarm_count: usize,
+        options: &ParserOptions,
+        timezones: &mut TzCache,
+    ) -> Result<Self, CalendarParseError> {
+        let strict = options.strict;
+
+        // The RANGE of the RECURRENCE-ID that is kept
+        let mut range = None;
+        let properties = properties.inspect(|property| {
+            if let Ok(property) = property {
+                let is_kept =
+                    range.is_none() || options.duplicates != DuplicatePropertyPolicy::FirstWins;
+                if property.name.eq_ignore_ascii_case("RECURRENCE-ID") && is_kept {
+                    range = Some(param(property, "RANGE"));
+                }
+            }
+        });
+
+        let mut event = Self::read_properties(
+            properties,
+            alarm_count,
+            options.duplicates,
+            strict,
+            timezones,
+        )?;
+        event.this_and_future = range
+            .flatten()
+            .is_some_and(|range| range.eq_ignore_ascii_case("THISANDFUTURE"));
+
+        if let Some(tz) = options.default_timezone {
+            let date_times = std::iter::once(&mut event.dt_start)
+                .chain(&mut event.created)
+                .chain(&mut event.dt_stamp)
+                .chain(&mut event.dt_end)
+                .chain(&mut event.last_modified)
+                .chain(&mut event.recurrence_id);
+            for date_time in date_times {
+                date_time.set_default_timezone(tz);
+            }
+        }
+
+        let warnings = &mut event.warnings;
+        check_range("PRIORITY", &mut event.priority, 0, 9, strict, warnings)?;
+        check_range(
+            "PERCENT-COMPLETE",
+            &mut event.percent_complete,
+            0,
+            100,
+            strict,
+            warnings,
+        )?;
+
+        Ok(event)
+    }
+
+    fn read_properties(
         properties: impl Iterator<Item = Result<Property, PropertyError>>,
+        alarm_count: usize,
+        duplicates: DuplicatePropertyPolicy,
+        strict: bool,
+        timezones: &mut TzCache,
     ) -> Result<Self, CalendarParseError> {
+        let warnings = Vec::new();
+        let this_and_future = false;
+
         event_from_properties! {
-            for property in properties;
+            for property in properties, duplicates, strict, timezones, else extra;
             "CREATED" => created: IcalDateTime,
             "DESCRIPTION" => description: IcalText,
             "DTSTART"! => dt_start: IcalDateTime,
             "DTSTAMP" => dt_stamp: IcalDateTime,
             "DTEND" => dt_end: IcalDateTime,
             "LAST-MODIFIED" => last_modified: IcalDateTime,
+            "GEO" => geo: IcalGeo,
             "LOCATION" => location: IcalText,
+            "ORGANIZER" => organizer: CalAddress,
+            "PERCENT-COMPLETE" => percent_complete: IcalInt,
+            "PRIORITY" => priority: IcalInt,
+            "RECURRENCE-ID" => recurrence_id: IcalDateTime,
             "SEQUENCE" => sequence: IcalInt = 0,
             "SUMMARY" => summary: IcalText,
             "UID"! => uid: IcalText,
+            + "ATTENDEE" => attendees: CalAddress,
+            use alarm_count,
+            use this_and_future,
+            use warnings,
+        }
+    }
+}
+
+/// Fails if `value` is out of `min..=max` in strict mode, and clamps it otherwise
+fn check_range(
+    property: &'static str,
+    value: &mut Option<i32>,
+    min: i32,
+    max: i32,
+    strict: bool,
+    warnings: &mut Vec<CalendarParseError>,
+) -> Result<(), CalendarParseError> {
+    let found = match *value {
+        Some(found) if !(min..=max).contains(&found) => found,
+        _ => return Ok(()),
+    };
+
+    let err = CalendarParseError::OutOfRange {
+        property,
+        found,
+        min,
+        max,
+    };
+    if strict {
+        return Err(err);
+    }
+
+    warnings.push(err);
+    *value = Some(found.clamp(min, max));
+    Ok(())
+}
+
+/// Suspicious time range of an event
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TimeRangeIssue {
+    /// `DTEND` precedes `DTSTART`, which RFC 5545 forbids
+    EndBeforeStart,
+    /// `DTEND` is `DTSTART`
+    ZeroLength,
+}
+
+impl Event {
+    /// Checks that the event doesn't end before it starts, when both date-times can be compared
+    pub fn time_range_issue(&self) -> Option<TimeRangeIssue> {
+        let end = self.dt_end.as_ref()?;
+
+        match end.cmp_instant(&self.dt_start)? {
+            Ordering::Less => Some(TimeRangeIssue::EndBeforeStart),
+            Ordering::Equal => Some(TimeRangeIssue::ZeroLength),
+            Ordering::Greater => None,
+        }
+    }
+
+    /// All of the date-times of the event
+    pub fn date_times(&self) -> impl Iterator<Item = &IcalDateTime> {
+        std::iter::once(&self.dt_start)
+            .chain(&self.created)
+            .chain(&self.dt_stamp)
+            .chain(&self.dt_end)
+            .chain(&self.last_modified)
+            .chain(&self.recurrence_id)
+    }
+
+    /// Writes the event as a `VEVENT`
+    pub fn write<W: Write>(&self, writer: &mut IcalWriter<W>) -> io::Result<()> {
+        writer.begin("VEVENT")?;
+        writer.text_property("UID", &self.uid)?;
+        if let Some(dt_stamp) = &self.dt_stamp {
+            writer.property(&dt_stamp.to_property("DTSTAMP"))?;
+        }
+        writer.property(&self.dt_start.to_property("DTSTART"))?;
+        if let Some(dt_end) = &self.dt_end {
+            writer.property(&dt_end.to_property("DTEND"))?;
+        }
+        if let Some(recurrence_id) = &self.recurrence_id {
+            let mut property = recurrence_id.to_property("RECURRENCE-ID");
+            if self.this_and_future {
+                property
+                    .params
+                    .get_or_insert_with(Vec::new)
+                    .push(("RANGE".into(), vec!["THISANDFUTURE".into()]));
+            }
+            writer.property(&property)?;
+        }
+        if let Some(created) = &self.created {
+            writer.property(&created.to_property("CREATED"))?;
+        }
+        if let Some(last_modified) = &self.last_modified {
+            writer.property(&last_modified.to_property("LAST-MODIFIED"))?;
+        }
+        if self.sequence != 0 {
+            writer.property(&Property {
+                name: "SEQUENCE".into(),
+                params: None,
+                value: Some(self.sequence.to_string()),
+            })?;
+        }
+        if let Some(summary) = &self.summary {
+            writer.text_property("SUMMARY", summary)?;
+        }
+        if let Some(description) = &self.description {
+            writer.text_property("DESCRIPTION", description)?;
+        }
+        if let Some(location) = &self.location {
+            writer.text_property("LOCATION", location)?;
+        }
+        for (name, value) in &[
+            ("PRIORITY", self.priority),
+            ("PERCENT-COMPLETE", self.percent_complete),
+        ] {
+            if let Some(value) = value {
+                writer.property(&Property {
+                    name: name.to_string(),
+                    params: None,
+                    value: Some(value.to_string()),
+                })?;
+            }
+        }
+        if let Some((lat, lng)) = self.geo {
+            writer.property(&Property {
+                name: "GEO".into(),
+                params: None,
+                value: Some(format!("{};{}", lat, lng)),
+            })?;
+        }
+        if let Some(organizer) = &self.organizer {
+            writer.property(&organizer.to_property("ORGANIZER"))?;
+        }
+        for attendee in &self.attendees {
+            writer.property(&attendee.to_property("ATTENDEE"))?;
+        }
+        for property in &self.extra {
+            writer.property(property)?;
+        }
+        writer.end("VEVENT")
+    }
+
+    /// Writes a `VCALENDAR` of the event alone, preceded by the `VTIMEZONE`s that it uses, which
+    /// an [`EventsReader`] reads back as the same event, but for its `VALARM`s
+    pub fn write_ics<W: Write>(&self, out: W) -> io::Result<()> {
+        write_calendar(&mut IcalWriter::new(out), std::slice::from_ref(self))
+    }
+}
+
+/// Settings of an [`EventsReader`], which are those of RFC 5545 by default
+#[derive(Clone, Debug)]
+pub struct ParserOptions {
+    strict: bool,
+    duplicates: DuplicatePropertyPolicy,
+    default_timezone: Option<Tz>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) max_property_length: Option<usize>,
+    pub(crate) max_events: Option<usize>,
+    components: Vec<String>,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            duplicates: DuplicatePropertyPolicy::default(),
+            default_timezone: None,
+            max_depth: None,
+            max_property_length: None,
+            max_events: None,
+            components: vec!["VEVENT".into()],
+        }
+    }
+}
+
+impl ParserOptions {
+    /// Sets whether values that are out of their range, and unknown escape sequences, are errors
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets what to do with repeated properties, which are the last ones by default
+    pub fn duplicate_properties(mut self, policy: DuplicatePropertyPolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Sets the time zone of the floating date-times, which are kept floating by default
+    pub fn default_timezone(mut self, tz: Tz) -> Self {
+        self.default_timezone = Some(tz);
+        self
+    }
+
+    /// Sets how deep the components of an event, such as its `VALARM`s, may be nested, which is
+    /// unlimited by default
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Sets how long a property may be, in bytes, once unfolded and with its name and parameters,
+    /// which is unlimited by default
+    ///
+    /// The input ends with an error at the first longer property, before it's read whole.
+    pub fn max_property_length(mut self, max_property_length: usize) -> Self {
+        self.max_property_length = Some(max_property_length);
+        self
+    }
+
+    /// Sets how many events may be read, which is unlimited by default
+    ///
+    /// The reader ends with [`CalendarParseError::TooManyEvents`] at the first event past them.
+    pub fn max_events(mut self, max_events: usize) -> Self {
+        self.max_events = Some(max_events);
+        self
+    }
+
+    /// Sets the names of the components that are read as events, which are only `VEVENT`s by
+    /// default
+    ///
+    /// The others, such as `VTODO`s, need the properties of events as well, such as `DTSTART`.
+    pub fn components(mut self, names: &[&str]) -> Self {
+        self.components = names.iter().map(|name| name.to_ascii_uppercase()).collect();
+        self
+    }
+
+    pub fn build<R: BufRead>(self, buf_read: R) -> EventsReader<R> {
+        EventsReader {
+            raw_reader: PropertyReader::with_max_line_length(buf_read, self.max_property_length),
+            parser: EventsParser::new(self),
         }
     }
 }
 
 pub struct EventsReader<R: BufRead> {
-    raw_reader: PropertyParser<R>,
+    raw_reader: PropertyReader<R>,
+    parser: EventsParser,
+}
+
+// Not generic, so that the type of the input isn't needed until it's given to the options
+impl EventsReader<io::Empty> {
+    pub fn builder() -> ParserOptions {
+        ParserOptions::default()
+    }
 }
 
 impl<R: BufRead> EventsReader<R> {
+    /// A reader with the default [`ParserOptions`]
     pub fn new(buf_read: R) -> Self {
-        let raw_reader = PropertyParser::new(ical::LineReader::new(buf_read));
+        ParserOptions::default().build(buf_read)
+    }
 
-        Self { raw_reader }
+    /// Position, starting at 1, of the `VCALENDAR` of the last event read, for streams that
+    /// concatenate several of them
+    pub fn calendar_seq(&self) -> usize {
+        self.parser.calendar_seq()
     }
 }
 
@@ -122,34 +546,722 @@ impl<R: BufRead> Iterator for EventsReader<R> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            break match self.raw_reader.next() {
-                None => None,
-                Some(Err(err)) => Some(Err(CalendarParseError::ParserError(err.into()))),
-                Some(Ok(mut property)) => {
-                    property.name.make_ascii_uppercase();
-                    match property.name.as_str() {
-                        "BEGIN" => match property.value.as_deref() {
-                            None => Some(Err(ParserError::InvalidComponent.into())),
-                            Some("VEVENT") => {
-                                Some(Event::from_properties(
-                                    (&mut self.raw_reader).take_while(
-                                        |property| !matches!(property, Ok(p) if p.name.as_str() == "END" && p.value.as_deref() == Some("VEVENT"))
-                                    )
-                                ))
-                            }
-                            Some("VCALENDAR") => continue,
-                            Some(_other) => {
-                                // TODO
-                                continue;
-                            }
-                        },
-                        _ => {
+            if self.parser.limit_reached() {
+                return None;
+            }
+            let event = match self.raw_reader.next() {
+                Some(property) => self.parser.property(property),
+                None => return self.parser.end(self.raw_reader.failed()),
+            };
+            if event.is_some() {
+                return event;
+            }
+        }
+    }
+}
+
+/// Events of the properties of a calendar, as they are read, so that readers of any input share
+/// it
+pub(crate) struct EventsParser {
+    options: ParserOptions,
+    calendar_seq: usize,
+    /// Whether the last `VCALENDAR` wasn't ended yet
+    in_calendar: bool,
+    /// The event being read, if any
+    event: Option<PendingEvent>,
+    timezones: TzCache,
+    /// Number of events that were read, whether or not they were valid
+    event_count: usize,
+    /// Emptied list of the properties of the last event, whose allocation the next one reuses
+    spare_properties: Vec<Result<Property, PropertyError>>,
+}
+
+/// Properties of an event, up to its `END`
+struct PendingEvent {
+    component: String,
+    properties: Vec<Result<Property, PropertyError>>,
+    /// Nesting of the sub-components that are skipped
+    depth: usize,
+    alarm_count: usize,
+}
+
+impl EventsParser {
+    pub(crate) fn new(options: ParserOptions) -> Self {
+        Self {
+            options,
+            calendar_seq: 0,
+            in_calendar: false,
+            event: None,
+            timezones: TzCache::new(),
+            event_count: 0,
+            spare_properties: Vec::new(),
+        }
+    }
+
+    pub(crate) fn calendar_seq(&self) -> usize {
+        self.calendar_seq
+    }
+
+    /// Whether more events than allowed were read, after which nothing else is
+    pub(crate) fn limit_reached(&self) -> bool {
+        self.options
+            .max_events
+            .is_some_and(|max_events| self.event_count > max_events)
+    }
+
+    /// Reads the next property, which may end an event
+    ///
+    /// Errors end the event that is being read, if any.
+    pub(crate) fn property(
+        &mut self,
+        property: Result<Property, CalendarParseError>,
+    ) -> Option<Result<Event, CalendarParseError>> {
+        let result = self.read_property(property);
+        if let Some(Err(_)) = result {
+            self.event = None;
+        }
+        result
+    }
+
+    fn read_property(
+        &mut self,
+        property: Result<Property, CalendarParseError>,
+    ) -> Option<Result<Event, CalendarParseError>> {
+        let mut property = match property {
+            Ok(property) => property,
+            Err(err) => return Some(Err(err)),
+        };
+        property.name.make_ascii_uppercase();
+
+        let event = match &mut self.event {
+            Some(event) => event,
+            None => {
+                match property.name.as_str() {
+                    "BEGIN" => match property.value.map(|value| value.to_ascii_uppercase()) {
+                        None => return Some(Err(ParserError::InvalidComponent.into())),
+                        Some(component) if self.options.components.contains(&component) => {
+                            self.event = Some(PendingEvent {
+                                component,
+                                properties: std::mem::take(&mut self.spare_properties),
+                                depth: 0,
+                                alarm_count: 0,
+                            });
+                        }
+                        Some(component) if component == "VCALENDAR" => {
+                            self.calendar_seq += 1;
+                            self.in_calendar = true;
+                        }
+                        Some(_other) => {
                             // TODO
-                            continue
                         }
+                    },
+                    "END"
+                        if property
+                            .value
+                            .as_deref()
+                            .is_some_and(|value| value.eq_ignore_ascii_case("VCALENDAR")) =>
+                    {
+                        self.in_calendar = false;
+                    }
+                    _ => {
+                        // TODO
                     }
                 }
-            };
+                return None;
+            }
+        };
+
+        let value = property.value.as_deref().unwrap_or_default();
+        match property.name.as_str() {
+            "BEGIN" => {
+                if event.depth == 0 && value.eq_ignore_ascii_case("VALARM") {
+                    event.alarm_count += 1;
+                }
+                event.depth += 1;
+                if let Some(max_depth) = self.options.max_depth {
+                    if event.depth > max_depth {
+                        return Some(Err(CalendarParseError::TooDeep(max_depth)));
+                    }
+                }
+            }
+            "END" if event.depth == 0 => {
+                if !value.eq_ignore_ascii_case(&event.component) {
+                    return Some(Err(ParserError::InvalidComponent.into()));
+                }
+                let event = self.event.take()?;
+                self.event_count += 1;
+                if let Some(max_events) = self.options.max_events {
+                    if self.event_count > max_events {
+                        return Some(Err(CalendarParseError::TooManyEvents(max_events)));
+                    }
+                }
+                let mut properties = event.properties;
+                let event = Event::from_properties(
+                    properties.drain(..),
+                    event.alarm_count,
+                    &self.options,
+                    &mut self.timezones,
+                );
+                self.spare_properties = properties;
+                return Some(event);
+            }
+            "END" => event.depth -= 1,
+            _ if event.depth == 0 => event.properties.push(Ok(decode_quoted_printable(property))),
+            _ => {}
+        }
+        None
+    }
+
+    /// Ends the input, which is truncated if it ends within a component, unless it `failed` with
+    /// an error that was already returned
+    pub(crate) fn end(&mut self, failed: bool) -> Option<Result<Event, CalendarParseError>> {
+        if let Some(event) = self.event.take() {
+            self.in_calendar = false;
+            return Some(Err(CalendarParseError::Truncated(event.component)));
+        }
+        if std::mem::take(&mut self.in_calendar) && !failed {
+            return Some(Err(CalendarParseError::Truncated("VCALENDAR".into())));
         }
+        None
+    }
+}
+
+/// Checks that a calendar starts with a `VCALENDAR`, that its components are balanced, and that its
+/// events can be read by a strict [`EventsReader`], without repeated properties nor negative
+/// durations
+pub fn validate_calendar(calendar: &str) -> Result<(), CalendarParseError> {
+    let header = calendar
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .split(&['\r', '\n'][..])
+        .next();
+    if !header.is_some_and(|line| line.trim_end().eq_ignore_ascii_case("BEGIN:VCALENDAR")) {
+        return Err(ParserError::MissingHeader.into());
+    }
+
+    for component in ComponentsReader::new(calendar.as_bytes()) {
+        component?;
+    }
+    let events = EventsReader::builder()
+        .duplicate_properties(DuplicatePropertyPolicy::Error)
+        .strict(true)
+        .build(calendar.as_bytes());
+    for event in events {
+        let event = event?;
+        if event.time_range_issue() == Some(TimeRangeIssue::EndBeforeStart) {
+            return Err(CalendarParseError::EndBeforeStart(event.uid));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::io::Cursor;
+
+    #[test]
+    fn read_event_with_alarms() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        BEGIN:VALARM\r\n\
+                        ACTION:DISPLAY\r\n\
+                        DESCRIPTION:Soon\r\n\
+                        TRIGGER:-PT15M\r\n\
+                        END:VALARM\r\n\
+                        BEGIN:VALARM\r\n\
+                        ACTION:AUDIO\r\n\
+                        TRIGGER:-PT5M\r\n\
+                        END:VALARM\r\n\
+                        SUMMARY:Lunch\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:second\r\n\
+                        DTSTART:20020112T100000Z\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+
+        let events = EventsReader::new(Cursor::new(calendar))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].alarm_count, 2);
+        assert_eq!(events[0].description, None);
+        assert_eq!(events[0].summary.as_deref(), Some("Lunch"));
+        assert_eq!(events[1].uid, "second");
+        assert_eq!(events[1].alarm_count, 0);
+    }
+
+    #[test]
+    fn read_this_and_future_range() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:standup\r\n\
+                        DTSTART:20020118T100000Z\r\n\
+                        RECURRENCE-ID;RANGE=THISANDFUTURE:20020117T100000Z\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:standup\r\n\
+                        DTSTART:20020111T110000Z\r\n\
+                        RECURRENCE-ID:20020110T100000Z\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+
+        let events = EventsReader::new(Cursor::new(calendar))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert!(events[0].this_and_future);
+        assert!(!events[1].this_and_future);
+
+        let mut written = Vec::new();
+        events[0].write(&mut IcalWriter::new(&mut written)).unwrap();
+        assert!(String::from_utf8(written)
+            .unwrap()
+            .contains("RECURRENCE-ID;RANGE=THISANDFUTURE:20020117T100000Z\r\n"));
+    }
+
+    #[test]
+    fn read_extra_properties() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        CATEGORIES:Food,Work\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        x-color;X-SHADE=dark:red\r\n\
+                        BEGIN:VALARM\r\n\
+                        ACTION:DISPLAY\r\n\
+                        END:VALARM\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+
+        let event = EventsReader::new(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .unwrap();
+
+        let extra = event
+            .extra
+            .iter()
+            .map(|property| (property.name.as_str(), property.value.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            extra,
+            [("CATEGORIES", Some("Food,Work")), ("X-COLOR", Some("red"))],
+        );
+        assert_eq!(
+            event.extra[1].params,
+            Some(vec![("X-SHADE".into(), vec!["dark".into()])]),
+        );
+    }
+
+    #[test]
+    fn write_ics_round_trip() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART;TZID=Europe/Paris:20020111T100000\r\n\
+                        DTEND:20020111T120000Z\r\n\
+                        SUMMARY:Lunch\\, maybe\r\n\
+                        GEO:48.85;2.35\r\n\
+                        ATTENDEE;CN=Jo;MEMBER=\"mailto:a@example.com\":mailto:jo@example.com\r\n\
+                        RRULE:FREQ=WEEKLY;BYDAY=FR\r\n\
+                        X-COLOR;X-SHADE=dark:red\r\n\
+                        PERCENT-COMPLETE:50\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        let event = EventsReader::new(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .unwrap();
+
+        let mut written = Vec::new();
+        event.write_ics(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(validate_calendar(&written).is_ok());
+        assert!(written.contains("BEGIN:VTIMEZONE\r\nTZID:Europe/Paris\r\n"));
+
+        let read = EventsReader::new(Cursor::new(&written))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.uid, event.uid);
+        assert_eq!(read.dt_start, event.dt_start);
+        assert_eq!(read.dt_end, event.dt_end);
+        assert_eq!(read.summary.as_deref(), Some("Lunch, maybe"));
+        assert_eq!(read.geo, event.geo);
+        assert_eq!(read.attendees, event.attendees);
+        assert_eq!(read.percent_complete, Some(50));
+        // Properties aren't comparable
+        assert_eq!(format!("{:?}", read.extra), format!("{:?}", event.extra));
+    }
+
+    #[test]
+    fn read_with_options() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:event\r\n\
+                        DTSTART:20020111T100000\r\n\
+                        DTEND:20020111T120000Z\r\n\
+                        BEGIN:VALARM\r\n\
+                        BEGIN:X-NESTED\r\n\
+                        END:X-NESTED\r\n\
+                        END:VALARM\r\n\
+                        END:VEVENT\r\n\
+                        begin:vtodo\r\n\
+                        UID:todo\r\n\
+                        DTSTART:20020112T100000Z\r\n\
+                        end:vtodo\r\n\
+                        END:VCALENDAR\r\n";
+
+        let event = EventsReader::builder()
+            .default_timezone(chrono_tz::Europe::Paris)
+            .build(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            event.dt_start,
+            IcalDateTime::from(chrono_tz::Europe::Paris.ymd(2002, 1, 11).and_hms(10, 0, 0)),
+        );
+        assert!(matches!(event.dt_end, Some(IcalDateTime::Utc(_))));
+
+        let uids = EventsReader::builder()
+            .components(&["VEVENT", "VTODO"])
+            .build(Cursor::new(calendar))
+            .map(|event| event.unwrap().uid)
+            .collect::<Vec<_>>();
+        assert_eq!(uids, ["event", "todo"]);
+
+        let mut events = EventsReader::builder()
+            .max_depth(1)
+            .build(Cursor::new(calendar));
+        assert!(matches!(
+            events.next(),
+            Some(Err(CalendarParseError::TooDeep(1))),
+        ));
+    }
+
+    #[test]
+    fn read_with_limits() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:second\r\n\
+                        DTSTART:20020112T100000Z\r\n\
+                        SUMMARY:A summary that is rather\r\n  long\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+
+        let mut events = EventsReader::builder()
+            .max_events(1)
+            .build(Cursor::new(calendar));
+        assert_eq!(events.next().unwrap().unwrap().uid, "first");
+        assert!(matches!(
+            events.next(),
+            Some(Err(CalendarParseError::TooManyEvents(1))),
+        ));
+        assert!(events.next().is_none());
+
+        let mut events = EventsReader::builder()
+            .max_property_length(32)
+            .build(Cursor::new(calendar));
+        assert_eq!(events.next().unwrap().unwrap().uid, "first");
+        assert!(matches!(
+            events.next(),
+            Some(Err(CalendarParseError::Io(_)))
+        ));
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn read_duplicate_properties() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        SUMMARY:Lunch\r\n\
+                        SUMMARY:Dinner\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        let read = |policy| {
+            EventsReader::builder()
+                .duplicate_properties(policy)
+                .build(Cursor::new(calendar))
+                .next()
+                .unwrap()
+        };
+
+        let first = read(DuplicatePropertyPolicy::FirstWins).unwrap();
+        assert_eq!(first.summary.as_deref(), Some("Lunch"));
+        let last = read(DuplicatePropertyPolicy::LastWins).unwrap();
+        assert_eq!(last.summary.as_deref(), Some("Dinner"));
+        assert!(matches!(
+            read(DuplicatePropertyPolicy::Error),
+            Err(CalendarParseError::DuplicateProperty("SUMMARY")),
+        ));
+        assert!(validate_calendar(calendar).is_err());
+    }
+
+    #[test]
+    fn check_time_ranges() {
+        let calendar = |dt_end: &str| {
+            format!(
+                "BEGIN:VCALENDAR\r\n\
+                 BEGIN:VEVENT\r\n\
+                 UID:first\r\n\
+                 DTSTART:20020111T100000Z\r\n\
+                 DTEND:{}\r\n\
+                 END:VEVENT\r\n\
+                 END:VCALENDAR\r\n",
+                dt_end,
+            )
+        };
+        let issue = |dt_end| {
+            let mut events = EventsReader::new(Cursor::new(calendar(dt_end)));
+            events.next().unwrap().unwrap().time_range_issue()
+        };
+
+        assert_eq!(issue("20020111T110000Z"), None);
+        assert_eq!(issue("20020111T100000Z"), Some(TimeRangeIssue::ZeroLength));
+        assert_eq!(
+            issue("20020111T090000Z"),
+            Some(TimeRangeIssue::EndBeforeStart)
+        );
+        // Floating date-times can't be compared to UTC ones
+        assert_eq!(issue("20020111T090000"), None);
+        assert!(validate_calendar(&calendar("20020111T090000Z")).is_err());
+    }
+
+    #[test]
+    fn check_numeric_ranges() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        PRIORITY:12\r\n\
+                        PERCENT-COMPLETE:50\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        let read = |strict| {
+            EventsReader::builder()
+                .strict(strict)
+                .build(Cursor::new(calendar))
+                .next()
+                .unwrap()
+        };
+
+        let lenient = read(false).unwrap();
+        assert_eq!(lenient.priority, Some(9));
+        assert_eq!(lenient.percent_complete, Some(50));
+        assert!(matches!(
+            lenient.warnings[..],
+            [CalendarParseError::OutOfRange {
+                property: "PRIORITY",
+                found: 12,
+                ..
+            }],
+        ));
+        assert!(read(true).is_err());
+        assert!(validate_calendar(calendar).is_err());
+    }
+
+    #[test]
+    fn read_quoted_parameters() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART;TZID=\"America/New_York\":20020111T100000\r\n\
+                        ORGANIZER;CN=\"Doe, John\";ROLE=CHAIR:mailto:john@example.com\r\n\
+                        ATTENDEE;CN=\"Jo: ^'JD^'\":mailto:jo@example.com\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        let event = EventsReader::new(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(
+            event.dt_start,
+            IcalDateTime::Tz(date_time, _) if date_time.timezone() == chrono_tz::America::New_York,
+        ));
+        let organizer = event.organizer.unwrap();
+        assert_eq!(organizer.cn.as_deref(), Some("Doe, John"));
+        assert_eq!(organizer.role.as_deref(), Some("CHAIR"));
+        assert_eq!(event.attendees[0].cn.as_deref(), Some("Jo: \"JD\""));
+    }
+
+    #[test]
+    fn read_any_line_endings() {
+        let calendar = "\u{feff}BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        SUMMARY:Lunch with\r\n  everyone\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+
+        for line_ending in ["\r\n", "\n", "\r"] {
+            let calendar = calendar.replace("\r\n", line_ending);
+            let event = EventsReader::new(Cursor::new(&calendar))
+                .next()
+                .unwrap()
+                .unwrap();
+            assert_eq!(event.summary.as_deref(), Some("Lunch with everyone"));
+            validate_calendar(&calendar).unwrap();
+        }
+    }
+
+    #[test]
+    fn read_lines_folded_within_characters() {
+        let description = "🎉 Party! 🎂🎈 Bring 🍕 and 🍻, 日本語も".repeat(8);
+        let mut calendar = b"BEGIN:VCALENDAR\r\n\
+                             BEGIN:VEVENT\r\n\
+                             UID:first\r\n\
+                             DTSTART:20020111T100000Z\r\n"
+            .to_vec();
+        // Folded every 75 octets, regardless of the UTF-8 sequences
+        let line = format!("DESCRIPTION:{}", description.replace(',', "\\,"));
+        for (i, chunk) in line.as_bytes().chunks(75).enumerate() {
+            if i > 0 {
+                calendar.push(b' ');
+            }
+            calendar.extend_from_slice(chunk);
+            calendar.extend_from_slice(b"\r\n");
+        }
+        calendar.extend_from_slice(b"END:VEVENT\r\nEND:VCALENDAR\r\n");
+        let mut lines = calendar.split(|&byte| byte == b'\n');
+        assert!(lines.any(|line| std::str::from_utf8(line).is_err()));
+
+        let event = EventsReader::new(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.description, Some(description));
+    }
+
+    #[test]
+    fn read_truncated_calendars() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        END:VEVENT\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:second\r\n\
+                        DTSTART:20020112T100000Z\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        let read = |calendar: &str| {
+            let events = EventsReader::new(Cursor::new(calendar));
+            let events: Vec<_> = events
+                .map(|event| event.map(|event| event.uid).map_err(|err| err.to_string()))
+                .collect();
+            events
+        };
+
+        assert_eq!(read(calendar), [Ok("first".into()), Ok("second".into())],);
+        let without_end = calendar.len() - "END:VCALENDAR\r\n".len();
+        assert_eq!(
+            read(&calendar[..without_end]),
+            [
+                Ok("first".into()),
+                Ok("second".into()),
+                Err("calendar ends within a VCALENDAR".into()),
+            ],
+        );
+        assert_eq!(
+            read(&calendar[..calendar.find("DTSTART:20020112").unwrap()]),
+            [
+                Ok("first".into()),
+                Err("calendar ends within a VEVENT".into()),
+            ],
+        );
+        assert!(validate_calendar(&calendar[..without_end]).is_err());
+
+        // Component names aren't case-sensitive
+        let lower_case_end = format!("{}END:vcalendar\r\n", &calendar[..without_end]);
+        assert_eq!(
+            read(&lower_case_end),
+            [Ok("first".into()), Ok("second".into())],
+        );
+    }
+
+    #[test]
+    fn read_quoted_printable_values() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        DESCRIPTION;ENCODING=QUOTED-PRINTABLE;CHARSET=UTF-8:Caf=C3=A9=0D=0A=\r\n\
+                        cr=C3=A8me\\, br=C3=BBl=C3=A9e\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        let event = EventsReader::new(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(event.description.as_deref(), Some("Café\r\ncrème, brûlée"),);
+    }
+
+    #[test]
+    fn read_invalid_utf8() {
+        let calendar = b"BEGIN:VCALENDAR\r\n\
+                         BEGIN:VEVENT\r\n\
+                         UID:first\r\n\
+                         DTSTART:20020111T100000Z\r\n\
+                         END:VEVENT\r\n\
+                         BEGIN:VEVENT\r\n\
+                         UID:\xff\r\n\
+                         END:VEVENT\r\n\
+                         END:VCALENDAR\r\n";
+        let mut events = EventsReader::new(Cursor::new(&calendar[..]));
+
+        assert_eq!(events.next().unwrap().unwrap().uid, "first");
+        assert!(matches!(
+            events.next(),
+            Some(Err(CalendarParseError::Io(_)))
+        ));
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn read_concatenated_calendars() {
+        let calendar = |uids: &[&str]| {
+            let events: String = uids
+                .iter()
+                .map(|uid| {
+                    format!(
+                        "BEGIN:VEVENT\r\nUID:{}\r\nDTSTART:20020111T100000Z\r\nEND:VEVENT\r\n",
+                        uid
+                    )
+                })
+                .collect();
+            format!("BEGIN:VCALENDAR\r\n{}END:VCALENDAR\r\n", events)
+        };
+        let stream = calendar(&["first", "second"]) + &calendar(&["third"]);
+
+        let mut reader = EventsReader::new(Cursor::new(stream));
+        let mut read = Vec::new();
+        while let Some(event) = reader.next() {
+            read.push((event.unwrap().uid, reader.calendar_seq()));
+        }
+
+        assert_eq!(
+            read,
+            [
+                ("first".into(), 1),
+                ("second".into(), 1),
+                ("third".into(), 2)
+            ],
+        );
     }
 }
@@ -0,0 +1,2024 @@
+//! Expansion of recurrence rules (`RRULE`), as defined by RFC 5545
+//!
+//! Occurrences are generated in the local time of their `DTSTART`, so that an event at 09:00 stays
+//! at 09:00 when clocks change, and only then given its time zone. Each period of the rule, such as
+//! a month for `FREQ=MONTHLY`, is expanded into the days and times that its `BY*` parts select.
+//!
+//! The occurrences of an event are its recurrence set, which adds the `RDATE`s to those of its
+//! `RRULE`, and removes the ones of its `EXDATE`s and of the `EXRULE`s of RFC 2445.
+
+use crate::types::{param, IcalDateTime, IcalDuration, TzCache, TzInfo};
+use chrono::{DateTime, Weekday};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
+use ical::property::Property;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::iter::Peekable;
+use std::str::FromStr;
+
+/// Occurrences aren't generated past this year, which ends rules that never match
+const MAX_YEAR: i32 = 9999;
+
+/// Rules that expand this many periods in a row without an occurrence are deemed to never match
+/// again, as `FREQ=SECONDLY;INTERVAL=2;BYSECOND=1` from an even second, whose periods would
+/// otherwise be expanded one by one until [`MAX_YEAR`]
+const MAX_EMPTY_PERIODS: u32 = 100_000;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Frequency {
+    Secondly,
+    Minutely,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A recurrence rule, with the lists of its `BY*` parts sorted
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<IcalDateTime>,
+    pub by_second: Vec<i32>,
+    pub by_minute: Vec<i32>,
+    pub by_hour: Vec<i32>,
+    /// Weekdays, with their position within the month or the year, if any
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_year_day: Vec<i32>,
+    pub by_week_no: Vec<i32>,
+    pub by_month: Vec<i32>,
+    /// Positions of the occurrences to keep among those of each period, counting from the end if
+    /// negative
+    pub by_set_pos: Vec<i32>,
+    pub week_start: Weekday,
+}
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum RRuleError {
+    #[error("missing FREQ")]
+    MissingFrequency,
+
+    #[error("invalid {part}: {value:?}")]
+    InvalidValue { part: String, value: String },
+
+    #[error("unknown rule part {0}")]
+    UnknownPart(String),
+
+    #[error("COUNT and UNTIL can't be both given")]
+    CountAndUntil,
+
+    #[error("a rule with a time of day requires a DATE-TIME DTSTART")]
+    TimeOfDate,
+}
+
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum RecurrenceSetError {
+    #[error("invalid {property}: {source}")]
+    Rule {
+        property: &'static str,
+        source: RRuleError,
+    },
+
+    #[error("invalid {property} value {value:?}")]
+    Date {
+        property: &'static str,
+        value: String,
+    },
+}
+
+fn parse_weekday(value: &str) -> Option<Weekday> {
+    match value.to_ascii_uppercase().as_str() {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// Reads an integer of `min..=max`, or also of `-max..=-min` if `signed`
+fn parse_number(value: &str, min: i32, max: i32, signed: bool) -> Option<i32> {
+    let number = value
+        .strip_prefix('+')
+        .unwrap_or(value)
+        .parse::<i32>()
+        .ok()?;
+    let magnitude = if signed { number.abs() } else { number };
+    Some(number).filter(|_| (min..=max).contains(&magnitude) && (!signed || number != 0))
+}
+
+fn parse_list(value: &str, min: i32, max: i32, signed: bool) -> Option<Vec<i32>> {
+    let mut numbers = value
+        .split(',')
+        .map(|number| parse_number(number, min, max, signed))
+        .collect::<Option<Vec<_>>>()?;
+    numbers.sort_unstable();
+    numbers.dedup();
+    Some(numbers)
+}
+
+/// Reads a `BYDAY` item, such as `MO`, `+2TU` or `-1FR`
+fn parse_weekday_num(value: &str) -> Option<(Option<i32>, Weekday)> {
+    let split = value.len().checked_sub(2)?;
+    let weekday = parse_weekday(value.get(split..)?)?;
+    let position = match &value[..split] {
+        "" => None,
+        position => Some(parse_number(position, 1, 53, true)?),
+    };
+    Some((position, weekday))
+}
+
+impl FromStr for RRule {
+    type Err = RRuleError;
+
+    /// Reads the value of an `RRULE` property, such as `FREQ=WEEKLY;BYDAY=MO,WE`
+    fn from_str(rule: &str) -> Result<Self, Self::Err> {
+        let rule = rule.trim();
+        let rule = match rule.get(..6) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("RRULE:") => &rule[6..],
+            _ => rule,
+        };
+
+        let mut freq = None;
+        let mut set_pos = "";
+        let mut parsed = RRule {
+            freq: Frequency::Yearly,
+            interval: 1,
+            count: None,
+            until: None,
+            by_second: Vec::new(),
+            by_minute: Vec::new(),
+            by_hour: Vec::new(),
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_year_day: Vec::new(),
+            by_week_no: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+            week_start: Weekday::Mon,
+        };
+
+        for part in rule.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part.split_once('=').unwrap_or((part, ""));
+            let name = name.to_ascii_uppercase();
+            let invalid = || RRuleError::InvalidValue {
+                part: name.clone(),
+                value: value.to_string(),
+            };
+
+            match name.as_str() {
+                "FREQ" => {
+                    freq = Some(match value.to_ascii_uppercase().as_str() {
+                        "SECONDLY" => Frequency::Secondly,
+                        "MINUTELY" => Frequency::Minutely,
+                        "HOURLY" => Frequency::Hourly,
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(invalid()),
+                    })
+                }
+                "INTERVAL" => {
+                    parsed.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|&interval| interval > 0)
+                        .ok_or_else(invalid)?
+                }
+                "COUNT" => parsed.count = Some(value.parse().map_err(|_| invalid())?),
+                "UNTIL" => {
+                    parsed.until = Some(
+                        IcalDateTime::from_value(value, None, &mut TzCache::new())
+                            .ok_or_else(invalid)?,
+                    )
+                }
+                "BYSECOND" => {
+                    // Leap seconds (60) don't exist in the local times that rules select
+                    parsed.by_second = parse_list(value, 0, 59, false).ok_or_else(invalid)?
+                }
+                "BYMINUTE" => {
+                    parsed.by_minute = parse_list(value, 0, 59, false).ok_or_else(invalid)?
+                }
+                "BYHOUR" => parsed.by_hour = parse_list(value, 0, 23, false).ok_or_else(invalid)?,
+                "BYDAY" => {
+                    parsed.by_day = value
+                        .split(',')
+                        .map(parse_weekday_num)
+                        .collect::<Option<_>>()
+                        .ok_or_else(invalid)?
+                }
+                "BYMONTHDAY" => {
+                    parsed.by_month_day = parse_list(value, 1, 31, true).ok_or_else(invalid)?
+                }
+                "BYYEARDAY" => {
+                    parsed.by_year_day = parse_list(value, 1, 366, true).ok_or_else(invalid)?
+                }
+                "BYWEEKNO" => {
+                    parsed.by_week_no = parse_list(value, 1, 53, true).ok_or_else(invalid)?
+                }
+                "BYMONTH" => {
+                    parsed.by_month = parse_list(value, 1, 12, false).ok_or_else(invalid)?
+                }
+                "WKST" => parsed.week_start = parse_weekday(value).ok_or_else(invalid)?,
+                "BYSETPOS" => {
+                    parsed.by_set_pos = parse_list(value, 1, 366, true).ok_or_else(invalid)?;
+                    set_pos = value;
+                }
+                name if name.starts_with("X-") => {}
+                _ => return Err(RRuleError::UnknownPart(name)),
+            }
+        }
+
+        parsed.freq = freq.ok_or(RRuleError::MissingFrequency)?;
+        if parsed.count.is_some() && parsed.until.is_some() {
+            return Err(RRuleError::CountAndUntil);
+        }
+        if let Some(max) = parsed.max_period_len() {
+            if !parsed.by_set_pos.is_empty()
+                && parsed
+                    .by_set_pos
+                    .iter()
+                    .all(|&pos| pos.unsigned_abs() > max)
+            {
+                return Err(RRuleError::InvalidValue {
+                    part: "BYSETPOS".into(),
+                    value: set_pos.into(),
+                });
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Local time of a date-time, midnight for a DATE
+pub fn local_naive(date_time: &IcalDateTime) -> NaiveDateTime {
+    match date_time {
+        IcalDateTime::Date(date) => date.and_hms(0, 0, 0),
+        IcalDateTime::Naive(date_time) => *date_time,
+        IcalDateTime::Utc(date_time) => date_time.naive_utc(),
+        IcalDateTime::Tz(date_time, _) => date_time.naive_local(),
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    NaiveDate::from_ymd(next_year, next_month, 1).pred().day()
+}
+
+fn days_in_year(year: i32) -> u32 {
+    NaiveDate::from_ymd(year, 12, 31).ordinal()
+}
+
+/// Whether the `index`th of `len` items, counted from 1, is their `position`th, counting from the
+/// end if `position` is negative
+fn is_position(index: u32, len: u32, position: i32) -> bool {
+    position == index as i32 || position == index as i32 - len as i32 - 1
+}
+
+/// Start of the week that contains `date`, weeks starting on `week_start`
+fn week_of(date: NaiveDate, week_start: Weekday) -> NaiveDate {
+    let offset =
+        (date.weekday().num_days_from_monday() + 7 - week_start.num_days_from_monday()) % 7;
+    date - Duration::days(offset.into())
+}
+
+/// Start of the first week of `year`, which is the first one with at least 4 days of the year
+fn first_week(year: i32, week_start: Weekday) -> NaiveDate {
+    let january_first = NaiveDate::from_ymd(year, 1, 1);
+    let start = week_of(january_first, week_start);
+    if january_first - start <= Duration::days(3) {
+        start
+    } else {
+        start + Duration::weeks(1)
+    }
+}
+
+impl RRule {
+    /// Most occurrences that a period can have, for the frequencies shorter than a day, whose
+    /// periods only have a single value of their own unit and of the larger ones
+    fn max_period_len(&self) -> Option<u32> {
+        let len = |by: &[i32]| by.len().max(1) as u32;
+        match self.freq {
+            Frequency::Secondly => Some(1),
+            Frequency::Minutely => Some(len(&self.by_second)),
+            Frequency::Hourly => Some(len(&self.by_minute) * len(&self.by_second)),
+            _ => None,
+        }
+    }
+
+    /// Occurrences of the rule for an event that starts at `dtstart`, in chronological order
+    ///
+    /// `DTSTART` is always the first occurrence, and counts as one for `COUNT`. The occurrences
+    /// have the time zone of `dtstart`, or are DATEs or floating date-times like it. Local times
+    /// that don't exist, when clocks are set forward, are read with the offset from before the gap.
+    pub fn occurrences(&self, dtstart: &IcalDateTime) -> Result<Occurrences, RRuleError> {
+        self.expand(dtstart, true)
+    }
+
+    /// Occurrences of the rule as an `EXRULE`, which only include `dtstart` if the rule selects it
+    pub fn exceptions(&self, dtstart: &IcalDateTime) -> Result<Occurrences, RRuleError> {
+        self.expand(dtstart, false)
+    }
+
+    /// The last occurrence of the rule for an event that starts at `dtstart` that precedes
+    /// `before`, if any
+    ///
+    /// Occurrences are looked for from `before` backwards, so that rules that started long ago
+    /// aren't expanded from their start, unless they have a `COUNT`, which requires counting their
+    /// occurrences from their start.
+    pub fn last_occurrence_before(
+        &self,
+        dtstart: &IcalDateTime,
+        before: &IcalDateTime,
+    ) -> Result<Option<IcalDateTime>, RRuleError> {
+        let occurrences = self.occurrences(dtstart)?;
+        if self.count.is_some() {
+            return Ok(occurrences
+                .take_while(|occurrence| cmp_date_times(occurrence, before).is_lt())
+                .last());
+        }
+        Ok(occurrences.last_before(before))
+    }
+
+    fn expand(
+        &self,
+        dtstart: &IcalDateTime,
+        always_start: bool,
+    ) -> Result<Occurrences, RRuleError> {
+        let has_time = !self.by_hour.is_empty()
+            || !self.by_minute.is_empty()
+            || !self.by_second.is_empty()
+            || self.freq < Frequency::Daily;
+        if dtstart.is_date() && has_time {
+            return Err(RRuleError::TimeOfDate);
+        }
+
+        let start = local_naive(dtstart);
+        let mut rule = self.clone();
+
+        // The parts that select days default to the day of DTSTART
+        if rule.by_week_no.is_empty()
+            && rule.by_year_day.is_empty()
+            && rule.by_month_day.is_empty()
+            && rule.by_day.is_empty()
+        {
+            match rule.freq {
+                Frequency::Yearly => {
+                    rule.by_month_day = vec![start.day() as i32];
+                    if rule.by_month.is_empty() {
+                        rule.by_month = vec![start.month() as i32];
+                    }
+                }
+                Frequency::Monthly => rule.by_month_day = vec![start.day() as i32],
+                Frequency::Weekly => rule.by_day = vec![(None, start.weekday())],
+                _ => {}
+            }
+        }
+
+        Ok(Occurrences {
+            rule,
+            dtstart: dtstart.clone(),
+            start,
+            always_start,
+            period: 0,
+            pending: VecDeque::new(),
+            emitted: 0,
+            done: false,
+        })
+    }
+
+    /// Whether the rule selects `date`, among the days of a period
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let month_len = days_in_month(date.year(), date.month());
+        let year_len = days_in_year(date.year());
+
+        let positions_in_month = self.freq == Frequency::Monthly
+            || (self.freq == Frequency::Yearly && !self.by_month.is_empty());
+        let positions_in_year = self.freq == Frequency::Yearly
+            && self.by_month.is_empty()
+            && self.by_week_no.is_empty();
+
+        let matches_weekday = |&(position, weekday): &(Option<i32>, Weekday)| {
+            if weekday != date.weekday() {
+                return false;
+            }
+            match position {
+                None => true,
+                Some(position) if positions_in_month => {
+                    let index = (date.day() - 1) / 7 + 1;
+                    let len = index + (month_len - date.day()) / 7;
+                    is_position(index, len, position)
+                }
+                Some(position) if positions_in_year => {
+                    let index = (date.ordinal() - 1) / 7 + 1;
+                    let len = index + (year_len - date.ordinal()) / 7;
+                    is_position(index, len, position)
+                }
+                // Positions are only meaningful within months and years
+                Some(_) => true,
+            }
+        };
+
+        (self.by_month.is_empty() || self.by_month.contains(&(date.month() as i32)))
+            && (self.by_year_day.is_empty()
+                || self
+                    .by_year_day
+                    .iter()
+                    .any(|&day| is_position(date.ordinal(), year_len, day)))
+            && (self.by_month_day.is_empty()
+                || self
+                    .by_month_day
+                    .iter()
+                    .any(|&day| is_position(date.day(), month_len, day)))
+            && (self.by_day.is_empty() || self.by_day.iter().any(matches_weekday))
+    }
+
+    /// Days of the weeks of `year` that `BYWEEKNO` selects
+    fn week_days(&self, year: i32) -> Vec<NaiveDate> {
+        let first = first_week(year, self.week_start);
+        let weeks = (first_week(year + 1, self.week_start) - first).num_weeks() as i32;
+
+        let mut days = Vec::new();
+        for &week in &self.by_week_no {
+            let week = if week > 0 { week } else { weeks + 1 + week };
+            if (1..=weeks).contains(&week) {
+                let monday = first + Duration::weeks((week - 1).into());
+                days.extend((0..7).map(|day| monday + Duration::days(day)));
+            }
+        }
+        days.sort_unstable();
+        days.dedup();
+        days
+    }
+}
+
+/// Iterator of the occurrences of an [`RRule`], see [`RRule::occurrences`]
+#[derive(Clone, Debug)]
+pub struct Occurrences {
+    rule: RRule,
+    dtstart: IcalDateTime,
+    /// Local time of `dtstart`
+    start: NaiveDateTime,
+    /// Whether `dtstart` is the first occurrence even if the rule doesn't select it
+    always_start: bool,
+    /// Number of periods expanded so far
+    period: i64,
+    /// Occurrences of the last period that weren't returned yet
+    pending: VecDeque<NaiveDateTime>,
+    emitted: u32,
+    done: bool,
+}
+
+impl Occurrences {
+    /// Start of the `period`th period, `None` if it's out of range
+    fn period_start(&self, period: i64) -> Option<NaiveDateTime> {
+        let start = self.start;
+        let steps = period.checked_mul(self.rule.interval.into())?;
+
+        let period_start = match self.rule.freq {
+            Frequency::Yearly => {
+                let year = i64::from(start.year()).checked_add(steps)?;
+                NaiveDate::from_ymd_opt(i32::try_from(year).ok()?, 1, 1)?.and_hms(0, 0, 0)
+            }
+            Frequency::Monthly => {
+                let month = i64::from(start.year()) * 12 + i64::from(start.month0()) + steps;
+                let year = i32::try_from(month.div_euclid(12)).ok()?;
+                NaiveDate::from_ymd_opt(year, month.rem_euclid(12) as u32 + 1, 1)?.and_hms(0, 0, 0)
+            }
+            Frequency::Weekly => {
+                let week = week_of(start.date(), self.rule.week_start).and_hms(0, 0, 0);
+                week.checked_add_signed(Duration::weeks(steps))?
+            }
+            Frequency::Daily => start
+                .date()
+                .and_hms(0, 0, 0)
+                .checked_add_signed(Duration::days(steps))?,
+            Frequency::Hourly => start
+                .date()
+                .and_hms(start.hour(), 0, 0)
+                .checked_add_signed(Duration::hours(steps))?,
+            Frequency::Minutely => start
+                .date()
+                .and_hms(start.hour(), start.minute(), 0)
+                .checked_add_signed(Duration::minutes(steps))?,
+            Frequency::Secondly => start.checked_add_signed(Duration::seconds(steps))?,
+        };
+
+        Some(period_start).filter(|period_start| period_start.year() <= MAX_YEAR)
+    }
+
+    /// Length of a period, for the frequencies shorter than a day
+    fn period_length(&self) -> Duration {
+        let unit = match self.rule.freq {
+            Frequency::Hourly => Duration::hours(1),
+            Frequency::Minutely => Duration::minutes(1),
+            _ => Duration::seconds(1),
+        };
+        unit * self.rule.interval as i32
+    }
+
+    /// Days of the period that starts at `period_start` that the rule selects
+    fn days(&self, period_start: NaiveDateTime) -> Vec<NaiveDate> {
+        let first = period_start.date();
+        let candidates: Vec<NaiveDate> = match self.rule.freq {
+            Frequency::Yearly if !self.rule.by_week_no.is_empty() => {
+                self.rule.week_days(first.year())
+            }
+            Frequency::Yearly => (0..days_in_year(first.year()))
+                .map(|day| first + Duration::days(day.into()))
+                .collect(),
+            Frequency::Monthly => (0..days_in_month(first.year(), first.month()))
+                .map(|day| first + Duration::days(day.into()))
+                .collect(),
+            Frequency::Weekly => (0..7).map(|day| first + Duration::days(day)).collect(),
+            _ => vec![first],
+        };
+
+        candidates
+            .into_iter()
+            .filter(|&day| self.rule.matches_day(day))
+            .collect()
+    }
+
+    /// Times of day of the period that starts at `period_start` that the rule selects
+    fn times(&self, period_start: NaiveDateTime) -> Vec<NaiveTime> {
+        let freq = self.rule.freq;
+        let values = |by: &[i32], of_start: u32, of_period: u32, per_period: bool| {
+            if per_period {
+                let of_period = of_period as i32;
+                if by.is_empty() || by.contains(&of_period) {
+                    vec![of_period]
+                } else {
+                    Vec::new()
+                }
+            } else if by.is_empty() {
+                vec![of_start as i32]
+            } else {
+                by.to_vec()
+            }
+        };
+
+        let hours = values(
+            &self.rule.by_hour,
+            self.start.hour(),
+            period_start.hour(),
+            freq <= Frequency::Hourly,
+        );
+        let minutes = values(
+            &self.rule.by_minute,
+            self.start.minute(),
+            period_start.minute(),
+            freq <= Frequency::Minutely,
+        );
+        let seconds = values(
+            &self.rule.by_second,
+            self.start.second(),
+            period_start.second(),
+            freq == Frequency::Secondly,
+        );
+
+        let mut times = Vec::new();
+        for &hour in &hours {
+            for &minute in &minutes {
+                times.extend(
+                    seconds.iter().map(|&second| {
+                        NaiveTime::from_hms(hour as u32, minute as u32, second as u32)
+                    }),
+                );
+            }
+        }
+        times
+    }
+
+    /// For the frequencies shorter than a day, the start and length of the day, hour or minute
+    /// of the period that starts at `period_start` if the rule selects none of its times, so that
+    /// the periods it contains are skipped at once
+    fn empty_unit(
+        &self,
+        period_start: NaiveDateTime,
+        days: &[NaiveDate],
+    ) -> Option<(NaiveDateTime, Duration)> {
+        let freq = self.rule.freq;
+        if freq >= Frequency::Daily {
+            return None;
+        }
+        let day = period_start.date().and_hms(0, 0, 0);
+        if days.is_empty() {
+            return Some((day, Duration::days(1)));
+        }
+
+        let selects = |by: &[i32], value: u32| by.is_empty() || by.contains(&(value as i32));
+        let hour = day + Duration::hours(period_start.hour().into());
+        if !selects(&self.rule.by_hour, period_start.hour()) {
+            return Some((hour, Duration::hours(1)));
+        }
+        let minute = hour + Duration::minutes(period_start.minute().into());
+        if freq <= Frequency::Minutely && !selects(&self.rule.by_minute, period_start.minute()) {
+            return Some((minute, Duration::minutes(1)));
+        }
+        if freq == Frequency::Secondly && !selects(&self.rule.by_second, period_start.second()) {
+            return Some((period_start, Duration::seconds(1)));
+        }
+        None
+    }
+
+    /// Expands the next period into `pending`, returning `false` once there are no more periods
+    fn expand_period(&mut self) -> bool {
+        let period_start = match self.period_start(self.period) {
+            Some(period_start) => period_start,
+            None => return false,
+        };
+
+        let days = self.days(period_start);
+        if let Some((unit_start, unit)) = self.empty_unit(period_start, &days) {
+            let length = self.period_length().num_seconds().max(1);
+            let skipped = ((unit_start + unit - period_start).num_seconds() + length - 1) / length;
+            self.period += skipped.max(1);
+            return true;
+        }
+        self.period += 1;
+
+        let occurrences = self.period_occurrences(period_start, &days);
+        self.pending.extend(occurrences);
+        true
+    }
+
+    /// Local times of the occurrences of the period that starts at `period_start`, on its `days`
+    fn period_occurrences(
+        &self,
+        period_start: NaiveDateTime,
+        days: &[NaiveDate],
+    ) -> Vec<NaiveDateTime> {
+        let times = self.times(period_start);
+        let set = days
+            .iter()
+            .flat_map(|day| times.iter().map(move |&time| day.and_time(time)))
+            .collect::<Vec<_>>();
+
+        let len = set.len() as u32;
+        set.into_iter()
+            .zip(1..)
+            .filter(|&(_, index)| {
+                self.rule.by_set_pos.is_empty()
+                    || self
+                        .rule
+                        .by_set_pos
+                        .iter()
+                        .any(|&position| is_position(index, len, position))
+            })
+            .map(|(occurrence, _)| occurrence)
+            .filter(|&occurrence| {
+                occurrence > self.start || (!self.always_start && occurrence == self.start)
+            })
+            .collect()
+    }
+
+    /// Index of the period that contains the local time `local`, negative if it precedes the
+    /// first one
+    fn period_of(&self, local: NaiveDateTime) -> Option<i64> {
+        let start = self.start;
+        let week_start = self.rule.week_start;
+        let units = match self.rule.freq {
+            Frequency::Yearly => i64::from(local.year() - start.year()),
+            Frequency::Monthly => {
+                i64::from(local.year() - start.year()) * 12 + i64::from(local.month0())
+                    - i64::from(start.month0())
+            }
+            Frequency::Weekly => {
+                (week_of(local.date(), week_start) - week_of(start.date(), week_start)).num_weeks()
+            }
+            Frequency::Daily => (local.date() - start.date()).num_days(),
+            _ => {
+                let length = self.period_length().num_seconds().max(1);
+                return Some(
+                    (local - self.period_start(0)?)
+                        .num_seconds()
+                        .div_euclid(length),
+                );
+            }
+        };
+        Some(units.div_euclid(self.rule.interval.into()))
+    }
+
+    /// Local time of `date_time` in the time zone of DTSTART, or as it is if either is floating
+    fn local_time_of(&self, date_time: &IcalDateTime) -> NaiveDateTime {
+        match (&self.dtstart, date_time.instant()) {
+            (IcalDateTime::Tz(dtstart, _), Some(instant)) => {
+                instant.with_timezone(&dtstart.timezone()).naive_local()
+            }
+            (IcalDateTime::Utc(_), Some(instant)) => instant.naive_utc(),
+            _ => local_naive(date_time),
+        }
+    }
+
+    /// The last occurrence that precedes `before`, looked for from the period of `before`
+    /// backwards, which ignores `COUNT`
+    ///
+    /// After [`MAX_EMPTY_PERIODS`] periods without an occurrence, the occurrences are expanded from
+    /// the start instead, which [`Iterator::next`] stops at such a gap too.
+    fn last_before(&self, before: &IcalDateTime) -> Option<IcalDateTime> {
+        let precedes = |date_time: &IcalDateTime| cmp_date_times(date_time, before).is_lt();
+        if !precedes(&self.dtstart) {
+            return None;
+        }
+
+        // Occurrences past UNTIL are never returned anyway
+        let mut last = self.local_time_of(before);
+        if let Some(until) = &self.rule.until {
+            let until = match until {
+                IcalDateTime::Date(until) => until.and_hms(23, 59, 59),
+                until => self.local_time_of(until),
+            };
+            last = last.min(until);
+        }
+
+        let mut period = self.period_of(last)?;
+        for _ in 0..MAX_EMPTY_PERIODS {
+            if period < 0 {
+                return Some(self.dtstart.clone());
+            }
+            let period_start = self.period_start(period)?;
+            let days = self.days(period_start);
+            if let Some((unit_start, _)) = self.empty_unit(period_start, &days) {
+                let before_unit = unit_start - Duration::seconds(1);
+                period = self.period_of(before_unit)?.min(period - 1);
+                continue;
+            }
+
+            let occurrence = self
+                .period_occurrences(period_start, &days)
+                .into_iter()
+                .rev()
+                .filter_map(|local| Some((self.date_time(local)?, local)))
+                .find(|(occurrence, local)| {
+                    precedes(occurrence) && !self.is_after_until(occurrence, *local)
+                });
+            if let Some((occurrence, _)) = occurrence {
+                return Some(occurrence);
+            }
+            period -= 1;
+        }
+        self.clone().take_while(precedes).last()
+    }
+
+    /// The occurrence whose local time is `local`, with the time zone of DTSTART
+    fn date_time(&self, local: NaiveDateTime) -> Option<IcalDateTime> {
+        self.dtstart.at_local_time(local)
+    }
+
+    /// Whether an occurrence is past the `UNTIL` of the rule
+    ///
+    /// A DATE `UNTIL` includes its whole day, and a floating one is compared with local times.
+    /// Other ones are instants, which floating occurrences are compared with as if they were UTC.
+    fn is_after_until(&self, occurrence: &IcalDateTime, local: NaiveDateTime) -> bool {
+        let instant = |date_time: &IcalDateTime| -> Option<DateTime<Utc>> {
+            match date_time {
+                IcalDateTime::Utc(date_time) => Some(*date_time),
+                IcalDateTime::Tz(date_time, _) => Some(date_time.with_timezone(&Utc)),
+                _ => None,
+            }
+        };
+
+        match &self.rule.until {
+            None => false,
+            Some(IcalDateTime::Date(until)) => local.date() > *until,
+            Some(IcalDateTime::Naive(until)) => local > *until,
+            Some(until) => match (instant(occurrence), instant(until)) {
+                (Some(occurrence), Some(until)) => occurrence > until,
+                _ => local > local_naive(until),
+            },
+        }
+    }
+}
+
+impl Iterator for Occurrences {
+    type Item = IcalDateTime;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.rule.count.is_some_and(|count| self.emitted >= count) {
+            return None;
+        }
+
+        let is_start = self.emitted == 0 && self.always_start;
+        let local = if is_start {
+            self.start
+        } else {
+            let mut empty_periods = 0;
+            loop {
+                if let Some(local) = self.pending.pop_front() {
+                    break local;
+                }
+                if empty_periods == MAX_EMPTY_PERIODS || !self.expand_period() {
+                    self.done = true;
+                    return None;
+                }
+                empty_periods += 1;
+            }
+        };
+
+        let occurrence = match self.date_time(local) {
+            Some(occurrence) if is_start || !self.is_after_until(&occurrence, local) => occurrence,
+            _ => {
+                self.done = true;
+                return None;
+            }
+        };
+        self.emitted += 1;
+        Some(occurrence)
+    }
+}
+
+/// Orders date-times by instant, or by local time if either of them is floating or a DATE
+pub fn cmp_date_times(a: &IcalDateTime, b: &IcalDateTime) -> Ordering {
+    a.cmp_instant(b)
+        .unwrap_or_else(|| local_naive(a).cmp(&local_naive(b)))
+}
+
+/// Whether an `EXDATE`, or an occurrence of an `EXRULE`, removes an occurrence
+///
+/// A DATE removes the occurrences of its whole day, and any date-time removes the DATE it falls on.
+fn excludes(exception: &IcalDateTime, occurrence: &IcalDateTime) -> bool {
+    if exception.is_date() || occurrence.is_date() {
+        local_naive(exception).date() == local_naive(occurrence).date()
+    } else {
+        cmp_date_times(exception, occurrence) == Ordering::Equal
+    }
+}
+
+/// The end of the occurrence at `start` of an event that starts at `dtstart`, and either ends at
+/// `dtend` or lasts for `duration`
+///
+/// Occurrences last as long as their event in local time, so that an event from 09:00 to 17:00
+/// keeps both times when clocks change, unless `dtend` has another time zone than `dtstart`. The
+/// days of `duration` are added to the local time of `start`, and its hours, minutes and seconds
+/// are exact. Events that have neither last a day if they start on a DATE, and no time otherwise.
+pub fn occurrence_end(
+    dtstart: &IcalDateTime,
+    dtend: Option<&IcalDateTime>,
+    duration: Option<IcalDuration>,
+    start: &IcalDateTime,
+) -> Option<IcalDateTime> {
+    let local = local_naive(start);
+
+    if let Some(dtend) = dtend {
+        let is_same_zone = std::mem::discriminant(dtstart) == std::mem::discriminant(dtend)
+            && dtstart.tz_id() == dtend.tz_id();
+        if let (false, Some(from), Some(to), Some(instant)) = (
+            is_same_zone,
+            dtstart.instant(),
+            dtend.instant(),
+            start.instant(),
+        ) {
+            return instant.checked_add_signed(to - from).map(IcalDateTime::Utc);
+        }
+
+        let length = local_naive(dtend) - local_naive(dtstart);
+        return start.at_local_time(local.checked_add_signed(length)?);
+    }
+
+    let duration = match duration {
+        Some(duration) => duration,
+        None if start.is_date() => IcalDuration {
+            days: 1,
+            seconds: 0,
+        },
+        None => return Some(start.clone()),
+    };
+    let seconds = Duration::seconds(duration.seconds);
+    let end = start.at_local_time(local.checked_add_signed(Duration::days(duration.days))?)?;
+    Some(match end {
+        IcalDateTime::Date(date) if duration.seconds == 0 => IcalDateTime::Date(date),
+        IcalDateTime::Date(date) => {
+            IcalDateTime::Naive(date.and_hms(0, 0, 0).checked_add_signed(seconds)?)
+        }
+        IcalDateTime::Naive(date_time) => {
+            IcalDateTime::Naive(date_time.checked_add_signed(seconds)?)
+        }
+        IcalDateTime::Utc(date_time) => IcalDateTime::Utc(date_time.checked_add_signed(seconds)?),
+        IcalDateTime::Tz(date_time, info) => IcalDateTime::Tz(
+            date_time.checked_add_signed(seconds)?,
+            TzInfo {
+                local_time: Default::default(),
+                ..info
+            },
+        ),
+    })
+}
+
+/// Date-times of an `RDATE` or `EXDATE`, whose value is a list, in the time zone of its `TZID`
+///
+/// The `PERIOD`s of an `RDATE` are read as their start.
+pub fn date_list(
+    property: &Property,
+    timezones: &mut TzCache,
+) -> Result<Vec<IcalDateTime>, String> {
+    let tz_id = param(property, "TZID");
+    property
+        .value
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            let start = value.split('/').next().unwrap_or_default();
+            IcalDateTime::from_value(start, tz_id.as_deref(), timezones)
+                .ok_or_else(|| value.to_string())
+        })
+        .collect()
+}
+
+/// The recurrence properties of an event
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecurrenceSet {
+    /// The last `RRULE`, as RFC 5545 allows only one
+    pub rule: Option<RRule>,
+    pub rdates: Vec<IcalDateTime>,
+    pub exrules: Vec<RRule>,
+    pub exdates: Vec<IcalDateTime>,
+}
+
+impl RecurrenceSet {
+    /// Reads the `RRULE`, `RDATE`, `EXRULE` and `EXDATE` properties among `properties`, ignoring
+    /// the other ones
+    pub fn from_properties<'a>(
+        properties: impl IntoIterator<Item = &'a Property>,
+    ) -> Result<Self, RecurrenceSetError> {
+        let mut set = Self::default();
+        let mut timezones = TzCache::new();
+
+        for property in properties {
+            let rule = |property: &'static str, value: &Option<String>| {
+                value
+                    .as_deref()
+                    .unwrap_or_default()
+                    .parse::<RRule>()
+                    .map_err(|source| RecurrenceSetError::Rule { property, source })
+            };
+            match property.name.as_str() {
+                "RRULE" => set.rule = Some(rule("RRULE", &property.value)?),
+                "EXRULE" => set.exrules.push(rule("EXRULE", &property.value)?),
+                "RDATE" => set
+                    .rdates
+                    .extend(date_list(property, &mut timezones).map_err(|value| {
+                        RecurrenceSetError::Date {
+                            property: "RDATE",
+                            value,
+                        }
+                    })?),
+                "EXDATE" => set
+                    .exdates
+                    .extend(date_list(property, &mut timezones).map_err(|value| {
+                        RecurrenceSetError::Date {
+                            property: "EXDATE",
+                            value,
+                        }
+                    })?),
+                _ => {}
+            }
+        }
+        Ok(set)
+    }
+
+    /// Whether the event has other occurrences than its `DTSTART`
+    pub fn is_recurring(&self) -> bool {
+        self.rule.is_some() || !self.rdates.is_empty()
+    }
+
+    /// Occurrences of an event that starts at `dtstart`, in chronological order and without
+    /// duplicates
+    ///
+    /// `DTSTART` is the first occurrence, unless an exception removes it. The `COUNT` of the
+    /// `RRULE` counts the occurrences that exceptions remove, as RFC 5545 requires.
+    pub fn occurrences(
+        &self,
+        dtstart: &IcalDateTime,
+    ) -> Result<SetOccurrences, RecurrenceSetError> {
+        let rule =
+            |property: &'static str| move |source| RecurrenceSetError::Rule { property, source };
+
+        let mut rdates = self.rdates.clone();
+        if self.rule.is_none() {
+            rdates.push(dtstart.clone());
+        }
+        rdates.sort_by(cmp_date_times);
+
+        Ok(SetOccurrences {
+            rule: match &self.rule {
+                Some(rrule) => Some(
+                    rrule
+                        .occurrences(dtstart)
+                        .map_err(rule("RRULE"))?
+                        .peekable(),
+                ),
+                None => None,
+            },
+            rdates: rdates.into(),
+            exrules: self
+                .exrules
+                .iter()
+                .map(|exrule| {
+                    Ok(exrule
+                        .exceptions(dtstart)
+                        .map_err(rule("EXRULE"))?
+                        .peekable())
+                })
+                .collect::<Result<_, _>>()?,
+            exdates: self.exdates.clone(),
+            last: None,
+        })
+    }
+}
+
+/// Iterator of the occurrences of a [`RecurrenceSet`], see [`RecurrenceSet::occurrences`]
+#[derive(Debug)]
+pub struct SetOccurrences {
+    rule: Option<Peekable<Occurrences>>,
+    /// Sorted
+    rdates: VecDeque<IcalDateTime>,
+    exrules: Vec<Peekable<Occurrences>>,
+    exdates: Vec<IcalDateTime>,
+    /// Last occurrence returned, which equal ones aren't returned again
+    last: Option<IcalDateTime>,
+}
+
+impl SetOccurrences {
+    /// The earliest occurrence of the rule and of the `RDATE`s
+    fn next_candidate(&mut self) -> Option<IcalDateTime> {
+        let from_rule = match (
+            self.rule.as_mut().and_then(Peekable::peek),
+            self.rdates.front(),
+        ) {
+            (Some(_), None) => true,
+            (Some(rule), Some(rdate)) => cmp_date_times(rule, rdate) != Ordering::Greater,
+            (None, _) => false,
+        };
+
+        if from_rule {
+            self.rule.as_mut().and_then(Iterator::next)
+        } else {
+            self.rdates.pop_front()
+        }
+    }
+
+    fn is_excluded(&mut self, occurrence: &IcalDateTime) -> bool {
+        if self
+            .exdates
+            .iter()
+            .any(|exdate| excludes(exdate, occurrence))
+        {
+            return true;
+        }
+
+        self.exrules.iter_mut().any(|exrule| {
+            while let Some(exception) = exrule.peek() {
+                if excludes(exception, occurrence) {
+                    return true;
+                }
+                if cmp_date_times(exception, occurrence) == Ordering::Greater {
+                    break;
+                }
+                exrule.next();
+            }
+            false
+        })
+    }
+}
+
+impl Iterator for SetOccurrences {
+    type Item = IcalDateTime;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let occurrence = self.next_candidate()?;
+            let is_duplicate = self
+                .last
+                .as_ref()
+                .is_some_and(|last| cmp_date_times(last, &occurrence) == Ordering::Equal);
+            if is_duplicate || self.is_excluded(&occurrence) {
+                continue;
+            }
+
+            self.last = Some(occurrence.clone());
+            return Some(occurrence);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Local times of the first `limit` occurrences of `rule` from `dtstart`, in New York like the
+    /// examples of RFC 5545
+    fn expand(dtstart: &str, rule: &str, limit: usize) -> Vec<String> {
+        let dtstart =
+            IcalDateTime::from_value(dtstart, Some("America/New_York"), &mut TzCache::new())
+                .unwrap();
+        let rule = rule.parse::<RRule>().unwrap();
+        rule.occurrences(&dtstart)
+            .unwrap()
+            .take(limit)
+            .map(|occurrence| {
+                let local = local_naive(&occurrence);
+                if local.time() == NaiveTime::from_hms(9, 0, 0) {
+                    local.format("%Y%m%d").to_string()
+                } else {
+                    local.format("%Y%m%dT%H%M%S").to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// Dates of a month, as `YYYYMM` followed by days
+    fn month(year_month: &str, days: &[u32]) -> Vec<String> {
+        days.iter()
+            .map(|day| format!("{}{:02}", year_month, day))
+            .collect()
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule = "RRULE:freq=monthly;INTERVAL=2;BYDAY=1SU,-1SU,+2MO,TU;WKST=SU;X-NAME=1"
+            .parse::<RRule>()
+            .unwrap();
+        assert_eq!(rule.freq, Frequency::Monthly);
+        assert_eq!(rule.interval, 2);
+        assert_eq!(
+            rule.by_day,
+            [
+                (Some(1), Weekday::Sun),
+                (Some(-1), Weekday::Sun),
+                (Some(2), Weekday::Mon),
+                (None, Weekday::Tue),
+            ],
+        );
+        assert_eq!(rule.week_start, Weekday::Sun);
+
+        let rule = "FREQ=YEARLY;BYMONTHDAY=-1,15,1;UNTIL=20000131T140000Z"
+            .parse::<RRule>()
+            .unwrap();
+        assert_eq!(rule.by_month_day, [-1, 1, 15]);
+        assert!(matches!(rule.until, Some(IcalDateTime::Utc(_))));
+
+        let error = |rule: &str| rule.parse::<RRule>().unwrap_err();
+        assert_eq!(error("INTERVAL=2"), RRuleError::MissingFrequency);
+        assert_eq!(
+            error("FREQ=DAILY;COUNT=2;UNTIL=19971224"),
+            RRuleError::CountAndUntil,
+        );
+        assert!(matches!(
+            error("FREQ=DAILY;INTERVAL=0"),
+            RRuleError::InvalidValue { .. }
+        ));
+        assert!(matches!(
+            error("FREQ=MONTHLY;BYMONTHDAY=0"),
+            RRuleError::InvalidValue { .. }
+        ));
+        assert!(matches!(
+            error("FREQ=MONTHLY;BYDAY=0MO"),
+            RRuleError::InvalidValue { .. }
+        ));
+        assert!(matches!(
+            error("FREQ=WEEKLY;BYHOUR=24"),
+            RRuleError::InvalidValue { .. }
+        ));
+        assert_eq!(
+            error("FREQ=WEEKLY;BYEASTER=0"),
+            RRuleError::UnknownPart("BYEASTER".into()),
+        );
+        assert!(matches!(
+            error("FREQ=MINUTELY;BYSECOND=60"),
+            RRuleError::InvalidValue { .. }
+        ));
+        // A period of these rules never has a second occurrence
+        assert!(matches!(
+            error("FREQ=SECONDLY;BYSETPOS=2"),
+            RRuleError::InvalidValue { .. }
+        ));
+        assert!(matches!(
+            error("FREQ=HOURLY;BYMINUTE=0,30;BYSETPOS=-3,3"),
+            RRuleError::InvalidValue { .. }
+        ));
+        assert!("FREQ=HOURLY;BYMINUTE=0,30;BYSETPOS=-1"
+            .parse::<RRule>()
+            .is_ok());
+        assert!(matches!(
+            error("FREQ=DAILY\r\nATTENDEE:mailto:x"),
+            RRuleError::InvalidValue { .. }
+        ));
+    }
+
+    #[test]
+    fn expand_daily_rules() {
+        assert_eq!(
+            expand("19970902T090000", "FREQ=DAILY;COUNT=10", 100),
+            month("199709", &[2, 3, 4, 5, 6, 7, 8, 9, 10, 11]),
+        );
+
+        let until = expand("19970902T090000", "FREQ=DAILY;UNTIL=19971224T000000Z", 200);
+        assert_eq!(until.len(), 113);
+        assert_eq!(until.last().unwrap(), "19971223");
+
+        assert_eq!(
+            expand("19970902T090000", "FREQ=DAILY;INTERVAL=2", 4),
+            month("199709", &[2, 4, 6, 8]),
+        );
+        assert_eq!(
+            expand("19970902T090000", "FREQ=DAILY;INTERVAL=10;COUNT=5", 100),
+            [month("199709", &[2, 12, 22]), month("199710", &[2, 12])].concat(),
+        );
+
+        // Every day in January, for 3 years
+        let yearly = expand(
+            "19980101T090000",
+            "FREQ=YEARLY;UNTIL=20000131T140000Z;BYMONTH=1;BYDAY=SU,MO,TU,WE,TH,FR,SA",
+            200,
+        );
+        let daily = expand(
+            "19980101T090000",
+            "FREQ=DAILY;UNTIL=20000131T140000Z;BYMONTH=1",
+            200,
+        );
+        assert_eq!(yearly.len(), 93);
+        assert_eq!(yearly.last().unwrap(), "20000131");
+        assert_eq!(yearly, daily);
+    }
+
+    #[test]
+    fn expand_weekly_rules() {
+        assert_eq!(
+            expand("19970902T090000", "FREQ=WEEKLY;COUNT=10", 100),
+            [
+                month("199709", &[2, 9, 16, 23, 30]),
+                month("199710", &[7, 14, 21, 28]),
+                month("199711", &[4]),
+            ]
+            .concat(),
+        );
+
+        let until = expand("19970902T090000", "FREQ=WEEKLY;UNTIL=19971224T000000Z", 100);
+        assert_eq!(until.len(), 17);
+        assert_eq!(until.last().unwrap(), "19971223");
+
+        assert_eq!(
+            expand("19970902T090000", "FREQ=WEEKLY;INTERVAL=2;WKST=SU", 5),
+            [month("199709", &[2, 16, 30]), month("199710", &[14, 28])].concat(),
+        );
+
+        let tuesdays_and_thursdays = [
+            month("199709", &[2, 4, 9, 11, 16, 18, 23, 25, 30]),
+            month("199710", &[2]),
+        ]
+        .concat();
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=WEEKLY;UNTIL=19971007T000000Z;WKST=SU;BYDAY=TU,TH",
+                100,
+            ),
+            tuesdays_and_thursdays,
+        );
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=WEEKLY;COUNT=10;WKST=SU;BYDAY=TU,TH",
+                100,
+            ),
+            tuesdays_and_thursdays,
+        );
+
+        assert_eq!(
+            expand(
+                "19970901T090000",
+                "FREQ=WEEKLY;INTERVAL=2;UNTIL=19971224T000000Z;WKST=SU;BYDAY=MO,WE,FR",
+                100,
+            ),
+            [
+                month("199709", &[1, 3, 5, 15, 17, 19, 29]),
+                month("199710", &[1, 3, 13, 15, 17, 27, 29, 31]),
+                month("199711", &[10, 12, 14, 24, 26, 28]),
+                month("199712", &[8, 10, 12, 22]),
+            ]
+            .concat(),
+        );
+
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=8;WKST=SU;BYDAY=TU,TH",
+                100,
+            ),
+            [
+                month("199709", &[2, 4, 16, 18, 30]),
+                month("199710", &[2, 14, 16])
+            ]
+            .concat(),
+        );
+    }
+
+    #[test]
+    fn expand_with_week_start() {
+        assert_eq!(
+            expand(
+                "19970805T090000",
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST=MO",
+                100,
+            ),
+            month("199708", &[5, 10, 19, 24]),
+        );
+        assert_eq!(
+            expand(
+                "19970805T090000",
+                "FREQ=WEEKLY;INTERVAL=2;COUNT=4;BYDAY=TU,SU;WKST=SU",
+                100,
+            ),
+            month("199708", &[5, 17, 19, 31]),
+        );
+    }
+
+    #[test]
+    fn expand_monthly_rules() {
+        assert_eq!(
+            expand("19970905T090000", "FREQ=MONTHLY;COUNT=10;BYDAY=1FR", 100),
+            [
+                month("199709", &[5]),
+                month("199710", &[3]),
+                month("199711", &[7]),
+                month("199712", &[5]),
+                month("199801", &[2]),
+                month("199802", &[6]),
+                month("199803", &[6]),
+                month("199804", &[3]),
+                month("199805", &[1]),
+                month("199806", &[5]),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            expand(
+                "19970905T090000",
+                "FREQ=MONTHLY;UNTIL=19971224T000000Z;BYDAY=1FR",
+                100,
+            ),
+            ["19970905", "19971003", "19971107", "19971205"],
+        );
+        assert_eq!(
+            expand(
+                "19970907T090000",
+                "FREQ=MONTHLY;INTERVAL=2;COUNT=10;BYDAY=1SU,-1SU",
+                100,
+            ),
+            [
+                month("199709", &[7, 28]),
+                month("199711", &[2, 30]),
+                month("199801", &[4, 25]),
+                month("199803", &[1, 29]),
+                month("199805", &[3, 31]),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            expand("19970922T090000", "FREQ=MONTHLY;COUNT=6;BYDAY=-2MO", 100),
+            ["19970922", "19971020", "19971117", "19971222", "19980119", "19980216"],
+        );
+        assert_eq!(
+            expand("19970928T090000", "FREQ=MONTHLY;BYMONTHDAY=-3", 6),
+            ["19970928", "19971029", "19971128", "19971229", "19980129", "19980226"],
+        );
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=MONTHLY;COUNT=10;BYMONTHDAY=2,15",
+                100
+            ),
+            [
+                month("199709", &[2, 15]),
+                month("199710", &[2, 15]),
+                month("199711", &[2, 15]),
+                month("199712", &[2, 15]),
+                month("199801", &[2, 15]),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            expand(
+                "19970930T090000",
+                "FREQ=MONTHLY;COUNT=10;BYMONTHDAY=1,-1",
+                100
+            ),
+            [
+                month("199709", &[30]),
+                month("199710", &[1, 31]),
+                month("199711", &[1, 30]),
+                month("199712", &[1, 31]),
+                month("199801", &[1, 31]),
+                month("199802", &[1]),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            expand(
+                "19970910T090000",
+                "FREQ=MONTHLY;INTERVAL=18;COUNT=10;BYMONTHDAY=10,11,12,13,14,15",
+                100,
+            ),
+            [
+                month("199709", &[10, 11, 12, 13, 14, 15]),
+                month("199903", &[10, 11, 12, 13]),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            expand("19970902T090000", "FREQ=MONTHLY;INTERVAL=2;BYDAY=TU", 18),
+            [
+                month("199709", &[2, 9, 16, 23, 30]),
+                month("199711", &[4, 11, 18, 25]),
+                month("199801", &[6, 13, 20, 27]),
+                month("199803", &[3, 10, 17, 24, 31]),
+            ]
+            .concat(),
+        );
+
+        // Days that don't exist in a month are skipped
+        assert_eq!(
+            expand(
+                "20070115T090000",
+                "FREQ=MONTHLY;BYMONTHDAY=15,30;COUNT=5",
+                100
+            ),
+            ["20070115", "20070130", "20070215", "20070315", "20070330"],
+        );
+    }
+
+    #[test]
+    fn expand_yearly_rules() {
+        assert_eq!(
+            expand("19970610T090000", "FREQ=YEARLY;COUNT=10;BYMONTH=6,7", 100),
+            (1997..=2001)
+                .flat_map(|year| [format!("{}0610", year), format!("{}0710", year)])
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            expand(
+                "19970310T090000",
+                "FREQ=YEARLY;INTERVAL=2;COUNT=10;BYMONTH=1,2,3",
+                100,
+            ),
+            [
+                "19970310", "19990110", "19990210", "19990310", "20010110", "20010210", "20010310",
+                "20030110", "20030210", "20030310",
+            ],
+        );
+        assert_eq!(
+            expand(
+                "19970101T090000",
+                "FREQ=YEARLY;INTERVAL=3;COUNT=10;BYYEARDAY=1,100,200",
+                100,
+            ),
+            [
+                "19970101", "19970410", "19970719", "20000101", "20000409", "20000718", "20030101",
+                "20030410", "20030719", "20060101",
+            ],
+        );
+        assert_eq!(
+            expand("19970519T090000", "FREQ=YEARLY;BYDAY=20MO", 3),
+            ["19970519", "19980518", "19990517"],
+        );
+        assert_eq!(
+            expand("19970512T090000", "FREQ=YEARLY;BYWEEKNO=20;BYDAY=MO", 3),
+            ["19970512", "19980511", "19990517"],
+        );
+        // The last week of 1998 is its 53rd, while the one of 1999 ends in 2000
+        assert_eq!(
+            expand("19971222T090000", "FREQ=YEARLY;BYWEEKNO=-1;BYDAY=MO", 3),
+            ["19971222", "19981228", "19991227"],
+        );
+        assert_eq!(
+            expand("19970313T090000", "FREQ=YEARLY;BYMONTH=3;BYDAY=TH", 11),
+            [
+                month("199703", &[13, 20, 27]),
+                month("199803", &[5, 12, 19, 26]),
+                month("199903", &[4, 11, 18, 25]),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            expand("19970605T090000", "FREQ=YEARLY;BYDAY=TH;BYMONTH=6,7,8", 14),
+            [
+                month("199706", &[5, 12, 19, 26]),
+                month("199707", &[3, 10, 17, 24, 31]),
+                month("199708", &[7, 14, 21, 28]),
+                month("199806", &[4]),
+            ]
+            .concat(),
+        );
+        assert_eq!(
+            expand(
+                "19961105T090000",
+                "FREQ=YEARLY;INTERVAL=4;BYMONTH=11;BYDAY=TU;BYMONTHDAY=2,3,4,5,6,7,8",
+                3,
+            ),
+            ["19961105", "20001107", "20041102"],
+        );
+    }
+
+    #[test]
+    fn expand_combined_day_rules() {
+        // Every Friday the 13th, after DTSTART
+        assert_eq!(
+            expand("19970902T090000", "FREQ=MONTHLY;BYDAY=FR;BYMONTHDAY=13", 6),
+            ["19970902", "19980213", "19980313", "19981113", "19990813", "20001013"],
+        );
+        // The first Saturday that follows the first Sunday of the month
+        assert_eq!(
+            expand(
+                "19970913T090000",
+                "FREQ=MONTHLY;BYDAY=SA;BYMONTHDAY=7,8,9,10,11,12,13",
+                10,
+            ),
+            [
+                "19970913", "19971011", "19971108", "19971213", "19980110", "19980207", "19980307",
+                "19980411", "19980509", "19980613",
+            ],
+        );
+    }
+
+    #[test]
+    fn expand_set_positions() {
+        // The third Tuesday, Wednesday or Thursday of the month
+        assert_eq!(
+            expand(
+                "19970904T090000",
+                "FREQ=MONTHLY;COUNT=3;BYDAY=TU,WE,TH;BYSETPOS=3",
+                100,
+            ),
+            ["19970904", "19971007", "19971106"],
+        );
+        assert_eq!(
+            expand(
+                "19970929T090000",
+                "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-2",
+                7,
+            ),
+            ["19970929", "19971030", "19971127", "19971230", "19980129", "19980226", "19980330",],
+        );
+        // The last weekday of the month
+        assert_eq!(
+            expand(
+                "19970930T090000",
+                "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+                6,
+            ),
+            ["19970930", "19971031", "19971128", "19971231", "19980130", "19980227"],
+        );
+        // Positions apply to the times of the period as well
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=DAILY;BYHOUR=9,12,17;BYSETPOS=1,-1;COUNT=4",
+                100,
+            ),
+            ["19970902", "19970902T170000", "19970903", "19970903T170000"],
+        );
+    }
+
+    #[test]
+    fn expand_rules_with_times() {
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=HOURLY;INTERVAL=3;UNTIL=19970902T210000Z",
+                100,
+            ),
+            ["19970902", "19970902T120000", "19970902T150000"],
+        );
+        assert_eq!(
+            expand("19970902T090000", "FREQ=MINUTELY;INTERVAL=15;COUNT=6", 100),
+            [
+                "19970902",
+                "19970902T091500",
+                "19970902T093000",
+                "19970902T094500",
+                "19970902T100000",
+                "19970902T101500",
+            ],
+        );
+        assert_eq!(
+            expand("19970902T090000", "FREQ=MINUTELY;INTERVAL=90;COUNT=4", 100),
+            [
+                "19970902",
+                "19970902T103000",
+                "19970902T120000",
+                "19970902T133000",
+            ],
+        );
+
+        // Every 20 minutes from 9:00 to 16:40, every day
+        let daily = expand(
+            "19970902T090000",
+            "FREQ=DAILY;BYHOUR=9,10,11,12,13,14,15,16;BYMINUTE=0,20,40",
+            48,
+        );
+        let minutely = expand(
+            "19970902T090000",
+            "FREQ=MINUTELY;INTERVAL=20;BYHOUR=9,10,11,12,13,14,15,16",
+            48,
+        );
+        assert_eq!(daily[1], "19970902T092000");
+        assert_eq!(daily[23], "19970902T164000");
+        assert_eq!(daily[24], "19970903");
+        assert_eq!(daily, minutely);
+
+        assert_eq!(
+            expand(
+                "19970902T090000",
+                "FREQ=MINUTELY;BYSECOND=0,30;COUNT=4",
+                100
+            ),
+            [
+                "19970902",
+                "19970902T090030",
+                "19970902T090100",
+                "19970902T090130",
+            ],
+        );
+        assert_eq!(
+            expand("19970902T090000", "FREQ=SECONDLY;INTERVAL=45;COUNT=3", 100),
+            ["19970902", "19970902T090045", "19970902T090130"],
+        );
+
+        // Hours and minutes that the rule doesn't select are skipped at once
+        assert_eq!(
+            expand("19970902T090000", "FREQ=MINUTELY;BYHOUR=8;BYMINUTE=0,30", 3),
+            ["19970902", "19970903T080000", "19970903T083000"],
+        );
+        assert_eq!(
+            expand("19970902T090000", "FREQ=SECONDLY;BYMINUTE=5;BYSECOND=0", 3),
+            ["19970902", "19970902T090500", "19970902T100500"],
+        );
+    }
+
+    #[test]
+    fn stop_expanding_rules_that_never_match() {
+        for rule in ["FREQ=MINUTELY;BYSECOND=60", "FREQ=SECONDLY;BYSETPOS=2"] {
+            assert!(rule.parse::<RRule>().is_err(), "{}", rule);
+        }
+
+        // Their periods aren't expanded one by one until MAX_YEAR
+        for rule in [
+            "FREQ=SECONDLY;INTERVAL=2;BYSECOND=1",
+            "FREQ=MINUTELY;INTERVAL=2;BYMINUTE=1",
+            "FREQ=HOURLY;INTERVAL=2;BYHOUR=10",
+            "FREQ=SECONDLY;BYMONTH=2;BYMONTHDAY=30",
+            "FREQ=DAILY;BYMONTH=2;BYMONTHDAY=30",
+        ] {
+            assert_eq!(expand("19970902T090000", rule, 2), ["19970902"], "{}", rule);
+        }
+    }
+
+    /// Local times of the first `limit` occurrences of the recurrence `properties` of an event that
+    /// starts at `dtstart` in New York, which is also the time zone of `TZID=NY`
+    fn expand_set(dtstart: &str, properties: &[(&str, &str)], limit: usize) -> Vec<String> {
+        let tz_id = "America/New_York";
+        let dtstart = IcalDateTime::from_value(dtstart, Some(tz_id), &mut TzCache::new()).unwrap();
+        let properties = properties
+            .iter()
+            .map(|(name, value)| {
+                let (name, params) = match name.split_once(';') {
+                    Some((name, "TZID=NY")) => (name, vec![("TZID".into(), vec![tz_id.into()])]),
+                    Some((name, param)) => {
+                        let (param, value) = param.split_once('=').unwrap();
+                        (name, vec![(param.into(), vec![value.into()])])
+                    }
+                    None => (*name, Vec::new()),
+                };
+                Property {
+                    name: name.to_string(),
+                    params: Some(params),
+                    value: Some(value.to_string()),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        RecurrenceSet::from_properties(&properties)
+            .unwrap()
+            .occurrences(&dtstart)
+            .unwrap()
+            .take(limit)
+            .map(|occurrence| {
+                let local = local_naive(&occurrence);
+                if occurrence.is_date() {
+                    local.format("%Y%m%d").to_string()
+                } else {
+                    local.format("%Y%m%dT%H%M%S").to_string()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn expand_recurrence_sets() {
+        assert_eq!(
+            expand_set(
+                "19970902T090000",
+                &[
+                    ("RRULE", "FREQ=DAILY;COUNT=5"),
+                    ("EXDATE;TZID=NY", "19970903T090000,19970905T090000"),
+                    ("RDATE;TZID=NY", "19970910T090000"),
+                    // Occurrences of the rule are only returned once
+                    ("RDATE;TZID=NY", "19970904T090000"),
+                ],
+                100,
+            ),
+            [
+                "19970902T090000",
+                "19970904T090000",
+                "19970906T090000",
+                "19970910T090000"
+            ],
+        );
+
+        // A DATE removes its whole day, and other date-times are compared as instants
+        assert_eq!(
+            expand_set(
+                "19970902T090000",
+                &[
+                    ("RRULE", "FREQ=DAILY;COUNT=4"),
+                    ("EXDATE;VALUE=DATE", "19970902"),
+                    ("EXDATE", "19970904T130000Z"),
+                    ("EXDATE", "19970905T090000Z"),
+                ],
+                100,
+            ),
+            ["19970903T090000", "19970905T090000"],
+        );
+
+        // Events may have RDATEs only, whose periods are read as their start
+        assert_eq!(
+            expand_set(
+                "19970902T090000",
+                &[
+                    ("RDATE;VALUE=PERIOD", "19970905T130000Z/19970905T150000Z"),
+                    ("RDATE;TZID=NY", "19970901T090000"),
+                ],
+                100,
+            ),
+            ["19970901T090000", "19970902T090000", "19970905T130000"],
+        );
+    }
+
+    #[test]
+    fn expand_exception_rules() {
+        // Only removes DTSTART if it selects it
+        assert_eq!(
+            expand_set(
+                "19970902T090000",
+                &[
+                    ("RRULE", "FREQ=DAILY;COUNT=7"),
+                    ("EXRULE", "FREQ=WEEKLY;BYDAY=SA,SU"),
+                ],
+                100,
+            ),
+            [
+                "19970902T090000",
+                "19970903T090000",
+                "19970904T090000",
+                "19970905T090000",
+                "19970908T090000",
+            ],
+        );
+        assert_eq!(
+            expand_set(
+                "19970902T090000",
+                &[
+                    ("RRULE", "FREQ=DAILY;COUNT=4"),
+                    ("EXRULE", "FREQ=DAILY;INTERVAL=2"),
+                ],
+                100,
+            ),
+            ["19970903T090000", "19970905T090000"],
+        );
+
+        let saturday = "FREQ=WEEKLY;BYDAY=SA".parse::<RRule>().unwrap();
+        let dtstart = IcalDateTime::Date(NaiveDate::from_ymd(1997, 9, 2));
+        assert_eq!(
+            saturday.exceptions(&dtstart).unwrap().next(),
+            Some(IcalDateTime::Date(NaiveDate::from_ymd(1997, 9, 6))),
+        );
+    }
+
+    #[test]
+    fn read_recurrence_sets() {
+        let property = |name: &str, value: &str| Property {
+            name: name.to_string(),
+            params: None,
+            value: Some(value.to_string()),
+        };
+
+        let set = RecurrenceSet::from_properties(&[
+            property("SUMMARY", "Standup"),
+            property("EXDATE", "20200101,20200102T090000"),
+        ])
+        .unwrap();
+        assert!(!set.is_recurring());
+        assert_eq!(
+            set.exdates,
+            [
+                IcalDateTime::Date(NaiveDate::from_ymd(2020, 1, 1)),
+                IcalDateTime::Naive(NaiveDate::from_ymd(2020, 1, 2).and_hms(9, 0, 0)),
+            ],
+        );
+
+        assert_eq!(
+            RecurrenceSet::from_properties(&[property("RDATE", "20200101,tomorrow")]),
+            Err(RecurrenceSetError::Date {
+                property: "RDATE",
+                value: "tomorrow".to_string(),
+            }),
+        );
+        assert_eq!(
+            RecurrenceSet::from_properties(&[property("EXRULE", "COUNT=2")]),
+            Err(RecurrenceSetError::Rule {
+                property: "EXRULE",
+                source: RRuleError::MissingFrequency,
+            }),
+        );
+    }
+
+    #[test]
+    fn find_last_occurrences_before() {
+        let mut timezones = TzCache::new();
+        let mut new_york = |value| {
+            IcalDateTime::from_value(value, Some("America/New_York"), &mut timezones).unwrap()
+        };
+        let dtstart = new_york("19970902T090000");
+        let befores = [
+            new_york("19970902T090000"),
+            new_york("19970902T090001"),
+            new_york("19971102T013000"),
+            new_york("19980101T000000"),
+            new_york("20000301T120000"),
+            IcalDateTime::from_value("20051231T235959Z", None, &mut TzCache::new()).unwrap(),
+        ];
+
+        for rule in [
+            "FREQ=DAILY;INTERVAL=3",
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH;WKST=SU",
+            "FREQ=MONTHLY;BYDAY=-1FR",
+            "FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1",
+            "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29",
+            "FREQ=YEARLY;BYWEEKNO=20;BYDAY=MO",
+            "FREQ=HOURLY;INTERVAL=7;BYDAY=SA",
+            "FREQ=MINUTELY;INTERVAL=90;BYHOUR=9,10",
+            "FREQ=DAILY;UNTIL=19971224T000000Z",
+            "FREQ=MONTHLY;COUNT=10;BYMONTHDAY=31",
+        ] {
+            let rule = rule.parse::<RRule>().unwrap();
+            for before in &befores {
+                let expected = rule
+                    .occurrences(&dtstart)
+                    .unwrap()
+                    .take_while(|occurrence| cmp_date_times(occurrence, before).is_lt())
+                    .last();
+                assert_eq!(
+                    rule.last_occurrence_before(&dtstart, before).unwrap(),
+                    expected,
+                    "{:?} before {:?}",
+                    rule,
+                    before,
+                );
+            }
+        }
+
+        // Rules that never match only have DTSTART
+        let never = "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30"
+            .parse::<RRule>()
+            .unwrap();
+        assert_eq!(
+            never.last_occurrence_before(&dtstart, &befores[5]).unwrap(),
+            Some(dtstart.clone()),
+        );
+        let never = "FREQ=SECONDLY;INTERVAL=2;BYSECOND=1"
+            .parse::<RRule>()
+            .unwrap();
+        assert_eq!(
+            never.last_occurrence_before(&dtstart, &befores[5]).unwrap(),
+            Some(dtstart),
+        );
+    }
+
+    #[test]
+    fn end_occurrences() {
+        let mut timezones = TzCache::new();
+        let mut new_york = |value| {
+            IcalDateTime::from_value(value, Some("America/New_York"), &mut timezones).unwrap()
+        };
+        let utc = |date_time: Option<IcalDateTime>| {
+            date_time
+                .and_then(|date_time| date_time.instant())
+                .unwrap()
+                .format("%Y%m%dT%H%M%SZ")
+                .to_string()
+        };
+
+        // The day of the clock change is an hour shorter, but the event still ends at 17:00
+        let dtstart = new_york("20220301T090000");
+        let dtend = new_york("20220301T170000");
+        let start = new_york("20220313T090000");
+        assert_eq!(
+            utc(occurrence_end(&dtstart, Some(&dtend), None, &start)),
+            "20220313T210000Z",
+        );
+
+        // While hours are exact, days are added to the local time
+        let duration = |days, seconds| Some(IcalDuration { days, seconds });
+        let start = new_york("20220312T090000");
+        assert_eq!(
+            utc(occurrence_end(&dtstart, None, duration(0, 86_400), &start)),
+            "20220313T140000Z",
+        );
+        assert_eq!(
+            utc(occurrence_end(&dtstart, None, duration(1, 0), &start)),
+            "20220313T130000Z",
+        );
+
+        // An end of another time zone is exact as well
+        let dtend = IcalDateTime::from_value("20220301T230000Z", None, &mut TzCache::new());
+        assert_eq!(
+            utc(occurrence_end(&dtstart, dtend.as_ref(), None, &start)),
+            "20220312T230000Z",
+        );
+
+        let date = IcalDateTime::Date(NaiveDate::from_ymd(2022, 3, 12));
+        assert_eq!(
+            occurrence_end(&date, None, None, &date),
+            Some(IcalDateTime::Date(NaiveDate::from_ymd(2022, 3, 13))),
+        );
+        assert_eq!(occurrence_end(&start, None, None, &start), Some(start));
+    }
+
+    #[test]
+    fn keep_local_times_across_clock_changes() {
+        let dtstart = IcalDateTime::from_value(
+            "19971021T090000",
+            Some("America/New_York"),
+            &mut TzCache::new(),
+        )
+        .unwrap();
+        let rule = "FREQ=WEEKLY;COUNT=2".parse::<RRule>().unwrap();
+        let utc = rule
+            .occurrences(&dtstart)
+            .unwrap()
+            .map(|occurrence| match occurrence {
+                IcalDateTime::Tz(date_time, info) => {
+                    assert_eq!(info.tz_id, "America/New_York");
+                    date_time.with_timezone(&Utc).format("%H:%M").to_string()
+                }
+                _ => panic!("the occurrence lost its time zone"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(utc, ["13:00", "14:00"]);
+    }
+
+    #[test]
+    fn expand_dates_and_floating_date_times() {
+        let date = IcalDateTime::Date(NaiveDate::from_ymd(2020, 2, 29));
+        let rule = "FREQ=YEARLY;UNTIL=20280229".parse::<RRule>().unwrap();
+        assert_eq!(
+            rule.occurrences(&date).unwrap().collect::<Vec<_>>(),
+            [2020, 2024, 2028]
+                .iter()
+                .map(|&year| IcalDateTime::Date(NaiveDate::from_ymd(year, 2, 29)))
+                .collect::<Vec<_>>(),
+        );
+
+        let hourly = "FREQ=HOURLY".parse::<RRule>().unwrap();
+        assert_eq!(
+            hourly.occurrences(&date).unwrap_err(),
+            RRuleError::TimeOfDate
+        );
+
+        let floating = IcalDateTime::Naive(NaiveDate::from_ymd(2020, 1, 1).and_hms(23, 0, 0));
+        let rule = "FREQ=DAILY;UNTIL=20200103T230000".parse::<RRule>().unwrap();
+        assert_eq!(rule.occurrences(&floating).unwrap().count(), 3);
+
+        // Rules that never match end
+        let never = "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=30"
+            .parse::<RRule>()
+            .unwrap();
+        assert_eq!(never.occurrences(&floating).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,186 @@
+//! Serialization of the types that can't derive it, with the `serde` feature
+//!
+//! Date-times of a time zone are objects of their RFC 3339 form and of their TZID, as the offset
+//! alone doesn't tell the time zone, and of how their local time was read:
+//!
+//! ```json
+//! { "date_time": "2002-01-10T12:00:00+01:00", "tz_id": "Europe/Paris", "local_time": "single" }
+//! ```
+//!
+//! Properties are objects of their `name`, `params` and `value`, as read.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// For `#[serde(with)]` on the `IcalDateTime::Tz` variant
+pub mod tz_date_time {
+    use super::*;
+    use crate::types::{LocalTime, TzInfo};
+    use chrono::{DateTime, FixedOffset, Offset};
+    use chrono_tz::Tz;
+    use serde::de::Error;
+
+    #[derive(Serialize)]
+    struct TzDateTimeRef<'a> {
+        date_time: DateTime<FixedOffset>,
+        tz_id: &'a str,
+        local_time: LocalTime,
+    }
+
+    #[derive(Deserialize)]
+    struct TzDateTime {
+        date_time: DateTime<FixedOffset>,
+        tz_id: String,
+        #[serde(default)]
+        local_time: LocalTime,
+    }
+
+    pub fn serialize<S: Serializer>(
+        date_time: &DateTime<Tz>,
+        info: &TzInfo,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        TzDateTimeRef {
+            date_time: date_time.with_timezone(&date_time.offset().fix()),
+            tz_id: &info.tz_id,
+            local_time: info.local_time,
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<(DateTime<Tz>, TzInfo), D::Error> {
+        let TzDateTime {
+            date_time,
+            tz_id,
+            local_time,
+        } = TzDateTime::deserialize(deserializer)?;
+        let tz = tz_id.parse::<Tz>().map_err(D::Error::custom)?;
+        let info = TzInfo { tz_id, local_time };
+        Ok((date_time.with_timezone(&tz), info))
+    }
+}
+
+/// For `#[serde(with)]` on `Vec<Property>` fields
+pub mod properties {
+    use super::*;
+    use ical::property::Property;
+
+    type Params = Option<Vec<(String, Vec<String>)>>;
+
+    #[derive(Serialize)]
+    struct PropertyRef<'a> {
+        name: &'a str,
+        params: &'a Params,
+        value: &'a Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct OwnedProperty {
+        name: String,
+        #[serde(default)]
+        params: Params,
+        #[serde(default)]
+        value: Option<String>,
+    }
+
+    pub fn serialize<S: Serializer>(
+        properties: &[Property],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(properties.iter().map(|property| PropertyRef {
+            name: &property.name,
+            params: &property.params,
+            value: &property.value,
+        }))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Property>, D::Error> {
+        let properties = Vec::<OwnedProperty>::deserialize(deserializer)?;
+
+        Ok(properties
+            .into_iter()
+            .map(|property| Property {
+                name: property.name,
+                params: property.params,
+                value: property.value,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::IcalDateTime;
+    use crate::EventsReader;
+    use chrono::TimeZone;
+    use serde_json::json;
+    use std::io::Cursor;
+
+    #[test]
+    fn serialize_date_times() {
+        let date_time =
+            IcalDateTime::from(chrono_tz::Europe::Paris.ymd(2002, 1, 10).and_hms(12, 0, 0));
+        let value = serde_json::to_value(&date_time).unwrap();
+
+        assert_eq!(
+            value,
+            json!({
+                "tz": {
+                    "date_time": "2002-01-10T12:00:00+01:00",
+                    "tz_id": "Europe/Paris",
+                    "local_time": "single",
+                },
+            }),
+        );
+        assert_eq!(
+            serde_json::from_value::<IcalDateTime>(value).unwrap(),
+            date_time
+        );
+
+        let date = IcalDateTime::Date(chrono::NaiveDate::from_ymd(2002, 1, 10));
+        assert_eq!(
+            serde_json::to_value(&date).unwrap(),
+            json!({ "date": "2002-01-10" })
+        );
+
+        let invalid = json!({ "tz": { "date_time": "2002-01-10T12:00:00Z", "tz_id": "Mars" } });
+        assert!(serde_json::from_value::<IcalDateTime>(invalid).is_err());
+    }
+
+    #[test]
+    fn serialize_events() {
+        let calendar = "BEGIN:VCALENDAR\r\n\
+                        BEGIN:VEVENT\r\n\
+                        UID:first\r\n\
+                        DTSTART:20020111T100000Z\r\n\
+                        DTEND;TZID=Europe/Paris:20020111T120000\r\n\
+                        ATTENDEE;CN=Jo;MEMBER=\"mailto:a@example.com\":mailto:jo@example.com\r\n\
+                        X-COLOR;X-SHADE=dark:red\r\n\
+                        PRIORITY:12\r\n\
+                        END:VEVENT\r\n\
+                        END:VCALENDAR\r\n";
+        let event = EventsReader::new(Cursor::new(calendar))
+            .next()
+            .unwrap()
+            .unwrap();
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["dt_start"], json!({ "utc": "2002-01-11T10:00:00Z" }));
+        assert_eq!(
+            value["extra"],
+            json!([{ "name": "X-COLOR", "params": [["X-SHADE", ["dark"]]], "value": "red" }]),
+        );
+        assert_eq!(value.get("warnings"), None);
+
+        let read: crate::Event = serde_json::from_value(value).unwrap();
+        assert_eq!(read.dt_start, event.dt_start);
+        assert_eq!(read.dt_end, event.dt_end);
+        assert_eq!(read.attendees, event.attendees);
+        assert_eq!(read.priority, Some(9));
+        assert_eq!(read.extra[0].params, event.extra[0].params);
+        assert!(read.warnings.is_empty());
+    }
+}
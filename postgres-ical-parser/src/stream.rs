@@ -0,0 +1,209 @@
+//! Asynchronous reading of events, with the `tokio` feature
+//!
+//! The input is normalized, and its properties are read as events, by the same code as for an
+//! [`EventsReader`](crate::EventsReader), so that both return the same events and errors.
+
+use super::input::{properties, Normalizer};
+use super::parser::{CalendarParseError, Event, EventsParser, ParserOptions};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncBufRead;
+
+/// Stream of the events of an [`AsyncBufRead`], which is read as by an
+/// [`EventsReader`](crate::EventsReader)
+pub struct EventsStream<R: AsyncBufRead + Unpin> {
+    inner: R,
+    normalizer: Normalizer,
+    parser: EventsParser,
+    /// Events of the lines that were read, that weren't returned yet
+    events: VecDeque<Result<Event, CalendarParseError>>,
+    done: bool,
+}
+
+impl ParserOptions {
+    pub fn build_stream<R: AsyncBufRead + Unpin>(self, reader: R) -> EventsStream<R> {
+        EventsStream {
+            inner: reader,
+            normalizer: Normalizer::new(self.max_property_length),
+            parser: EventsParser::new(self),
+            events: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> EventsStream<R> {
+    /// A stream with the default [`ParserOptions`]
+    pub fn new(reader: R) -> Self {
+        ParserOptions::default().build_stream(reader)
+    }
+
+    /// Position, starting at 1, of the `VCALENDAR` of the last event returned, for streams that
+    /// concatenate several of them
+    ///
+    /// The events of a chunk of the input are read at once, so this is rather the `VCALENDAR` of
+    /// the last one of them.
+    pub fn calendar_seq(&self) -> usize {
+        self.parser.calendar_seq()
+    }
+
+    /// Reads the events of the complete lines that were normalized
+    fn read_lines(&mut self) {
+        let lines = self.normalizer.take_lines();
+        for property in properties(&lines) {
+            self.events.extend(self.parser.property(property));
+            if self.parser.limit_reached() {
+                self.done = true;
+                return;
+            }
+        }
+    }
+
+    /// Reads the events of the rest of the input, after it ended with `error`, if any
+    fn end(&mut self, error: Option<io::Error>) {
+        self.normalizer.finish();
+        self.read_lines();
+        if self.done {
+            return;
+        }
+
+        let normalizer = &self.normalizer;
+        let error = error.or_else(|| normalizer.is_invalid().then(|| normalizer.error()));
+        let failed = error.is_some();
+        if let Some(error) = error {
+            // Which ends the event being read
+            self.events.extend(self.parser.property(Err(error.into())));
+        }
+        self.events.extend(self.parser.end(failed));
+        self.done = true;
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for EventsStream<R> {
+    type Item = Result<Event, CalendarParseError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(event) = this.events.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+
+            let chunk = match Pin::new(&mut this.inner).poll_fill_buf(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(err)) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(err)) => {
+                    this.end(Some(err));
+                    continue;
+                }
+                Poll::Ready(Ok(chunk)) => chunk,
+            };
+
+            let chunk_len = chunk.len();
+            if chunk_len == 0 {
+                this.end(None);
+                continue;
+            }
+
+            this.normalizer.write(chunk);
+            Pin::new(&mut this.inner).consume(chunk_len);
+            if this.normalizer.is_invalid() {
+                this.end(None);
+            } else {
+                this.read_lines();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventsReader;
+    use futures_executor::block_on;
+    use std::future::poll_fn;
+    use std::io::Cursor;
+
+    /// Results of the events of a stream, in the form of those of an `EventsReader`
+    fn read_stream<R: AsyncBufRead + Unpin>(mut stream: EventsStream<R>) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Some(event) = block_on(poll_fn(|cx| Pin::new(&mut stream).poll_next(cx))) {
+            events.push(describe(event));
+        }
+        events
+    }
+
+    fn read(calendar: &[u8]) -> Vec<String> {
+        EventsReader::new(Cursor::new(calendar))
+            .map(describe)
+            .collect()
+    }
+
+    fn describe(event: Result<Event, CalendarParseError>) -> String {
+        match event {
+            Ok(event) => format!("{} {:?}", event.uid, event.summary),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn stream_events() {
+        let calendars: &[&[u8]] = &[
+            b"\xef\xbb\xbfBEGIN:VCALENDAR\r\n\
+              BEGIN:VEVENT\r\n\
+              UID:first\r\n\
+              DTSTART:20020111T100000Z\r\n\
+              SUMMARY:Lun\r\n ch\r\n\
+              END:VEVENT\r\n\
+              BEGIN:VEVENT\r\n\
+              UID:second\r\n\
+              DTSTART:20020112T100000Z\r\n\
+              SUMMARY;ENCODING=QUOTED-PRINTABLE:Caf=\r\n=C3=A9\r\n\
+              END:VEVENT\r\n\
+              END:VCALENDAR\r\n",
+            b"BEGIN:VCALENDAR\nBEGIN:VEVENT\nUID:first\nDTSTART:20020111T100000Z\n",
+            b"BEGIN:VCALENDAR\nBEGIN:VEVENT\nUID:first\nDTSTART:nope\nEND:VEVENT\n",
+            b"BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:\xff\r\nEND:VEVENT\r\n",
+        ];
+
+        for calendar in calendars {
+            let expected = read(calendar);
+            assert!(!expected.is_empty());
+            assert_eq!(read_stream(EventsStream::new(*calendar)), expected);
+
+            // Chunks of a single byte, so that lines are split
+            let chunked = tokio::io::BufReader::with_capacity(1, *calendar);
+            assert_eq!(read_stream(EventsStream::new(chunked)), expected);
+        }
+    }
+
+    #[test]
+    fn stream_with_options() {
+        let calendar = b"BEGIN:VCALENDAR\r\n\
+                         BEGIN:VEVENT\r\n\
+                         UID:first\r\n\
+                         DTSTART:20020111T100000Z\r\n\
+                         PRIORITY:12\r\n\
+                         END:VEVENT\r\n\
+                         END:VCALENDAR\r\n";
+
+        let stream = EventsReader::builder()
+            .strict(true)
+            .build_stream(&calendar[..]);
+        let events = read_stream(stream);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].contains("PRIORITY"));
+
+        let stream = EventsReader::builder()
+            .max_events(0)
+            .build_stream(&calendar[..]);
+        assert_eq!(read_stream(stream), ["the calendar has more than 0 events"]);
+    }
+}
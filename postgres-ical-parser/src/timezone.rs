@@ -0,0 +1,175 @@
+//! `VTIMEZONE` definitions, generated from the tz database of chrono-tz
+//!
+//! chrono-tz doesn't expose the transitions of a time zone, so they are found by sampling its
+//! offset every day, and searching the exact second at which it changes. Each transition is
+//! written as its own observance rather than as a recurrence rule, which every client understands.
+
+use super::writer::{IcalWriter, Property};
+use chrono::{Duration, FixedOffset, NaiveDate, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use std::io::{self, Write};
+
+/// Offset of a time zone at some instant
+struct Observance {
+    offset: FixedOffset,
+    is_daylight: bool,
+    name: String,
+}
+
+impl Observance {
+    fn at(tz: Tz, utc: &NaiveDateTime) -> Self {
+        let offset = tz.offset_from_utc_datetime(utc);
+
+        Observance {
+            offset: offset.fix(),
+            is_daylight: !offset.dst_offset().is_zero(),
+            name: offset.abbreviation().to_string(),
+        }
+    }
+
+    fn differs(&self, other: &Self) -> bool {
+        self.offset != other.offset || self.is_daylight != other.is_daylight
+    }
+}
+
+impl<W: Write> IcalWriter<W> {
+    /// Writes the definition of `tz` for the years `from_year` to `to_year`, included
+    pub fn vtimezone(&mut self, tz: Tz, from_year: i32, to_year: i32) -> io::Result<()> {
+        let year_start = |year: i32| {
+            let date = NaiveDate::from_ymd_opt(year, 1, 1);
+            date.map(|date| date.and_hms(0, 0, 0))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "year out of range"))
+        };
+        let start = year_start(from_year)?;
+        let end = year_start(to_year.saturating_add(1))?;
+
+        self.begin("VTIMEZONE")?;
+        self.text_property("TZID", tz.name())?;
+
+        let mut current = Observance::at(tz, &start);
+        self.observance(&start, &current, &current)?;
+
+        let mut day = start;
+        while day < end {
+            let next_day = day + Duration::days(1);
+            let next = Observance::at(tz, &next_day);
+
+            if current.differs(&next) {
+                let transition = find_transition(tz, day, next_day, &current);
+                let next = Observance::at(tz, &transition);
+                self.observance(&transition, &current, &next)?;
+                current = next;
+            }
+
+            day = next_day;
+        }
+
+        self.end("VTIMEZONE")
+    }
+
+    /// Writes the observance that starts at the instant `utc`
+    fn observance(
+        &mut self,
+        utc: &NaiveDateTime,
+        from: &Observance,
+        to: &Observance,
+    ) -> io::Result<()> {
+        let component = if to.is_daylight {
+            "DAYLIGHT"
+        } else {
+            "STANDARD"
+        };
+
+        // DTSTART is a local time, in the offset that precedes the observance
+        let local = *utc + Duration::seconds(from.offset.local_minus_utc() as i64);
+
+        self.begin(component)?;
+        self.property(&Property {
+            name: "DTSTART".into(),
+            params: None,
+            value: Some(local.format("%Y%m%dT%H%M%S").to_string()),
+        })?;
+        self.property(&utc_offset("TZOFFSETFROM", from.offset))?;
+        self.property(&utc_offset("TZOFFSETTO", to.offset))?;
+        self.text_property("TZNAME", &to.name)?;
+        self.end(component)
+    }
+}
+
+/// First second after `before`, and up to `after`, whose observance differs from `current`
+fn find_transition(
+    tz: Tz,
+    mut before: NaiveDateTime,
+    mut after: NaiveDateTime,
+    current: &Observance,
+) -> NaiveDateTime {
+    while after - before > Duration::seconds(1) {
+        let middle = before + (after - before) / 2;
+        if Observance::at(tz, &middle).differs(current) {
+            after = middle;
+        } else {
+            before = middle;
+        }
+    }
+
+    after
+}
+
+fn utc_offset(name: &str, offset: FixedOffset) -> Property {
+    let seconds = offset.local_minus_utc();
+    let (sign, seconds) = if seconds < 0 {
+        ('-', -seconds)
+    } else {
+        ('+', seconds)
+    };
+
+    let mut value = format!("{}{:02}{:02}", sign, seconds / 3600, seconds / 60 % 60);
+    if seconds % 60 != 0 {
+        value += &format!("{:02}", seconds % 60);
+    }
+
+    Property {
+        name: name.into(),
+        params: None,
+        value: Some(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_vtimezone() {
+        let mut writer = IcalWriter::new(Vec::new());
+        writer
+            .vtimezone(chrono_tz::Europe::Paris, 2022, 2022)
+            .unwrap();
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert_eq!(
+            written,
+            "BEGIN:VTIMEZONE\r\n\
+             TZID:Europe/Paris\r\n\
+             BEGIN:STANDARD\r\n\
+             DTSTART:20220101T010000\r\n\
+             TZOFFSETFROM:+0100\r\n\
+             TZOFFSETTO:+0100\r\n\
+             TZNAME:CET\r\n\
+             END:STANDARD\r\n\
+             BEGIN:DAYLIGHT\r\n\
+             DTSTART:20220327T020000\r\n\
+             TZOFFSETFROM:+0100\r\n\
+             TZOFFSETTO:+0200\r\n\
+             TZNAME:CEST\r\n\
+             END:DAYLIGHT\r\n\
+             BEGIN:STANDARD\r\n\
+             DTSTART:20221030T030000\r\n\
+             TZOFFSETFROM:+0200\r\n\
+             TZOFFSETTO:+0100\r\n\
+             TZNAME:CET\r\n\
+             END:STANDARD\r\n\
+             END:VTIMEZONE\r\n",
+        );
+    }
+}
@@ -1,6 +1,8 @@
-use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
 use chrono_tz::Tz;
 use ical::property::Property;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 
 type Result<T> = std::result::Result<T, String>;
 
@@ -10,47 +12,293 @@ pub trait IcalType {
     type Output;
 
     fn parse(property: Property) -> Result<Self::Output>;
+
+    /// Like [`parse`](Self::parse), but rejecting the values that are only tolerated
+    fn parse_strict(property: Property) -> Result<Self::Output> {
+        Self::parse(property)
+    }
+
+    /// Like [`parse`](Self::parse), or [`parse_strict`](Self::parse_strict), with the time zones
+    /// that the reader already looked up
+    fn parse_cached(
+        property: Property,
+        strict: bool,
+        timezones: &mut TzCache,
+    ) -> Result<Self::Output> {
+        let _ = timezones;
+        if strict {
+            Self::parse_strict(property)
+        } else {
+            Self::parse(property)
+        }
+    }
+}
+
+/// Time zones of the TZIDs that a reader looked up, as feeds repeat the same few of them on most
+/// of their date-times
+#[derive(Debug, Default)]
+pub struct TzCache(HashMap<String, Option<Tz>>);
+
+impl TzCache {
+    /// Above which TZIDs aren't cached anymore, as they could all be distinct unknown ones
+    const MAX_LEN: usize = 64;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The time zone of `tz_id`, if it's one of the tz database
+    pub fn get(&mut self, tz_id: &str) -> Option<Tz> {
+        if let Some(&tz) = self.0.get(tz_id) {
+            return tz;
+        }
+
+        let tz = tz_id.parse::<Tz>().ok();
+        if self.0.len() < Self::MAX_LEN {
+            self.0.insert(tz_id.to_string(), tz);
+        }
+        tz
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum IcalDateTime {
+    /// A DATE, as of `VALUE=DATE`, such as the start of an all-day event
+    Date(NaiveDate),
+    /// A floating date-time, whose time zone is the one of whoever reads it
     Naive(NaiveDateTime),
     Utc(DateTime<Utc>),
-    Tz(DateTime<Tz>),
+    /// A date-time of a TZID, with how its local time was read
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialization::tz_date_time"))]
+    Tz(DateTime<Tz>, TzInfo),
+}
+
+/// How a date-time of a TZID was read
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TzInfo {
+    /// The TZID parameter as it was written, once unquoted and decoded
+    pub tz_id: String,
+    pub local_time: LocalTime,
+}
+
+/// Whether a local time exists, and once, in its time zone
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LocalTime {
+    /// The local time occurs once, as most do
+    #[default]
+    Single,
+    /// The local time occurs twice, when clocks are set back, and is the first of them
+    Ambiguous,
+    /// The local time is skipped, when clocks are set forward, and is read with the offset from
+    /// before the gap
+    Nonexistent,
+}
+
+/// A date-time of a time zone that was built rather than read, whose TZID is the name of its time
+/// zone
+impl From<DateTime<Tz>> for IcalDateTime {
+    fn from(date_time: DateTime<Tz>) -> Self {
+        let tz_id = date_time.timezone().name().to_string();
+        Self::Tz(
+            date_time,
+            TzInfo {
+                tz_id,
+                local_time: LocalTime::Single,
+            },
+        )
+    }
 }
 
 impl IcalType for IcalDateTime {
     const TYPE_NAME: &'static str = "DATE-TIME";
     type Output = Self;
 
+    /// DATE values are read whether or not they have `VALUE=DATE`
     fn parse(property: Property) -> Result<Self::Output> {
-        let value_string = property.value.unwrap_or_default();
+        Self::parse_cached(property, false, &mut TzCache::new())
+    }
+
+    fn parse_strict(property: Property) -> Result<Self::Output> {
+        Self::parse_cached(property, true, &mut TzCache::new())
+    }
+
+    fn parse_cached(
+        property: Property,
+        strict: bool,
+        timezones: &mut TzCache,
+    ) -> Result<Self::Output> {
+        let is_date =
+            param(&property, "VALUE").is_some_and(|value| value.eq_ignore_ascii_case("DATE"));
+        let tz_id = param(&property, "TZID");
+        let value = property.value.unwrap_or_default();
+
+        match Self::from_value(&value, tz_id.as_deref(), timezones) {
+            Some(date_time) if !strict || date_time.is_date() == is_date => Ok(date_time),
+            _ => Err(value),
+        }
+    }
+}
+
+/// The date-time of `tz` whose local time is `date_time`, as defined by RFC 5545, and whether that
+/// local time was ambiguous or nonexistent
+///
+/// Local times that occur twice, when clocks are set back, are the first of them. Local times that
+/// are skipped, when clocks are set forward, are read with the offset from before the gap.
+fn local_date_time(tz: Tz, date_time: NaiveDateTime) -> Option<(DateTime<Tz>, LocalTime)> {
+    match tz.from_local_datetime(&date_time) {
+        LocalResult::Single(date_time) => Some((date_time, LocalTime::Single)),
+        LocalResult::Ambiguous(earliest, _) => Some((earliest, LocalTime::Ambiguous)),
+        LocalResult::None => {
+            // Gaps are shorter than a day
+            let before = date_time.checked_sub_signed(Duration::days(1))?;
+            let offset = tz.offset_from_local_datetime(&before).earliest()?.fix();
+            let utc =
+                date_time.checked_sub_signed(Duration::seconds(offset.local_minus_utc().into()))?;
+            Some((tz.from_utc_datetime(&utc), LocalTime::Nonexistent))
+        }
+    }
+}
+
+impl IcalDateTime {
+    /// Reads a DATE or DATE-TIME value, and its TZID parameter if any
+    ///
+    /// DATEs have no time zone, so their TZID is ignored.
+    pub(crate) fn from_value(
+        value: &str,
+        tz_id: Option<&str>,
+        timezones: &mut TzCache,
+    ) -> Option<Self> {
+        if value.len() == 8 {
+            return NaiveDate::parse_from_str(value, "%Y%m%d")
+                .ok()
+                .map(Self::Date);
+        }
 
-        let value = value_string.as_str();
         let (date_time, is_utc) = match value.strip_suffix('Z') {
             Some(date_time) => (date_time, true),
             None => (value, false),
         };
 
-        let date_time = match NaiveDateTime::parse_from_str(date_time, "%Y%m%dT%H%M%S") {
-            Ok(date_time) => date_time,
-            Err(_) => return Err(value_string), // TODO
-        };
-
-        let params = property.params.as_deref().unwrap_or_default();
-        let tz_id = params
-            .iter()
-            .rfind(|(n, _)| n == "TZID")
-            .and_then(|(_, v)| v.last());
+        let date_time = NaiveDateTime::parse_from_str(date_time, "%Y%m%dT%H%M%S").ok()?;
 
         match (is_utc, tz_id) {
-            (true, Some(_)) => Err(value_string), // TODO
+            (true, Some(_)) => None, // TODO
             (false, Some(tz_id)) => {
-                let tz = tz_id.parse::<Tz>().map_err(|_| value_string)?; // TODO
-                Ok(Self::Tz(tz.from_local_datetime(&date_time).unwrap())) // TODO unwrap
+                let (date_time, local_time) = local_date_time(timezones.get(tz_id)?, date_time)?;
+                let tz_id = tz_id.to_string();
+                Some(Self::Tz(date_time, TzInfo { tz_id, local_time }))
             }
-            (true, None) => Ok(Self::Utc(Utc.from_utc_datetime(&date_time))),
-            (false, None) => Ok(Self::Naive(date_time)),
+            (true, None) => Some(Self::Utc(Utc.from_utc_datetime(&date_time))),
+            (false, None) => Some(Self::Naive(date_time)),
+        }
+    }
+
+    /// Gives a floating date-time the time zone `tz`
+    ///
+    /// DATEs are kept as they are, as they are days rather than instants.
+    pub fn set_default_timezone(&mut self, tz: Tz) {
+        if let Self::Naive(date_time) = *self {
+            if let Some((date_time, local_time)) = local_date_time(tz, date_time) {
+                let tz_id = tz.name().to_string();
+                *self = Self::Tz(date_time, TzInfo { tz_id, local_time });
+            }
+        }
+    }
+
+    /// Whether this was read from a DATE, rather than a DATE-TIME
+    pub fn is_date(&self) -> bool {
+        matches!(self, Self::Date(_))
+    }
+
+    /// The TZID parameter this was read with, as it was written
+    pub fn tz_id(&self) -> Option<&str> {
+        match self {
+            Self::Tz(_, info) => Some(&info.tz_id),
+            _ => None,
+        }
+    }
+
+    /// Whether the local time of this exists, and once, in its time zone
+    ///
+    /// Date-times that have no time zone are always [`LocalTime::Single`].
+    pub fn local_time(&self) -> LocalTime {
+        match self {
+            Self::Tz(_, info) => info.local_time,
+            _ => LocalTime::Single,
+        }
+    }
+
+    /// The date-time of the same kind as this one, and of the same time zone, whose local time is
+    /// `local`, or the day of `local` if this is a DATE
+    ///
+    /// Local times that don't exist are read as by [`local_date_time`]. `None` if `local` is out
+    /// of the range of the time zone.
+    pub fn at_local_time(&self, local: NaiveDateTime) -> Option<Self> {
+        Some(match self {
+            Self::Date(_) => Self::Date(local.date()),
+            Self::Naive(_) => Self::Naive(local),
+            Self::Utc(_) => Self::Utc(Utc.from_utc_datetime(&local)),
+            Self::Tz(date_time, info) => {
+                let (date_time, local_time) = local_date_time(date_time.timezone(), local)?;
+                let tz_id = info.tz_id.clone();
+                Self::Tz(date_time, TzInfo { tz_id, local_time })
+            }
+        })
+    }
+
+    /// The DATE-TIME property `name` with this value, and its TZID, or the DATE property with
+    /// `VALUE=DATE`
+    ///
+    /// The TZID is the name of the time zone, which the `VTIMEZONE`s that are written use too.
+    pub fn to_property(&self, name: &str) -> Property {
+        const FORMAT: &str = "%Y%m%dT%H%M%S";
+
+        let (value, param) = match self {
+            Self::Date(date) => (date.format("%Y%m%d").to_string(), Some(("VALUE", "DATE"))),
+            Self::Naive(date_time) => (date_time.format(FORMAT).to_string(), None),
+            Self::Utc(date_time) => (format!("{}Z", date_time.format(FORMAT)), None),
+            Self::Tz(date_time, _) => (
+                date_time.format(FORMAT).to_string(),
+                Some(("TZID", date_time.timezone().name())),
+            ),
+        };
+
+        Property {
+            name: name.to_string(),
+            params: param.map(|(param, value)| vec![(param.to_string(), vec![value.to_string()])]),
+            value: Some(value),
+        }
+    }
+
+    /// Compares two date-times that are either both floating, or both at a given instant
+    ///
+    /// DATEs are floating, at the midnight that starts them.
+    pub fn cmp_instant(&self, other: &Self) -> Option<Ordering> {
+        match (self.floating(), other.floating()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => Some(self.instant()?.cmp(&other.instant()?)),
+        }
+    }
+
+    fn floating(&self) -> Option<NaiveDateTime> {
+        match self {
+            Self::Date(date) => Some(date.and_hms(0, 0, 0)),
+            Self::Naive(date_time) => Some(*date_time),
+            _ => None,
+        }
+    }
+
+    /// The instant of this, `None` if it's floating or a DATE
+    pub fn instant(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Date(_) | Self::Naive(_) => None,
+            Self::Utc(date_time) => Some(*date_time),
+            Self::Tz(date_time, _) => Some(date_time.with_timezone(&Utc)),
         }
     }
 }
@@ -71,33 +319,313 @@ impl IcalType for IcalInt {
     }
 }
 
+/// A DURATION, such as `P1DT12H`, split as RFC 5545 requires into the days that are added to the
+/// local time of date-times, and the seconds that are exact
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct IcalDuration {
+    /// Days, and 7 per week
+    pub days: i64,
+    /// Hours, minutes and seconds, in seconds
+    pub seconds: i64,
+}
+
+impl IcalDuration {
+    fn from_value(value: &str) -> Option<Self> {
+        let (sign, value) = match value.as_bytes().first()? {
+            b'-' => (-1, &value[1..]),
+            b'+' => (1, &value[1..]),
+            _ => (1, value),
+        };
+        let mut rest = value.strip_prefix('P')?;
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut duration = Self::default();
+        let mut in_time = false;
+        while !rest.is_empty() {
+            if let Some(time) = rest.strip_prefix('T') {
+                if in_time || time.is_empty() {
+                    return None;
+                }
+                in_time = true;
+                rest = time;
+                continue;
+            }
+
+            let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+            let number = rest[..digits].parse::<i64>().ok()?;
+            let (days, seconds) = match (in_time, rest[digits..].chars().next()?) {
+                (false, 'W') => (number.checked_mul(7)?, 0),
+                (false, 'D') => (number, 0),
+                (true, 'H') => (0, number.checked_mul(3600)?),
+                (true, 'M') => (0, number.checked_mul(60)?),
+                (true, 'S') => (0, number),
+                _ => return None,
+            };
+            duration.days = duration.days.checked_add(days)?;
+            duration.seconds = duration.seconds.checked_add(seconds)?;
+            rest = &rest[digits + 1..];
+        }
+
+        Some(Self {
+            days: sign * duration.days,
+            seconds: sign * duration.seconds,
+        })
+    }
+}
+
+impl IcalType for IcalDuration {
+    const TYPE_NAME: &'static str = "DURATION";
+    type Output = Self;
+
+    fn parse(property: Property) -> Result<Self::Output> {
+        let value = property.value.unwrap_or_default();
+        Self::from_value(&value).ok_or(value)
+    }
+}
+
+/// Latitude and longitude, in degrees
+pub struct IcalGeo;
+
+impl IcalType for IcalGeo {
+    const TYPE_NAME: &'static str = "GEO";
+    type Output = (f32, f32);
+
+    fn parse(property: Property) -> Result<Self::Output> {
+        let value = property.value.unwrap_or_default();
+
+        let coordinates = value.split_once(';').and_then(|(lat, lng)| {
+            let lat = lat.trim().parse::<f32>().ok()?;
+            let lng = lng.trim().parse::<f32>().ok()?;
+            let is_valid = (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lng);
+            is_valid.then_some((lat, lng))
+        });
+
+        coordinates.ok_or(value)
+    }
+}
+
+/// Calendar user, such as the organizer or an attendee of an event
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CalAddress {
+    /// URI of the user, usually `mailto:…`
+    pub address: String,
+    /// Common name
+    pub cn: Option<String>,
+    /// Participation status, as in `ACCEPTED`
+    pub partstat: Option<String>,
+    /// Participation role, as in `REQ-PARTICIPANT`
+    pub role: Option<String>,
+    /// Other parameters, such as `MEMBER` or `DELEGATED-TO`, with all of their values
+    pub params: Vec<(String, Vec<String>)>,
+}
+
+/// Parameters of a CAL-ADDRESS that are fields of [`CalAddress`]
+const CAL_ADDRESS_PARAMS: &[&str] = &["CN", "PARTSTAT", "ROLE"];
+
+impl CalAddress {
+    /// The CAL-ADDRESS property `name` with this value, and its parameters
+    pub fn to_property(&self, name: &str) -> Property {
+        let params = [
+            ("CN", &self.cn),
+            ("PARTSTAT", &self.partstat),
+            ("ROLE", &self.role),
+        ]
+        .iter()
+        .filter_map(|(param, value)| {
+            value
+                .as_ref()
+                .map(|value| (param.to_string(), vec![value.clone()]))
+        })
+        .chain(self.params.iter().cloned())
+        .collect();
+
+        Property {
+            name: name.to_string(),
+            params: Some(params),
+            value: Some(self.address.clone()),
+        }
+    }
+}
+
+impl IcalType for CalAddress {
+    const TYPE_NAME: &'static str = "CAL-ADDRESS";
+    type Output = Self;
+
+    fn parse(property: Property) -> Result<Self::Output> {
+        let cn = param(&property, "CN");
+        let partstat = param(&property, "PARTSTAT");
+        let role = param(&property, "ROLE");
+        let params = property
+            .params
+            .iter()
+            .flatten()
+            .filter(|(name, _)| {
+                !CAL_ADDRESS_PARAMS
+                    .iter()
+                    .any(|param| param.eq_ignore_ascii_case(name))
+            })
+            .map(|(name, values)| {
+                let values = values.iter().map(|value| decode_param_value(value));
+                (name.to_ascii_uppercase(), values.collect())
+            })
+            .collect();
+
+        match property.value {
+            Some(address) if !address.is_empty() => Ok(CalAddress {
+                address,
+                cn,
+                partstat,
+                role,
+                params,
+            }),
+            value => Err(value.unwrap_or_default()),
+        }
+    }
+}
+
+/// Last value of the parameter `name` of a property, unquoted and decoded
+///
+/// Parameters that may have several values, such as `MEMBER`, are rather read by iterating over
+/// the parameters of the property, and decoding each value.
+pub fn param(property: &Property, name: &str) -> Option<String> {
+    property
+        .params
+        .iter()
+        .flatten()
+        .rfind(|(n, _)| n.eq_ignore_ascii_case(name))
+        .and_then(|(_, values)| values.last())
+        .map(|value| decode_param_value(value))
+}
+
+/// Unquotes and decodes a parameter value
+///
+/// Quotes are usually stripped by the property parser already, but not from the values of
+/// properties that were built otherwise. The `^` sequences of [RFC 6868][rfc6868] are decoded.
+///
+/// [rfc6868]: https://datatracker.ietf.org/doc/html/rfc6868
+pub fn decode_param_value(value: &str) -> String {
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+
+    let mut decoded = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '^' {
+            decoded.push(c);
+            continue;
+        }
+
+        // Unknown sequences are kept as they are, as the RFC requires
+        match chars.next() {
+            Some('n') => decoded.push('\n'),
+            Some('\'') => decoded.push('"'),
+            Some('^') => decoded.push('^'),
+            Some(c) => {
+                decoded.push('^');
+                decoded.push(c);
+            }
+            None => decoded.push('^'),
+        }
+    }
+
+    decoded
+}
+
+/// Decodes the value of a property that has `ENCODING=QUOTED-PRINTABLE`, and removes that parameter
+///
+/// Soft line breaks were already removed while unfolding. The decoded bytes are read as UTF-8, and
+/// the `=` that don't start a hexadecimal sequence are kept as they are.
+pub fn decode_quoted_printable(mut property: Property) -> Property {
+    let is_quoted_printable = param(&property, "ENCODING")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("QUOTED-PRINTABLE"));
+    if !is_quoted_printable {
+        return property;
+    }
+
+    if let Some(params) = &mut property.params {
+        params.retain(|(name, _)| !name.eq_ignore_ascii_case("ENCODING"));
+    }
+
+    if let Some(value) = &property.value {
+        let bytes = value.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .filter(|_| bytes[i] == b'=')
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            match hex {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                None => {
+                    decoded.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        property.value = Some(String::from_utf8_lossy(&decoded).into_owned());
+    }
+
+    property
+}
+
 pub struct IcalText;
 
 impl IcalType for IcalText {
     const TYPE_NAME: &'static str = "TEXT";
     type Output = String;
 
+    /// Unknown escape sequences, and a trailing backslash, are kept as they are
     fn parse(property: Property) -> Result<Self::Output> {
-        let value = property.value.unwrap_or_default();
+        unescape(property.value.unwrap_or_default(), false)
+    }
 
-        // We attempt to reuse the string buffer if there's no replacement to be done
-        if let Some(idx) = value.find('\\') {
-            // FIXME: This algorithm is stupid and won't work as expected for i.e. «\\\\;»
-            //        It should also probably fail if an invalid escape sequence is used
+    fn parse_strict(property: Property) -> Result<Self::Output> {
+        unescape(property.value.unwrap_or_default(), true)
+    }
+}
 
-            let mut clone = value[..idx].to_string();
-            clone += &value[idx..]
-                .replace("\\n", "\n")
-                .replace("\\N", "\n")
-                .replace("\\;", ";")
-                .replace("\\,", ",")
-                .replace("\\\\", "\\");
+/// Replaces the escape sequences of a TEXT value in a single pass
+pub(crate) fn unescape(value: String, strict: bool) -> Result<String> {
+    // We attempt to reuse the string buffer if there's no replacement to be done
+    let idx = match value.find('\\') {
+        Some(idx) => idx,
+        None => return Ok(value),
+    };
 
-            Ok(clone)
-        } else {
-            Ok(value)
+    let mut unescaped = String::with_capacity(value.len());
+    unescaped.push_str(&value[..idx]);
+
+    let mut chars = value[idx..].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(c @ ('\\' | ';' | ',')) => unescaped.push(c),
+            Some(c) if !strict => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None if !strict => unescaped.push('\\'),
+            _ => return Err(value),
         }
     }
+
+    Ok(unescaped)
 }
 
 #[cfg(test)]
@@ -133,11 +661,257 @@ mod tests {
 
         assert_eq!(
             IcalDateTime::parse(p!(""; "TZID"="Europe/Paris": "20020110T123045")).unwrap(),
-            IcalDateTime::Tz(Paris.ymd(2002, 1, 10).and_hms(12, 30, 45)),
+            IcalDateTime::from(Paris.ymd(2002, 1, 10).and_hms(12, 30, 45)),
+        );
+
+        assert_eq!(
+            IcalDateTime::parse(p!(""; "VALUE"="DATE": "20020110")).unwrap(),
+            IcalDateTime::Date(NaiveDate::from_ymd(2002, 1, 10)),
         );
+        assert!(IcalDateTime::parse(p!("": "20020110")).unwrap().is_date());
+    }
+
+    #[test]
+    fn parse_ical_date_time_strict() {
+        assert!(IcalDateTime::parse_strict(p!(""; "VALUE"="date": "20020110")).is_ok());
+        assert!(IcalDateTime::parse_strict(p!("": "20020110")).is_err());
+        assert!(IcalDateTime::parse_strict(p!(""; "VALUE"="DATE": "20020110T123045")).is_err());
+        assert!(IcalDateTime::parse_strict(p!("": "20020110T123045")).is_ok());
+    }
+
+    #[test]
+    fn ical_date_time_to_property() {
+        use chrono_tz::Europe::Paris;
+
+        for date_time in [
+            IcalDateTime::Naive(NaiveDate::from_ymd(2002, 1, 10).and_hms(12, 30, 45)),
+            IcalDateTime::Utc(Utc.ymd(2002, 1, 10).and_hms(12, 30, 45)),
+            IcalDateTime::from(Paris.ymd(2002, 1, 10).and_hms(12, 30, 45)),
+            IcalDateTime::Date(NaiveDate::from_ymd(2002, 1, 10)),
+        ] {
+            let property = date_time.to_property("DTSTART");
+            assert_eq!(IcalDateTime::parse(property).unwrap(), date_time);
+        }
+    }
+
+    #[test]
+    fn compare_ical_date_times() {
+        let utc = IcalDateTime::Utc(Utc.ymd(2002, 1, 10).and_hms(11, 0, 0));
+        let paris = IcalDateTime::from(chrono_tz::Europe::Paris.ymd(2002, 1, 10).and_hms(12, 0, 0));
+        let naive = IcalDateTime::Naive(NaiveDate::from_ymd(2002, 1, 10).and_hms(12, 0, 0));
+        let date = IcalDateTime::Date(NaiveDate::from_ymd(2002, 1, 10));
+
+        assert_eq!(utc.cmp_instant(&paris), Some(Ordering::Equal));
+        assert_eq!(naive.cmp_instant(&naive), Some(Ordering::Equal));
+        assert_eq!(naive.cmp_instant(&paris), None);
+        assert_eq!(date.cmp_instant(&naive), Some(Ordering::Less));
+        assert_eq!(date.cmp_instant(&utc), None);
+    }
+
+    #[test]
+    fn parse_ical_duration() {
+        let duration = |value: &str| {
+            IcalDuration::parse(Property {
+                name: "DURATION".into(),
+                params: None,
+                value: Some(value.into()),
+            })
+        };
+        let days_seconds = |days, seconds| Ok(IcalDuration { days, seconds });
+
+        assert_eq!(duration("PT1H0M0S"), days_seconds(0, 3600));
+        assert_eq!(duration("P15DT5H0M20S"), days_seconds(15, 18_020));
+        assert_eq!(duration("P7W"), days_seconds(49, 0));
+        assert_eq!(duration("+P1D"), days_seconds(1, 0));
+        assert_eq!(duration("-PT15M"), days_seconds(0, -900));
+        for invalid in ["", "P", "PT", "P1H", "PT1D", "P1DT", "1D", "P1D2"] {
+            assert!(duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_ical_geo() {
+        assert_eq!(
+            IcalGeo::parse(p!("GEO": "37.5;-122.25")).unwrap(),
+            (37.5, -122.25),
+        );
+        assert!(IcalGeo::parse(p!("GEO": "37.386013")).is_err());
+        assert!(IcalGeo::parse(p!("GEO": "91;0")).is_err());
+    }
+
+    #[test]
+    fn parse_cal_address() {
+        assert_eq!(
+            CalAddress::parse(
+                p!("ATTENDEE"; "CN"="Jo Doe"; "PARTSTAT"="ACCEPTED": "mailto:jo@example.com")
+            )
+            .unwrap(),
+            CalAddress {
+                address: "mailto:jo@example.com".into(),
+                cn: Some("Jo Doe".into()),
+                partstat: Some("ACCEPTED".into()),
+                role: None,
+                params: Vec::new(),
+            },
+        );
+
+        let property = p!("ATTENDEE"; "MEMBER"="mailto:a@example.com"; "X-NOTE"="^'b^'": "mailto:jo@example.com");
+        let mut property_with_members = property.clone();
+        property_with_members.params.as_mut().unwrap()[0]
+            .1
+            .push("mailto:b@example.com".into());
+        let address = CalAddress::parse(property_with_members).unwrap();
+        assert_eq!(
+            address.params,
+            [
+                (
+                    "MEMBER".into(),
+                    vec!["mailto:a@example.com".into(), "mailto:b@example.com".into()],
+                ),
+                ("X-NOTE".into(), vec!["\"b\"".into()]),
+            ],
+        );
+
+        assert!(CalAddress::parse(p!("ATTENDEE"; "CN"="Jo Doe": "")).is_err());
+    }
+
+    #[test]
+    fn decode_quoted_printable_values() {
+        let property = p!("SUMMARY"; "ENCODING"="QUOTED-PRINTABLE"; "CHARSET"="UTF-8": "Caf=C3=A9 =3D=0D=0A=3 =");
+        let property = decode_quoted_printable(property);
+        assert_eq!(property.value.as_deref(), Some("Café =\r\n=3 ="));
+        assert_eq!(
+            property.params,
+            Some(vec![("CHARSET".into(), vec!["UTF-8".into()])]),
+        );
+
+        let property = p!("SUMMARY": "a=3D");
+        assert_eq!(
+            decode_quoted_printable(property).value.as_deref(),
+            Some("a=3D")
+        );
+    }
+
+    fn text(value: &str) -> Property {
+        Property {
+            name: "DESCRIPTION".into(),
+            params: None,
+            value: Some(value.into()),
+        }
+    }
+
+    #[test]
+    fn parse_ical_text() {
+        for (escaped, unescaped) in [
+            ("", ""),
+            ("Lunch", "Lunch"),
+            ("Lunch\\, then coffee", "Lunch, then coffee"),
+            ("a\\;b", "a;b"),
+            ("a\\nb\\Nc", "a\nb\nc"),
+            ("C:\\\\", "C:\\"),
+            ("\\\\;", "\\;"),
+            ("\\\\\\;", "\\;"),
+            ("\\\\n", "\\n"),
+            ("\\\\\\n", "\\\n"),
+            ("\\\\\\\\", "\\\\"),
+            ("é\\,ü", "é,ü"),
+        ] {
+            assert_eq!(IcalText::parse(text(escaped)).unwrap(), unescaped);
+            assert_eq!(IcalText::parse_strict(text(escaped)).unwrap(), unescaped);
+        }
+    }
+
+    #[test]
+    fn unescape_escaped_text() {
+        for text in ["", "a;b,c", "C:\\", "\\;\\,\\n", "\n\\\n", "\\\\"] {
+            let escaped = crate::writer::escape_text(text);
+            assert_eq!(unescape(escaped, true).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn parse_ical_text_invalid_escape() {
+        for (escaped, lenient) in [
+            ("\\t", "\\t"),
+            ("a\\:b", "a\\:b"),
+            ("\\\\\\x", "\\\\x"),
+            ("\\é", "\\é"),
+            ("a\\", "a\\"),
+            ("\\\\\\", "\\\\"),
+        ] {
+            assert_eq!(IcalText::parse(text(escaped)).unwrap(), lenient);
+            assert_eq!(IcalText::parse_strict(text(escaped)).unwrap_err(), escaped);
+        }
+    }
+
+    #[test]
+    fn parse_quoted_params() {
+        assert_eq!(
+            IcalDateTime::parse(p!(""; "TZID"="\"Europe/Paris\"": "20020110T123045")).unwrap(),
+            IcalDateTime::from(
+                chrono_tz::Europe::Paris
+                    .ymd(2002, 1, 10)
+                    .and_hms(12, 30, 45)
+            ),
+        );
+
+        let property =
+            p!("ATTENDEE"; "cn"="\"Doe, ^'Jo^'\""; "ROLE"="a^^b^nc^x": "mailto:jo@example.com");
+        assert_eq!(param(&property, "CN").unwrap(), "Doe, \"Jo\"");
+        assert_eq!(param(&property, "ROLE").unwrap(), "a^b\nc^x");
+        assert_eq!(param(&property, "PARTSTAT"), None);
+    }
+
+    #[test]
+    fn parse_ical_date_time_at_transitions() {
+        use chrono_tz::Europe::Paris;
+
+        // Clocks were set back from 03:00 to 02:00
+        assert_eq!(
+            IcalDateTime::parse(p!(""; "TZID"="Europe/Paris": "20021027T023000")).unwrap(),
+            IcalDateTime::Tz(
+                Utc.ymd(2002, 10, 27)
+                    .and_hms(0, 30, 0)
+                    .with_timezone(&Paris),
+                TzInfo {
+                    tz_id: "Europe/Paris".into(),
+                    local_time: LocalTime::Ambiguous,
+                },
+            ),
+        );
+        // Clocks were set forward from 02:00 to 03:00
+        let skipped =
+            IcalDateTime::parse(p!(""; "TZID"="Europe/Paris": "20020331T023000")).unwrap();
+        assert_eq!(skipped.local_time(), LocalTime::Nonexistent);
+        assert!(matches!(
+            skipped,
+            IcalDateTime::Tz(date_time, _) if date_time == Paris.ymd(2002, 3, 31).and_hms(3, 30, 0)
+        ));
+    }
+
+    #[test]
+    fn cache_time_zones() {
+        let mut timezones = TzCache::new();
+        assert_eq!(
+            timezones.get("Europe/Paris"),
+            Some(chrono_tz::Europe::Paris)
+        );
+        assert_eq!(
+            timezones.get("Europe/Paris"),
+            Some(chrono_tz::Europe::Paris)
+        );
+        assert_eq!(timezones.get("Eastern Standard Time"), None);
+        assert_eq!(timezones.0.len(), 2);
+
+        for i in 0..2 * TzCache::MAX_LEN {
+            timezones.get(&format!("X-{}", i));
+        }
+        assert_eq!(timezones.0.len(), TzCache::MAX_LEN);
+        assert_eq!(timezones.get("Asia/Tokyo"), Some(chrono_tz::Asia::Tokyo));
     }
 
     #[test]
+    #[allow(clippy::redundant_pattern_matching)]
     fn parse_ical_date_time_invalid() {
         assert!(matches!(
             IcalDateTime::parse(p!(""; "TZID"="Middle_Earth/Minas_Tirith": "20020110T123045")),
@@ -0,0 +1,407 @@
+//! Serialization of ical components
+//!
+//! Content lines end with CRLF, and are folded so that none is longer than 75 octets, without
+//! splitting UTF-8 sequences. Parameter values are quoted when needed, and the characters they
+//! can't contain are encoded as defined by [RFC 6868][rfc6868].
+//!
+//! Names that aren't made of letters, digits and dashes, and lines that contain a CR or an LF,
+//! which would start other content lines, fail with [`io::ErrorKind::InvalidInput`].
+//!
+//! [rfc6868]: https://datatracker.ietf.org/doc/html/rfc6868
+
+use super::types::IcalDateTime;
+use super::Event;
+use chrono::Datelike;
+use chrono_tz::Tz;
+pub use ical::property::Property;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Longest content line, in octets, without its line break
+const MAX_LINE_LENGTH: usize = 75;
+
+/// Product identifier of the calendars that are written
+pub const PRODID: &str = "-//edgarogh//postgres-ical//EN";
+
+pub struct IcalWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> IcalWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    pub fn begin(&mut self, component: &str) -> io::Result<()> {
+        check_name(component)?;
+        self.write_line(&format!("BEGIN:{}", component))
+    }
+
+    pub fn end(&mut self, component: &str) -> io::Result<()> {
+        check_name(component)?;
+        self.write_line(&format!("END:{}", component))
+    }
+
+    /// Writes a property whose value is already in its ical form, such as an escaped TEXT
+    pub fn property(&mut self, property: &Property) -> io::Result<()> {
+        check_name(&property.name)?;
+        let mut line = property.name.to_ascii_uppercase();
+
+        for (name, values) in property.params.iter().flatten() {
+            check_name(name)?;
+            line.push(';');
+            line.push_str(&name.to_ascii_uppercase());
+            line.push('=');
+            let values = values.iter().map(|value| param_value(value));
+            line.push_str(&values.collect::<Vec<_>>().join(","));
+        }
+
+        line.push(':');
+        line.push_str(property.value.as_deref().unwrap_or_default());
+        self.write_line(&line)
+    }
+
+    /// Writes a TEXT property without parameters
+    pub fn text_property(&mut self, name: &str, value: &str) -> io::Result<()> {
+        self.property(&Property {
+            name: name.to_string(),
+            params: None,
+            value: Some(escape_text(value)),
+        })
+    }
+
+    /// Writes content lines that are already serialized, such as an entire component
+    ///
+    /// The lines are unfolded, and folded again, so that they may have any line endings.
+    pub fn content(&mut self, content: &str) -> io::Result<()> {
+        let mut line = String::new();
+
+        for physical_line in content.split('\n') {
+            let physical_line = physical_line.strip_suffix('\r').unwrap_or(physical_line);
+
+            match physical_line.strip_prefix(&[' ', '\t'][..]) {
+                Some(continuation) => line.push_str(continuation),
+                None => {
+                    if !line.is_empty() {
+                        self.write_line(&line)?;
+                    }
+                    line = physical_line.to_string();
+                }
+            }
+        }
+
+        if !line.is_empty() {
+            self.write_line(&line)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if line.contains(&['\r', '\n'][..]) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a content line contains a line break",
+            ));
+        }
+
+        let mut rest = line;
+        let mut limit = MAX_LINE_LENGTH;
+
+        while rest.len() > limit {
+            let mut split = limit;
+            while !rest.is_char_boundary(split) {
+                split -= 1;
+            }
+
+            self.out.write_all(&rest.as_bytes()[..split])?;
+            self.out.write_all(b"\r\n ")?;
+            rest = &rest[split..];
+            // The leading space of continuation lines counts
+            limit = MAX_LINE_LENGTH - 1;
+        }
+
+        self.out.write_all(rest.as_bytes())?;
+        self.out.write_all(b"\r\n")
+    }
+}
+
+/// Writes a `VCALENDAR` of events
+///
+/// The `VTIMEZONE`s of the time zones that the events use are written first, as some clients
+/// don't know the tz database names. They cover the years of the date-times of the events.
+pub fn write_calendar<W: Write>(writer: &mut IcalWriter<W>, events: &[Event]) -> io::Result<()> {
+    writer.begin("VCALENDAR")?;
+    writer.text_property("VERSION", "2.0")?;
+    writer.text_property("PRODID", PRODID)?;
+
+    let mut timezones: BTreeMap<&str, (Tz, i32, i32)> = BTreeMap::new();
+    for date_time in events.iter().flat_map(Event::date_times) {
+        if let IcalDateTime::Tz(date_time, _) = date_time {
+            let tz = date_time.timezone();
+            let year = date_time.year();
+            let (_, from, to) = timezones.entry(tz.name()).or_insert((tz, year, year));
+            *from = year.min(*from);
+            *to = year.max(*to);
+        }
+    }
+
+    for &(tz, from, to) in timezones.values() {
+        writer.vtimezone(tz, from, to)?;
+    }
+    for event in events {
+        event.write(writer)?;
+    }
+
+    writer.end("VCALENDAR")
+}
+
+/// Escapes a TEXT value
+pub fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Whether a component, property or parameter name is an iana-token or an x-name
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
+
+fn check_name(name: &str) -> io::Result<()> {
+    if is_valid_name(name) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} isn't a valid name", name),
+        ))
+    }
+}
+
+/// Encodes a parameter value, quoting it if it contains separators
+fn param_value(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '^' => encoded.push_str("^^"),
+            '\n' => encoded.push_str("^n"),
+            '"' => encoded.push_str("^'"),
+            '\r' => {}
+            c => encoded.push(c),
+        }
+    }
+
+    if encoded.contains(&[':', ';', ','][..]) {
+        format!("\"{}\"", encoded)
+    } else {
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CalAddress;
+    use crate::{validate_calendar, EventsReader};
+    use chrono::{TimeZone, Utc};
+    use std::io::Cursor;
+
+    fn write(f: impl FnOnce(&mut IcalWriter<Vec<u8>>) -> io::Result<()>) -> String {
+        let mut writer = IcalWriter::new(Vec::new());
+        f(&mut writer).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn write_text_property() {
+        assert_eq!(
+            write(|w| w.text_property("summary", "Lunch; then, coffee\\tea\nor not")),
+            "SUMMARY:Lunch\\; then\\, coffee\\\\tea\\nor not\r\n",
+        );
+    }
+
+    #[test]
+    fn write_params() {
+        let property = Property {
+            name: "ATTENDEE".into(),
+            params: Some(vec![
+                ("CN".into(), vec!["Doe, \"Jo\"".into()]),
+                ("ROLE".into(), vec!["CHAIR".into()]),
+            ]),
+            value: Some("mailto:jo@example.com".into()),
+        };
+
+        assert_eq!(
+            write(|w| w.property(&property)),
+            "ATTENDEE;CN=\"Doe, ^'Jo^'\";ROLE=CHAIR:mailto:jo@example.com\r\n",
+        );
+    }
+
+    #[test]
+    fn reject_line_breaks() {
+        let write_error = |f: fn(&mut IcalWriter<Vec<u8>>) -> io::Result<()>| {
+            let mut writer = IcalWriter::new(Vec::new());
+            let kind = f(&mut writer).unwrap_err().kind();
+            assert!(writer.into_inner().is_empty());
+            kind
+        };
+        fn property(name: &str, value: &str) -> Property {
+            Property {
+                name: name.into(),
+                params: Some(vec![("X-PARAM".into(), vec!["a\r\nb".into()])]),
+                value: Some(value.into()),
+            }
+        }
+
+        let errors = [
+            write_error(|w| w.property(&property("RRULE", "FREQ=DAILY\r\nATTENDEE:x"))),
+            write_error(|w| w.property(&property("X-A\nB", "value"))),
+            write_error(|w| w.property(&property("X-A:B", "value"))),
+            write_error(|w| w.property(&property("", "value"))),
+            write_error(|w| w.content("SUMMARY:a\rATTENDEE:x")),
+            write_error(|w| w.begin("VEVENT\r\nATTENDEE:x")),
+        ];
+        assert!(errors
+            .iter()
+            .all(|kind| *kind == io::ErrorKind::InvalidInput));
+
+        // Parameter values encode line breaks instead
+        assert_eq!(
+            write(|w| w.property(&property("x-name", "value"))),
+            "X-NAME;X-PARAM=a^nb:value\r\n",
+        );
+    }
+
+    #[test]
+    fn write_content() {
+        assert_eq!(
+            write(|w| w.content("BEGIN:VEVENT\nSUMMARY:Lun\r\n ch\n\nEND:VEVENT")),
+            "BEGIN:VEVENT\r\nSUMMARY:Lunch\r\nEND:VEVENT\r\n",
+        );
+    }
+
+    #[test]
+    fn write_event() {
+        let event = Event {
+            alarm_count: 0,
+            attendees: vec![CalAddress {
+                address: "mailto:jo@example.com".into(),
+                cn: Some("Doe, \"Jo\"".into()),
+                partstat: Some("ACCEPTED".into()),
+                role: None,
+                params: vec![(
+                    "DELEGATED-FROM".into(),
+                    vec![
+                        "mailto:a@example.com".into(),
+                        "mailto:\"b\"@example.com".into(),
+                    ],
+                )],
+            }],
+            created: None,
+            description: Some("Bring: cake, plates\nand forks".repeat(5)),
+            dt_stamp: Some(IcalDateTime::Utc(Utc.ymd(2002, 1, 1).and_hms(8, 0, 0))),
+            dt_start: IcalDateTime::from(
+                chrono_tz::Europe::Paris.ymd(2002, 1, 10).and_hms(12, 0, 0),
+            ),
+            dt_end: None,
+            last_modified: None,
+            geo: None,
+            location: None,
+            organizer: Some(CalAddress {
+                address: "mailto:boss@example.com".into(),
+                cn: None,
+                partstat: None,
+                role: None,
+                params: Vec::new(),
+            }),
+            percent_complete: None,
+            priority: Some(1),
+            recurrence_id: None,
+            this_and_future: false,
+            sequence: 2,
+            summary: Some("Lunch; probably".into()),
+            uid: "lunch@example.com".into(),
+            extra: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        let written = write(|w| event.write(w));
+        let read = EventsReader::new(Cursor::new(written))
+            .next()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(read.uid, event.uid);
+        assert_eq!(read.summary, event.summary);
+        assert_eq!(read.description, event.description);
+        assert_eq!(read.dt_stamp, event.dt_stamp);
+        assert_eq!(read.dt_start, event.dt_start);
+        assert_eq!(read.sequence, event.sequence);
+        assert_eq!(read.organizer, event.organizer);
+        assert_eq!(read.attendees, event.attendees);
+        assert_eq!(read.priority, event.priority);
+    }
+
+    #[test]
+    fn validate_written_calendar() {
+        let event = Event {
+            alarm_count: 0,
+            attendees: Vec::new(),
+            created: None,
+            description: None,
+            dt_stamp: None,
+            dt_start: IcalDateTime::from(
+                chrono_tz::Europe::Paris.ymd(2002, 1, 10).and_hms(12, 0, 0),
+            ),
+            dt_end: None,
+            last_modified: None,
+            geo: None,
+            location: None,
+            organizer: None,
+            percent_complete: None,
+            priority: None,
+            recurrence_id: None,
+            this_and_future: false,
+            sequence: 0,
+            summary: None,
+            uid: "lunch@example.com".into(),
+            extra: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        let written = write(|w| write_calendar(w, &[event]));
+        assert!(validate_calendar(&written).is_ok());
+        assert!(validate_calendar(&written.replace("END:VEVENT", "")).is_err());
+        assert!(validate_calendar("BEGIN:VEVENT\r\nEND:VEVENT\r\n").is_err());
+    }
+
+    #[test]
+    fn fold_long_lines() {
+        let written = write(|w| w.text_property("DESCRIPTION", &"é".repeat(100)));
+
+        for line in written.split_terminator("\r\n") {
+            assert!(line.len() <= MAX_LINE_LENGTH);
+        }
+        let unfolded = written.replace("\r\n ", "");
+        assert_eq!(unfolded, format!("DESCRIPTION:{}\r\n", "é".repeat(100)));
+    }
+}
@@ -0,0 +1,88 @@
+//! Inline attachments, whose `ATTACH` properties hold base64 data rather than a URI
+
+use crate::{guc, Attachment};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use pgx::*;
+use postgres_ical_parser::types::param;
+use postgres_ical_parser::RawComponent;
+
+/// Parameters that name the file of an attachment, as none is standard
+const FILENAME_PARAMS: &[&str] = &["FILENAME", "X-FILENAME", "X-APPLE-FILENAME"];
+
+/// The inline attachments of a component and of its sub-components, such as the sounds of alarms
+pub fn attachments(component: &RawComponent, out: &mut Vec<Attachment>) {
+    // The components left to read, with the UID of the closest component that contains them and
+    // has one, without recursion over the tree
+    let mut stack = vec![(component, None)];
+
+    while let Some((component, uid)) = stack.pop() {
+        let uid = component
+            .properties
+            .iter()
+            .find(|property| property.name == "UID")
+            .and_then(|property| property.value.as_deref())
+            .or(uid);
+
+        component_attachments(component, uid.unwrap_or_default(), out);
+
+        let sub_components = component.components.iter().rev();
+        stack.extend(sub_components.map(|sub_component| (sub_component, uid)));
+    }
+}
+
+/// The inline attachments of a component itself
+fn component_attachments(component: &RawComponent, uid: &str, out: &mut Vec<Attachment>) {
+    for property in &component.properties {
+        if property.name != "ATTACH" {
+            continue;
+        }
+        let is_base64 = param(property, "ENCODING")
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("BASE64"));
+        if !is_base64 {
+            continue;
+        }
+
+        // Checked before decoding too, so that huge attachments aren't decoded to be skipped
+        let max_size = guc::MAX_ATTACHMENT_SIZE.get() as usize * 1024;
+        let value = property.value.as_deref().unwrap_or_default();
+        if value.len() / 4 * 3 > max_size {
+            skip_too_large(uid, value.len() / 4 * 3);
+            continue;
+        }
+
+        let data = match STANDARD.decode(value) {
+            Ok(data) => data,
+            Err(_) if !guc::STRICT.get() => {
+                warning!(
+                    "skipping an attachment of {}, as it isn't valid base64",
+                    uid
+                );
+                continue;
+            }
+            Err(err) => error!("invalid base64 attachment of {}: {}", uid, err),
+        };
+
+        if data.len() > max_size {
+            skip_too_large(uid, data.len());
+            continue;
+        }
+
+        out.push(Attachment {
+            uid: uid.to_string(),
+            fmttype: param(property, "FMTTYPE"),
+            filename: FILENAME_PARAMS
+                .iter()
+                .find_map(|name| param(property, name)),
+            data,
+        });
+    }
+}
+
+fn skip_too_large(uid: &str, size: usize) {
+    warning!(
+        "skipping an attachment of {}, as its {} bytes exceed postgres_ical.max_attachment_size",
+        uid,
+        size,
+    );
+}
@@ -0,0 +1,128 @@
+//! The `ical_cal_address` composite type, of the organizer and attendees of the [`Component`] rows
+//!
+//! pgx can't return composite types that it doesn't define, so their datums are formed by hand.
+//! The Rust types are named after their SQL types, as that's how pgx refers to them in the
+//! generated schema. `_ical_cal_address` is the name of the array type that Postgres creates.
+//!
+//! pgx doesn't order the functions that return these types after their declaration, so the
+//! declaration comes first in the schema.
+//!
+//! [`Component`]: crate::Component
+
+#![allow(non_camel_case_types)]
+
+use pgx::*;
+use postgres_ical_parser::types::CalAddress;
+use serde_json::{Map, Value};
+
+/// Calendar user, with the parameters of its property
+#[derive(Clone, Debug, PartialEq)]
+pub struct ical_cal_address {
+    pub cal_address: String,
+    pub cn: Option<String>,
+    pub partstat: Option<String>,
+    pub role: Option<String>,
+    /// Object of the other parameters, whose values are arrays
+    pub params: Value,
+}
+
+/// Array of [`ical_cal_address`]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct _ical_cal_address(pub Vec<ical_cal_address>);
+
+extension_sql!(
+    r#"
+CREATE TYPE ical_cal_address AS (
+    cal_address text,
+    cn text,
+    partstat text,
+    role text,
+    params jsonb
+);
+"#,
+    name = "ical_cal_address",
+    bootstrap,
+    creates = [Type(ical_cal_address), Type(_ical_cal_address)],
+);
+
+impl From<CalAddress> for ical_cal_address {
+    fn from(address: CalAddress) -> Self {
+        let mut params = Map::new();
+        for (name, values) in address.params {
+            let entry = params
+                .entry(name)
+                .or_insert_with(|| Value::Array(Vec::new()));
+            if let Value::Array(entry) = entry {
+                entry.extend(values.into_iter().map(Value::from));
+            }
+        }
+
+        ical_cal_address {
+            cal_address: address.address,
+            cn: address.cn,
+            partstat: address.partstat,
+            role: address.role,
+            params: Value::Object(params),
+        }
+    }
+}
+
+impl IntoDatum for ical_cal_address {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        let values = [
+            self.cal_address.into_datum(),
+            self.cn.into_datum(),
+            self.partstat.into_datum(),
+            self.role.into_datum(),
+            JsonB(self.params).into_datum(),
+        ];
+
+        let mut datums: Vec<_> = values.iter().map(|value| value.unwrap_or(0)).collect();
+        let mut nulls: Vec<_> = values.iter().map(Option::is_none).collect();
+
+        unsafe {
+            let tuple_desc = pg_sys::lookup_rowtype_tupdesc(Self::type_oid(), -1);
+            let tuple =
+                pg_sys::heap_form_tuple(tuple_desc, datums.as_mut_ptr(), nulls.as_mut_ptr());
+            let datum = pg_sys::heap_copy_tuple_as_datum(tuple, tuple_desc);
+            pg_sys::DecrTupleDescRefCount(tuple_desc);
+            Some(datum)
+        }
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        regtypein("ical_cal_address")
+    }
+}
+
+impl IntoDatum for _ical_cal_address {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        let element_type = ical_cal_address::type_oid();
+        let mut datums: Vec<_> = self
+            .0
+            .into_iter()
+            .map(|address| address.into_datum().unwrap())
+            .collect();
+
+        unsafe {
+            let mut len = 0;
+            let mut by_val = false;
+            let mut align = 0;
+            pg_sys::get_typlenbyvalalign(element_type, &mut len, &mut by_val, &mut align);
+
+            let array = pg_sys::construct_array(
+                datums.as_mut_ptr(),
+                datums.len() as i32,
+                element_type,
+                len as i32,
+                by_val,
+                align,
+            );
+            Some(array as pg_sys::Datum)
+        }
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        unsafe { pg_sys::get_array_type(ical_cal_address::type_oid()) }
+    }
+}
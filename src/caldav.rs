@@ -0,0 +1,347 @@
+//! CalDAV ([RFC 4791](https://datatracker.ietf.org/doc/html/rfc4791)) calendar queries
+//!
+//! Instead of downloading a whole calendar collection, a `calendar-query` REPORT lets the server
+//! select the events that overlap a time range, and return them as separate calendar objects.
+//! Collections themselves are discovered with PROPFIND requests.
+
+use crate::fetch::{self, FetchOptions, PreparedRequest, RequestBody};
+use pgx::*;
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::name::ResolveResult;
+use quick_xml::NsReader;
+use time::{OffsetDateTime, UtcOffset};
+use url::Url;
+
+const DAV: &[u8] = b"DAV:";
+const CALDAV: &[u8] = b"urn:ietf:params:xml:ns:caldav";
+const CALENDARSERVER: &[u8] = b"http://calendarserver.org/ns/";
+const APPLE_ICAL: &[u8] = b"http://apple.com/ns/ical/";
+
+/// Sends a WebDAV request whose body and response are XML, raising an SQL error if it fails
+pub fn request(
+    url: &str,
+    options: &FetchOptions,
+    method: &'static str,
+    depth: u8,
+    body: String,
+) -> Element {
+    let mut options = options.clone();
+    options.headers.extend([
+        (
+            "Content-Type".into(),
+            "application/xml; charset=utf-8".into(),
+        ),
+        ("Depth".into(), depth.to_string()),
+    ]);
+
+    let body = RequestBody {
+        method,
+        data: body.into_bytes(),
+    };
+
+    let mut response = Vec::new();
+    PreparedRequest::with_body(url, &options, Some(body))
+        .perform(&mut response)
+        .into_result()
+        .unwrap_or_else(|err| error!("couldn't query {}: {}", url, err));
+
+    parse_tree(&response)
+        .unwrap_or_else(|err| error!("invalid WebDAV response from {}: {}", url, err))
+}
+
+/// Body of a `calendar-query` REPORT requesting the events that overlap the range between `start`
+/// and `end`, or every event if both are unbounded
+///
+/// With a `uid`, only the events whose UID contains it are requested.
+pub fn calendar_query(
+    start: Option<OffsetDateTime>,
+    end: Option<OffsetDateTime>,
+    uid: Option<&str>,
+) -> String {
+    let mut time_range = String::new();
+    if start.is_some() || end.is_some() {
+        time_range.push_str("<C:time-range");
+        if let Some(start) = start {
+            time_range.push_str(&format!(" start=\"{}\"", format_utc(start)));
+        }
+        if let Some(end) = end {
+            time_range.push_str(&format!(" end=\"{}\"", format_utc(end)));
+        }
+        time_range.push_str("/>");
+    }
+
+    let uid_filter = uid
+        .map(|uid| {
+            format!(
+                "<C:prop-filter name=\"UID\">\
+                 <C:text-match collation=\"i;octet\">{}</C:text-match>\
+                 </C:prop-filter>",
+                escape(uid),
+            )
+        })
+        .unwrap_or_default();
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <C:calendar-query xmlns:D=\"DAV:\" xmlns:C=\"urn:ietf:params:xml:ns:caldav\">\
+         <D:prop><C:calendar-data/></D:prop>\
+         <C:filter><C:comp-filter name=\"VCALENDAR\"><C:comp-filter name=\"VEVENT\">{}{}\
+         </C:comp-filter></C:comp-filter></C:filter>\
+         </C:calendar-query>",
+        time_range, uid_filter,
+    )
+}
+
+/// Formats a date-time as the UTC `DATE-TIME` expected by `time-range` elements
+fn format_utc(date_time: OffsetDateTime) -> String {
+    let utc = date_time.to_offset(UtcOffset::UTC);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        utc.year(),
+        utc.month() as u8,
+        utc.day(),
+        utc.hour(),
+        utc.minute(),
+        utc.second(),
+    )
+}
+
+/// Calendar object returned in a `multistatus` response
+pub struct CalendarObject {
+    /// Absolute URL of the resource
+    pub url: String,
+    /// Calendar containing the object, as iCalendar text
+    pub calendar_data: String,
+}
+
+/// Extracts the calendar objects of a `multistatus` response to a request sent to `base`,
+/// skipping the resources that have no `calendar-data`
+pub fn calendar_objects(multistatus: &Element, base: &str) -> Vec<CalendarObject> {
+    responses(multistatus, base)
+        .filter_map(|(url, response)| {
+            let calendar_data = response.descendant(CALDAV, b"calendar-data")?.text.clone();
+            Some(CalendarObject { url, calendar_data })
+        })
+        .filter(|object| !object.calendar_data.trim().is_empty())
+        .collect()
+}
+
+/// Calendar collection found by [`discover`]
+pub struct Collection {
+    pub url: String,
+    pub display_name: Option<String>,
+    /// Changes whenever an object of the collection does, see
+    /// [caldav-ctag](https://github.com/apple/ccs-calendarserver/blob/master/doc/Extensions/caldav-ctag.txt)
+    pub ctag: Option<String>,
+    pub color: Option<String>,
+}
+
+/// Finds the calendar collections of the current user
+///
+/// `url` may be the server's root (or any URL that reports the `current-user-principal`), a
+/// principal, or a calendar home. The collections are listed from the `calendar-home-set` if one
+/// is found, and from `url` otherwise. Only the `href`s on the origin of `url` are followed, as
+/// they are requested with the credentials of `options`.
+pub fn discover(url: &str, options: &FetchOptions) -> Vec<Collection> {
+    let home = calendar_home(url, options).unwrap_or_else(|| url.to_string());
+
+    let multistatus = request(
+        &home,
+        options,
+        "PROPFIND",
+        1,
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <D:propfind xmlns:D=\"DAV:\" xmlns:CS=\"http://calendarserver.org/ns/\" \
+         xmlns:A=\"http://apple.com/ns/ical/\">\
+         <D:prop><D:resourcetype/><D:displayname/><CS:getctag/><A:calendar-color/></D:prop>\
+         </D:propfind>"
+            .into(),
+    );
+
+    responses(&multistatus, &home)
+        .filter(|(collection, _)| is_same_origin(url, collection))
+        .filter(|(_, response)| {
+            response
+                .descendant(DAV, b"resourcetype")
+                .is_some_and(|types| types.child(CALDAV, b"calendar").is_some())
+        })
+        .map(|(url, response)| Collection {
+            url,
+            display_name: response.descendant_text(DAV, b"displayname"),
+            ctag: response.descendant_text(CALENDARSERVER, b"getctag"),
+            color: response.descendant_text(APPLE_ICAL, b"calendar-color"),
+        })
+        .collect()
+}
+
+/// Follows `current-user-principal` and `calendar-home-set` from `url`
+fn calendar_home(url: &str, options: &FetchOptions) -> Option<String> {
+    let mut url = url.to_string();
+
+    // The principal's properties are only needed when `url` isn't the principal itself
+    for _ in 0..2 {
+        let multistatus = request(
+            &url,
+            options,
+            "PROPFIND",
+            0,
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+             <D:propfind xmlns:D=\"DAV:\" xmlns:C=\"urn:ietf:params:xml:ns:caldav\">\
+             <D:prop><D:current-user-principal/><C:calendar-home-set/></D:prop>\
+             </D:propfind>"
+                .into(),
+        );
+
+        let home = multistatus
+            .descendant(CALDAV, b"calendar-home-set")
+            .and_then(|home| home.child(DAV, b"href"))
+            .and_then(|href| resolve(&url, &href.text))
+            .filter(|home| is_same_origin(&url, home));
+        if home.is_some() {
+            return home;
+        }
+
+        let principal = multistatus
+            .descendant(DAV, b"current-user-principal")
+            .and_then(|principal| principal.child(DAV, b"href"))
+            .and_then(|href| resolve(&url, &href.text))
+            .filter(|principal| is_same_origin(&url, principal))?;
+        if principal == url {
+            return None;
+        }
+        url = principal;
+    }
+
+    None
+}
+
+/// `response` elements of a `multistatus`, with the absolute URL of their resource
+fn responses<'a>(
+    multistatus: &'a Element,
+    base: &'a str,
+) -> impl Iterator<Item = (String, &'a Element)> {
+    multistatus
+        .children(DAV, b"response")
+        .filter_map(move |response| {
+            let url = resolve(base, &response.child(DAV, b"href")?.text)?;
+            Some((url, response))
+        })
+}
+
+fn is_same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => fetch::is_same_origin(&a, &b),
+        _ => false,
+    }
+}
+
+fn resolve(base: &str, href: &str) -> Option<String> {
+    let href = href.trim();
+    match Url::parse(base) {
+        Ok(base) => base.join(href).ok().map(String::from),
+        Err(_) => Some(href.to_string()),
+    }
+}
+
+/// XML element, with its namespace resolved
+///
+/// WebDAV responses are small enough to be loaded as a tree, which is much easier to query than
+/// a stream of events.
+pub struct Element {
+    namespace: Vec<u8>,
+    name: Vec<u8>,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn is(&self, namespace: &[u8], name: &[u8]) -> bool {
+        self.namespace == namespace && self.name == name
+    }
+
+    fn children<'a>(
+        &'a self,
+        namespace: &'a [u8],
+        name: &'a [u8],
+    ) -> impl Iterator<Item = &'a Element> {
+        self.children
+            .iter()
+            .filter(move |child| child.is(namespace, name))
+    }
+
+    fn child(&self, namespace: &[u8], name: &[u8]) -> Option<&Element> {
+        self.children.iter().find(|child| child.is(namespace, name))
+    }
+
+    /// First element with this name among the descendants, depth-first
+    fn descendant(&self, namespace: &[u8], name: &[u8]) -> Option<&Element> {
+        self.children.iter().find_map(|child| {
+            if child.is(namespace, name) {
+                Some(child)
+            } else {
+                child.descendant(namespace, name)
+            }
+        })
+    }
+
+    /// Trimmed text of a descendant, if it isn't empty
+    fn descendant_text(&self, namespace: &[u8], name: &[u8]) -> Option<String> {
+        let text = self.descendant(namespace, name)?.text.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+}
+
+fn parse_tree(xml: &[u8]) -> Result<Element, quick_xml::Error> {
+    let mut reader = NsReader::from_reader(xml);
+    let mut buf = Vec::new();
+    let mut stack: Vec<Element> = Vec::new();
+
+    loop {
+        let (namespace, event) = reader.read_resolved_event_into(&mut buf)?;
+        let namespace = match namespace {
+            ResolveResult::Bound(namespace) => namespace.into_inner().to_vec(),
+            _ => Vec::new(),
+        };
+
+        let element = |name: &[u8]| Element {
+            namespace,
+            name: name.to_vec(),
+            text: String::new(),
+            children: Vec::new(),
+        };
+
+        match event {
+            Event::Start(start) => stack.push(element(start.local_name().as_ref())),
+            Event::Empty(empty) => {
+                let empty = element(empty.local_name().as_ref());
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(empty),
+                    None => return Ok(empty),
+                }
+            }
+            Event::End(_) => {
+                // quick-xml checks that end tags match their start tags
+                let ended = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(ended),
+                    None => return Ok(ended),
+                }
+            }
+            Event::Text(text) => {
+                if let Some(current) = stack.last_mut() {
+                    current.text.push_str(&text.unescape()?);
+                }
+            }
+            Event::CData(data) => {
+                if let Some(current) = stack.last_mut() {
+                    current.text.push_str(&String::from_utf8_lossy(&data));
+                }
+            }
+            Event::Eof => return Err(quick_xml::Error::UnexpectedEof("document".into())),
+            _ => {}
+        }
+
+        buf.clear();
+    }
+}
@@ -0,0 +1,131 @@
+//! Columns of the [`Component`] rows, for the code that reads, writes or compares them by name,
+//! such as foreign tables, synchronized tables and diffs
+
+use crate::cal_address::{_ical_cal_address, ical_cal_address};
+use crate::{Class, Component, ComponentType, Status};
+use pgx::*;
+
+/// Declares the columns, which must match [`Component`]'s fields
+macro_rules! component_columns {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        pub const COLUMNS: &[&str] = &[$(stringify!($field)),*];
+
+        #[allow(deprecated)]
+        pub fn column_type(index: usize) -> pg_sys::Oid {
+            [$(<$ty as IntoDatum>::type_oid()),*][index]
+        }
+
+        pub fn column_datums(component: Component) -> Vec<Option<pg_sys::Datum>> {
+            vec![$(component.$field.into_datum()),*]
+        }
+
+        /// Columns whose values differ between two components
+        pub fn changed_columns(old: &Component, new: &Component) -> Vec<&'static str> {
+            let mut changed = Vec::new();
+            $(
+                if !old.$field.same_value(&new.$field) {
+                    changed.push(stringify!($field));
+                }
+            )*
+            changed
+        }
+    };
+}
+
+/// Equality of column values, as most pgx types don't implement [`PartialEq`]
+trait SameValue {
+    fn same_value(&self, other: &Self) -> bool;
+}
+
+macro_rules! same_value_by_eq {
+    ($($ty:ty),*) => {
+        $(impl SameValue for $ty {
+            fn same_value(&self, other: &Self) -> bool {
+                self == other
+            }
+        })*
+    };
+}
+
+same_value_by_eq!(
+    String,
+    i16,
+    i32,
+    i64,
+    f32,
+    ComponentType,
+    Class,
+    Status,
+    ical_cal_address,
+    _ical_cal_address
+);
+
+/// Timestamps are equal if they represent the same instant, whatever their offset
+impl SameValue for TimestampWithTimeZone {
+    fn same_value(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl SameValue for Timestamp {
+    fn same_value(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: SameValue> SameValue for Option<T> {
+    fn same_value(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.same_value(b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+impl<T: SameValue> SameValue for Vec<T> {
+    fn same_value(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(a, b)| a.same_value(b))
+    }
+}
+
+component_columns! {
+    component_type: ComponentType,
+    attachment: Option<String>,
+    attendees: _ical_cal_address,
+    categories: Vec<String>,
+    class: Option<Class>,
+    comment: Vec<String>,
+    completed: Option<TimestampWithTimeZone>,
+    completed_naive: Option<Timestamp>,
+    created: Option<TimestampWithTimeZone>,
+    created_naive: Option<Timestamp>,
+    description: Option<String>,
+    dt_stamp: Option<TimestampWithTimeZone>,
+    dt_stamp_naive: Option<Timestamp>,
+    dt_start: Option<TimestampWithTimeZone>,
+    dt_start_naive: Option<Timestamp>,
+    dt_end: Option<TimestampWithTimeZone>,
+    dt_end_naive: Option<Timestamp>,
+    due: Option<TimestampWithTimeZone>,
+    due_naive: Option<Timestamp>,
+    duration: Option<crate::Interval>,
+    geo_lat: Option<f32>,
+    geo_lng: Option<f32>,
+    last_modified: Option<TimestampWithTimeZone>,
+    last_modified_naive: Option<Timestamp>,
+    location: Option<String>,
+    organizer: Option<ical_cal_address>,
+    percent_complete: Option<i32>,
+    priority: Option<i32>,
+    recurrence_id: Option<TimestampWithTimeZone>,
+    recurrence_id_naive: Option<Timestamp>,
+    resources: Vec<String>,
+    status: Option<Status>,
+    sequence: i32,
+    summary: Option<String>,
+    uid: String,
+    source_url: Option<String>,
+    calendar_seq: i32,
+    event_ordinality: i64,
+    alarm_count: i32,
+}
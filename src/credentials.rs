@@ -0,0 +1,95 @@
+//! Named secrets that fetch options refer to, so that they don't appear in queries
+//!
+//! Credentials are rows of `postgres_ical.credentials`, whose row-level security policy only shows
+//! them to the members of the role that set them, and to superusers. Each role has its own names,
+//! by which fetch options refer to the credentials of the current user, so that roles can't take
+//! or probe each other's names. The table isn't a configuration table of the extension, so that
+//! `pg_dump` doesn't write its secrets in plain text, and credentials must be set again once a dump
+//! is restored.
+
+use crate::fetch::FetchOptions;
+use pgx::*;
+
+extension_sql!(
+    r#"
+CREATE TABLE postgres_ical.credentials (
+    name text NOT NULL,
+    owner name NOT NULL DEFAULT current_user,
+    kind text NOT NULL CHECK (kind IN (
+        'password', 'bearer_token', 'proxy_password', 'ssl_key_password',
+        'oauth2_client_secret', 'oauth2_refresh_token'
+    )),
+    secret text NOT NULL,
+    PRIMARY KEY (owner, name)
+);
+
+ALTER TABLE postgres_ical.credentials ENABLE ROW LEVEL SECURITY;
+CREATE POLICY owner ON postgres_ical.credentials
+    USING (pg_has_role(owner, 'MEMBER'))
+    WITH CHECK (owner = current_user);
+GRANT USAGE ON SCHEMA postgres_ical TO PUBLIC;
+GRANT SELECT, INSERT, UPDATE, DELETE ON postgres_ical.credentials TO PUBLIC;
+
+CREATE FUNCTION pg_ical_set_credential(name text, kind text, secret text) RETURNS void
+    LANGUAGE sql AS $$
+INSERT INTO postgres_ical.credentials (name, kind, secret) VALUES ($1, $2, $3)
+    ON CONFLICT (owner, name) DO UPDATE SET kind = excluded.kind, secret = excluded.secret;
+$$;
+
+CREATE FUNCTION pg_ical_drop_credential(name text) RETURNS boolean
+    LANGUAGE sql AS $$
+WITH dropped AS (
+    DELETE FROM postgres_ical.credentials
+        WHERE credentials.name = $1 AND owner = current_user
+        RETURNING 1
+)
+SELECT EXISTS (SELECT FROM dropped);
+$$;
+"#,
+    name = "postgres_ical_credentials",
+    requires = ["postgres_ical_feeds"],
+);
+
+impl FetchOptions {
+    /// Fills the options named by the kinds of the [`credentials`][Self::credentials], raising an
+    /// SQL error if the current user has none of one of their names
+    ///
+    /// Must be called from the backend's thread.
+    pub fn apply_credentials(&mut self) {
+        for name in std::mem::take(&mut self.credentials) {
+            let (kind, secret) = Spi::get_two_with_args::<String, String>(
+                "SELECT kind, secret FROM postgres_ical.credentials \
+                    WHERE name = $1 AND owner = current_user",
+                vec![(PgBuiltInOids::TEXTOID.oid(), name.clone().into_datum())],
+            );
+            let (kind, secret) = match (kind, secret) {
+                (Some(kind), Some(secret)) => (kind, secret),
+                _ => error!("credential {:?} doesn't exist", name),
+            };
+
+            let option = match kind.as_str() {
+                "password" => &mut self.password,
+                "bearer_token" => &mut self.bearer_token,
+                "proxy_password" => &mut self.proxy_password,
+                "ssl_key_password" => &mut self.ssl_key_password,
+                "oauth2_client_secret" => match &mut self.oauth2 {
+                    Some(oauth2) => &mut oauth2.client_secret,
+                    None => error!("credential {:?} requires the oauth2 fetch option", name),
+                },
+                "oauth2_refresh_token" => match &mut self.oauth2 {
+                    Some(oauth2) => &mut oauth2.refresh_token,
+                    None => error!("credential {:?} requires the oauth2 fetch option", name),
+                },
+                _ => unreachable!("checked by the table"),
+            };
+
+            if option.is_some() {
+                error!(
+                    "credential {:?} sets the {} fetch option, which is already set",
+                    name, kind,
+                );
+            }
+            *option = Some(secret);
+        }
+    }
+}
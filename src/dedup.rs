@@ -0,0 +1,99 @@
+//! Reconciliation of the components that share a UID, such as in feeds that append updated
+//! versions of their events, or of the same events found in several feeds
+
+use crate::diff::{key, Key};
+use crate::Component;
+use std::collections::{HashMap, HashSet};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+type Version = (i32, Option<OffsetDateTime>, Option<PrimitiveDateTime>);
+
+fn version(component: &Component) -> Version {
+    (
+        component.sequence,
+        component.last_modified.as_ref().map(|time| **time),
+        component.last_modified_naive.as_ref().map(|time| **time),
+    )
+}
+
+/// Keeps the latest version of each component, at the position of its first version
+///
+/// Components are identified by their `uid` and `recurrence_id`, so that the overrides of a
+/// recurring event are kept. Versions are ordered by `sequence`, then by `last_modified`, and the
+/// last of equal versions wins, as updates are usually appended.
+pub fn deduplicate(components: impl Iterator<Item = Component>) -> Vec<Component> {
+    let mut positions: HashMap<Key, usize> = HashMap::new();
+    let mut latest: Vec<Component> = Vec::new();
+
+    for component in components {
+        match positions.get(&key(&component)) {
+            Some(&position) => {
+                if version(&component) >= version(&latest[position]) {
+                    latest[position] = component;
+                }
+            }
+            None => {
+                positions.insert(key(&component), latest.len());
+                latest.push(component);
+            }
+        }
+    }
+
+    latest
+}
+
+/// Times and summary of an event, which the copies of an event share across feeds even when their
+/// UIDs differ
+type Occurrence = (
+    String,
+    Option<OffsetDateTime>,
+    Option<PrimitiveDateTime>,
+    Option<OffsetDateTime>,
+    Option<PrimitiveDateTime>,
+);
+
+fn occurrence(component: &Component) -> Option<Occurrence> {
+    Some((
+        component.summary.clone()?,
+        component.dt_start.as_ref().map(|time| **time),
+        component.dt_start_naive.as_ref().map(|time| **time),
+        component.dt_end.as_ref().map(|time| **time),
+        component.dt_end_naive.as_ref().map(|time| **time),
+    ))
+}
+
+/// Merges the components of several feeds, given in the order of their feeds, collapsing the
+/// copies of an event
+///
+/// Copies share their UID, and are reconciled as by [`deduplicate`], or have the same summary and
+/// times in different feeds, in which case the copy of the first feed is kept. Events without a
+/// summary are only matched by UID, as are those of a same feed, which may well have two distinct
+/// events at the same time.
+pub fn merge(components: impl Iterator<Item = Component>) -> Vec<Component> {
+    let mut feeds: HashMap<Occurrence, Option<String>> = HashMap::new();
+    let mut duplicates = HashSet::new();
+
+    let components = deduplicate(components);
+    for (position, component) in components.iter().enumerate() {
+        let occurrence = match occurrence(component) {
+            Some(occurrence) => occurrence,
+            None => continue,
+        };
+        match feeds.get(&occurrence) {
+            Some(feed) if *feed != component.source_url => {
+                duplicates.insert(position);
+            }
+            Some(_) => {}
+            None => {
+                feeds.insert(occurrence, component.source_url.clone());
+            }
+        }
+    }
+
+    components
+        .into_iter()
+        .enumerate()
+        .filter(|(position, _)| !duplicates.contains(position))
+        .map(|(_, component)| component)
+        .collect()
+}
@@ -0,0 +1,91 @@
+//! Differences between two versions of a calendar
+
+use crate::columns::changed_columns;
+use crate::{Component, ComponentChange};
+use pgx::*;
+use std::collections::HashMap;
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+/// Columns that don't make a component modified
+const IGNORED_COLUMNS: &[&str] = &[
+    "dt_stamp",
+    "dt_stamp_naive",
+    "source_url",
+    "calendar_seq",
+    "event_ordinality",
+];
+
+/// Identity of a component, whose versions share it
+pub(crate) type Key = (String, Option<OffsetDateTime>, Option<PrimitiveDateTime>);
+
+pub(crate) fn key(component: &Component) -> Key {
+    (
+        component.uid.clone(),
+        component.recurrence_id.as_ref().map(|time| **time),
+        component.recurrence_id_naive.as_ref().map(|time| **time),
+    )
+}
+
+fn change(
+    component: &Component,
+    change_type: &str,
+    changed_fields: Vec<String>,
+) -> ComponentChange {
+    let (uid, recurrence_id, recurrence_id_naive) = key(component);
+    ComponentChange {
+        uid,
+        recurrence_id: recurrence_id.map(|time| {
+            TimestampWithTimeZone::new(
+                PrimitiveDateTime::new(time.date(), time.time()),
+                time.offset(),
+            )
+        }),
+        recurrence_id_naive: recurrence_id_naive.map(Timestamp::new),
+        change_type: change_type.to_string(),
+        changed_fields,
+    }
+}
+
+/// Removed and modified components in the order of `old`, then added ones in the order of `new`
+///
+/// If several components share a key, only the last one of each calendar is compared.
+pub fn diff(old: Vec<Component>, new: Vec<Component>) -> Vec<ComponentChange> {
+    let mut new_by_key: HashMap<Key, &Component> = HashMap::new();
+    for component in &new {
+        new_by_key.insert(key(component), component);
+    }
+    let mut old_by_key: HashMap<Key, &Component> = HashMap::new();
+    for component in &old {
+        old_by_key.insert(key(component), component);
+    }
+
+    let mut changes = Vec::new();
+
+    for (old_key, old_component) in old.iter().map(|component| (key(component), component)) {
+        if !std::ptr::eq(old_by_key[&old_key], old_component) {
+            continue;
+        }
+
+        match new_by_key.get(&old_key) {
+            None => changes.push(change(old_component, "removed", Vec::new())),
+            Some(new_component) => {
+                let changed_fields: Vec<String> = changed_columns(old_component, new_component)
+                    .into_iter()
+                    .filter(|column| !IGNORED_COLUMNS.contains(column))
+                    .map(String::from)
+                    .collect();
+                if !changed_fields.is_empty() {
+                    changes.push(change(new_component, "modified", changed_fields));
+                }
+            }
+        }
+    }
+
+    for (new_key, new_component) in new.iter().map(|component| (key(component), component)) {
+        if std::ptr::eq(new_by_key[&new_key], new_component) && !old_by_key.contains_key(&new_key) {
+            changes.push(change(new_component, "added", Vec::new()));
+        }
+    }
+
+    changes
+}
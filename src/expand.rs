@@ -0,0 +1,308 @@
+//! Expansion of the recurring events of calendars into their occurrences
+//!
+//! Floating date-times and dates have no instant of their own, so they are compared with the
+//! bounds of the expanded range in the time zone of the session.
+//!
+//! The events that share the UID of a recurring one and have a `RECURRENCE-ID` override its
+//! occurrences: they are returned instead of the occurrence they replace, and those that have
+//! `RANGE=THISANDFUTURE` move the following occurrences as much as they moved their own.
+
+use crate::{guc, serialize_datetime, Occurrence};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use chrono_tz::Tz;
+use pgx::*;
+use postgres_ical_parser::recurrence::RecurrenceSet;
+use postgres_ical_parser::recurrence::{cmp_date_times, local_naive, occurrence_end};
+use postgres_ical_parser::types::{IcalDateTime, IcalDuration, IcalType};
+use postgres_ical_parser::Event;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::rc::Rc;
+
+/// The time zone of the session, which must be one of the tz database
+pub fn session_timezone() -> Tz {
+    let name = Spi::get_one::<String>("SELECT current_setting('TimeZone')").unwrap_or_default();
+    name.parse().unwrap_or_else(|_| {
+        error!(
+            "the TimeZone of the session, {:?}, isn't a time zone of the tz database",
+            name,
+        )
+    })
+}
+
+pub fn to_utc(date_time: &TimestampWithTimeZone) -> DateTime<Utc> {
+    Utc.timestamp(date_time.unix_timestamp(), date_time.nanosecond())
+}
+
+/// Time range that occurrences must start in, and how far rules may be expanded to find them
+pub struct Range {
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    /// Time zone of the floating date-times
+    timezone: Tz,
+    /// `postgres_ical.max_occurrences`, if any
+    max_occurrences: Option<usize>,
+    /// `postgres_ical.expansion_horizon` days from now, if any
+    horizon: Option<DateTime<Utc>>,
+}
+
+impl Range {
+    /// Half-open range, unbounded on the sides that are `None`
+    ///
+    /// Must be called from the backend's thread, as it reads the time zone of the session and the
+    /// limits of the expansion.
+    pub fn new(start: Option<TimestampWithTimeZone>, end: Option<TimestampWithTimeZone>) -> Self {
+        let max_occurrences = guc::MAX_OCCURRENCES.get();
+        let horizon = guc::EXPANSION_HORIZON.get();
+
+        Range {
+            start: start.as_ref().map(to_utc),
+            end: end.as_ref().map(to_utc),
+            timezone: session_timezone(),
+            max_occurrences: Some(max_occurrences as usize).filter(|&max| max > 0),
+            horizon: Some(horizon)
+                .filter(|&days| days > 0)
+                .and_then(|days| Utc::now().checked_add_signed(Duration::days(days.into()))),
+        }
+    }
+
+    fn instant(&self, date_time: &IcalDateTime) -> DateTime<Utc> {
+        let floating = match date_time {
+            IcalDateTime::Utc(date_time) => return *date_time,
+            IcalDateTime::Tz(date_time, _) => return date_time.with_timezone(&Utc),
+            IcalDateTime::Date(date) => date.and_hms(0, 0, 0),
+            IcalDateTime::Naive(date_time) => *date_time,
+        };
+        let mut date_time = IcalDateTime::Naive(floating);
+        date_time.set_default_timezone(self.timezone);
+        match date_time {
+            IcalDateTime::Tz(date_time, _) => date_time.with_timezone(&Utc),
+            _ => Utc.from_utc_datetime(&floating),
+        }
+    }
+
+    fn contains(&self, date_time: &IcalDateTime) -> bool {
+        let instant = self.instant(date_time);
+        self.start.is_none_or(|start| instant >= start) && self.end.is_none_or(|end| instant < end)
+    }
+
+    /// Whether occurrences starting at `date_time` or later are all past the range
+    fn is_past(&self, date_time: &IcalDateTime) -> bool {
+        self.end.is_some_and(|end| self.instant(date_time) >= end)
+    }
+
+    /// Fails if the `count`th occurrence of `rule`, which starts at `date_time`, is past the
+    /// limits of the expansion
+    pub fn check_limits(&self, rule: impl Display, count: usize, date_time: &IcalDateTime) {
+        if let Some(max_occurrences) = self.max_occurrences.filter(|&max| count > max) {
+            error!(
+                "{} has more than {} occurrences, the postgres_ical.max_occurrences limit; bound \
+                 the expanded range, or raise the limit",
+                rule, max_occurrences,
+            );
+        }
+        if let Some(horizon) = self
+            .horizon
+            .filter(|&horizon| self.instant(date_time) > horizon)
+        {
+            error!(
+                "{} has occurrences after {}, the postgres_ical.expansion_horizon limit; bound \
+                 the expanded range, or raise the limit",
+                rule,
+                horizon.format("%Y-%m-%d %H:%M:%S UTC"),
+            );
+        }
+    }
+}
+
+/// How long the occurrences of an event last
+#[derive(Clone)]
+struct Length {
+    dt_start: IcalDateTime,
+    dt_end: Option<IcalDateTime>,
+    duration: Option<IcalDuration>,
+}
+
+impl Length {
+    fn of(event: &Event) -> Self {
+        let duration = event
+            .extra
+            .iter()
+            .rfind(|property| property.name == "DURATION")
+            .map(|property| {
+                IcalDuration::parse(property.clone()).unwrap_or_else(|value| {
+                    error!("event {}: invalid DURATION: {:?}", event.uid, value)
+                })
+            });
+
+        Length {
+            dt_start: event.dt_start.clone(),
+            dt_end: event.dt_end.clone(),
+            duration,
+        }
+    }
+
+    /// The end of the occurrence that starts at `start`
+    fn end(&self, start: &IcalDateTime) -> IcalDateTime {
+        occurrence_end(&self.dt_start, self.dt_end.as_ref(), self.duration, start)
+            .unwrap_or_else(|| start.clone())
+    }
+}
+
+/// An event that overrides occurrences of the recurring event of its UID
+#[derive(Clone)]
+pub struct Override {
+    recurrence_id: IcalDateTime,
+    length: Length,
+    this_and_future: bool,
+}
+
+impl Override {
+    fn replaces(&self, recurrence_id: &IcalDateTime) -> bool {
+        cmp_date_times(&self.recurrence_id, recurrence_id) == Ordering::Equal
+    }
+}
+
+/// The overrides among `events`, by UID, sorted by `RECURRENCE-ID`
+pub fn overrides(events: &[Event]) -> HashMap<String, Vec<Override>> {
+    let mut overrides = HashMap::<_, Vec<_>>::new();
+    for event in events {
+        if let Some(recurrence_id) = &event.recurrence_id {
+            overrides
+                .entry(event.uid.clone())
+                .or_default()
+                .push(Override {
+                    recurrence_id: recurrence_id.clone(),
+                    length: Length::of(event),
+                    this_and_future: event.this_and_future,
+                });
+        }
+    }
+    for overrides in overrides.values_mut() {
+        overrides.sort_by(|a, b| cmp_date_times(&a.recurrence_id, &b.recurrence_id));
+    }
+    overrides
+}
+
+/// The start and end of the occurrence originally at `recurrence_id`, once moved by the last
+/// `THISANDFUTURE` override that precedes it, which it then lasts as long as
+///
+/// The occurrence is moved by as much local time as the override was, so that it keeps its time
+/// of day when clocks change.
+fn reschedule(
+    recurrence_id: &IcalDateTime,
+    length: &Length,
+    overrides: &[Override],
+) -> (IcalDateTime, IcalDateTime) {
+    let moved = overrides.iter().rfind(|o| {
+        o.this_and_future && cmp_date_times(&o.recurrence_id, recurrence_id) != Ordering::Greater
+    });
+    let start = moved.and_then(|o| {
+        let offset = local_naive(&o.length.dt_start) - local_naive(&o.recurrence_id);
+        let local = local_naive(recurrence_id).checked_add_signed(offset)?;
+        recurrence_id.at_local_time(local)
+    });
+
+    let length = moved.map_or(length, |o| &o.length);
+    let start = start.unwrap_or_else(|| recurrence_id.clone());
+    let end = length.end(&start);
+    (start, end)
+}
+
+fn occurrence(
+    event: &Event,
+    recurrence_id: Option<IcalDateTime>,
+    dt_start: IcalDateTime,
+    dt_end: IcalDateTime,
+) -> Occurrence {
+    let (recurrence_id, recurrence_id_naive) =
+        recurrence_id.map(serialize_datetime).unwrap_or_default();
+    let (dt_start, dt_start_naive) = serialize_datetime(dt_start);
+    let (dt_end, dt_end_naive) = serialize_datetime(dt_end);
+
+    Occurrence {
+        uid: event.uid.clone(),
+        recurrence_id,
+        recurrence_id_naive,
+        dt_start,
+        dt_start_naive,
+        dt_end,
+        dt_end_naive,
+        transparent: event
+            .extra
+            .iter()
+            .rfind(|property| property.name == "TRANSP")
+            .and_then(|property| property.value.as_deref())
+            .is_some_and(|value| value.eq_ignore_ascii_case("TRANSPARENT")),
+    }
+}
+
+/// The occurrences of `event` that start in `range`, in chronological order
+///
+/// An event without an `RRULE` or an `RDATE` has a single occurrence, which keeps its
+/// `RECURRENCE-ID` if it overrides an occurrence of another one. The occurrences of a recurring
+/// event are those of its recurrence set, identified by their original start, as their overrides
+/// would be. `overrides` are those of the event, see [`overrides`], whose own occurrences aren't
+/// returned here.
+pub fn expand(
+    event: Event,
+    overrides: Vec<Override>,
+    range: Rc<Range>,
+) -> Box<dyn Iterator<Item = Occurrence>> {
+    let set = RecurrenceSet::from_properties(&event.extra)
+        .unwrap_or_else(|err| error!("event {}: {}", event.uid, err));
+
+    if !set.is_recurring() {
+        if !range.contains(&event.dt_start) {
+            return Box::new(std::iter::empty());
+        }
+        let recurrence_id = event.recurrence_id.clone();
+        let dt_start = event.dt_start.clone();
+        let dt_end = Length::of(&event).end(&dt_start);
+        return Box::new(std::iter::once(occurrence(
+            &event,
+            recurrence_id,
+            dt_start,
+            dt_end,
+        )));
+    }
+
+    let occurrences = set
+        .occurrences(&event.dt_start)
+        .unwrap_or_else(|err| error!("event {}: {}", event.uid, err));
+
+    // Past the last THISANDFUTURE override, occurrences are all moved alike, so stay in order
+    let last_move = overrides
+        .iter()
+        .rfind(|o| o.this_and_future)
+        .map(|o| o.recurrence_id.clone());
+    let length = Length::of(&event);
+    let end = Rc::clone(&range);
+    let uid = event.uid.clone();
+    Box::new(
+        occurrences
+            .map(move |recurrence_id| {
+                let (start, end) = reschedule(&recurrence_id, &length, &overrides);
+                let is_overridden = overrides.iter().any(|o| o.replaces(&recurrence_id));
+                (recurrence_id, start, end, is_overridden)
+            })
+            .zip(1..)
+            .take_while(move |((recurrence_id, start, _, _), count)| {
+                check_for_interrupts!();
+                let is_moved_alike = last_move.as_ref().is_none_or(|last_move| {
+                    cmp_date_times(recurrence_id, last_move) == Ordering::Greater
+                });
+                if is_moved_alike && end.is_past(start) {
+                    return false;
+                }
+                end.check_limits(format_args!("event {}", uid), *count, recurrence_id);
+                true
+            })
+            .map(|(occurrence, _)| occurrence)
+            .filter(move |(_, start, _, is_overridden)| !is_overridden && range.contains(start))
+            .map(move |(recurrence_id, start, end, _)| {
+                occurrence(&event, Some(recurrence_id), start, end)
+            }),
+    )
+}
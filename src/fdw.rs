@@ -0,0 +1,557 @@
+//! `postgres_ical_fdw`, a foreign data wrapper exposing remote calendars as foreign tables
+//!
+//! ```sql
+//! CREATE SERVER ical FOREIGN DATA WRAPPER postgres_ical_fdw;
+//! CREATE USER MAPPING FOR CURRENT_USER SERVER ical OPTIONS (username 'alice', password '...');
+//! CREATE FOREIGN TABLE team_cal (uid text, summary text, dt_start timestamptz)
+//!     SERVER ical OPTIONS (url 'https://example.com/team.ics');
+//! ```
+//!
+//! The columns of a foreign table are matched by name with the ones returned by
+//! [`pg_ical_curl`][crate::pg_ical_curl], and may be any subset of them. With the `caldav` option,
+//! the `url` is a CalDAV collection that is queried as by
+//! [`pg_caldav_query`][crate::pg_caldav_query].
+//!
+//! The collections of a CalDAV user can be imported as foreign tables at once:
+//!
+//! ```sql
+//! IMPORT FOREIGN SCHEMA caldav FROM SERVER ical INTO calendars
+//!     OPTIONS (url 'https://dav.example.com/');
+//! ```
+
+mod quals;
+
+use crate::columns::{column_datums, column_type, COLUMNS};
+use crate::fetch::{self, FetchOptions};
+use crate::sql::{quote_identifier, quote_literal};
+use crate::{buffered, caldav, caldav_components, pg_ical_internal, Component};
+use pgx::*;
+use quals::PreparedQuals;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_void;
+use std::ptr;
+
+const FOREIGN_SERVER_RELATION_ID: pg_sys::Oid = 1417;
+const USER_MAPPING_RELATION_ID: pg_sys::Oid = 1418;
+const FOREIGN_TABLE_RELATION_ID: pg_sys::Oid = 3118;
+const ANUM_PG_USER_MAPPING_UMOPTIONS: pg_sys::AttrNumber = 4;
+
+/// Options accepted on each kind of object, the others are rejected by the validator
+const SERVER_OPTIONS: &[&str] = &["options", "headers"];
+const USER_MAPPING_OPTIONS: &[&str] = &["username", "password", "bearer_token"];
+const FOREIGN_TABLE_OPTIONS: &[&str] = &["url", "caldav"];
+
+/// Rows are only known once the calendar is fetched, this is a rough guess for the planner
+const ESTIMATED_ROWS: f64 = 1000.0;
+
+extension_sql!(
+    r#"
+CREATE FUNCTION postgres_ical_fdw_handler() RETURNS fdw_handler
+    AS 'MODULE_PATHNAME', 'postgres_ical_fdw_handler' LANGUAGE C STRICT;
+CREATE FUNCTION postgres_ical_fdw_validator(text[], oid) RETURNS void
+    AS 'MODULE_PATHNAME', 'postgres_ical_fdw_validator' LANGUAGE C STRICT;
+CREATE FOREIGN DATA WRAPPER postgres_ical_fdw
+    HANDLER postgres_ical_fdw_handler
+    VALIDATOR postgres_ical_fdw_validator;
+"#,
+    name = "postgres_ical_fdw",
+);
+
+#[no_mangle]
+#[doc(hidden)]
+pub extern "C" fn pg_finfo_postgres_ical_fdw_handler() -> &'static pg_sys::Pg_finfo_record {
+    const V1: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1
+}
+
+#[no_mangle]
+#[doc(hidden)]
+pub extern "C" fn pg_finfo_postgres_ical_fdw_validator() -> &'static pg_sys::Pg_finfo_record {
+    const V1: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn postgres_ical_fdw_handler(_fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    let mut routine = PgBox::<pg_sys::FdwRoutine>::alloc_node(pg_sys::NodeTag_T_FdwRoutine);
+
+    routine.GetForeignRelSize = Some(get_foreign_rel_size);
+    routine.GetForeignPaths = Some(get_foreign_paths);
+    routine.GetForeignPlan = Some(get_foreign_plan);
+    routine.BeginForeignScan = Some(begin_foreign_scan);
+    routine.IterateForeignScan = Some(iterate_foreign_scan);
+    routine.ReScanForeignScan = Some(rescan_foreign_scan);
+    routine.EndForeignScan = Some(end_foreign_scan);
+    routine.ImportForeignSchema = Some(import_foreign_schema);
+
+    routine.into_pg() as pg_sys::Datum
+}
+
+/// Checks the options of the servers, user mappings and foreign tables
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn postgres_ical_fdw_validator(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    let options = unsafe {
+        options(pg_sys::untransformRelOptions(pg_getarg_datum_raw(
+            fcinfo, 0,
+        )))
+    };
+    let catalog = pg_getarg::<pg_sys::Oid>(fcinfo, 1).unwrap_or_default();
+
+    let (kind, accepted) = match catalog {
+        FOREIGN_SERVER_RELATION_ID => ("server", SERVER_OPTIONS),
+        USER_MAPPING_RELATION_ID => ("user mapping", USER_MAPPING_OPTIONS),
+        FOREIGN_TABLE_RELATION_ID => ("foreign table", FOREIGN_TABLE_OPTIONS),
+        _ => ("foreign data wrapper", &[][..]),
+    };
+
+    for (name, value) in &options {
+        if !accepted.contains(&name.as_str()) {
+            error!(
+                "invalid option \"{}\" for a postgres_ical_fdw {}, expected one of: {}",
+                name,
+                kind,
+                accepted.join(", "),
+            );
+        }
+
+        if name == "caldav" {
+            parse_bool(value)
+                .unwrap_or_else(|| error!("option \"caldav\" must be a boolean, not {:?}", value));
+        }
+
+        if name == "options" || name == "headers" {
+            serde_json::from_str::<Value>(value)
+                .unwrap_or_else(|err| error!("option \"{}\" must be a JSON object: {}", name, err));
+        }
+    }
+
+    if catalog == FOREIGN_TABLE_RELATION_ID && !options.iter().any(|(name, _)| name == "url") {
+        error!("postgres_ical_fdw foreign tables require a \"url\" option");
+    }
+
+    if catalog == FOREIGN_SERVER_RELATION_ID {
+        // Raises the same errors as a scan would
+        fetch_options(&options, &[]);
+    }
+
+    pg_return_void()
+}
+
+/// Reads a list of `DefElem`s, as found in the catalogs
+unsafe fn options(list: *mut pg_sys::List) -> Vec<(String, String)> {
+    PgList::<pg_sys::DefElem>::from_pg(list)
+        .iter_ptr()
+        .map(|def| {
+            let name = CStr::from_ptr((*def).defname)
+                .to_string_lossy()
+                .into_owned();
+            let value = CStr::from_ptr(pg_sys::defGetString(def))
+                .to_string_lossy()
+                .into_owned();
+            (name, value)
+        })
+        .collect()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    let value = CString::new(value).ok()?;
+    let mut result = false;
+    unsafe { pg_sys::parse_bool(value.as_ptr(), &mut result) }.then_some(result)
+}
+
+/// Options of the current user's mapping for `server`, or of the `PUBLIC` mapping, if any
+///
+/// Unlike `GetUserMapping`, a missing mapping isn't an error, as public calendars need no
+/// credentials.
+unsafe fn user_mapping_options(server: pg_sys::Oid) -> Vec<(String, String)> {
+    for user in [pg_sys::GetUserId(), pg_sys::InvalidOid] {
+        let cache = pg_sys::SysCacheIdentifier_USERMAPPINGUSERSERVER as i32;
+        let tuple = pg_sys::SearchSysCache2(cache, user as pg_sys::Datum, server as pg_sys::Datum);
+        if tuple.is_null() {
+            continue;
+        }
+
+        let mut is_null = false;
+        let datum =
+            pg_sys::SysCacheGetAttr(cache, tuple, ANUM_PG_USER_MAPPING_UMOPTIONS, &mut is_null);
+        let options = if is_null {
+            Vec::new()
+        } else {
+            options(pg_sys::untransformRelOptions(datum))
+        };
+
+        pg_sys::ReleaseSysCache(tuple);
+        return options;
+    }
+
+    Vec::new()
+}
+
+/// Builds the fetch options from the server's options, with the credentials of the user mapping
+fn fetch_options(server: &[(String, String)], user_mapping: &[(String, String)]) -> FetchOptions {
+    let get = |options: &[(String, String)], name: &str| {
+        options
+            .iter()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.clone())
+    };
+
+    let json =
+        |name| {
+            get(server, name).map(|value| {
+                JsonB(serde_json::from_str(&value).unwrap_or_else(|err| {
+                    error!("option \"{}\" must be a JSON object: {}", name, err)
+                }))
+            })
+        };
+
+    let mut options = FetchOptions::from_jsonb(json("options")).with_headers(json("headers"));
+
+    for (name, value) in user_mapping {
+        match name.as_str() {
+            "username" => options.username = Some(value.clone()),
+            "password" => options.password = Some(value.clone()),
+            "bearer_token" => options.bearer_token = Some(value.clone()),
+            _ => {}
+        }
+    }
+
+    options
+}
+
+/// Scan state, kept in `fdw_state`
+struct ScanState {
+    url: String,
+    /// Whether `url` is a CalDAV collection, to be queried with a REPORT
+    caldav: bool,
+    options: FetchOptions,
+    quals: PreparedQuals,
+    /// Index in [`COLUMNS`] of each attribute of the foreign table, `None` for dropped ones
+    columns: Vec<Option<usize>>,
+    /// Started on the first iteration, so that `EXPLAIN` doesn't fetch anything
+    components: Option<Box<dyn Iterator<Item = Component>>>,
+}
+
+#[pg_guard]
+unsafe extern "C" fn get_foreign_rel_size(
+    _root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    _foreign_table_id: pg_sys::Oid,
+) {
+    (*baserel).rows = ESTIMATED_ROWS;
+}
+
+#[pg_guard]
+unsafe extern "C" fn get_foreign_paths(
+    root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    _foreign_table_id: pg_sys::Oid,
+) {
+    // Fetching dominates the cost, whatever the number of rows
+    let path = pg_sys::create_foreignscan_path(
+        root,
+        baserel,
+        ptr::null_mut(),
+        (*baserel).rows,
+        1000.0,
+        1000.0 + (*baserel).rows,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+    );
+    pg_sys::add_path(baserel, path as *mut pg_sys::Path);
+}
+
+#[pg_guard]
+unsafe extern "C" fn get_foreign_plan(
+    _root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    foreign_table_id: pg_sys::Oid,
+    _best_path: *mut pg_sys::ForeignPath,
+    tlist: *mut pg_sys::List,
+    scan_clauses: *mut pg_sys::List,
+    outer_plan: *mut pg_sys::Plan,
+) -> *mut pg_sys::ForeignScan {
+    let (fdw_private, fdw_exprs) = quals::extract(scan_clauses, (*baserel).relid, foreign_table_id);
+
+    // Every qual is checked by the executor, including the pushed down ones
+    let scan_clauses = pg_sys::extract_actual_clauses(scan_clauses, false);
+
+    pg_sys::make_foreignscan(
+        tlist,
+        scan_clauses,
+        (*baserel).relid,
+        fdw_exprs,
+        fdw_private,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        outer_plan,
+    )
+}
+
+#[pg_guard]
+unsafe extern "C" fn begin_foreign_scan(node: *mut pg_sys::ForeignScanState, eflags: i32) {
+    if eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as i32 != 0 {
+        return;
+    }
+
+    let relation = PgRelation::from_pg((*node).ss.ss_currentRelation);
+    let table = pg_sys::GetForeignTable(relation.oid());
+    let server = pg_sys::GetForeignServer((*table).serverid);
+
+    let table_options = options((*table).options);
+    let table_option = |name| {
+        table_options
+            .iter()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let url = table_option("url")
+        .unwrap_or_else(|| error!("foreign table {} has no \"url\" option", relation.name()))
+        .to_string();
+    let caldav = table_option("caldav").and_then(parse_bool) == Some(true);
+
+    let options = fetch_options(
+        &options((*server).options),
+        &user_mapping_options((*server).serverid),
+    );
+
+    let tuple_desc = PgTupleDesc::from_relation(&relation);
+    let columns = tuple_desc
+        .iter()
+        .map(|attribute| {
+            if attribute.is_dropped() {
+                return None;
+            }
+
+            let name = attribute.name();
+            let index = COLUMNS
+                .iter()
+                .position(|column| *column == name)
+                .unwrap_or_else(|| {
+                    error!(
+                        "column \"{}\" of foreign table {} isn't a postgres_ical_fdw column, expected one of: {}",
+                        name,
+                        relation.name(),
+                        COLUMNS.join(", "),
+                    )
+                });
+
+            let expected = column_type(index);
+            if attribute.atttypid != expected {
+                let expected = CStr::from_ptr(pg_sys::format_type_be(expected));
+                error!(
+                    "column \"{}\" of foreign table {} must be of type {}",
+                    name,
+                    relation.name(),
+                    expected.to_string_lossy(),
+                );
+            }
+
+            Some(index)
+        })
+        .collect();
+
+    let plan = (*node).ss.ps.plan as *mut pg_sys::ForeignScan;
+    let quals = PreparedQuals::new(plan, &mut (*node).ss.ps);
+
+    let state = ScanState {
+        url,
+        caldav,
+        options,
+        quals,
+        columns,
+        components: None,
+    };
+    // Dropped with the query's memory context, even if the scan fails and never ends, so that its
+    // download is aborted
+    (*node).fdw_state =
+        PgMemoryContexts::CurrentMemoryContext.leak_and_drop_on_delete(state) as *mut c_void;
+}
+
+#[pg_guard]
+unsafe extern "C" fn iterate_foreign_scan(
+    node: *mut pg_sys::ForeignScanState,
+) -> *mut pg_sys::TupleTableSlot {
+    let state = &mut *((*node).fdw_state as *mut ScanState);
+    let slot = (*node).ss.ss_ScanTupleSlot;
+
+    if let Some(clear) = (*(*slot).tts_ops).clear {
+        clear(slot);
+    }
+
+    let ScanState {
+        url,
+        caldav,
+        options,
+        quals,
+        columns,
+        components,
+    } = state;
+
+    let components = components.get_or_insert_with(|| {
+        let filter = quals.evaluate((*node).ss.ps.ps_ExprContext);
+        if filter.matches_nothing() {
+            return Box::new(std::iter::empty());
+        }
+
+        let components: Box<dyn Iterator<Item = Component>> = if *caldav {
+            let (start, end) = filter.time_range();
+            let query = caldav::calendar_query(start, end, filter.uid());
+            Box::new(caldav_components(url, options, query))
+        } else {
+            let download = fetch::curl_get(url, options);
+            let url = url.clone();
+            Box::new(
+                pg_ical_internal(buffered(download)).map(move |component| Component {
+                    source_url: Some(url.clone()),
+                    ..component
+                }),
+            )
+        };
+
+        // Skips the conversion of the components that the executor would filter out anyway
+        Box::new(components.filter(move |component| filter.matches(component)))
+    });
+
+    let component = match components.next() {
+        Some(component) => component,
+        // An empty slot ends the scan
+        None => return slot,
+    };
+
+    let datums = column_datums(component);
+    for (i, column) in columns.iter().enumerate() {
+        let datum = column.and_then(|index| datums[index]);
+        *(*slot).tts_values.add(i) = datum.unwrap_or(0);
+        *(*slot).tts_isnull.add(i) = datum.is_none();
+    }
+
+    pg_sys::ExecStoreVirtualTuple(slot)
+}
+
+#[pg_guard]
+unsafe extern "C" fn rescan_foreign_scan(node: *mut pg_sys::ForeignScanState) {
+    let state = &mut *((*node).fdw_state as *mut ScanState);
+    state.components = None;
+}
+
+#[pg_guard]
+unsafe extern "C" fn end_foreign_scan(node: *mut pg_sys::ForeignScanState) {
+    let state = (*node).fdw_state as *mut ScanState;
+    if !state.is_null() {
+        // The state itself is dropped with the memory context, but the transfer can end now
+        (*state).components = None;
+    }
+}
+
+/// Creates a foreign table for each calendar collection found by [`caldav::discover`]
+///
+/// The remote schema must be `caldav`, and the `url` option is the URL from which collections are
+/// discovered. Tables are named after the display names of the collections, and have every
+/// column.
+#[pg_guard]
+unsafe extern "C" fn import_foreign_schema(
+    stmt: *mut pg_sys::ImportForeignSchemaStmt,
+    server_oid: pg_sys::Oid,
+) -> *mut pg_sys::List {
+    let remote_schema = CStr::from_ptr((*stmt).remote_schema).to_string_lossy();
+    if remote_schema != "caldav" {
+        error!(
+            "postgres_ical_fdw can only import the \"caldav\" schema, not \"{}\"",
+            remote_schema,
+        );
+    }
+
+    let mut url = None;
+    for (name, value) in options((*stmt).options) {
+        match name.as_str() {
+            "url" => url = Some(value),
+            _ => error!(
+                "invalid option \"{}\" for IMPORT FOREIGN SCHEMA, expected one of: url",
+                name,
+            ),
+        }
+    }
+    let url = url.unwrap_or_else(|| error!("IMPORT FOREIGN SCHEMA requires a \"url\" option"));
+
+    let server = pg_sys::GetForeignServer(server_oid);
+    let options = fetch_options(
+        &options((*server).options),
+        &user_mapping_options(server_oid),
+    );
+    let server_name = CStr::from_ptr((*server).servername).to_string_lossy();
+
+    let columns = COLUMNS
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            let column_type = CStr::from_ptr(pg_sys::format_type_be(column_type(index)));
+            format!(
+                "{} {}",
+                quote_identifier(column),
+                column_type.to_string_lossy(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut table_names = HashSet::new();
+    let mut commands = PgList::<std::os::raw::c_char>::new();
+
+    for collection in caldav::discover(&url, &options) {
+        let base_name = table_name(&collection);
+        let mut name = base_name.clone();
+        let mut suffix = 1;
+        while !table_names.insert(name.clone()) {
+            suffix += 1;
+            name = format!("{}_{}", base_name, suffix);
+        }
+
+        let command = format!(
+            "CREATE FOREIGN TABLE {} ({}) SERVER {} OPTIONS (url {}, caldav 'true')",
+            quote_identifier(&name),
+            columns,
+            quote_identifier(&server_name),
+            quote_literal(&collection.url),
+        );
+        let command = CString::new(command).unwrap();
+        commands.push(pg_sys::pstrdup(command.as_ptr()));
+    }
+
+    commands.into_pg()
+}
+
+/// Lowercase identifier made of the display name of a collection, or of the last segment of its
+/// URL
+fn table_name(collection: &caldav::Collection) -> String {
+    let name = collection.display_name.clone().unwrap_or_else(|| {
+        collection
+            .url
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    });
+
+    let mut table_name = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            table_name.push(c);
+        } else if !table_name.is_empty() && !table_name.ends_with('_') {
+            table_name.push('_');
+        }
+    }
+
+    match table_name.trim_end_matches('_') {
+        "" => "calendar".to_string(),
+        table_name => table_name.to_string(),
+    }
+}
@@ -0,0 +1,314 @@
+//! Quals pushed down to the scans of foreign tables
+//!
+//! Comparisons of `dt_start` or `dt_end` with a `timestamptz`, and of `uid` with a `text`, whose
+//! other side is known when the scan starts (a constant, a parameter, `now()`…) are evaluated
+//! once per scan. Components that can't match are then skipped before they're converted into
+//! tuples, and CalDAV tables only request the matching ones from the server. The quals are still
+//! checked by the executor, so pushing them down only has to be conservative. `uid` is compared
+//! byte for byte, so its comparisons are only pushed down under deterministic collations.
+
+use crate::Component;
+use pgx::*;
+use std::cmp::Ordering;
+use std::ffi::c_void;
+use time::{Duration, OffsetDateTime};
+
+const TEXT_EQ: pg_sys::Oid = 98;
+const TIMESTAMPTZ_EQ: pg_sys::Oid = 1320;
+const TIMESTAMPTZ_LT: pg_sys::Oid = 1322;
+const TIMESTAMPTZ_LE: pg_sys::Oid = 1323;
+const TIMESTAMPTZ_GT: pg_sys::Oid = 1324;
+const TIMESTAMPTZ_GE: pg_sys::Oid = 1325;
+
+const DEFAULT_COLLATION: pg_sys::Oid = 100;
+const C_COLLATION: pg_sys::Oid = 950;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Column {
+    DtStart,
+    DtEnd,
+    Uid,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    /// Comparison obtained by swapping the operands
+    fn commute(self) -> Self {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Equal => Comparison::Equal,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Greater => Comparison::Less,
+        }
+    }
+
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+            Comparison::Greater => ordering == Ordering::Greater,
+        }
+    }
+
+    /// Whether the column is bounded from below, or from above, by the value
+    fn bounds(self) -> (bool, bool) {
+        match self {
+            Comparison::Less | Comparison::LessOrEqual => (false, true),
+            Comparison::Equal => (true, true),
+            Comparison::GreaterOrEqual | Comparison::Greater => (true, false),
+        }
+    }
+}
+
+/// `column <comparison> value`, where the value is the expression of the same index in the
+/// plan's `fdw_exprs`
+///
+/// Quals are kept in the plan's `fdw_private` as integers, which can be copied by the planner.
+#[derive(Clone, Copy)]
+pub struct Qual {
+    column: Column,
+    comparison: Comparison,
+}
+
+impl Qual {
+    const COLUMNS: [Column; 3] = [Column::DtStart, Column::DtEnd, Column::Uid];
+    const COMPARISONS: [Comparison; 5] = [
+        Comparison::Less,
+        Comparison::LessOrEqual,
+        Comparison::Equal,
+        Comparison::GreaterOrEqual,
+        Comparison::Greater,
+    ];
+
+    fn encode(self) -> i32 {
+        self.column as i32 * 8 + self.comparison as i32
+    }
+
+    fn decode(code: i32) -> Self {
+        Qual {
+            column: Self::COLUMNS[code as usize / 8],
+            comparison: Self::COMPARISONS[code as usize % 8],
+        }
+    }
+}
+
+/// Finds the quals of `scan_clauses` that can be pushed down, returning the lists to store as the
+/// plan's `fdw_private` and `fdw_exprs`
+pub unsafe fn extract(
+    scan_clauses: *mut pg_sys::List,
+    relid: pg_sys::Index,
+    table: pg_sys::Oid,
+) -> (*mut pg_sys::List, *mut pg_sys::List) {
+    let mut private: *mut pg_sys::List = std::ptr::null_mut();
+    let mut exprs = PgList::<pg_sys::Expr>::new();
+
+    for info in PgList::<pg_sys::RestrictInfo>::from_pg(scan_clauses).iter_ptr() {
+        if (*info).pseudoconstant {
+            continue;
+        }
+
+        if let Some((qual, value)) = recognize((*info).clause, relid, table) {
+            private = pg_sys::lappend_int(private, qual.encode());
+            exprs.push(value);
+        }
+    }
+
+    (private, exprs.into_pg())
+}
+
+unsafe fn recognize(
+    clause: *mut pg_sys::Expr,
+    relid: pg_sys::Index,
+    table: pg_sys::Oid,
+) -> Option<(Qual, *mut pg_sys::Expr)> {
+    if (*(clause as *mut pg_sys::Node)).type_ != pg_sys::NodeTag_T_OpExpr {
+        return None;
+    }
+
+    let op = clause as *mut pg_sys::OpExpr;
+    let (comparison, is_text) = match (*op).opno {
+        TEXT_EQ if is_deterministic((*op).inputcollid) => (Comparison::Equal, true),
+        TIMESTAMPTZ_LT => (Comparison::Less, false),
+        TIMESTAMPTZ_LE => (Comparison::LessOrEqual, false),
+        TIMESTAMPTZ_EQ => (Comparison::Equal, false),
+        TIMESTAMPTZ_GE => (Comparison::GreaterOrEqual, false),
+        TIMESTAMPTZ_GT => (Comparison::Greater, false),
+        _ => return None,
+    };
+
+    let args = PgList::<pg_sys::Expr>::from_pg((*op).args);
+    if args.len() != 2 {
+        return None;
+    }
+    let (left, right) = (args.get_ptr(0)?, args.get_ptr(1)?);
+
+    let (var, value, comparison) = match (column_var(left, relid), column_var(right, relid)) {
+        (Some(var), None) => (var, right, comparison),
+        (None, Some(var)) => (var, left, comparison.commute()),
+        _ => return None,
+    };
+
+    if !pg_sys::is_pseudo_constant_clause(value as *mut pg_sys::Node) {
+        return None;
+    }
+
+    let name = pg_sys::get_attname(table, (*var).varattno, false);
+    let column = match (std::ffi::CStr::from_ptr(name).to_bytes(), is_text) {
+        (b"dt_start", false) => Column::DtStart,
+        (b"dt_end", false) => Column::DtEnd,
+        (b"uid", true) => Column::Uid,
+        _ => return None,
+    };
+
+    Some((Qual { column, comparison }, value))
+}
+
+/// Whether the strings that are equal under `collation` are the same bytes
+unsafe fn is_deterministic(collation: pg_sys::Oid) -> bool {
+    match collation {
+        pg_sys::InvalidOid | DEFAULT_COLLATION | C_COLLATION => true,
+        #[cfg(not(any(feature = "pg10", feature = "pg11")))]
+        collation => pg_sys::get_collation_isdeterministic(collation),
+        // Nondeterministic collations were added in PostgreSQL 12
+        #[cfg(any(feature = "pg10", feature = "pg11"))]
+        _ => true,
+    }
+}
+
+/// The expression if it's a column of the scanned relation
+unsafe fn column_var(expr: *mut pg_sys::Expr, relid: pg_sys::Index) -> Option<*mut pg_sys::Var> {
+    if (*(expr as *mut pg_sys::Node)).type_ != pg_sys::NodeTag_T_Var {
+        return None;
+    }
+
+    let var = expr as *mut pg_sys::Var;
+    ((*var).varno == relid && (*var).varlevelsup == 0 && (*var).varattno > 0).then_some(var)
+}
+
+/// Pushed down quals, ready to be evaluated
+pub struct PreparedQuals(Vec<(Qual, *mut pg_sys::ExprState)>);
+
+impl PreparedQuals {
+    /// Initializes the expressions of a plan's quals, in the scan's `BeginForeignScan`
+    pub unsafe fn new(plan: *mut pg_sys::ForeignScan, parent: *mut pg_sys::PlanState) -> Self {
+        let private = PgList::<c_void>::from_pg((*plan).fdw_private);
+        let exprs = PgList::<pg_sys::ExprState>::from_pg(pg_sys::ExecInitExprList(
+            (*plan).fdw_exprs,
+            parent,
+        ));
+
+        PreparedQuals(
+            private
+                .iter_int()
+                .map(Qual::decode)
+                .zip(exprs.iter_ptr())
+                .collect(),
+        )
+    }
+
+    /// Computes the values the columns are compared with
+    pub unsafe fn evaluate(&self, econtext: *mut pg_sys::ExprContext) -> Filter {
+        let mut filter = Filter::default();
+
+        for &(qual, state) in &self.0 {
+            let mut is_null = false;
+            let evaluate = (*state).evalfunc.unwrap();
+            let datum = evaluate(state, econtext, &mut is_null);
+
+            let condition = if qual.column == Column::Uid {
+                String::from_datum(datum, is_null, pg_sys::TEXTOID).map(Condition::Uid)
+            } else {
+                TimestampWithTimeZone::from_datum(datum, is_null, pg_sys::TIMESTAMPTZOID)
+                    .map(|value| Condition::Time(qual.column, qual.comparison, *value))
+            };
+
+            match condition {
+                Some(condition) => filter.conditions.push(condition),
+                // Comparisons with NULL are never true
+                None => filter.matches_nothing = true,
+            }
+        }
+
+        filter
+    }
+}
+
+enum Condition {
+    Time(Column, Comparison, OffsetDateTime),
+    Uid(String),
+}
+
+/// Conditions that every row returned by a scan must meet
+#[derive(Default)]
+pub struct Filter {
+    conditions: Vec<Condition>,
+    matches_nothing: bool,
+}
+
+impl Filter {
+    /// Whether no component can match, so that the calendar doesn't even need to be fetched
+    pub fn matches_nothing(&self) -> bool {
+        self.matches_nothing
+    }
+
+    pub fn matches(&self, component: &Component) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::Time(column, comparison, value) => {
+                let time = match column {
+                    Column::DtStart => &component.dt_start,
+                    _ => &component.dt_end,
+                };
+                time.as_ref()
+                    .is_some_and(|time| comparison.holds((**time).cmp(value)))
+            }
+            Condition::Uid(uid) => component.uid == *uid,
+        })
+    }
+
+    /// Time range that the matching events overlap, as defined by CalDAV's `time-range`
+    ///
+    /// Both the start and the end of an event are bounded by the bounds of either, as long as it
+    /// doesn't end before it starts. The range is widened by a second on each side, as CalDAV
+    /// date-times have no fractional seconds and some servers exclude the bounds.
+    pub fn time_range(&self) -> (Option<OffsetDateTime>, Option<OffsetDateTime>) {
+        let mut start: Option<OffsetDateTime> = None;
+        let mut end: Option<OffsetDateTime> = None;
+
+        for condition in &self.conditions {
+            if let Condition::Time(_, comparison, value) = condition {
+                let (lower, upper) = comparison.bounds();
+                if lower {
+                    start = Some(start.map_or(*value, |start| start.max(*value)));
+                }
+                if upper {
+                    end = Some(end.map_or(*value, |end| end.min(*value)));
+                }
+            }
+        }
+
+        (
+            start.map(|start| start - Duration::SECOND),
+            end.map(|end| end + Duration::SECOND),
+        )
+    }
+
+    pub fn uid(&self) -> Option<&str> {
+        self.conditions
+            .iter()
+            .find_map(|condition| match condition {
+                Condition::Uid(uid) => Some(uid.as_str()),
+                _ => None,
+            })
+    }
+}
@@ -0,0 +1,189 @@
+//! Scheduled refresh of the feeds listed in `postgres_ical.feeds`
+//!
+//! Each feed is refreshed by `postgres_ical.refresh_feed`, which synchronizes its target table with
+//! `pg_ical_sync` in a subtransaction, so that a failing feed only records its error. When the
+//! library is in `shared_preload_libraries`, a background worker refreshes the feeds that are due;
+//! otherwise `postgres_ical.refresh_feeds()` can be scheduled by other means.
+
+use crate::guc;
+use pgx::bgworkers::*;
+use pgx::*;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+extension_sql!(
+    r#"
+CREATE SCHEMA postgres_ical;
+
+CREATE TABLE postgres_ical.feeds (
+    url text NOT NULL,
+    target regclass NOT NULL,
+    refresh_interval interval NOT NULL DEFAULT '1 hour',
+    options jsonb,
+    last_refresh timestamptz,
+    last_error text,
+    prefetch_at time[],
+    prefetch_lead interval NOT NULL DEFAULT '5 minutes',
+    PRIMARY KEY (url, target)
+);
+SELECT pg_catalog.pg_extension_config_dump('postgres_ical.feeds', '');
+
+-- A feed is due once its refresh interval elapsed, or when one of the times of day at which it's
+-- queried is less than its prefetch lead away and it wasn't refreshed since that window started
+CREATE FUNCTION postgres_ical.feed_is_due(feed postgres_ical.feeds) RETURNS boolean
+    LANGUAGE sql STABLE SET search_path FROM CURRENT AS $$
+SELECT feed.last_refresh IS NULL
+    OR feed.last_refresh + feed.refresh_interval <= now()
+    OR EXISTS (
+        SELECT FROM unnest(feed.prefetch_at) AS time_of_day,
+            LATERAL (VALUES (current_date + time_of_day), (current_date + 1 + time_of_day))
+                AS occurrence (query_time)
+        WHERE now() BETWEEN query_time - feed.prefetch_lead AND query_time
+            AND feed.last_refresh < query_time - feed.prefetch_lead
+    );
+$$;
+
+-- Concurrent refreshes keep the view readable, but require a unique index on plain columns and
+-- can't populate a view for the first time
+CREATE FUNCTION pg_ical_refresh_matview(view regclass) RETURNS void
+    LANGUAGE plpgsql AS $$
+DECLARE
+    populated boolean;
+BEGIN
+    SELECT relispopulated INTO populated FROM pg_catalog.pg_class
+        WHERE oid = view AND relkind = 'm';
+    IF NOT FOUND THEN
+        RAISE EXCEPTION '% is not a materialized view', view;
+    END IF;
+
+    IF populated AND EXISTS (
+        SELECT FROM pg_catalog.pg_index
+            WHERE indrelid = view AND indisunique AND indisvalid
+                AND indexprs IS NULL AND indpred IS NULL
+    ) THEN
+        EXECUTE format('REFRESH MATERIALIZED VIEW CONCURRENTLY %s', view);
+    ELSE
+        EXECUTE format('REFRESH MATERIALIZED VIEW %s', view);
+    END IF;
+END
+$$;
+
+CREATE FUNCTION postgres_ical.refresh_feed(feed_url text, feed_target regclass) RETURNS boolean
+    LANGUAGE plpgsql SET search_path FROM CURRENT AS $$
+DECLARE
+    feed postgres_ical.feeds;
+BEGIN
+    SELECT * INTO feed FROM postgres_ical.feeds
+        WHERE url = feed_url AND target = feed_target
+        FOR UPDATE SKIP LOCKED;
+    IF NOT FOUND THEN
+        RETURN false;
+    END IF;
+
+    BEGIN
+        -- A materialized view fetches its calendars itself, with the options of its definition
+        IF EXISTS (SELECT FROM pg_catalog.pg_class WHERE oid = feed.target AND relkind = 'm') THEN
+            PERFORM pg_ical_refresh_matview(feed.target);
+        ELSE
+            PERFORM pg_ical_sync(feed.url, feed.target, feed.options);
+        END IF;
+
+        UPDATE postgres_ical.feeds SET last_refresh = now(), last_error = NULL
+            WHERE url = feed.url AND target = feed.target;
+        RETURN true;
+    EXCEPTION WHEN OTHERS THEN
+        RAISE WARNING 'couldn''t refresh % into %: %', feed.url, feed.target, SQLERRM;
+        UPDATE postgres_ical.feeds SET last_refresh = now(), last_error = SQLERRM
+            WHERE url = feed.url AND target = feed.target;
+        RETURN false;
+    END;
+END
+$$;
+
+CREATE FUNCTION postgres_ical.refresh_feeds() RETURNS integer
+    LANGUAGE plpgsql SET search_path FROM CURRENT AS $$
+DECLARE
+    feed record;
+    refreshed integer := 0;
+BEGIN
+    FOR feed IN SELECT url, target FROM postgres_ical.feeds
+        WHERE postgres_ical.feed_is_due(feeds)
+    LOOP
+        IF postgres_ical.refresh_feed(feed.url, feed.target) THEN
+            refreshed := refreshed + 1;
+        END IF;
+    END LOOP;
+    RETURN refreshed;
+END
+$$;
+"#,
+    name = "postgres_ical_feeds",
+);
+
+/// Registers the background worker, if the library is being preloaded
+pub fn init() {
+    if unsafe { !pg_sys::process_shared_preload_libraries_in_progress } {
+        return;
+    }
+
+    BackgroundWorkerBuilder::new("postgres_ical feed refresher")
+        .set_function("postgres_ical_feeds_worker_main")
+        .set_library("postgres_ical")
+        .enable_spi_access()
+        .set_restart_time(Some(Duration::from_secs(60)))
+        .load();
+}
+
+#[pg_guard]
+#[no_mangle]
+pub extern "C" fn postgres_ical_feeds_worker_main(_arg: pg_sys::Datum) {
+    BackgroundWorker::attach_signal_handlers(SignalWakeFlags::SIGHUP | SignalWakeFlags::SIGTERM);
+    BackgroundWorker::connect_worker_to_spi(guc::WORKER_DATABASE.get().as_deref(), None);
+
+    loop {
+        // Each feed is refreshed in its own transaction, so that slow fetches don't keep the
+        // previous refreshes uncommitted
+        for (url, target) in due_feeds() {
+            BackgroundWorker::transaction(|| {
+                Spi::get_one_with_args::<bool>(
+                    "SELECT postgres_ical.refresh_feed($1, $2::regclass)",
+                    vec![
+                        (PgBuiltInOids::TEXTOID.oid(), url.into_datum()),
+                        (PgBuiltInOids::TEXTOID.oid(), target.into_datum()),
+                    ],
+                );
+            });
+        }
+
+        let naptime = Duration::from_secs(guc::WORKER_NAPTIME.get() as u64);
+        if !BackgroundWorker::wait_latch(Some(naptime)) {
+            return;
+        }
+    }
+}
+
+/// URLs and target tables of the feeds to refresh, none if the extension isn't installed in the
+/// worker's database
+fn due_feeds() -> Vec<(String, String)> {
+    let mut feeds = Vec::new();
+
+    BackgroundWorker::transaction(AssertUnwindSafe(|| {
+        let installed =
+            Spi::get_one::<bool>("SELECT to_regclass('postgres_ical.feeds') IS NOT NULL");
+        if installed != Some(true) {
+            return;
+        }
+
+        let (urls, targets) = Spi::get_two::<Vec<String>, Vec<String>>(
+            "SELECT array_agg(url), array_agg(target::text) FROM postgres_ical.feeds \
+             WHERE postgres_ical.feed_is_due(feeds)",
+        );
+        feeds.extend(
+            urls.unwrap_or_default()
+                .into_iter()
+                .zip(targets.unwrap_or_default()),
+        );
+    }));
+
+    feeds
+}
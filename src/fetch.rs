@@ -0,0 +1,1025 @@
+//! Remote calendar fetching
+//!
+//! Transfers are done by libcurl, or by `ureq` when the extension is built without the `curl`
+//! feature, for hosts where linking libcurl is a packaging problem. The latter doesn't support
+//! every fetch option.
+
+#[cfg(feature = "curl")]
+mod curl_backend;
+#[cfg(feature = "curl")]
+use curl_backend as backend;
+
+#[cfg(all(feature = "ureq", not(feature = "curl")))]
+mod ureq_backend;
+#[cfg(all(feature = "ureq", not(feature = "curl")))]
+use ureq_backend as backend;
+
+#[cfg(not(any(feature = "curl", feature = "ureq")))]
+compile_error!("either the `curl` or the `ureq` feature must be enabled to fetch calendars");
+
+pub(crate) mod audit;
+mod pipe;
+
+use audit::FetchRecord;
+use pipe::PipeReader;
+
+use crate::oauth::OAuth2;
+use crate::transcode::{self, Utf8Writer};
+use crate::url_policy::{self, ResolvedHost};
+use crate::{guc, privileges};
+use encoding_rs::Encoding;
+use pgx::*;
+use serde::Deserialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Per-call options of the functions that fetch remote calendars, given as a `jsonb` object
+///
+/// Every field is optional, and unknown fields are rejected.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchOptions {
+    /// Proxy URL, overriding `postgres_ical.proxy` (an empty string disables the proxy)
+    pub proxy: Option<String>,
+
+    pub proxy_username: Option<String>,
+
+    pub proxy_password: Option<String>,
+
+    /// User name sent to the server, using [`auth_method`][Self::auth_method]
+    pub username: Option<String>,
+
+    pub password: Option<String>,
+
+    /// Defaults to [`AuthMethod::Basic`]
+    pub auth_method: Option<AuthMethod>,
+
+    /// Token sent in an `Authorization: Bearer` header. Can't be used with a user name.
+    pub bearer_token: Option<String>,
+
+    /// Refresh token exchanged for the access token sent as a [`bearer_token`][Self::bearer_token]
+    pub oauth2: Option<OAuth2>,
+
+    /// Names of the credentials that fill the options of their kind, see [`crate::credentials`]
+    pub credentials: Vec<String>,
+
+    /// Path to a TLS client certificate, overriding `postgres_ical.ssl_cert`
+    ///
+    /// Reading a file from the server requires the privileges of `pg_read_server_files`.
+    pub ssl_cert: Option<String>,
+
+    /// Path to the private key of [`ssl_cert`][Self::ssl_cert], overriding
+    /// `postgres_ical.ssl_key`
+    pub ssl_key: Option<String>,
+
+    pub ssl_key_password: Option<String>,
+
+    /// Format of the certificate: `PEM` (default), `DER` or `P12`
+    pub ssl_cert_type: Option<String>,
+
+    /// Path to a bundle of trusted certificate authorities, overriding `postgres_ical.ssl_ca_file`
+    ///
+    /// Reading a file from the server requires the privileges of `pg_read_server_files`.
+    pub ssl_ca_file: Option<String>,
+
+    /// Expected public key of the server, as `sha256//<base64>` hashes separated by `;`
+    pub ssl_pinned_public_key: Option<String>,
+
+    /// Overrides `postgres_ical.user_agent`
+    pub user_agent: Option<String>,
+
+    /// Number of times a request is retried after a transient failure (a 5xx status, a timeout, a
+    /// connection failure...), overriding `postgres_ical.retries`
+    pub retries: Option<i32>,
+
+    /// Delay before the first retry, in milliseconds, overriding `postgres_ical.retry_backoff`.
+    /// It doubles on each subsequent retry.
+    pub retry_backoff: Option<i32>,
+
+    /// Connection timeout, in seconds, overriding `postgres_ical.connect_timeout` (0 disables it)
+    pub connect_timeout: Option<i32>,
+
+    /// Timeout of each attempt as a whole, in seconds, overriding `postgres_ical.timeout` (0
+    /// disables it)
+    pub timeout: Option<i32>,
+
+    /// Restricts connections to IPv4 or IPv6 addresses
+    pub ip_version: Option<IpVersion>,
+
+    /// DNS servers used instead of the system's, as comma-separated `address[:port]`
+    ///
+    /// Requires a libcurl built with c-ares, and can't be used while
+    /// `postgres_ical.block_private_addresses` is on, as addresses are then checked and pinned
+    /// with the system's resolver.
+    pub dns_servers: Option<String>,
+
+    /// Addresses used for hosts instead of resolving them, as `host:port:address[,address...]`
+    /// entries, like curl's `--resolve`
+    pub resolve: Vec<String>,
+
+    /// Path of a Unix socket that requests are sent through instead of connecting to the host of
+    /// the URL, overriding `postgres_ical.unix_socket` (an empty string connects to the host)
+    ///
+    /// Connecting to a socket of the server requires the privileges of `pg_read_server_files`.
+    pub unix_socket: Option<String>,
+
+    /// Whether the server certificate and host name are verified, which they are by default
+    ///
+    /// Disabling verification makes the connection vulnerable to man-in-the-middle attacks, and
+    /// raises a warning every time it's used.
+    pub ssl_verify: Option<bool>,
+
+    /// Additional request headers, given as a separate `jsonb` parameter
+    #[serde(skip)]
+    pub headers: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    Basic,
+    Digest,
+    Ntlm,
+    Negotiate,
+    /// Lets curl pick the most secure method the server supports
+    Any,
+}
+
+impl FetchOptions {
+    pub fn from_jsonb(options: Option<JsonB>) -> Self {
+        match options {
+            None => Self::default(),
+            Some(JsonB(value)) => serde_json::from_value(value)
+                .unwrap_or_else(|err| error!("invalid fetch options: {}", err)),
+        }
+    }
+
+    /// The options of a request to another origin than the one they were given for, without the
+    /// credentials of the server, nor the `Authorization` and `Cookie` headers
+    ///
+    /// The named [`credentials`][Self::credentials] are applied first, so that the ones of the
+    /// proxy and of the client certificate are kept.
+    pub fn without_credentials(&self) -> Self {
+        let mut options = self.clone();
+        options.apply_credentials();
+
+        options.username = None;
+        options.password = None;
+        options.auth_method = None;
+        options.bearer_token = None;
+        options.oauth2 = None;
+        options.headers.retain(|(name, _)| {
+            !name.eq_ignore_ascii_case("Authorization") && !name.eq_ignore_ascii_case("Cookie")
+        });
+        options
+    }
+
+    /// Reads request headers from a `jsonb` object whose values are either strings or arrays of
+    /// strings, for headers that must be repeated
+    pub fn with_headers(mut self, headers: Option<JsonB>) -> Self {
+        let headers = match headers {
+            None => return self,
+            Some(JsonB(Value::Object(headers))) => headers,
+            Some(JsonB(other)) => error!("headers must be a jsonb object, got {}", other),
+        };
+
+        for (name, value) in headers {
+            if name.is_empty() || !name.bytes().all(is_header_name_byte) {
+                error!("invalid header name {:?}", name);
+            }
+
+            let values = match value {
+                Value::String(value) => vec![value],
+                Value::Array(values) => values
+                    .into_iter()
+                    .map(|value| match value {
+                        Value::String(value) => value,
+                        other => error!("invalid value {} for header {}", other, name),
+                    })
+                    .collect(),
+                other => error!("invalid value {} for header {}", other, name),
+            };
+
+            for value in values {
+                if value.contains(&['\r', '\n', '\0'][..]) {
+                    error!("header {} contains a line break or a NUL character", name);
+                }
+
+                self.headers.push((name.clone(), value));
+            }
+        }
+
+        self
+    }
+
+    /// Proxy URL to use, if any, with the setting as a fallback
+    fn proxy(&self) -> Option<String> {
+        match &self.proxy {
+            Some(proxy) => {
+                if !proxy.is_empty() {
+                    url_policy::check_proxy(proxy);
+                }
+                Some(proxy.clone())
+            }
+            None => guc::PROXY.get(),
+        }
+    }
+
+    /// `User-Agent` header to send, if any, with the setting as a fallback
+    fn user_agent(&self) -> Option<String> {
+        self.user_agent
+            .clone()
+            .or_else(|| guc::USER_AGENT.get())
+            .filter(|user_agent| !user_agent.is_empty())
+    }
+
+    fn unix_socket(&self) -> Option<String> {
+        self.unix_socket
+            .clone()
+            .or_else(|| guc::UNIX_SOCKET.get())
+            .filter(|path| !path.is_empty())
+    }
+
+    fn ssl_cert(&self) -> Option<String> {
+        self.ssl_cert.clone().or_else(|| guc::SSL_CERT.get())
+    }
+
+    fn ssl_key(&self) -> Option<String> {
+        self.ssl_key.clone().or_else(|| guc::SSL_KEY.get())
+    }
+
+    fn ssl_ca_file(&self) -> Option<String> {
+        self.ssl_ca_file.clone().or_else(|| guc::SSL_CA_FILE.get())
+    }
+
+    fn connect_timeout(&self) -> Option<Duration> {
+        let seconds = self
+            .connect_timeout
+            .unwrap_or_else(|| guc::CONNECT_TIMEOUT.get());
+        (seconds > 0).then(|| Duration::from_secs(seconds as u64))
+    }
+
+    /// Timeout of each attempt, which is at most the time left before the statement times out,
+    /// so that the transfer doesn't outlive the statement
+    fn timeout(&self) -> Option<Duration> {
+        let seconds = self.timeout.unwrap_or_else(|| guc::TIMEOUT.get());
+        let timeout = (seconds > 0).then(|| Duration::from_secs(seconds as u64));
+
+        match (timeout, statement_time_left()) {
+            (Some(timeout), Some(left)) => Some(timeout.min(left)),
+            (timeout, left) => timeout.or(left),
+        }
+    }
+
+    /// Parses the [`resolve`][Self::resolve] entries, raising SQL errors
+    fn resolve_overrides(&self) -> Vec<ResolvedHost> {
+        self.resolve
+            .iter()
+            .map(|entry| {
+                let invalid = || -> ! {
+                    error!(
+                        "invalid resolve entry {:?}, expected host:port:address[,address...]",
+                        entry,
+                    )
+                };
+
+                let mut parts = entry.splitn(3, ':');
+                let (domain, port, addresses) = match (parts.next(), parts.next(), parts.next()) {
+                    (Some(domain), Some(port), Some(addresses)) if !domain.is_empty() => {
+                        (domain, port, addresses)
+                    }
+                    _ => invalid(),
+                };
+
+                let port = port.parse().unwrap_or_else(|_| invalid());
+                let addresses = addresses
+                    .split(',')
+                    .map(|address| {
+                        address
+                            .trim_start_matches('[')
+                            .trim_end_matches(']')
+                            .parse()
+                            .unwrap_or_else(|_| invalid())
+                    })
+                    .collect();
+
+                ResolvedHost {
+                    domain: domain.to_string(),
+                    port,
+                    addresses,
+                }
+            })
+            .collect()
+    }
+
+    /// Checks the options that don't depend on the HTTP client, raising SQL errors
+    fn check(&self) {
+        if self.username.is_some() && self.bearer_token.is_some() {
+            error!("the username and bearer_token fetch options are mutually exclusive");
+        }
+
+        if self.oauth2.is_some() && (self.username.is_some() || self.bearer_token.is_some()) {
+            error!("the oauth2 fetch option can't be used with a username or a bearer_token");
+        }
+
+        if self.username.is_none() && (self.password.is_some() || self.auth_method.is_some()) {
+            error!("the password and auth_method fetch options require a username");
+        }
+
+        if self.ssl_cert.is_some() || self.ssl_key.is_some() || self.ssl_ca_file.is_some() {
+            privileges::require_read_server_files(
+                "the ssl_cert, ssl_key and ssl_ca_file fetch options",
+            );
+        }
+
+        if self
+            .unix_socket
+            .as_deref()
+            .is_some_and(|path| !path.is_empty())
+        {
+            privileges::require_read_server_files("the unix_socket fetch option");
+        }
+
+        if let Some(cert_type) = &self.ssl_cert_type {
+            if !["PEM", "DER", "P12"].contains(&cert_type.to_ascii_uppercase().as_str()) {
+                error!(
+                    "invalid ssl_cert_type {:?}, expected PEM, DER or P12",
+                    cert_type
+                );
+            }
+        }
+
+        if let Some(pinned) = &self.ssl_pinned_public_key {
+            // curl also accepts a path to a key file, which we don't want to expose
+            if !pinned.split(';').all(|hash| hash.starts_with("sha256//")) {
+                error!("ssl_pinned_public_key must be a list of sha256//<base64> hashes");
+            }
+        }
+
+        if let Some(token) = &self.bearer_token {
+            if token.contains(&['\r', '\n', '\0'][..]) {
+                error!("bearer_token contains a line break or a NUL character");
+            }
+        }
+
+        if self.dns_servers.is_some() && guc::BLOCK_PRIVATE_ADDRESSES.get() {
+            error!(
+                "the dns_servers fetch option can't be used while \
+                 postgres_ical.block_private_addresses is on"
+            );
+        }
+
+        if self.ssl_verify == Some(false) {
+            warning!(
+                "TLS verification is disabled for this request, the connection is vulnerable to \
+                 man-in-the-middle attacks"
+            );
+        }
+    }
+
+    /// Request headers, including the bearer token's
+    fn request_headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.headers.clone();
+        if let Some(token) = &self.bearer_token {
+            headers.push(("Authorization".into(), format!("Bearer {}", token)));
+        }
+        headers
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl IpVersion {
+    fn accepts(self, address: &IpAddr) -> bool {
+        match self {
+            IpVersion::Any => true,
+            IpVersion::Ipv4 => address.is_ipv4(),
+            IpVersion::Ipv6 => address.is_ipv6(),
+        }
+    }
+}
+
+/// Time left before the current statement is cancelled by `statement_timeout`, if it's set
+fn statement_time_left() -> Option<Duration> {
+    let (statement_timeout, elapsed) = unsafe {
+        let elapsed = pg_sys::GetCurrentTimestamp() - pg_sys::GetCurrentStatementStartTimestamp();
+        (pg_sys::StatementTimeout, elapsed)
+    };
+    if statement_timeout <= 0 {
+        return None;
+    }
+
+    // Timestamps are in microseconds. A timeout of 0 would disable curl's, hence the minimum.
+    let left = (statement_timeout as i64 * 1000 - elapsed).max(1000);
+    Some(Duration::from_micros(left as u64))
+}
+
+/// Header names are RFC 7230 tokens
+fn is_header_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Rewrites the `webcal://` and `webcals://` URLs that calendar applications hand out to their
+/// `http://` and `https://` equivalents
+fn rewrite_webcal(url: &str) -> Cow<'_, str> {
+    let (scheme, rest) = match url.split_once("://") {
+        Some(split) => split,
+        None => return url.into(),
+    };
+
+    if scheme.eq_ignore_ascii_case("webcal") {
+        format!("http://{}", rest).into()
+    } else if scheme.eq_ignore_ascii_case("webcals") {
+        format!("https://{}", rest).into()
+    } else {
+        url.into()
+    }
+}
+
+fn is_success_status(status: u32) -> bool {
+    (200..300).contains(&status)
+}
+
+fn is_transient_status(status: u32) -> bool {
+    (500..600).contains(&status)
+}
+
+const MAX_REDIRECTIONS: usize = 10;
+
+/// A request whose URL was checked and whose options were applied, so that it can be performed
+/// from any thread
+pub struct PreparedRequest {
+    transfer: backend::Transfer,
+    retries: u32,
+    backoff: u64,
+    /// Only recorded when requests are logged
+    record: Option<FetchRecord>,
+    /// Set when the response isn't wanted anymore, to abort the transfer
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PreparedRequest {
+    /// Must be called from the backend's thread, as it reads settings and may raise SQL errors
+    pub fn new(url: &str, options: &FetchOptions) -> Self {
+        Self::with_body(url, options, None)
+    }
+
+    /// Prepares a request with another method than `GET`, see [`new`][Self::new]
+    pub fn with_body(url: &str, options: &FetchOptions, body: Option<RequestBody>) -> Self {
+        privileges::require_fetch("fetching remote calendars");
+
+        let mut options = options.clone();
+        options.apply_credentials();
+
+        let url = rewrite_webcal(url);
+        let mut hosts = options.resolve_overrides();
+        if options.unix_socket().is_some() {
+            // The host of the URL is never connected to
+            url_policy::check_patterns(&url);
+        } else {
+            hosts.iter().for_each(url_policy::check_override);
+            hosts.extend(url_policy::check_url(&url, &hosts));
+        }
+
+        // The HTTP client only filters the addresses it resolves itself
+        let ip_version = options.ip_version.unwrap_or(IpVersion::Any);
+        for host in &mut hosts {
+            host.addresses.retain(|address| ip_version.accepts(address));
+            if host.addresses.is_empty() {
+                error!(
+                    "host {} has no address of the requested IP version",
+                    host.domain
+                );
+            }
+        }
+
+        options.check();
+        if let Some(oauth2) = options.oauth2.take() {
+            options.bearer_token = Some(oauth2.access_token(&options));
+        }
+
+        let transfer = backend::Transfer::new(&url, hosts, &options, body);
+
+        let retries = options.retries.unwrap_or_else(|| guc::RETRIES.get()).max(0) as u32;
+        let backoff = options
+            .retry_backoff
+            .unwrap_or_else(|| guc::RETRY_BACKOFF.get())
+            .max(0) as u64;
+
+        Self {
+            transfer,
+            retries,
+            backoff,
+            record: FetchRecord::start(&url),
+            cancelled: Arc::default(),
+        }
+    }
+
+    /// Flag that aborts the request once set, from any thread
+    pub fn cancellation(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancelled)
+    }
+
+    /// Performs the request until its headers are received, without downloading the body
+    ///
+    /// Must be called from the backend's thread, as the request may be logged.
+    pub fn headers(mut self) -> Result<Response, backend::Error> {
+        let started = Instant::now();
+        let result = self.transfer.headers();
+
+        if let Some(mut record) = self.record {
+            let status = result.as_ref().map_or(0, |response| response.status);
+            record.finish(1, status, 0, started.elapsed());
+            record.log(result.as_ref().err().map(ToString::to_string));
+        }
+
+        result
+    }
+
+    /// Performs the request, retrying it if needed, and writes the body converted to UTF-8 to
+    /// `writer`
+    ///
+    /// Bodies of responses without a 2xx status aren't written, such responses are reported as a
+    /// [`FetchError::Status`] instead.
+    pub fn perform<W: Write>(mut self, writer: W) -> Outcome {
+        let started = Instant::now();
+        let mut attempts = 0;
+        let mut sink = Sink {
+            writer: Some(writer),
+            body: None,
+            status: 0,
+            status_line: String::new(),
+            location: None,
+            encoding: None,
+            received: 0,
+            cancelled: Arc::clone(&self.cancelled),
+        };
+
+        let result = self.perform_attempts(&mut sink, &mut attempts);
+
+        if let Some(record) = &mut self.record {
+            record.finish(attempts, sink.status, sink.received, started.elapsed());
+        }
+
+        Outcome {
+            result,
+            record: self.record,
+        }
+    }
+
+    fn perform_attempts<W: Write>(
+        &mut self,
+        sink: &mut Sink<W>,
+        attempts: &mut u32,
+    ) -> Result<(), FetchError> {
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                let delay = self.backoff.saturating_mul(1 << (attempt - 1).min(16));
+                std::thread::sleep(Duration::from_millis(delay));
+            }
+
+            let can_retry = attempt < self.retries;
+            *attempts += 1;
+            let result = self.transfer.perform(sink);
+
+            // Once part of the body went to the parser, retrying would duplicate it
+            let retry = can_retry
+                && sink.body.is_none()
+                && !sink.is_cancelled()
+                && match &result {
+                    Ok(()) => is_transient_status(sink.status),
+                    Err(err) => backend::is_transient_error(err),
+                };
+
+            if !retry {
+                if sink.is_cancelled() {
+                    return Err(FetchError::Cancelled);
+                }
+                result?;
+                break;
+            }
+        }
+
+        if let Some(body) = sink.body.take() {
+            body.finish().map_err(|_| FetchError::Cancelled)?;
+        }
+
+        if !is_success_status(sink.status) {
+            return Err(FetchError::Status {
+                status_line: sink.status_line.clone(),
+                location: sink.location.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Result of [`PreparedRequest::perform`], which may have to be logged from the backend's thread
+#[must_use]
+pub struct Outcome {
+    result: Result<(), FetchError>,
+    record: Option<FetchRecord>,
+}
+
+impl Outcome {
+    /// Logs the request if `postgres_ical.log_fetches` is enabled, and returns its result
+    ///
+    /// Must be called from the backend's thread.
+    pub fn into_result(self) -> Result<(), FetchError> {
+        if let Some(record) = self.record {
+            record.log(self.result.as_ref().err().map(ToString::to_string));
+        }
+        self.result
+    }
+}
+
+/// Method and body of a request that isn't a plain `GET`
+///
+/// The `Content-Type` of the body must be given as one of the [`FetchOptions::headers`]. The body
+/// of a `HEAD` request is ignored.
+pub struct RequestBody {
+    pub method: &'static str,
+    pub data: Vec<u8>,
+}
+
+/// Receives the responses of the successive attempts of a request, forwarding the body of the
+/// successful one
+struct Sink<W: Write> {
+    writer: Option<W>,
+    body: Option<Utf8Writer<W>>,
+    // The status and encoding are only known once the headers are received
+    status: u32,
+    status_line: String,
+    location: Option<String>,
+    encoding: Option<&'static Encoding>,
+    /// Bytes of the bodies of every attempt, whatever their status
+    received: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<W: Write> Sink<W> {
+    /// Starts a new response, after an informational response or a retry
+    fn status(&mut self, status: u32, status_line: &str) {
+        self.status = status;
+        self.status_line = status_line.trim().to_string();
+        self.location = None;
+        self.encoding = None;
+    }
+
+    fn header(&mut self, name: &str, value: &str) {
+        if name.eq_ignore_ascii_case("Content-Type") {
+            self.encoding = transcode::encoding_from_content_type(value);
+        } else if name.eq_ignore_ascii_case("Location") {
+            self.location = Some(value.to_string());
+        }
+    }
+
+    /// Whether the transfer should be aborted, as nobody is reading the response anymore
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.received += data.len() as u64;
+        if !is_success_status(self.status) {
+            // Error pages aren't calendars
+            return Ok(());
+        }
+
+        let writer = &mut self.writer;
+        let encoding = self.encoding.unwrap_or(encoding_rs::UTF_8);
+        self.body
+            .get_or_insert_with(|| Utf8Writer::new(encoding, writer.take().unwrap()))
+            .write_all(data)
+    }
+}
+
+/// Reason why a calendar couldn't be fetched
+#[derive(Debug)]
+pub enum FetchError {
+    /// The transfer itself failed (resolution, connection, TLS, timeout...)
+    Transfer(backend::Error),
+    /// The server answered with a status other than 2xx
+    Status {
+        status_line: String,
+        location: Option<String>,
+    },
+    /// The response was abandoned by its reader, as when the query is cancelled
+    Cancelled,
+}
+
+impl From<backend::Error> for FetchError {
+    fn from(err: backend::Error) -> Self {
+        FetchError::Transfer(err)
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::Transfer(err) => err.fmt(f),
+            FetchError::Status {
+                status_line,
+                location: Some(location),
+            } => write!(
+                f,
+                "server answered {:?}, redirecting to {} (redirections aren't followed)",
+                status_line, location,
+            ),
+            FetchError::Status { status_line, .. } => {
+                write!(f, "server answered {:?}", status_line)
+            }
+            FetchError::Cancelled => f.write_str("the transfer was cancelled"),
+        }
+    }
+}
+
+/// Status and headers of an HTTP response
+pub struct Response {
+    pub status: u32,
+    /// URL of the request that got this response
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Response {
+    /// Value of the last header with this (case-insensitive) name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .rev()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn redirect_location(&self) -> Option<&str> {
+        match self.status {
+            301 | 302 | 303 | 307 | 308 => self.header("Location"),
+            _ => None,
+        }
+    }
+}
+
+/// Fetches the status and headers of `url` without downloading the body, following redirections
+///
+/// With `head`, `HEAD` requests are sent, rather than `GET` requests that are aborted once the
+/// headers are received. Redirections are followed here rather than by the HTTP client, so that
+/// each of them is checked against the URL policy. As with curl, the credentials and the
+/// `Authorization` and `Cookie` headers are only sent to the origin of `url`.
+pub fn fetch_headers(url: &str, options: &FetchOptions, head: bool) -> Response {
+    let mut url = rewrite_webcal(url).into_owned();
+    let origin = Url::parse(&url).ok();
+    let mut cross_origin_options = None;
+
+    for _ in 0..=MAX_REDIRECTIONS {
+        let is_same_origin = match (&origin, Url::parse(&url)) {
+            (Some(origin), Ok(url)) => is_same_origin(origin, &url),
+            _ => false,
+        };
+        let options = if is_same_origin {
+            options
+        } else {
+            &*cross_origin_options.get_or_insert_with(|| options.without_credentials())
+        };
+
+        let body = head.then(|| RequestBody {
+            method: "HEAD",
+            data: Vec::new(),
+        });
+        let response = PreparedRequest::with_body(&url, options, body)
+            .headers()
+            .unwrap_or_else(|err| error!("couldn't fetch {}: {}", url, err));
+
+        match response.redirect_location() {
+            Some(location) => {
+                url = Url::parse(&url)
+                    .and_then(|base| base.join(location))
+                    .unwrap_or_else(|err| error!("invalid redirection to {:?}: {}", location, err))
+                    .into();
+            }
+            None => return response,
+        }
+    }
+
+    error!("too many redirections, last one to {}", url);
+}
+
+/// Whether two URLs have the same scheme, host and port
+pub(crate) fn is_same_origin(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme()
+        && a.host_str() == b.host_str()
+        && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// Body of a calendar being downloaded by another thread, see [`curl_get`]
+///
+/// Once the body is fully read, the transfer's outcome is checked, and an SQL error is raised if
+/// it failed. This happens in the backend's thread, which is the only one allowed to do so.
+pub struct Download {
+    reader: PipeReader,
+    url: String,
+    handle: Option<JoinHandle<Outcome>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Drop for Download {
+    /// Aborts the transfer if the body wasn't read until its end, as when the query is cancelled
+    /// or the parser fails, and waits for its thread
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.reader.close();
+        join_aborted(self.handle.take());
+    }
+}
+
+impl Download {
+    fn join(&mut self) {
+        let result = match self.handle.take() {
+            Some(handle) => handle.join(),
+            None => return,
+        };
+
+        match result.map(Outcome::into_result) {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => error!("couldn't fetch {}: {}", self.url, err),
+            Err(_) => error!("couldn't fetch {}: the transfer thread panicked", self.url),
+        }
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            // The writer was dropped, the transfer is over
+            self.join();
+        }
+        Ok(read)
+    }
+}
+
+/// Longest time a dropped download waits for its aborted transfer threads, which then go on
+/// detached
+///
+/// Aborted transfers usually stop within a second, when libcurl next calls its progress callback.
+const ABORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Waits for the threads of aborted transfers, without raising errors, as this happens while a
+/// download is dropped, possibly because of an error
+fn join_aborted<T>(handles: impl IntoIterator<Item = JoinHandle<T>>) {
+    let deadline = Instant::now() + ABORT_TIMEOUT;
+
+    for handle in handles {
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        // The outcome of an aborted transfer doesn't matter
+        let _ = handle.join();
+    }
+}
+
+/// The HTTP client that fetches calendars, and its TLS library, with their versions
+pub fn backend_description() -> String {
+    backend::description()
+}
+
+/// Fetches `url` in another thread, so that the calendar is parsed while it's downloaded
+///
+/// The body is handed to the parser in chunks of `postgres_ical.pipe_buffer_size`. The pipe has
+/// no capacity of its own: handing a chunk over blocks the download until the parser takes it, so
+/// a slow parser pauses the transfer rather than letting chunks pile up in memory.
+pub fn curl_get(url: &str, options: &FetchOptions) -> Download {
+    let (reader, writer) = pipe::pipe();
+    let capacity = guc::PIPE_BUFFER_SIZE.get().max(1) as usize * 1024;
+    let writer = BufWriter::with_capacity(capacity, writer);
+    let request = PreparedRequest::new(url, options);
+    let cancelled = request.cancellation();
+
+    let handle = std::thread::spawn(move || request.perform(writer));
+
+    Download {
+        reader,
+        url: url.to_string(),
+        handle: Some(handle),
+        cancelled,
+    }
+}
+
+/// Fetches several calendars concurrently, with at most `postgres_ical.max_parallel_fetches`
+/// transfers at once, and yields their URLs and bodies in the order they complete
+///
+/// Unlike [`curl_get`], bodies are fully buffered, as they are parsed one after the other. A
+/// transfer thread waits until its body is taken by the parser before starting another one, so
+/// that at most one body per thread is buffered.
+pub fn curl_get_many(urls: Vec<String>, options: &FetchOptions) -> ManyDownloads {
+    let requests = urls
+        .into_iter()
+        .map(|url| {
+            let request = PreparedRequest::new(&url, options);
+            (url, request)
+        })
+        .collect::<Vec<_>>();
+    let cancellations = requests
+        .iter()
+        .map(|(_, request)| request.cancellation())
+        .collect();
+
+    let parallelism = (guc::MAX_PARALLEL_FETCHES.get().max(1) as usize).min(requests.len());
+    let queue = Arc::new(Mutex::new(requests.into_iter()));
+    let (sender, receiver) = mpsc::sync_channel(0);
+
+    let handles = (0..parallelism)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+
+            std::thread::spawn(move || loop {
+                let next = queue.lock().unwrap().next();
+                let (url, request) = match next {
+                    // The other requests were cancelled along with this one
+                    Some((_, request)) if request.cancelled.load(Ordering::Relaxed) => break,
+                    Some(next) => next,
+                    None => break,
+                };
+
+                let mut body = Vec::new();
+                let outcome = request.perform(&mut body);
+                if sender.send((url, body, outcome)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+
+    ManyDownloads {
+        receiver,
+        handles,
+        cancellations,
+    }
+}
+
+/// Bodies of calendars being downloaded by other threads, see [`curl_get_many`]
+///
+/// Failed transfers raise SQL errors as their bodies are reached. Once dropped, the remaining
+/// transfers are aborted.
+pub struct ManyDownloads {
+    receiver: Receiver<(String, Vec<u8>, Outcome)>,
+    handles: Vec<JoinHandle<()>>,
+    cancellations: Vec<Arc<AtomicBool>>,
+}
+
+impl Iterator for ManyDownloads {
+    type Item = (String, Vec<u8>);
+
+    /// Must be called from the backend's thread, as it checks for interrupts while waiting
+    fn next(&mut self) -> Option<Self::Item> {
+        let (url, body, outcome) = loop {
+            match self.receiver.recv_timeout(pipe::INTERRUPT_CHECK_INTERVAL) {
+                Ok(received) => break received,
+                Err(RecvTimeoutError::Timeout) => {
+                    check_for_interrupts!();
+                }
+                // Every transfer is over
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        };
+
+        match outcome.into_result() {
+            Ok(()) => Some((url, body)),
+            Err(err) => error!("couldn't fetch {}: {}", url, err),
+        }
+    }
+}
+
+impl Drop for ManyDownloads {
+    fn drop(&mut self) {
+        for cancelled in &self.cancellations {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+
+        // Threads waiting to hand their body over give up once the receiver is dropped
+        let (_, receiver) = mpsc::sync_channel(0);
+        self.receiver = receiver;
+
+        join_aborted(self.handles.drain(..));
+    }
+}
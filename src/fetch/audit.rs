@@ -0,0 +1,108 @@
+//! Logging of the requests made by the extension, for audits of the traffic initiated by the
+//! database, see `postgres_ical.log_fetches`
+
+use crate::guc;
+use pgx::*;
+use std::time::Duration;
+use url::Url;
+
+/// Query parameters whose name contains one of these are redacted, as they usually carry
+/// credentials, such as the `key` of the Google Calendar API
+const SECRET_PARAMETERS: &[&str] = &["key", "token", "secret", "password", "auth", "sig"];
+
+const REDACTED: &str = "redacted";
+
+/// A request to log once it's over
+pub struct FetchRecord {
+    level: PgLogLevel,
+    url: String,
+    role: String,
+    attempts: u32,
+    status: u32,
+    bytes: u64,
+    duration: Duration,
+}
+
+impl FetchRecord {
+    /// Starts recording a request to `url`, unless requests aren't logged
+    ///
+    /// Must be called from the backend's thread, as it reads settings and the current role.
+    pub fn start(url: &str) -> Option<Self> {
+        let level = guc::LOG_FETCHES.get().level()?;
+        let role = Spi::get_one::<String>("SELECT current_user::text").unwrap_or_default();
+
+        Some(Self {
+            level,
+            url: redact(url),
+            role,
+            attempts: 0,
+            status: 0,
+            bytes: 0,
+            duration: Duration::ZERO,
+        })
+    }
+
+    /// Records the outcome of the last attempt, and the time spent since the first one
+    pub fn finish(&mut self, attempts: u32, status: u32, bytes: u64, duration: Duration) {
+        self.attempts = attempts;
+        self.status = status;
+        self.bytes = bytes;
+        self.duration = duration;
+    }
+
+    /// Must be called from the backend's thread
+    pub fn log(self, error: Option<String>) {
+        let mut message = format!(
+            "postgres_ical fetch of {} by {}: status {}, {} bytes, {} ms, {} attempt(s)",
+            self.url,
+            self.role,
+            self.status,
+            self.bytes,
+            self.duration.as_millis(),
+            self.attempts,
+        );
+        if let Some(error) = error {
+            message.push_str(", error: ");
+            message.push_str(&error);
+        }
+
+        pgx::log::elog(self.level, &message);
+    }
+}
+
+/// Hides the credentials of `url`, in its user info and in the query parameters that look like
+/// secrets
+pub(crate) fn redact(url: &str) -> String {
+    let mut url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return REDACTED.to_string(),
+    };
+
+    if !url.username().is_empty() {
+        let _ = url.set_username(REDACTED);
+    }
+    if url.password().is_some() {
+        let _ = url.set_password(Some(REDACTED));
+    }
+
+    let is_secret = |name: &str| {
+        let name = name.to_ascii_lowercase();
+        SECRET_PARAMETERS.iter().any(|secret| name.contains(secret))
+    };
+    if url.query_pairs().any(|(name, _)| is_secret(&name)) {
+        let pairs = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if is_secret(&name) {
+                    REDACTED.into()
+                } else {
+                    value
+                };
+                (name.into_owned(), value.into_owned())
+            })
+            .collect::<Vec<_>>();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    url.into()
+}
@@ -0,0 +1,311 @@
+//! Transfers done by libcurl
+//!
+//! [`curl`] is used instead of a Rustier alternative to make [`postgres_ical`] as lightweight as
+//! possible
+
+use super::{AuthMethod, FetchOptions, IpVersion, RequestBody, Response, Sink};
+use crate::url_policy::ResolvedHost;
+use curl::easy::{Auth, Easy, IpResolve, List};
+use pgx::*;
+use std::cell::RefCell;
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+pub type Error = curl::Error;
+
+/// Maximum number of idle handles kept in [`POOL`]
+const MAX_POOLED_HANDLES: usize = 8;
+
+/// Handles of finished transfers
+///
+/// A handle keeps its connections, TLS sessions and DNS cache after it's reset, so reusing it
+/// spares a full handshake when the same hosts are fetched repeatedly by a backend, as in a sync
+/// loop. curl only reuses a connection for a request whose TLS and proxy settings match.
+static POOL: Mutex<Vec<Easy>> = Mutex::new(Vec::new());
+
+pub struct Transfer {
+    /// Only taken when the transfer is dropped
+    easy: Option<Easy>,
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        if let (Some(easy), Ok(mut pool)) = (self.easy.take(), POOL.lock()) {
+            if pool.len() < MAX_POOLED_HANDLES {
+                pool.push(easy);
+            }
+        }
+    }
+}
+
+impl Transfer {
+    /// Must be called from the backend's thread, as it reads settings and may raise SQL errors
+    pub fn new(
+        url: &str,
+        hosts: Vec<ResolvedHost>,
+        options: &FetchOptions,
+        body: Option<RequestBody>,
+    ) -> Self {
+        let pooled = POOL.lock().ok().and_then(|mut pool| pool.pop());
+        let mut easy = match pooled {
+            Some(mut easy) => {
+                easy.reset();
+                easy
+            }
+            None => Easy::new(),
+        };
+        easy.url(url).unwrap();
+
+        if !hosts.is_empty() {
+            let mut resolve = List::new();
+            for host in hosts {
+                let addresses = host
+                    .addresses
+                    .iter()
+                    .map(|ip| match ip {
+                        IpAddr::V4(ip) => ip.to_string(),
+                        IpAddr::V6(ip) => format!("[{}]", ip),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                resolve
+                    .append(&format!("{}:{}:{}", host.domain, host.port, addresses))
+                    .unwrap();
+            }
+            easy.resolve(resolve).unwrap();
+        }
+
+        // Advertises and transparently decodes every encoding libcurl was built with, usually gzip
+        // and deflate, sometimes br
+        easy.accept_encoding("").unwrap();
+        configure(&mut easy, options);
+
+        match body {
+            // A custom HEAD request would wait for the body announced by the response
+            Some(body) if body.method == "HEAD" => easy.nobody(true).unwrap(),
+            Some(body) => {
+                easy.custom_request(body.method).unwrap();
+                easy.post_fields_copy(&body.data).unwrap();
+            }
+            None => {}
+        }
+
+        Self { easy: Some(easy) }
+    }
+
+    fn easy(&mut self) -> &mut Easy {
+        self.easy.as_mut().unwrap()
+    }
+
+    /// Performs the request until its headers are received, without downloading the body
+    pub fn headers(&mut self) -> Result<Response, Error> {
+        let mut status = 0;
+        let mut headers = Vec::new();
+
+        let easy = self.easy();
+        let mut transfer = easy.transfer();
+        transfer
+            .header_function(|header| {
+                if let Some(header_status) = parse_status_line(header) {
+                    // New response, after an informational response
+                    status = header_status;
+                    headers.clear();
+                } else if let Some(header) = parse_header(header) {
+                    headers.push(header);
+                }
+                true
+            })
+            .unwrap();
+        // Aborts the transfer as soon as the body starts
+        transfer.write_function(|_| Ok(0)).unwrap();
+
+        match transfer.perform() {
+            Err(err) if !err.is_write_error() => return Err(err),
+            _ => {}
+        }
+        std::mem::drop(transfer);
+
+        let url = easy.effective_url()?.unwrap_or_default().to_string();
+
+        Ok(Response {
+            status,
+            url,
+            headers,
+        })
+    }
+
+    /// Performs one attempt of the request, passing the response to `sink`
+    pub fn perform<W: Write>(&mut self, sink: &mut Sink<W>) -> Result<(), Error> {
+        // Every callback needs the sink
+        let sink = RefCell::new(sink);
+
+        let easy = self.easy();
+        easy.progress(true).unwrap();
+        let mut transfer = easy.transfer();
+        transfer
+            .header_function(|header| {
+                if let Some(status) = parse_status_line(header) {
+                    sink.borrow_mut()
+                        .status(status, &String::from_utf8_lossy(header));
+                } else if let Some((name, value)) = parse_header(header) {
+                    sink.borrow_mut().header(&name, &value);
+                }
+                true
+            })
+            .unwrap();
+        transfer
+            .write_function(|data| {
+                // Writing less than the data aborts the transfer
+                match sink.borrow_mut().write(data) {
+                    Ok(()) => Ok(data.len()),
+                    Err(_) => Ok(0),
+                }
+            })
+            .unwrap();
+        // Called at least once per second, even when no data is received
+        transfer
+            .progress_function(|_, _, _, _| !sink.borrow().is_cancelled())
+            .unwrap();
+
+        transfer.perform()
+    }
+}
+
+/// Version of libcurl, and of its TLS library
+pub fn description() -> String {
+    let version = curl::Version::get();
+    match version.ssl_version() {
+        Some(ssl_version) => format!("libcurl/{} {}", version.version(), ssl_version),
+        None => format!("libcurl/{}", version.version()),
+    }
+}
+
+pub fn is_transient_error(err: &Error) -> bool {
+    err.is_couldnt_resolve_host()
+        || err.is_couldnt_connect()
+        || err.is_operation_timedout()
+        || err.is_send_error()
+        || err.is_recv_error()
+        || err.is_got_nothing()
+        || err.is_partial_file()
+}
+
+fn configure(easy: &mut Easy, options: &FetchOptions) {
+    if let Some(proxy) = options.proxy() {
+        easy.proxy(&proxy).unwrap();
+    }
+
+    if let Some(username) = &options.proxy_username {
+        easy.proxy_username(username).unwrap();
+    }
+
+    if let Some(password) = &options.proxy_password {
+        easy.proxy_password(password).unwrap();
+    }
+
+    if let Some(path) = options.unix_socket() {
+        easy.unix_socket(&path).unwrap();
+    }
+
+    if let Some(user_agent) = options.user_agent() {
+        easy.useragent(&user_agent).unwrap();
+    }
+
+    if let Some(timeout) = options.connect_timeout() {
+        easy.connect_timeout(timeout).unwrap();
+    }
+
+    if let Some(timeout) = options.timeout() {
+        easy.timeout(timeout).unwrap();
+    }
+
+    match options.ip_version {
+        Some(IpVersion::Ipv4) => easy.ip_resolve(IpResolve::V4).unwrap(),
+        Some(IpVersion::Ipv6) => easy.ip_resolve(IpResolve::V6).unwrap(),
+        Some(IpVersion::Any) | None => {}
+    }
+
+    if let Some(servers) = &options.dns_servers {
+        easy.dns_servers(servers)
+            .unwrap_or_else(|err| error!("couldn't use the DNS servers {:?}: {}", servers, err));
+    }
+
+    if let Some(username) = &options.username {
+        easy.username(username).unwrap();
+        easy.password(options.password.as_deref().unwrap_or_default())
+            .unwrap();
+        easy.http_auth(&to_curl_auth(
+            options.auth_method.unwrap_or(AuthMethod::Basic),
+        ))
+        .unwrap();
+    }
+
+    if let Some(cert) = options.ssl_cert() {
+        easy.ssl_cert(cert).unwrap();
+    }
+
+    if let Some(key) = options.ssl_key() {
+        easy.ssl_key(key).unwrap();
+    }
+
+    if let Some(password) = &options.ssl_key_password {
+        easy.key_password(password).unwrap();
+    }
+
+    if let Some(cert_type) = &options.ssl_cert_type {
+        easy.ssl_cert_type(&cert_type.to_ascii_uppercase()).unwrap();
+    }
+
+    if let Some(ca_file) = options.ssl_ca_file() {
+        easy.cainfo(ca_file).unwrap();
+    }
+
+    if let Some(pinned) = &options.ssl_pinned_public_key {
+        easy.pinned_public_key(pinned).unwrap();
+    }
+
+    if options.ssl_verify == Some(false) {
+        easy.ssl_verify_peer(false).unwrap();
+        easy.ssl_verify_host(false).unwrap();
+    }
+
+    let mut list = List::new();
+    for (name, value) in options.request_headers() {
+        list.append(&format!("{}: {}", name, value)).unwrap();
+    }
+    easy.http_headers(list).unwrap();
+}
+
+fn to_curl_auth(method: AuthMethod) -> Auth {
+    let mut auth = Auth::new();
+    match method {
+        AuthMethod::Basic => auth.basic(true),
+        AuthMethod::Digest => auth.digest(true),
+        AuthMethod::Ntlm => auth.ntlm(true),
+        AuthMethod::Negotiate => auth.gssnegotiate(true),
+        AuthMethod::Any => auth.basic(true).digest(true).ntlm(true).gssnegotiate(true),
+    };
+    auth
+}
+
+/// Extracts the status code from a raw status line header, such as `HTTP/1.1 200 OK`
+fn parse_status_line(header: &[u8]) -> Option<u32> {
+    if !header.starts_with(b"HTTP/") {
+        return None;
+    }
+
+    std::str::from_utf8(header)
+        .ok()?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+fn parse_header(header: &[u8]) -> Option<(String, String)> {
+    let header = std::str::from_utf8(header).ok()?;
+    let (name, value) = header.split_once(':')?;
+    Some((name.trim().to_string(), value.trim().to_string()))
+}
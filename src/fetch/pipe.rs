@@ -0,0 +1,83 @@
+//! Pipe between a transfer thread and the backend's thread, which parses what it reads
+//!
+//! The pipe has no capacity of its own: a chunk is only handed over once the reader wants one, so a
+//! slow parser pauses the transfer. While it waits for a chunk, the reader keeps checking for
+//! interrupts, so that a query can be cancelled even though the server sends nothing.
+
+use pgx::*;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::time::Duration;
+
+/// How often a waiting reader checks for interrupts
+pub const INTERRUPT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    let reader = PipeReader {
+        receiver,
+        chunk: Vec::new(),
+        position: 0,
+    };
+    (reader, PipeWriter { sender })
+}
+
+/// Must only be read from the backend's thread, as it checks for interrupts
+pub struct PipeReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl PipeReader {
+    /// Disconnects the pipe, so that the writer fails instead of waiting for the reader
+    pub fn close(&mut self) {
+        let (_, receiver) = mpsc::sync_channel(0);
+        self.receiver = receiver;
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv_timeout(INTERRUPT_CHECK_INTERVAL) {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    check_for_interrupts!();
+                }
+                // The writer was dropped
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let remaining = &self.chunk[self.position..];
+        let read = remaining.len().min(buf.len());
+        buf[..read].copy_from_slice(&remaining[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+pub struct PipeWriter {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the reader was dropped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
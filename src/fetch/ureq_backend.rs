@@ -0,0 +1,263 @@
+//! Transfers done by [`ureq`], for builds without libcurl
+//!
+//! Only basic authentication is supported, and the TLS options (client certificates, custom
+//! certificate authorities, pinning...) are refused.
+
+use super::{AuthMethod, FetchOptions, IpVersion, RequestBody, Response, Sink};
+use crate::url_policy::ResolvedHost;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use pgx::*;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use url::Url;
+
+#[derive(Debug)]
+pub enum Error {
+    Transport(Box<ureq::Transport>),
+    /// The body couldn't be read
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(err) => err.fmt(f),
+            Error::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+pub struct Transfer {
+    request: ureq::Request,
+    body: Option<Vec<u8>>,
+}
+
+impl Transfer {
+    /// Must be called from the backend's thread, as it reads settings and may raise SQL errors
+    pub fn new(
+        url: &str,
+        hosts: Vec<ResolvedHost>,
+        options: &FetchOptions,
+        body: Option<RequestBody>,
+    ) -> Self {
+        refuse_unsupported(options);
+
+        // Redirections are never followed by the client, see `fetch_headers`
+        let mut agent = ureq::AgentBuilder::new().redirects(0);
+
+        if !hosts.is_empty() || options.ip_version.is_some() {
+            let ip_version = options.ip_version.unwrap_or(IpVersion::Any);
+            let pinned = hosts
+                .into_iter()
+                .map(|host| {
+                    let netloc = format!("{}:{}", host.domain, host.port);
+                    let addresses = host
+                        .addresses
+                        .iter()
+                        .map(|ip| SocketAddr::new(*ip, host.port))
+                        .collect::<Vec<_>>();
+                    (netloc, addresses)
+                })
+                .collect::<Vec<_>>();
+
+            agent = agent.resolver(move |netloc: &str| {
+                match pinned
+                    .iter()
+                    .find(|(pinned, _)| netloc.eq_ignore_ascii_case(pinned))
+                {
+                    Some((_, addresses)) => Ok(addresses.clone()),
+                    None => netloc.to_socket_addrs().map(|addresses| {
+                        addresses
+                            .filter(|address| ip_version.accepts(&address.ip()))
+                            .collect()
+                    }),
+                }
+            });
+        }
+
+        if let Some(proxy) = options.proxy().filter(|proxy| !proxy.is_empty()) {
+            agent = agent.proxy(proxy_with_credentials(&proxy, options));
+        }
+
+        if let Some(user_agent) = options.user_agent() {
+            agent = agent.user_agent(&user_agent);
+        }
+
+        if let Some(timeout) = options.connect_timeout() {
+            agent = agent.timeout_connect(timeout);
+        }
+
+        if let Some(timeout) = options.timeout() {
+            agent = agent.timeout(timeout);
+        }
+
+        let method = body.as_ref().map_or("GET", |body| body.method);
+        let mut request = agent.build().request(method, url);
+
+        if let Some(username) = &options.username {
+            let credentials = format!(
+                "{}:{}",
+                username,
+                options.password.as_deref().unwrap_or_default(),
+            );
+            request = request.set(
+                "Authorization",
+                &format!("Basic {}", STANDARD.encode(credentials)),
+            );
+        }
+
+        // Setting a header replaces its previous values, so repeated headers are joined
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for (name, value) in options.request_headers() {
+            match headers
+                .iter_mut()
+                .find(|(header_name, _)| header_name.eq_ignore_ascii_case(&name))
+            {
+                Some((_, values)) => *values = format!("{}, {}", values, value),
+                None => headers.push((name, value)),
+            }
+        }
+        for (name, value) in headers {
+            request = request.set(&name, &value);
+        }
+
+        Self {
+            request,
+            body: body
+                .filter(|body| body.method != "HEAD")
+                .map(|body| body.data),
+        }
+    }
+
+    /// Performs the request until its headers are received, without downloading the body
+    pub fn headers(&mut self) -> Result<Response, Error> {
+        let response = self.call()?;
+
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .flat_map(|name| {
+                response
+                    .all(&name)
+                    .into_iter()
+                    .map(|value| (name.clone(), value.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        Ok(Response {
+            status: response.status().into(),
+            url: response.get_url().to_string(),
+            headers,
+        })
+    }
+
+    /// Performs one attempt of the request, passing the response to `sink`
+    pub fn perform<W: Write>(&mut self, sink: &mut Sink<W>) -> Result<(), Error> {
+        let response = self.call()?;
+
+        sink.status(
+            response.status().into(),
+            &format!(
+                "{} {} {}",
+                response.http_version(),
+                response.status(),
+                response.status_text(),
+            ),
+        );
+        for name in response.headers_names() {
+            for value in response.all(&name) {
+                sink.header(&name, value);
+            }
+        }
+
+        let mut reader = response.into_reader();
+        let mut buffer = vec![0; 16 * 1024];
+        loop {
+            if sink.is_cancelled() {
+                return Ok(());
+            }
+
+            match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => sink.write(&buffer[..read]).map_err(Error::Io)?,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(Error::Io(err)),
+            }
+        }
+    }
+
+    fn call(&self) -> Result<ureq::Response, Error> {
+        let request = self.request.clone();
+        let result = match &self.body {
+            Some(body) => request.send_bytes(body),
+            None => request.call(),
+        };
+
+        match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+            Err(ureq::Error::Transport(err)) => Err(Error::Transport(Box::new(err))),
+        }
+    }
+}
+
+/// The HTTP client and its TLS library, which are built in
+pub fn description() -> String {
+    "ureq/2 rustls".to_string()
+}
+
+pub fn is_transient_error(err: &Error) -> bool {
+    match err {
+        Error::Transport(err) => matches!(
+            err.kind(),
+            ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io,
+        ),
+        Error::Io(_) => true,
+    }
+}
+
+fn refuse_unsupported(options: &FetchOptions) {
+    let unsupported = [
+        ("ssl_cert", options.ssl_cert().is_some()),
+        ("ssl_key", options.ssl_key().is_some()),
+        ("ssl_key_password", options.ssl_key_password.is_some()),
+        ("ssl_cert_type", options.ssl_cert_type.is_some()),
+        ("ssl_ca_file", options.ssl_ca_file().is_some()),
+        (
+            "ssl_pinned_public_key",
+            options.ssl_pinned_public_key.is_some(),
+        ),
+        ("ssl_verify", options.ssl_verify == Some(false)),
+        ("dns_servers", options.dns_servers.is_some()),
+        ("unix_socket", options.unix_socket().is_some()),
+        (
+            "auth_method",
+            !matches!(options.auth_method, None | Some(AuthMethod::Basic)),
+        ),
+    ];
+
+    if let Some((option, _)) = unsupported.iter().find(|(_, used)| *used) {
+        error!(
+            "{} isn't supported when postgres-ical is built without libcurl",
+            option,
+        );
+    }
+}
+
+/// [`ureq::Proxy`] only takes credentials embedded in the URL
+fn proxy_with_credentials(proxy: &str, options: &FetchOptions) -> ureq::Proxy {
+    let mut url =
+        Url::parse(proxy).unwrap_or_else(|err| error!("invalid proxy URL {:?}: {}", proxy, err));
+
+    if let Some(username) = &options.proxy_username {
+        let _ = url.set_username(username);
+    }
+    if let Some(password) = &options.proxy_password {
+        let _ = url.set_password(Some(password));
+    }
+
+    ureq::Proxy::new(url.as_str())
+        .unwrap_or_else(|err| error!("invalid proxy URL {:?}: {}", proxy, err))
+}
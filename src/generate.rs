@@ -0,0 +1,171 @@
+//! Generation of ical files
+
+use pgx::*;
+use postgres_ical_parser::recurrence::RRule;
+use postgres_ical_parser::types::IcalDateTime;
+use postgres_ical_parser::writer::{escape_text, IcalWriter, Property, PRODID};
+use std::io;
+use time::OffsetDateTime;
+
+/// Writes a `VCALENDAR`, whose components are written by `write_components`
+///
+/// `method` is the iTIP method of the calendar, if it's a scheduling message.
+pub fn calendar(
+    method: Option<&str>,
+    write_components: impl FnOnce(&mut IcalWriter<Vec<u8>>) -> io::Result<()>,
+) -> String {
+    let mut writer = IcalWriter::new(Vec::new());
+
+    let result = (|| {
+        writer.begin("VCALENDAR")?;
+        writer.text_property("VERSION", "2.0")?;
+        writer.text_property("PRODID", PRODID)?;
+        if let Some(method) = method {
+            writer.text_property("METHOD", method)?;
+        }
+        write_components(&mut writer)?;
+        writer.end("VCALENDAR")
+    })();
+
+    // Writing to a vector only fails on names and values that would break content lines
+    result.unwrap_or_else(|err| error!("can't write the calendar: {}", err));
+    String::from_utf8(writer.into_inner()).unwrap()
+}
+
+/// Fields of a single event
+#[derive(Default)]
+pub struct Event<'a> {
+    pub uid: &'a str,
+    pub summary: Option<&'a str>,
+    pub dt_start: Option<OffsetDateTime>,
+    pub dt_end: Option<OffsetDateTime>,
+    pub description: Option<&'a str>,
+    pub location: Option<&'a str>,
+    /// Recurrence rule, as in `FREQ=WEEKLY;BYDAY=MO`
+    pub rrule: Option<&'a str>,
+    pub categories: &'a [String],
+    pub sequence: Option<i32>,
+    pub status: Option<&'a str>,
+    pub organizer: Option<&'a str>,
+    pub attendees: &'a [Attendee<'a>],
+}
+
+pub struct Attendee<'a> {
+    pub address: &'a str,
+    pub role: Option<&'a str>,
+    pub partstat: Option<&'a str>,
+    pub rsvp: bool,
+}
+
+/// Writes a `VEVENT`, whose DTSTAMP is the current time
+pub fn event(event: &Event) -> String {
+    let mut writer = IcalWriter::new(Vec::new());
+
+    // Writing to a vector only fails on names and values that would break content lines
+    write_event(&mut writer, event).unwrap_or_else(|err| error!("can't write the event: {}", err));
+    String::from_utf8(writer.into_inner()).unwrap()
+}
+
+pub fn write_event(writer: &mut IcalWriter<Vec<u8>>, event: &Event) -> io::Result<()> {
+    writer.begin("VEVENT")?;
+    writer.text_property("UID", event.uid)?;
+    writer.property(&date_time("DTSTAMP", OffsetDateTime::now_utc()))?;
+    if let Some(dt_start) = event.dt_start {
+        writer.property(&date_time("DTSTART", dt_start))?;
+    }
+    if let Some(dt_end) = event.dt_end {
+        writer.property(&date_time("DTEND", dt_end))?;
+    }
+    if let Some(summary) = event.summary {
+        writer.text_property("SUMMARY", summary)?;
+    }
+    if let Some(description) = event.description {
+        writer.text_property("DESCRIPTION", description)?;
+    }
+    if let Some(location) = event.location {
+        writer.text_property("LOCATION", location)?;
+    }
+    if let Some(rrule) = event.rrule {
+        // Checked rather than trusted, as it's written as is
+        if let Err(err) = rrule.parse::<RRule>() {
+            error!("invalid RRULE: {}", err);
+        }
+        writer.property(&Property {
+            name: "RRULE".into(),
+            params: None,
+            value: Some(rrule.into()),
+        })?;
+    }
+    if !event.categories.is_empty() {
+        let categories = event
+            .categories
+            .iter()
+            .map(|category| escape_text(category));
+        writer.property(&Property {
+            name: "CATEGORIES".into(),
+            params: None,
+            value: Some(categories.collect::<Vec<_>>().join(",")),
+        })?;
+    }
+    if let Some(sequence) = event.sequence {
+        writer.property(&Property {
+            name: "SEQUENCE".into(),
+            params: None,
+            value: Some(sequence.to_string()),
+        })?;
+    }
+    if let Some(status) = event.status {
+        writer.text_property("STATUS", status)?;
+    }
+    if let Some(organizer) = event.organizer {
+        writer.property(&Property {
+            name: "ORGANIZER".into(),
+            params: None,
+            value: Some(cal_address(organizer)),
+        })?;
+    }
+    for attendee in event.attendees {
+        let mut params = Vec::new();
+        if let Some(role) = attendee.role {
+            params.push(("ROLE".into(), vec![role.into()]));
+        }
+        if let Some(partstat) = attendee.partstat {
+            params.push(("PARTSTAT".into(), vec![partstat.into()]));
+        }
+        if attendee.rsvp {
+            params.push(("RSVP".into(), vec!["TRUE".into()]));
+        }
+        writer.property(&Property {
+            name: "ATTENDEE".into(),
+            params: Some(params),
+            value: Some(cal_address(attendee.address)),
+        })?;
+    }
+    writer.end("VEVENT")
+}
+
+/// A CAL-ADDRESS, from either an URI or an email address
+///
+/// Addresses often come from the rows of triggers, so they're checked rather than trusted.
+fn cal_address(address: &str) -> String {
+    if address.chars().any(char::is_control) {
+        error!(
+            "invalid address {:?}: it contains control characters",
+            address
+        );
+    }
+
+    if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("mailto:{}", address)
+    }
+}
+
+/// A DATE-TIME property, in UTC
+fn date_time(name: &str, date_time: OffsetDateTime) -> Property {
+    use chrono::TimeZone;
+
+    let date_time = chrono::Utc.timestamp(date_time.unix_timestamp(), 0);
+    IcalDateTime::Utc(date_time).to_property(name)
+}
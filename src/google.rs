@@ -0,0 +1,277 @@
+//! Google Calendar API client
+//!
+//! Some calendars are only available through the [Google Calendar API][api], and not as an
+//! iCalendar export. Their events are normalized into the same [`Component`] rows as parsed ones.
+//!
+//! [api]: https://developers.google.com/calendar/api/v3/reference/events/list
+
+use crate::cal_address::{_ical_cal_address, ical_cal_address};
+use crate::fetch::{FetchOptions, PreparedRequest};
+use crate::{to_time, Class, Component, ComponentType, Status};
+use pgx::*;
+use serde::Deserialize;
+use time::UtcOffset;
+use url::Url;
+
+const API_URL: &str = "https://www.googleapis.com/calendar/v3/calendars";
+
+/// Largest page size accepted by the API
+const MAX_RESULTS: &str = "2500";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventList {
+    #[serde(default)]
+    items: Vec<GoogleEvent>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEvent {
+    id: String,
+    #[serde(rename = "iCalUID")]
+    ical_uid: Option<String>,
+    status: Option<String>,
+    summary: Option<String>,
+    description: Option<String>,
+    location: Option<String>,
+    created: Option<String>,
+    updated: Option<String>,
+    start: Option<EventTime>,
+    end: Option<EventTime>,
+    original_start_time: Option<EventTime>,
+    sequence: Option<i32>,
+    visibility: Option<String>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    organizer: Option<Person>,
+    #[serde(default)]
+    attendees: Vec<Person>,
+    reminders: Option<Reminders>,
+}
+
+/// Either a date, for all-day events, or a date-time
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventTime {
+    date: Option<String>,
+    date_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Attachment {
+    file_url: String,
+}
+
+/// The overrides are the alarms of the event, while the default reminders of the calendar aren't
+/// given
+#[derive(Deserialize)]
+struct Reminders {
+    #[serde(default)]
+    overrides: Vec<serde_json::Value>,
+}
+
+/// Organizer or attendee
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Person {
+    email: Option<String>,
+    display_name: Option<String>,
+    response_status: Option<String>,
+    #[serde(default)]
+    optional: bool,
+}
+
+/// Lists every event of a calendar, following the result pages
+///
+/// Recurring events are returned once, as with an iCalendar export, rather than expanded.
+pub fn events(calendar_id: &str, api_key: Option<&str>, options: &FetchOptions) -> Vec<Component> {
+    let mut url = Url::parse(API_URL).unwrap();
+    url.path_segments_mut()
+        .unwrap()
+        .extend(&[calendar_id, "events"]);
+
+    let mut components = Vec::new();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let mut page_url = url.clone();
+        {
+            let mut query = page_url.query_pairs_mut();
+            query.append_pair("maxResults", MAX_RESULTS);
+            if let Some(api_key) = api_key {
+                query.append_pair("key", api_key);
+            }
+            if let Some(page_token) = &page_token {
+                query.append_pair("pageToken", page_token);
+            }
+        }
+
+        let mut response = Vec::new();
+        PreparedRequest::new(page_url.as_str(), options)
+            .perform(&mut response)
+            .into_result()
+            .unwrap_or_else(|err| error!("couldn't list the events of {}: {}", calendar_id, err));
+
+        let list: EventList = serde_json::from_slice(&response).unwrap_or_else(|err| {
+            error!("invalid Google Calendar API response: {}", err);
+        });
+
+        let first_ordinality = components.len() as i64 + 1;
+        components.extend(list.items.into_iter().zip(first_ordinality..).map(
+            |(event, event_ordinality)| Component {
+                source_url: Some(url.to_string()),
+                event_ordinality,
+                ..convert_event(event)
+            },
+        ));
+
+        match list.next_page_token {
+            Some(token) => page_token = Some(token),
+            None => return components,
+        }
+    }
+}
+
+/// The participation of organizers isn't given, as it isn't in iCalendar exports
+fn convert_person(person: Person, is_attendee: bool) -> Option<ical_cal_address> {
+    let partstat = match person.response_status.as_deref() {
+        Some("needsAction") => Some("NEEDS-ACTION"),
+        Some("declined") => Some("DECLINED"),
+        Some("tentative") => Some("TENTATIVE"),
+        Some("accepted") => Some("ACCEPTED"),
+        _ => None,
+    };
+    let role = if person.optional {
+        "OPT-PARTICIPANT"
+    } else {
+        "REQ-PARTICIPANT"
+    };
+
+    Some(ical_cal_address {
+        cal_address: format!("mailto:{}", person.email?),
+        cn: person.display_name,
+        partstat: partstat.filter(|_| is_attendee).map(String::from),
+        role: is_attendee.then(|| role.to_string()),
+        params: serde_json::Value::Object(Default::default()),
+    })
+}
+
+fn convert_event(event: GoogleEvent) -> Component {
+    let (created, created_naive) = parse_date_time(event.created.as_deref());
+    let (last_modified, last_modified_naive) = parse_date_time(event.updated.as_deref());
+    let (dt_start, dt_start_naive) = parse_event_time(event.start.as_ref());
+    let (dt_end, dt_end_naive) = parse_event_time(event.end.as_ref());
+    let (recurrence_id, recurrence_id_naive) = parse_event_time(event.original_start_time.as_ref());
+
+    Component {
+        component_type: ComponentType::VEVENT,
+        attachment: event
+            .attachments
+            .into_iter()
+            .next()
+            .map(|attachment| attachment.file_url),
+        attendees: _ical_cal_address(
+            event
+                .attendees
+                .into_iter()
+                .filter_map(|attendee| convert_person(attendee, true))
+                .collect(),
+        ),
+        categories: Vec::new(),
+        class: event
+            .visibility
+            .as_deref()
+            .and_then(|visibility| match visibility {
+                "public" => Some(Class::PUBLIC),
+                "private" => Some(Class::PRIVATE),
+                "confidential" => Some(Class::CONFIDENTIAL),
+                _ => None,
+            }),
+        comment: Vec::new(),
+        completed: None,
+        completed_naive: None,
+        created,
+        created_naive,
+        description: event.description,
+        dt_stamp: None,
+        dt_stamp_naive: None,
+        dt_start,
+        dt_start_naive,
+        dt_end,
+        dt_end_naive,
+        due: None,
+        due_naive: None,
+        duration: None,
+        geo_lat: None,
+        geo_lng: None,
+        last_modified,
+        last_modified_naive,
+        location: event.location,
+        organizer: event
+            .organizer
+            .and_then(|organizer| convert_person(organizer, false)),
+        percent_complete: None,
+        priority: None,
+        recurrence_id,
+        recurrence_id_naive,
+        resources: Vec::new(),
+        status: event.status.as_deref().and_then(|status| match status {
+            "tentative" => Some(Status::TENTATIVE),
+            "confirmed" => Some(Status::CONFIRMED),
+            "cancelled" => Some(Status::CANCELLED),
+            _ => None,
+        }),
+        sequence: event.sequence.unwrap_or_default(),
+        summary: event.summary,
+        uid: event.ical_uid.unwrap_or(event.id),
+        source_url: None,
+        calendar_seq: 1,
+        event_ordinality: 0,
+        alarm_count: event
+            .reminders
+            .map_or(0, |reminders| reminders.overrides.len() as i32),
+    }
+}
+
+fn parse_event_time(
+    time: Option<&EventTime>,
+) -> (Option<TimestampWithTimeZone>, Option<Timestamp>) {
+    match time {
+        Some(EventTime {
+            date_time: Some(date_time),
+            ..
+        }) => parse_date_time(Some(date_time)),
+        Some(EventTime {
+            date: Some(date), ..
+        }) => {
+            let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .unwrap_or_else(|err| error!("invalid date {:?}: {}", date, err));
+            (
+                None,
+                Some(Timestamp::new(to_time(date.and_hms_opt(0, 0, 0).unwrap()))),
+            )
+        }
+        _ => (None, None),
+    }
+}
+
+/// Parses an RFC 3339 date-time, keeping its offset
+fn parse_date_time(date_time: Option<&str>) -> (Option<TimestampWithTimeZone>, Option<Timestamp>) {
+    let date_time = match date_time {
+        Some(date_time) => chrono::DateTime::parse_from_rfc3339(date_time)
+            .unwrap_or_else(|err| error!("invalid date-time {:?}: {}", date_time, err)),
+        None => return (None, None),
+    };
+
+    let offset = UtcOffset::from_whole_seconds(date_time.offset().local_minus_utc()).unwrap();
+    (
+        Some(TimestampWithTimeZone::new(
+            to_time(date_time.naive_local()),
+            offset,
+        )),
+        None,
+    )
+}
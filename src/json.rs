@@ -0,0 +1,294 @@
+//! JSON documents of raw components
+//!
+//! A component is represented as
+//! `{"component": "VEVENT", "properties": {"SUMMARY": [{"value": "…", "params": {…}}], …}, "components": […]}`.
+//! Every occurrence of a property is kept, in order, with its parameters, whose values are
+//! arrays of all of their values, unquoted. The values of the single-valued TEXT properties are
+//! unescaped, while the other values are kept as written.
+//!
+//! In the other direction, events are built from objects whose keys are property names, such as
+//! `{"uid": "…", "summary": "…", "dtstart": "2022-03-01T10:00:00+01:00"}`. The `properties` of
+//! the documents above are accepted as well. Names must be iana-tokens or x-names, other than
+//! `BEGIN` and `END`, and the values that aren't escaped as TEXT can't contain control
+//! characters, so that every key only writes its own property.
+
+use crate::generate;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use pgx::*;
+use postgres_ical_parser::types::{decode_param_value, IcalText, IcalType};
+use postgres_ical_parser::writer::{escape_text, is_valid_name, IcalWriter, Property};
+use postgres_ical_parser::RawComponent;
+use serde_json::{Map, Value};
+
+/// Properties whose value is a single TEXT by default
+const TEXT_PROPERTIES: &[&str] = &[
+    "ACTION",
+    "CLASS",
+    "COMMENT",
+    "CONTACT",
+    "DESCRIPTION",
+    "LOCATION",
+    "PRODID",
+    "RELATED-TO",
+    "STATUS",
+    "SUMMARY",
+    "TRANSP",
+    "TZID",
+    "TZNAME",
+    "UID",
+];
+
+/// Properties whose value is a DATE-TIME, or a DATE, by default
+const DATE_TIME_PROPERTIES: &[&str] = &[
+    "COMPLETED",
+    "CREATED",
+    "DTEND",
+    "DTSTAMP",
+    "DTSTART",
+    "DUE",
+    "EXDATE",
+    "LAST-MODIFIED",
+    "RDATE",
+    "RECURRENCE-ID",
+];
+
+pub fn component_to_json(component: RawComponent) -> Value {
+    // The components being converted, each one a sub-component of the previous one, without
+    // recursion over the tree
+    let mut stack = vec![PendingDocument::new(component)];
+
+    loop {
+        let pending = stack.last_mut().expect("a component is being converted");
+        match pending.sub_components.next() {
+            Some(sub_component) => stack.push(PendingDocument::new(sub_component)),
+            None => {
+                let document = stack
+                    .pop()
+                    .expect("a component is being converted")
+                    .finish();
+                match stack.last_mut() {
+                    Some(parent) => parent.components.push(document),
+                    None => return document,
+                }
+            }
+        }
+    }
+}
+
+/// The document of a component, until the documents of its sub-components are built
+struct PendingDocument {
+    name: String,
+    properties: Map<String, Value>,
+    sub_components: std::vec::IntoIter<RawComponent>,
+    components: Vec<Value>,
+}
+
+impl PendingDocument {
+    fn new(component: RawComponent) -> Self {
+        PendingDocument {
+            name: component.name,
+            properties: properties_to_json(component.properties),
+            sub_components: component.components.into_iter(),
+            components: Vec::new(),
+        }
+    }
+
+    fn finish(self) -> Value {
+        let mut document = Map::new();
+        document.insert("component".into(), self.name.into());
+        document.insert("properties".into(), self.properties.into());
+        document.insert("components".into(), self.components.into());
+        document.into()
+    }
+}
+
+/// The occurrences of each property, by name
+fn properties_to_json(occurrences: Vec<Property>) -> Map<String, Value> {
+    let mut properties = Map::new();
+
+    for property in occurrences {
+        let params: Map<String, Value> = property
+            .params
+            .iter()
+            .flatten()
+            .map(|(name, values)| {
+                let values: Vec<_> = values
+                    .iter()
+                    .map(|value| decode_param_value(value))
+                    .collect();
+                (name.to_ascii_uppercase(), values.into())
+            })
+            .collect();
+
+        let name = property.name.clone();
+        let is_text = TEXT_PROPERTIES.contains(&name.as_str())
+            && !params.contains_key("VALUE")
+            && property.value.as_deref().is_some();
+        let value = if is_text {
+            IcalText::parse(property).ok().map(Value::from)
+        } else {
+            property.value.map(Value::from)
+        };
+
+        let mut occurrence = Map::new();
+        occurrence.insert("value".into(), value.unwrap_or(Value::Null));
+        occurrence.insert("params".into(), params.into());
+
+        match properties
+            .entry(name)
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            Value::Array(occurrences) => occurrences.push(occurrence.into()),
+            _ => unreachable!(),
+        }
+    }
+
+    properties
+}
+
+/// Builds a `VCALENDAR` from an array of event objects
+pub fn calendar_from_json(events: &Value) -> String {
+    let events = match events {
+        Value::Array(events) => events,
+        other => error!("events must be a jsonb array, got {}", other),
+    };
+
+    generate::calendar(None, |writer| write_events(writer, events))
+}
+
+fn write_events(writer: &mut IcalWriter<Vec<u8>>, events: &[Value]) -> std::io::Result<()> {
+    for event in events {
+        let event = match event {
+            Value::Object(event) => event,
+            other => error!("events must be jsonb objects, got {}", other),
+        };
+
+        let properties: Vec<Property> = event
+            .iter()
+            .flat_map(|(name, value)| to_properties(name, value))
+            .collect();
+
+        if !properties.iter().any(|property| property.name == "UID") {
+            error!("every event must have a uid");
+        }
+
+        writer.begin("VEVENT")?;
+        // DTSTAMP is required
+        if !properties.iter().any(|property| property.name == "DTSTAMP") {
+            writer.property(&Property {
+                name: "DTSTAMP".into(),
+                params: None,
+                value: Some(Utc::now().format("%Y%m%dT%H%M%SZ").to_string()),
+            })?;
+        }
+        for property in &properties {
+            writer.property(property)?;
+        }
+        writer.end("VEVENT")?;
+    }
+
+    Ok(())
+}
+
+/// Occurrences of a property, given either as a value, as `{"value": …, "params": {…}}`, or as an
+/// array of those
+fn to_properties(name: &str, value: &Value) -> Vec<Property> {
+    let name = name.to_ascii_uppercase();
+    if !is_valid_name(&name) || name == "BEGIN" || name == "END" {
+        error!("invalid property name {:?}", name);
+    }
+
+    let occurrences = match value {
+        Value::Array(occurrences) => occurrences.iter().collect(),
+        value => vec![value],
+    };
+
+    occurrences
+        .into_iter()
+        .filter_map(|occurrence| {
+            let (value, params) = match occurrence {
+                Value::Object(object) => (
+                    object.get("value").unwrap_or(&Value::Null),
+                    object.get("params"),
+                ),
+                value => (value, None),
+            };
+
+            let mut params: Vec<(String, Vec<String>)> = match params {
+                None | Some(Value::Null) => Vec::new(),
+                Some(Value::Object(params)) => params
+                    .iter()
+                    .map(|(name, values)| {
+                        if !is_valid_name(name) {
+                            error!("invalid parameter name {:?}", name);
+                        }
+                        let values = match values {
+                            Value::Array(values) => values.iter().map(to_string).collect(),
+                            value => vec![to_string(value)],
+                        };
+                        (name.to_ascii_uppercase(), values)
+                    })
+                    .collect(),
+                Some(other) => error!("the params of {} must be an object, got {}", name, other),
+            };
+
+            let value = match value {
+                Value::Null => return None,
+                Value::String(value) => to_ical_value(&name, value, &mut params),
+                Value::Bool(true) => "TRUE".into(),
+                Value::Bool(false) => "FALSE".into(),
+                value => value.to_string(),
+            };
+
+            Some(Property {
+                name: name.clone(),
+                params: Some(params),
+                value: Some(value),
+            })
+        })
+        .collect()
+}
+
+fn to_string(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Escapes TEXT values, and converts the date-times written as in JSON, such as by `to_jsonb`
+fn to_ical_value(name: &str, value: &str, params: &mut Vec<(String, Vec<String>)>) -> String {
+    if params.iter().any(|(param, _)| param == "VALUE") {
+        return raw_value(name, value);
+    }
+
+    if TEXT_PROPERTIES.contains(&name) || name == "CATEGORIES" || name == "RESOURCES" {
+        return escape_text(value);
+    }
+
+    if DATE_TIME_PROPERTIES.contains(&name) {
+        if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+            return date_time
+                .with_timezone(&Utc)
+                .format("%Y%m%dT%H%M%SZ")
+                .to_string();
+        }
+        if let Ok(date_time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+            return date_time.format("%Y%m%dT%H%M%S").to_string();
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            params.push(("VALUE".into(), vec!["DATE".into()]));
+            return date.format("%Y%m%d").to_string();
+        }
+    }
+
+    raw_value(name, value)
+}
+
+/// A value that is written as is
+fn raw_value(name: &str, value: &str) -> String {
+    if value.chars().any(char::is_control) {
+        error!("the value of {} contains control characters", name);
+    }
+    value.to_string()
+}
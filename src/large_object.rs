@@ -0,0 +1,62 @@
+//! Streaming reads of large objects
+
+use pgx::*;
+use std::io::{self, BufRead, Read};
+
+/// Size of the chunks read with each `lo_get` call
+const CHUNK_SIZE: i32 = 64 * 1024;
+
+/// Reads a large object chunk by chunk, so that it's never entirely loaded in memory
+///
+/// Chunks are read through SPI with `lo_get`, which checks the caller's privileges on the object.
+pub struct LargeObjectReader {
+    oid: pg_sys::Oid,
+    offset: i64,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl LargeObjectReader {
+    pub fn new(oid: pg_sys::Oid) -> Self {
+        Self {
+            oid,
+            offset: 0,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for LargeObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl BufRead for LargeObjectReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position == self.chunk.len() {
+            self.chunk = Spi::get_one_with_args(
+                "SELECT lo_get($1, $2, $3)",
+                vec![
+                    (PgBuiltInOids::OIDOID.oid(), self.oid.into_datum()),
+                    (PgBuiltInOids::INT8OID.oid(), self.offset.into_datum()),
+                    (PgBuiltInOids::INT4OID.oid(), CHUNK_SIZE.into_datum()),
+                ],
+            )
+            .unwrap_or_default();
+            self.position = 0;
+            self.offset += self.chunk.len() as i64;
+        }
+
+        Ok(&self.chunk[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.chunk.len());
+    }
+}
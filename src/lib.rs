@@ -1,38 +1,49 @@
+// `#[pg_extern_columns]` expands the row structures to (long) tuple types
+#![allow(clippy::type_complexity)]
+
+use cal_address::{_ical_cal_address, ical_cal_address};
 use chrono::{Datelike, Timelike};
-use curl::easy::Easy;
+use flate2::read::GzDecoder;
 use pgx::*;
 use pgx_named_columns::*;
-use pipe::PipeReader;
+use postgres_ical_parser::borrowed::{EventRef, EventRefsReader};
 use postgres_ical_parser::types::IcalDateTime;
 use postgres_ical_parser::{CalendarParseError, Event};
-use std::io::{BufRead, BufReader, Cursor, Write};
-use std::thread::JoinHandle;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
 use time::{PrimitiveDateTime, UtcOffset};
 
-pg_module_magic!();
-
-/// [`curl`] is used instead of a Rustier alternative to make [`postgres_ical`] as lightweight as
-/// possible
-fn curl_get(url: &str) -> (PipeReader, JoinHandle<()>) {
-    let (reader, mut writer) = pipe::pipe_buffered();
-
-    let mut easy = Easy::new();
-    easy.url(url).unwrap();
-
-    let handle = std::thread::spawn(move || {
-        let mut transfer = easy.transfer();
-        transfer
-            .write_function(move |data| {
-                writer.write_all(data).unwrap();
-                Ok(data.len())
-            })
-            .unwrap();
+mod attachments;
+mod cal_address;
+mod caldav;
+mod columns;
+mod credentials;
+mod dedup;
+mod diff;
+mod expand;
+mod fdw;
+mod feeds;
+mod fetch;
+mod generate;
+mod google;
+mod guc;
+mod json;
+mod large_object;
+mod oauth;
+#[cfg(feature = "postgis")]
+mod postgis;
+mod privileges;
+mod sql;
+mod sync;
+mod transcode;
+mod url_policy;
 
-        transfer.perform().unwrap();
-        std::mem::drop(transfer);
-    });
+pg_module_magic!();
 
-    (reader, handle)
+#[pg_guard]
+pub extern "C" fn _PG_init() {
+    guc::init();
+    feeds::init();
 }
 
 fn to_time(d: impl Datelike + Timelike) -> PrimitiveDateTime {
@@ -63,12 +74,13 @@ fn to_time(d: impl Datelike + Timelike) -> PrimitiveDateTime {
 
 fn serialize_datetime(date: IcalDateTime) -> (Option<TimestampWithTimeZone>, Option<Timestamp>) {
     match date {
+        IcalDateTime::Date(date) => (None, Some(Timestamp::new(to_time(date.and_hms(0, 0, 0))))),
         IcalDateTime::Naive(naive) => (None, Some(Timestamp::new(to_time(naive)))),
         IcalDateTime::Utc(utc) => (
             Some(TimestampWithTimeZone::new(to_time(utc), UtcOffset::UTC)),
             None,
         ),
-        IcalDateTime::Tz(tz) => {
+        IcalDateTime::Tz(tz, _) => {
             use chrono::Offset;
             let offset = tz.offset().fix().local_minus_utc();
             let offset = UtcOffset::from_whole_seconds(offset).unwrap();
@@ -81,7 +93,7 @@ fn serialize_datetime(date: IcalDateTime) -> (Option<TimestampWithTimeZone>, Opt
 #[deprecated]
 type Interval = i16;
 
-#[derive(PostgresEnum)]
+#[derive(PartialEq, PostgresEnum)]
 pub enum ComponentType {
     VCALENDAR,
     VEVENT,
@@ -92,14 +104,14 @@ pub enum ComponentType {
     VALARM,
 }
 
-#[derive(PostgresEnum)]
+#[derive(PartialEq, PostgresEnum)]
 pub enum Class {
     PUBLIC,
     PRIVATE,
     CONFIDENTIAL,
 }
 
-#[derive(PostgresEnum)]
+#[derive(PartialEq, PostgresEnum)]
 pub enum Status {
     TENTATIVE,
     CONFIRMED,
@@ -112,9 +124,12 @@ pub enum Status {
 }
 
 /// Represents a row returned by [pg_ical] or [pg_ical_curl]
+///
+/// The `ical_event` composite type has the same columns, and must be kept in sync.
 pub struct Component {
     pub component_type: ComponentType,
     pub attachment: Option<String>,
+    pub attendees: _ical_cal_address,
     pub categories: Vec<String>,
     pub class: Option<Class>,
     pub comment: Vec<String>,
@@ -131,23 +146,45 @@ pub struct Component {
     pub dt_end_naive: Option<Timestamp>,
     pub due: Option<TimestampWithTimeZone>,
     pub due_naive: Option<Timestamp>,
+    #[allow(deprecated)]
     pub duration: Option<Interval>,
     pub geo_lat: Option<f32>,
     pub geo_lng: Option<f32>,
     pub last_modified: Option<TimestampWithTimeZone>,
     pub last_modified_naive: Option<Timestamp>,
     pub location: Option<String>,
+    pub organizer: Option<ical_cal_address>,
     pub percent_complete: Option<i32>,
     pub priority: Option<i32>,
+    /// Original start of the occurrence of a recurring event that this component overrides
+    pub recurrence_id: Option<TimestampWithTimeZone>,
+    pub recurrence_id_naive: Option<Timestamp>,
     pub resources: Vec<String>,
     pub status: Option<Status>,
     pub sequence: i32,
     pub summary: Option<String>,
     pub uid: String,
+    /// URL the component was fetched from, for the functions that fetch remote calendars
+    pub source_url: Option<String>,
+    /// Position, starting at 1, of the `VCALENDAR` of the component, in inputs that concatenate
+    /// several of them
+    pub calendar_seq: i32,
+    /// Position, starting at 1, of the component in its input
+    pub event_ordinality: i64,
+    /// Number of `VALARM`s of the component
+    pub alarm_count: i32,
 }
 
-fn convert_component(res: Result<Event, CalendarParseError>) -> Component {
-    let event = res.unwrap();
+fn convert_component(
+    res: Result<Event, CalendarParseError>,
+    calendar_seq: usize,
+    event_ordinality: i64,
+) -> Component {
+    let event = res.unwrap_or_else(|err| error!("invalid calendar: {}", err));
+
+    for warning in &event.warnings {
+        warning!("event {}: {}", event.uid, warning);
+    }
 
     let (created, created_naive) = event.created.map(serialize_datetime).unwrap_or_default();
     let (dt_stamp, dt_stamp_naive) = event.dt_stamp.map(serialize_datetime).unwrap_or_default();
@@ -157,10 +194,15 @@ fn convert_component(res: Result<Event, CalendarParseError>) -> Component {
         .last_modified
         .map(serialize_datetime)
         .unwrap_or_default();
+    let (recurrence_id, recurrence_id_naive) = event
+        .recurrence_id
+        .map(serialize_datetime)
+        .unwrap_or_default();
 
     Component {
         component_type: ComponentType::VEVENT,
-        attachment: None,       // TODO
+        attachment: None, // TODO
+        attendees: _ical_cal_address(event.attendees.into_iter().map(Into::into).collect()),
         categories: Vec::new(), // TODO
         class: None,            // TODO
         comment: Vec::new(),    // TODO
@@ -178,24 +220,125 @@ fn convert_component(res: Result<Event, CalendarParseError>) -> Component {
         due: None,       // TODO
         due_naive: None, // TODO
         duration: None,  // TODO
-        geo_lat: None,   // TODO
-        geo_lng: None,   // TODO
+        geo_lat: event.geo.map(|(lat, _)| lat),
+        geo_lng: event.geo.map(|(_, lng)| lng),
         last_modified,
         last_modified_naive,
         location: event.location,
-        percent_complete: None, // TODO
-        priority: None,         // TODO
-        resources: Vec::new(),  // TODO
-        status: None,           // TODO
+        organizer: event.organizer.map(Into::into),
+        percent_complete: event.percent_complete,
+        priority: event.priority,
+        recurrence_id,
+        recurrence_id_naive,
+        resources: Vec::new(), // TODO
+        status: None,          // TODO
         sequence: event.sequence,
         summary: event.summary,
         uid: event.uid,
+        source_url: None,
+        calendar_seq: calendar_seq as i32,
+        event_ordinality,
+        alarm_count: event.alarm_count as i32,
     }
 }
 
-fn pg_ical_internal(calendar: impl BufRead) -> impl Iterator<Item = Component> {
-    let parser = postgres_ical_parser::EventsReader::new(calendar);
-    parser.map(convert_component)
+/// Applies `postgres_ical.invalid_time_ranges`, and tells whether the event is kept
+fn check_time_range(event: &Result<Event, CalendarParseError>) -> bool {
+    use guc::InvalidTimeRanges;
+    use postgres_ical_parser::TimeRangeIssue;
+
+    let (uid, issue) = match event {
+        Ok(event) => match event.time_range_issue() {
+            Some(issue) => (&event.uid, issue),
+            None => return true,
+        },
+        Err(_) => return true,
+    };
+
+    match (guc::INVALID_TIME_RANGES.get(), issue) {
+        (InvalidTimeRanges::warn, TimeRangeIssue::EndBeforeStart) => {
+            warning!("event {} ends before it starts", uid);
+            true
+        }
+        (InvalidTimeRanges::warn, TimeRangeIssue::ZeroLength) => {
+            warning!("event {} ends when it starts", uid);
+            true
+        }
+        (InvalidTimeRanges::drop, TimeRangeIssue::EndBeforeStart) => false,
+        (InvalidTimeRanges::error, TimeRangeIssue::EndBeforeStart) => {
+            error!("event {} ends before it starts", uid)
+        }
+        _ => true,
+    }
+}
+
+/// Ends the reading of a truncated calendar with a warning, unless `postgres_ical.strict` is on,
+/// so that the components before the truncation are kept
+fn tolerate_truncation<T>(
+    item: Result<T, CalendarParseError>,
+) -> Option<Result<T, CalendarParseError>> {
+    match item {
+        Err(CalendarParseError::Truncated(component)) if !guc::STRICT.get() => {
+            warning!(
+                "the calendar ends within a {}, only the components before it are returned",
+                component
+            );
+            None
+        }
+        item => Some(item),
+    }
+}
+
+/// Buffers a download or a file with the capacity of `postgres_ical.read_buffer_size`
+fn buffered<R: Read>(inner: R) -> BufReader<R> {
+    let capacity = guc::READ_BUFFER_SIZE.get().max(1) as usize * 1024;
+    BufReader::with_capacity(capacity, inner)
+}
+
+/// Options of the parser, from the settings of the session
+fn parser_options() -> postgres_ical_parser::ParserOptions {
+    let mut options = postgres_ical_parser::EventsReader::builder()
+        .duplicate_properties(guc::DUPLICATE_PROPERTIES.get().into())
+        .strict(guc::STRICT.get());
+    // 0 means no limit
+    if guc::MAX_EVENTS.get() > 0 {
+        options = options.max_events(guc::MAX_EVENTS.get() as usize);
+    }
+    if guc::MAX_PROPERTY_LENGTH.get() > 0 {
+        options = options.max_property_length(guc::MAX_PROPERTY_LENGTH.get() as usize * 1024);
+    }
+    if guc::MAX_NESTING_DEPTH.get() > 0 {
+        options = options.max_depth(guc::MAX_NESTING_DEPTH.get() as usize);
+    }
+    if let Some(tz) = guc::DEFAULT_TIMEZONE.get().filter(|tz| !tz.is_empty()) {
+        let tz = tz.parse::<chrono_tz::Tz>().unwrap_or_else(|_| {
+            error!("invalid value for postgres_ical.default_timezone: {:?}", tz)
+        });
+        options = options.default_timezone(tz);
+    }
+    options
+}
+
+fn pg_ical_internal<'a>(calendar: impl BufRead + 'a) -> Box<dyn Iterator<Item = Component> + 'a> {
+    let mut parser = parser_options().build(calendar);
+
+    let components = (1..)
+        .map_while(move |event_ordinality| {
+            // Calendars may be large enough for their parsing to outlast a cancellation
+            check_for_interrupts!();
+            let event = parser.next().and_then(tolerate_truncation)?;
+            Some((event, parser.calendar_seq(), event_ordinality))
+        })
+        .filter(|(event, ..)| check_time_range(event))
+        .map(|(event, calendar_seq, event_ordinality)| {
+            convert_component(event, calendar_seq, event_ordinality)
+        });
+
+    if guc::DEDUPLICATE_UIDS.get() {
+        Box::new(dedup::deduplicate(components).into_iter())
+    } else {
+        Box::new(components)
+    }
 }
 
 /// Load an [`ical`][ical] file from an in-memory text representation
@@ -210,7 +353,60 @@ pub fn pg_ical(calendar: String) -> impl Iterator<Item = Component> {
     pg_ical_internal(BufReader::new(Cursor::new(calendar.into_bytes())))
 }
 
-/// Load an [`ical`][ical] file from an URL, making a [curl] request in the process
+// `ical_event` has the columns of `Component`, in the same order, so that the rows of the functions
+// that return components can be passed around whole
+extension_sql!(
+    r#"
+CREATE TYPE ical_event AS (
+    component_type ComponentType,
+    attachment text,
+    attendees ical_cal_address[],
+    categories text[],
+    class Class,
+    comment text[],
+    completed timestamptz,
+    completed_naive timestamp,
+    created timestamptz,
+    created_naive timestamp,
+    description text,
+    dt_stamp timestamptz,
+    dt_stamp_naive timestamp,
+    dt_start timestamptz,
+    dt_start_naive timestamp,
+    dt_end timestamptz,
+    dt_end_naive timestamp,
+    due timestamptz,
+    due_naive timestamp,
+    duration smallint,
+    geo_lat real,
+    geo_lng real,
+    last_modified timestamptz,
+    last_modified_naive timestamp,
+    location text,
+    organizer ical_cal_address,
+    percent_complete integer,
+    priority integer,
+    recurrence_id timestamptz,
+    recurrence_id_naive timestamp,
+    resources text[],
+    status Status,
+    sequence integer,
+    summary text,
+    uid text,
+    source_url text,
+    calendar_seq integer,
+    event_ordinality bigint,
+    alarm_count integer
+);
+
+CREATE FUNCTION pg_ical_records(calendar text) RETURNS SETOF ical_event
+    LANGUAGE sql STRICT AS $$ SELECT * FROM pg_ical(calendar) $$;
+"#,
+    name = "ical_event",
+    requires = [ComponentType, Class, Status, "ical_cal_address", pg_ical],
+);
+
+/// Load a gzip-compressed [`ical`][ical] file from an in-memory binary representation
 ///
 /// The number of columns may increase at any moment without it being considered a breaking change.
 /// For forward-compatibility, when consuming this function's output, always do an explicit select.
@@ -218,12 +414,1202 @@ pub fn pg_ical(calendar: String) -> impl Iterator<Item = Component> {
 ///
 /// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
 #[pg_extern_columns("src/lib.rs")]
-pub fn pg_ical_curl(url: &str) -> impl Iterator<Item = Component> {
-    let (reader, handle) = curl_get(url);
-    let mut handle = Some(handle);
+pub fn pg_ical_gzip(calendar: Vec<u8>) -> impl Iterator<Item = Component> {
+    pg_ical_internal(BufReader::new(GzDecoder::new(Cursor::new(calendar))))
+}
+
+/// Load the components of an [`ical`][ical] file as `jsonb` documents
+///
+/// Unlike [`pg_ical`], every component of the calendar is returned, with all of its properties and
+/// their parameters, and its sub-components such as alarms. This suits schema-less ingestion into
+/// `jsonb` columns. The documents are described by the [`json`] module. Calendars whose
+/// components are nested deeper than [`postgres_ical_parser::MAX_COMPONENT_DEPTH`] levels are
+/// invalid, and the limits of [`pg_ical`] apply, `postgres_ical.max_events` to every component.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern]
+pub fn pg_ical_to_jsonb(calendar: String) -> impl Iterator<Item = JsonB> {
+    parser_options()
+        .build_raw_components(Cursor::new(calendar.into_bytes()))
+        .map_while(tolerate_truncation)
+        .map(|component| {
+            let component = component.unwrap_or_else(|err| error!("invalid calendar: {}", err));
+            JsonB(json::component_to_json(component))
+        })
+}
+
+/// Represents a row returned by [pg_ical_attachments]
+pub struct Attachment {
+    /// UID of the component, or of the component of the alarm, that has the attachment
+    pub uid: String,
+    /// Media type, as in `application/pdf`
+    pub fmttype: Option<String>,
+    pub filename: Option<String>,
+    pub data: Vec<u8>,
+}
+
+/// Load the inline attachments of an [`ical`][ical] file
+///
+/// Attachments whose `ATTACH` property has `ENCODING=BASE64` are decoded, while the ones that are
+/// URIs are skipped. Attachments that are larger than `postgres_ical.max_attachment_size` are
+/// skipped with a warning. The limits of [`pg_ical_to_jsonb`] apply.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_attachments(calendar: String) -> impl Iterator<Item = Attachment> {
+    parser_options()
+        .build_raw_components(Cursor::new(calendar.into_bytes()))
+        .map_while(tolerate_truncation)
+        .flat_map(|component| {
+            let component = component.unwrap_or_else(|err| error!("invalid calendar: {}", err));
+            let mut attachments = Vec::new();
+            attachments::attachments(&component, &mut attachments);
+            attachments
+        })
+}
+
+/// Versions of the extension, of its parser and of the tz database it embeds, with its HTTP client
+/// and its enabled features, as a `jsonb` object
+///
+/// This tells how the extension of a server was built, without access to the host.
+#[pg_extern]
+pub fn pg_ical_version() -> JsonB {
+    let features = [
+        ("pg10", cfg!(feature = "pg10")),
+        ("pg11", cfg!(feature = "pg11")),
+        ("pg12", cfg!(feature = "pg12")),
+        ("pg13", cfg!(feature = "pg13")),
+        ("pg14", cfg!(feature = "pg14")),
+        ("curl", cfg!(feature = "curl")),
+        ("ureq", cfg!(feature = "ureq")),
+        ("postgis", cfg!(feature = "postgis")),
+    ];
+    let features = features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| *feature)
+        .collect::<Vec<_>>();
 
-    pg_ical_internal(reader).chain(std::iter::from_fn(move || {
-        handle.take().unwrap().join().unwrap();
-        None
+    JsonB(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "parser_version": postgres_ical_parser::VERSION,
+        "tzdata_version": postgres_ical_parser::TZDATA_VERSION,
+        "http_backend": fetch::backend_description(),
+        "features": features,
     }))
 }
+
+/// Build an [`ical`][ical] file from a `jsonb` array of events
+///
+/// Each event is an object whose keys are the names of its properties, such as
+/// `{"uid": "…", "summary": "…", "dtstart": "2022-03-01T10:00:00+01:00"}`, as described by the
+/// [`json`] module. Every event needs a UID, and is given the current time as its DTSTAMP unless it
+/// has one.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern]
+pub fn ical_from_jsonb(events: JsonB) -> String {
+    json::calendar_from_json(&events.0)
+}
+
+/// Serialize a single `VEVENT`
+///
+/// Date-times are written in UTC, and the DTSTAMP is the current time. `rrule` is a recurrence rule
+/// such as `FREQ=WEEKLY;BYDAY=MO`, that is written as is once it's checked to be valid. The result
+/// can be gathered into a calendar with `ical_agg`, or embedded in one.
+#[pg_extern]
+#[allow(clippy::too_many_arguments)]
+pub fn ical_event(
+    uid: Option<&str>,
+    summary: Option<default!(&str, NULL)>,
+    dtstart: Option<default!(TimestampWithTimeZone, NULL)>,
+    dtend: Option<default!(TimestampWithTimeZone, NULL)>,
+    description: Option<default!(&str, NULL)>,
+    location: Option<default!(&str, NULL)>,
+    rrule: Option<default!(&str, NULL)>,
+    categories: Option<default!(Vec<String>, NULL)>,
+) -> String {
+    // Not strict, so that any field but the UID may be NULL
+    generate::event(&generate::Event {
+        uid: required(uid, "uid"),
+        summary,
+        dt_start: dtstart.map(|dt_start| *dt_start),
+        dt_end: dtend.map(|dt_end| *dt_end),
+        description,
+        location,
+        rrule,
+        categories: &categories.unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+/// Final function of the `ical_agg(text)` aggregate, that builds an [`ical`][ical] file out of
+/// serialized components
+///
+/// The components, such as `VEVENT`s, are folded again as they're written.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern]
+pub fn ical_agg_final(components: Vec<Option<String>>) -> String {
+    generate::calendar(None, |writer| {
+        components
+            .iter()
+            .flatten()
+            .try_for_each(|component| writer.content(component))
+    })
+}
+
+extension_sql!(
+    r#"
+CREATE AGGREGATE ical_agg(text) (
+    SFUNC = array_append,
+    STYPE = text[],
+    FINALFUNC = ical_agg_final,
+    INITCOND = '{}'
+);
+"#,
+    name = "ical_agg",
+    requires = [ical_agg_final],
+);
+
+/// Fails if a required argument of a non-strict function is NULL
+fn required<T>(value: Option<T>, name: &str) -> T {
+    value.unwrap_or_else(|| error!("{} can't be NULL", name))
+}
+
+/// The SEQUENCE of the update of an event whose last version that was sent had `sequence`
+fn next_sequence(sequence: i32) -> i32 {
+    sequence
+        .checked_add(1)
+        .unwrap_or_else(|| error!("the SEQUENCE {} can't be incremented", sequence))
+}
+
+/// Build an [iTIP][itip] `REQUEST`, inviting attendees to an event or updating it
+///
+/// `organizer` and `attendees` are email addresses or `mailto:` URIs. `sequence` is the SEQUENCE
+/// of the last version of the event that was sent, which is incremented so that the attendees
+/// apply the update, or `NULL` for a new event. Date-times are written in UTC.
+///
+/// [itip]: https://datatracker.ietf.org/doc/html/rfc5546
+#[pg_extern]
+#[allow(clippy::too_many_arguments)]
+pub fn ical_itip_request(
+    uid: Option<&str>,
+    organizer: Option<&str>,
+    attendees: Option<Vec<String>>,
+    summary: Option<default!(&str, NULL)>,
+    dtstart: Option<default!(TimestampWithTimeZone, NULL)>,
+    dtend: Option<default!(TimestampWithTimeZone, NULL)>,
+    description: Option<default!(&str, NULL)>,
+    location: Option<default!(&str, NULL)>,
+    rrule: Option<default!(&str, NULL)>,
+    sequence: Option<default!(i32, NULL)>,
+) -> String {
+    let attendees = required(attendees, "attendees");
+    let attendees: Vec<_> = attendees
+        .iter()
+        .map(|address| generate::Attendee {
+            address,
+            role: Some("REQ-PARTICIPANT"),
+            partstat: Some("NEEDS-ACTION"),
+            rsvp: true,
+        })
+        .collect();
+
+    let event = generate::Event {
+        uid: required(uid, "uid"),
+        summary,
+        dt_start: dtstart.map(|dt_start| *dt_start),
+        dt_end: dtend.map(|dt_end| *dt_end),
+        description,
+        location,
+        rrule,
+        sequence: Some(sequence.map_or(0, next_sequence)),
+        organizer: Some(required(organizer, "organizer")),
+        attendees: &attendees,
+        ..Default::default()
+    };
+    generate::calendar(Some("REQUEST"), |writer| {
+        generate::write_event(writer, &event)
+    })
+}
+
+/// Build an [iTIP][itip] `CANCEL`, telling attendees that an event was cancelled
+///
+/// `sequence` is the SEQUENCE of the last version of the event that was sent, which is
+/// incremented. `dtstart` may be given so that calendar clients can show which event it was.
+///
+/// [itip]: https://datatracker.ietf.org/doc/html/rfc5546
+#[pg_extern]
+pub fn ical_itip_cancel(
+    uid: Option<&str>,
+    organizer: Option<&str>,
+    attendees: Option<Vec<String>>,
+    sequence: Option<default!(i32, NULL)>,
+    summary: Option<default!(&str, NULL)>,
+    dtstart: Option<default!(TimestampWithTimeZone, NULL)>,
+) -> String {
+    let attendees = required(attendees, "attendees");
+    let attendees: Vec<_> = attendees
+        .iter()
+        .map(|address| generate::Attendee {
+            address,
+            role: None,
+            partstat: None,
+            rsvp: false,
+        })
+        .collect();
+
+    let event = generate::Event {
+        uid: required(uid, "uid"),
+        summary,
+        dt_start: dtstart.map(|dt_start| *dt_start),
+        sequence: Some(next_sequence(sequence.unwrap_or_default())),
+        status: Some("CANCELLED"),
+        organizer: Some(required(organizer, "organizer")),
+        attendees: &attendees,
+        ..Default::default()
+    };
+    generate::calendar(Some("CANCEL"), |writer| {
+        generate::write_event(writer, &event)
+    })
+}
+
+/// Build an [iTIP][itip] `REPLY`, in which an attendee accepts or declines an invitation
+///
+/// `partstat` is the participation status of the attendee, such as `ACCEPTED`, `DECLINED` or
+/// `TENTATIVE`. `sequence` is the SEQUENCE of the invitation that is answered.
+///
+/// [itip]: https://datatracker.ietf.org/doc/html/rfc5546
+#[pg_extern]
+pub fn ical_itip_reply(
+    uid: Option<&str>,
+    organizer: Option<&str>,
+    attendee: Option<&str>,
+    partstat: Option<&str>,
+    sequence: Option<default!(i32, NULL)>,
+) -> String {
+    let partstat = required(partstat, "partstat").to_ascii_uppercase();
+    let attendees = [generate::Attendee {
+        address: required(attendee, "attendee"),
+        role: None,
+        partstat: Some(&partstat),
+        rsvp: false,
+    }];
+
+    let event = generate::Event {
+        uid: required(uid, "uid"),
+        sequence,
+        organizer: Some(required(organizer, "organizer")),
+        attendees: &attendees,
+        ..Default::default()
+    };
+    generate::calendar(Some("REPLY"), |writer| {
+        generate::write_event(writer, &event)
+    })
+}
+
+/// Check that a text is an [`ical`][ical] file that [`pg_ical`] can read
+///
+/// This is the check of the `ics` domain, so that the calendars stored in `ics` columns can always
+/// be parsed again.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern(immutable, parallel_safe)]
+pub fn ics_is_valid(calendar: &str) -> bool {
+    postgres_ical_parser::validate_calendar(calendar).is_ok()
+}
+
+/// Explain why a text isn't an [`ical`][ical] file that [`pg_ical`] can read, `NULL` if it is one
+///
+/// Performs the same check as [`ics_is_valid`].
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern(immutable, parallel_safe)]
+pub fn ics_validation_error(calendar: &str) -> Option<String> {
+    postgres_ical_parser::validate_calendar(calendar)
+        .err()
+        .map(|err| err.to_string())
+}
+
+extension_sql!(
+    r#"
+CREATE DOMAIN ics AS text CHECK (ics_is_valid(VALUE));
+"#,
+    name = "ics",
+    requires = [ics_is_valid],
+);
+
+/// Count the components of type `component` of an [`ical`][ical] file, `VEVENT`s by default
+///
+/// Components are counted at any depth, without being read, so it's much cheaper than counting the
+/// rows of [`pg_ical`], for monitoring feeds. Calendars aren't checked either, so the components of
+/// an invalid calendar are still counted.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern(immutable, parallel_safe)]
+pub fn pg_ical_count(calendar: &str, component: default!(&str, "'VEVENT'")) -> i64 {
+    postgres_ical_parser::borrowed::count_components(calendar, component) as i64
+}
+
+/// Represents the row returned by [pg_ical_stats]
+pub struct CalendarStats {
+    pub events: i64,
+    pub todos: i64,
+    pub journals: i64,
+    pub earliest_start: Option<TimestampWithTimeZone>,
+    pub earliest_start_naive: Option<Timestamp>,
+    pub latest_start: Option<TimestampWithTimeZone>,
+    pub latest_start_naive: Option<Timestamp>,
+    /// Events with an `RRULE` or an `RDATE`
+    pub recurring_events: i64,
+    /// Distinct TZIDs of the date-times of the events
+    pub timezones: Vec<String>,
+    /// Problems that were worked around
+    pub warnings: i64,
+    /// Events that couldn't be read
+    pub invalid_events: i64,
+}
+
+/// Widens the earliest and latest date-times of `range` to `date_time`, which must be floating if
+/// the date-times of `range` are, and at a given instant otherwise
+fn widen_range(range: &mut Option<(IcalDateTime, IcalDateTime)>, date_time: &IcalDateTime) {
+    use std::cmp::Ordering;
+
+    match range {
+        None => *range = Some((date_time.clone(), date_time.clone())),
+        Some((earliest, latest)) => {
+            if date_time.cmp_instant(earliest) == Some(Ordering::Less) {
+                *earliest = date_time.clone();
+            }
+            if date_time.cmp_instant(latest) == Some(Ordering::Greater) {
+                *latest = date_time.clone();
+            }
+        }
+    }
+}
+
+/// Summarize an [`ical`][ical] file in a single row, for monitoring its ingestion
+///
+/// Events are read as by [`pg_ical`], but outside of strict mode, so that the problems that
+/// `postgres_ical.strict` would turn into errors are counted as warnings instead. The events that
+/// still can't be read are counted as invalid rather than failing the query. The earliest and
+/// latest `DTSTART`s are those of the events at a given instant, and of the floating ones and
+/// all-day events in the `_naive` columns.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_stats(calendar: &str) -> impl Iterator<Item = CalendarStats> {
+    use postgres_ical_parser::borrowed::count_components;
+
+    let parser = postgres_ical_parser::EventsReader::builder()
+        .duplicate_properties(guc::DUPLICATE_PROPERTIES.get().into())
+        .build(calendar.as_bytes());
+
+    let mut stats = CalendarStats {
+        events: 0,
+        todos: count_components(calendar, "VTODO") as i64,
+        journals: count_components(calendar, "VJOURNAL") as i64,
+        earliest_start: None,
+        earliest_start_naive: None,
+        latest_start: None,
+        latest_start_naive: None,
+        recurring_events: 0,
+        timezones: Vec::new(),
+        warnings: 0,
+        invalid_events: 0,
+    };
+    let mut zoned_starts = None;
+    let mut floating_starts = None;
+    let mut timezones = std::collections::BTreeSet::new();
+
+    for event in parser {
+        check_for_interrupts!();
+        stats.events += 1;
+        let event = match event {
+            Ok(event) => event,
+            Err(_) => {
+                stats.invalid_events += 1;
+                continue;
+            }
+        };
+
+        stats.warnings += event.warnings.len() as i64;
+        if event
+            .extra
+            .iter()
+            .any(|property| property.name == "RRULE" || property.name == "RDATE")
+        {
+            stats.recurring_events += 1;
+        }
+        for date_time in event.date_times() {
+            if let IcalDateTime::Tz(_, info) = date_time {
+                timezones.insert(info.tz_id.clone());
+            }
+        }
+
+        match event.dt_start {
+            IcalDateTime::Date(_) | IcalDateTime::Naive(_) => {
+                widen_range(&mut floating_starts, &event.dt_start)
+            }
+            IcalDateTime::Utc(_) | IcalDateTime::Tz(..) => {
+                widen_range(&mut zoned_starts, &event.dt_start)
+            }
+        }
+    }
+
+    if let Some((earliest, latest)) = zoned_starts {
+        stats.earliest_start = serialize_datetime(earliest).0;
+        stats.latest_start = serialize_datetime(latest).0;
+    }
+    if let Some((earliest, latest)) = floating_starts {
+        stats.earliest_start_naive = serialize_datetime(earliest).1;
+        stats.latest_start_naive = serialize_datetime(latest).1;
+    }
+    stats.timezones = timezones.into_iter().collect();
+
+    std::iter::once(stats)
+}
+
+/// Represents the row returned by [pg_ical_timezones]
+pub struct TimezoneUsage {
+    pub tz_id: String,
+    /// `iana`, `vtimezone` or `unknown`
+    pub resolution: String,
+    pub property_count: i64,
+}
+
+/// List the TZIDs of an [`ical`][ical] file, to debug the time zones of its events
+///
+/// Each TZID that its properties refer to, or that one of its `VTIMEZONE`s defines, is returned
+/// once, with the number of properties that refer to it. Its `resolution` is `iana` if it's a time
+/// zone of the tz database, which is how date-times are read. Otherwise, it's `vtimezone` if the
+/// calendar defines it, which isn't enough for [`pg_ical`] to read the date-times of that TZID,
+/// and `unknown` if it doesn't.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_timezones(calendar: &str) -> impl Iterator<Item = TimezoneUsage> {
+    postgres_ical_parser::borrowed::tz_ids(calendar)
+        .into_iter()
+        .map(|usage| {
+            let resolution = if usage.tz_id.parse::<chrono_tz::Tz>().is_ok() {
+                "iana"
+            } else if usage.defined {
+                "vtimezone"
+            } else {
+                "unknown"
+            };
+
+            TimezoneUsage {
+                resolution: resolution.to_string(),
+                property_count: usage.properties as i64,
+                tz_id: usage.tz_id,
+            }
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// First event of a calendar, for the extraction functions, which read a single event per row
+fn first_event(calendar: &str) -> Option<EventRef<'_>> {
+    EventRefsReader::new(calendar)
+        .next()?
+        .map(Some)
+        .unwrap_or_else(|err| error!("invalid calendar: {}", err))
+}
+
+/// Extract the property `field` of the first event of an [`ical`][ical] file, as a text
+///
+/// The value is unescaped, and `NULL` if the event doesn't have the property, or if there's no
+/// event. If the event has the property several times, the last one is returned. Being immutable,
+/// it can be used in expression indexes and generated columns over stored calendars.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern(immutable, parallel_safe)]
+pub fn ical_extract(calendar: &str, field: &str) -> Option<String> {
+    first_event(calendar)?.text(field).map(Into::into)
+}
+
+/// Extract the property `field` of the first event of an [`ical`][ical] file, as a date-time
+///
+/// Only UTC date-times and date-times of a time zone are returned. Dates and floating date-times
+/// are `NULL`, since their instant depends on the time zone of the session, which would make the
+/// function depend on more than its arguments.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern(immutable, parallel_safe)]
+pub fn ical_extract_timestamptz(calendar: &str, field: &str) -> Option<TimestampWithTimeZone> {
+    let event = first_event(calendar)?;
+    let property = event.property(field)?;
+    let date_time = property.date_time().unwrap_or_else(|| {
+        error!(
+            "invalid calendar: {} isn't a date-time: {:?}",
+            property.name, property.value,
+        )
+    });
+    serialize_datetime(date_time).0
+}
+
+/// Extract every occurrence of the property `field` of the first event of an [`ical`][ical] file,
+/// in order, such as its `ATTENDEE`s
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern(immutable, parallel_safe)]
+pub fn ical_extract_array(calendar: &str, field: &str) -> Option<Vec<String>> {
+    let event = first_event(calendar)?;
+    Some(
+        event
+            .properties
+            .iter()
+            .filter(|property| property.is(field))
+            .map(|property| property.text().into_owned())
+            .collect(),
+    )
+}
+
+extension_sql!(
+    r#"
+-- The columns to check are the arguments of the trigger, so that one function serves every table
+CREATE FUNCTION pg_ical_check_trigger() RETURNS trigger
+    LANGUAGE plpgsql AS $$
+DECLARE
+    column_name text;
+    calendar text;
+    previous text;
+    problem text;
+BEGIN
+    IF TG_NARGS = 0 THEN
+        RAISE EXCEPTION 'pg_ical_check_trigger requires the names of the columns to check';
+    END IF;
+
+    FOREACH column_name IN ARRAY TG_ARGV LOOP
+        EXECUTE format('SELECT ($1).%I::text', column_name) INTO calendar USING NEW;
+        IF TG_OP = 'UPDATE' THEN
+            EXECUTE format('SELECT ($1).%I::text', column_name) INTO previous USING OLD;
+            CONTINUE WHEN calendar IS NOT DISTINCT FROM previous;
+        END IF;
+
+        problem := ics_validation_error(calendar);
+        IF problem IS NOT NULL THEN
+            RAISE EXCEPTION 'column % of % isn''t a valid calendar', column_name, TG_TABLE_NAME
+                USING ERRCODE = 'check_violation', DETAIL = problem,
+                    COLUMN = column_name, TABLE = TG_TABLE_NAME, SCHEMA = TG_TABLE_SCHEMA;
+        END IF;
+    END LOOP;
+
+    RETURN NEW;
+END
+$$;
+"#,
+    name = "pg_ical_check_trigger",
+    requires = [ics_validation_error],
+);
+
+/// Load a [`jCal`][jcal] document, the JSON representation of an [`ical`][ical] file
+///
+/// `calendar` is either a `vcalendar` component or an array of them. Its events are returned as
+/// the same rows as [`pg_ical`]'s.
+///
+/// The number of columns may increase at any moment without it being considered a breaking change.
+/// For forward-compatibility, when consuming this function's output, always do an explicit select.
+/// Column deletion or altering is — however, and obviously — considered breaking.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+/// [jcal]: https://datatracker.ietf.org/doc/html/rfc7265
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_jcal(calendar: JsonB) -> impl Iterator<Item = Component> {
+    postgres_ical_parser::jcal_calendars(&calendar.0)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, events)| events.into_iter().map(move |event| (i + 1, event)))
+        .zip(1..)
+        .map(|((calendar_seq, event), event_ordinality)| {
+            convert_component(event, calendar_seq, event_ordinality)
+        })
+}
+
+/// Load an [`ical`][ical] file from an in-memory binary representation, in the given encoding
+///
+/// `encoding` is a [WHATWG encoding label][labels], such as `utf8`, `latin1` or `windows-1252`. A
+/// byte order mark at the start of `calendar` takes precedence over it. Invalid sequences are
+/// replaced by U+FFFD instead of failing.
+///
+/// The number of columns may increase at any moment without it being considered a breaking change.
+/// For forward-compatibility, when consuming this function's output, always do an explicit select.
+/// Column deletion or altering is — however, and obviously — considered breaking.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+/// [labels]: https://encoding.spec.whatwg.org/#names-and-labels
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_bytea(
+    calendar: Vec<u8>,
+    encoding: default!(&str, "'utf8'"),
+) -> impl Iterator<Item = Component> {
+    let encoding = encoding_rs::Encoding::for_label(encoding.as_bytes())
+        .unwrap_or_else(|| error!("unknown encoding \"{}\"", encoding));
+    let (calendar, _, _) = encoding.decode(&calendar);
+
+    pg_ical_internal(BufReader::new(Cursor::new(
+        calendar.into_owned().into_bytes(),
+    )))
+}
+
+/// Load an [`ical`][ical] file stored as a large object, reading it as it's parsed
+///
+/// The large object is read in chunks, with the privileges of the caller, as `lo_get` would.
+///
+/// The number of columns may increase at any moment without it being considered a breaking change.
+/// For forward-compatibility, when consuming this function's output, always do an explicit select.
+/// Column deletion or altering is — however, and obviously — considered breaking.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_lo(calendar: pg_sys::Oid) -> impl Iterator<Item = Component> {
+    pg_ical_internal(large_object::LargeObjectReader::new(calendar))
+}
+
+/// Load an [`ical`][ical] file from the server's filesystem, reading it as it's parsed
+///
+/// Relative paths are relative to the data directory. Requires the privileges of the
+/// `pg_read_server_files` role, like `pg_read_file`.
+///
+/// The number of columns may increase at any moment without it being considered a breaking change.
+/// For forward-compatibility, when consuming this function's output, always do an explicit select.
+/// Column deletion or altering is — however, and obviously — considered breaking.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_file(path: &str) -> impl Iterator<Item = Component> {
+    privileges::require_read_server_files("pg_ical_file");
+
+    let file =
+        File::open(path).unwrap_or_else(|err| error!("couldn't open file \"{}\": {}", path, err));
+
+    pg_ical_internal(buffered(file))
+}
+
+/// Load an [`ical`][ical] file from an URL, making a [curl] request in the process
+///
+/// `options` is an optional `jsonb` object, whose accepted keys are the fields of
+/// [`FetchOptions`][fetch::FetchOptions]. `headers` is an optional `jsonb` object of additional
+/// request headers, as in `{"Accept": "text/calendar"}`.
+///
+/// The number of columns may increase at any moment without it being considered a breaking change.
+/// For forward-compatibility, when consuming this function's output, always do an explicit select.
+/// Column deletion or altering is — however, and obviously — considered breaking.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_curl(
+    url: &str,
+    options: Option<default!(JsonB, NULL)>,
+    headers: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = Component> {
+    let options = fetch::FetchOptions::from_jsonb(options).with_headers(headers);
+    let download = fetch::curl_get(url, &options);
+    let source_url = url.to_string();
+
+    pg_ical_internal(buffered(download)).map(move |component| Component {
+        source_url: Some(source_url.clone()),
+        ..component
+    })
+}
+
+/// Load several [`ical`][ical] files from their URLs, fetching them concurrently
+///
+/// At most `postgres_ical.max_parallel_fetches` calendars are fetched at once. The components of
+/// every calendar are returned, in no particular order, with their `source_url`. `options` and
+/// `headers` are the same as [`pg_ical_curl`]'s, and apply to every request.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_curl_many(
+    urls: Vec<String>,
+    options: Option<default!(JsonB, NULL)>,
+    headers: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = Component> {
+    let options = fetch::FetchOptions::from_jsonb(options).with_headers(headers);
+
+    fetch::curl_get_many(urls, &options).flat_map(|(url, body)| {
+        pg_ical_internal(BufReader::new(Cursor::new(body))).map(move |component| Component {
+            source_url: Some(url.clone()),
+            ..component
+        })
+    })
+}
+
+/// Merge several [`ical`][ical] files from their URLs into a single calendar
+///
+/// Calendars are fetched as by [`pg_ical_curl_many`], but their components are returned in the
+/// order of `urls`, with their `source_url`. When `dedup` is on, the copies of an event found in
+/// several calendars, as when someone is invited from several shared calendars, are returned once:
+/// components that share a `uid` and `recurrence_id` are reconciled as by
+/// `postgres_ical.deduplicate_uids`, and events with the same `summary`, start and end in
+/// different calendars are returned as in the first of them. All the components are held in memory
+/// until the last calendar is fetched.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_merge(
+    urls: Vec<String>,
+    dedup: default!(bool, true),
+    options: Option<default!(JsonB, NULL)>,
+    headers: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = Component> {
+    let positions = urls
+        .iter()
+        .enumerate()
+        .map(|(position, url)| (url.clone(), position))
+        .rev()
+        .collect::<std::collections::HashMap<_, _>>();
+
+    let mut components = pg_ical_curl_many(urls, options, headers).collect::<Vec<_>>();
+    // Stable, so that the components of each calendar keep their order
+    components.sort_by_key(|component| component.source_url.as_ref().map(|url| positions[url]));
+
+    if dedup {
+        dedup::merge(components.into_iter()).into_iter()
+    } else {
+        components.into_iter()
+    }
+}
+
+/// Represents the row returned by [pg_ical_occurrences]
+pub struct Occurrence {
+    pub uid: String,
+    /// Original start of the occurrence, which identifies it among those of its event
+    pub recurrence_id: Option<TimestampWithTimeZone>,
+    pub recurrence_id_naive: Option<Timestamp>,
+    pub dt_start: Option<TimestampWithTimeZone>,
+    pub dt_start_naive: Option<Timestamp>,
+    pub dt_end: Option<TimestampWithTimeZone>,
+    pub dt_end_naive: Option<Timestamp>,
+    /// Whether the event is `TRANSP:TRANSPARENT`, and doesn't make its attendees busy
+    pub transparent: bool,
+}
+
+/// `dtstart` in the time zone `tzid` of [`rrule_occurrences`]
+fn rrule_dtstart(dtstart: &TimestampWithTimeZone, tzid: Option<&str>) -> IcalDateTime {
+    let tz = match tzid {
+        Some(tzid) => tzid
+            .parse::<chrono_tz::Tz>()
+            .unwrap_or_else(|_| error!("unknown time zone {:?}", tzid)),
+        None => expand::session_timezone(),
+    };
+    IcalDateTime::Tz(
+        expand::to_utc(dtstart).with_timezone(&tz),
+        postgres_ical_parser::types::TzInfo {
+            tz_id: tz.name().to_string(),
+            local_time: Default::default(),
+        },
+    )
+}
+
+/// Expand a recurrence rule into the start of each of its occurrences
+///
+/// `rrule` is the value of an `RRULE` property, such as `FREQ=WEEKLY;BYDAY=MO,WE`. Occurrences are
+/// generated in the local time of `dtstart` in `tzid`, the time zone of the session by default, so
+/// that they keep their time of day when clocks change. `dtstart` is always the first occurrence.
+/// Rules without a `COUNT` nor an `UNTIL` only end in year 9999, so their occurrences should be
+/// limited by the query, as expanding them past `postgres_ical.max_occurrences` occurrences or
+/// past `postgres_ical.expansion_horizon` fails.
+#[pg_extern(stable, parallel_safe)]
+pub fn rrule_occurrences(
+    rrule: &str,
+    dtstart: TimestampWithTimeZone,
+    tzid: Option<default!(&str, NULL)>,
+) -> impl Iterator<Item = TimestampWithTimeZone> {
+    let dtstart = rrule_dtstart(&dtstart, tzid);
+    let limits = expand::Range::new(None, None);
+
+    rrule
+        .parse::<postgres_ical_parser::recurrence::RRule>()
+        .and_then(|rule| rule.occurrences(&dtstart))
+        .unwrap_or_else(|err| error!("invalid RRULE: {}", err))
+        .zip(1..)
+        .filter_map(move |(occurrence, count)| {
+            check_for_interrupts!();
+            limits.check_limits("the RRULE", count, &occurrence);
+            serialize_datetime(occurrence).0
+        })
+}
+
+/// Find the last occurrence of a recurrence rule that starts before `ts`
+///
+/// `rrule`, `dtstart` and `tzid` are the same as [`rrule_occurrences`]'s. The occurrences are
+/// looked for from `ts` backwards rather than expanded from `dtstart`, unless the rule has a
+/// `COUNT`. `NULL` if `ts` doesn't follow `dtstart`.
+#[pg_extern(stable, parallel_safe)]
+pub fn rrule_last_occurrence_before(
+    rrule: &str,
+    dtstart: TimestampWithTimeZone,
+    ts: TimestampWithTimeZone,
+    tzid: Option<default!(&str, NULL)>,
+) -> Option<TimestampWithTimeZone> {
+    let dtstart = rrule_dtstart(&dtstart, tzid);
+    let before = IcalDateTime::Utc(expand::to_utc(&ts));
+
+    rrule
+        .parse::<postgres_ical_parser::recurrence::RRule>()
+        .and_then(|rule| rule.last_occurrence_before(&dtstart, &before))
+        .unwrap_or_else(|err| error!("invalid RRULE: {}", err))
+        .and_then(|occurrence| serialize_datetime(occurrence).0)
+}
+
+/// Expand the recurring events of an [`ical`][ical] file into their occurrences, without their
+/// `occurrence` range, which [`pg_ical_expand`] adds as pgx can't return ranges
+///
+/// Each event that has an `RRULE` or an `RDATE` returns a row for each of its occurrences, which
+/// its `EXDATE`s and `EXRULE`s remove, identified by its `uid` and `recurrence_id`, which is the
+/// original start of the occurrence. Other events return a single row, whose `recurrence_id` is
+/// the one of the event, if it overrides an occurrence. Overridden occurrences are only returned
+/// as their override, and an override with `RANGE=THISANDFUTURE` moves the occurrences that follow
+/// it as much as it moved its own.
+///
+/// Each occurrence ends as long after its start as its event does, in the local time of the
+/// event, or after the `DURATION` of the event. Events that have neither last a day if they start
+/// on a date, and no time otherwise.
+///
+/// Only the occurrences that start between `range_start`, included, and `range_end`, excluded, are
+/// returned, and either bound may be `NULL`. Floating date-times and dates are compared with them
+/// in the time zone of the session. Expanding a rule past `postgres_ical.max_occurrences`
+/// occurrences, including those before `range_start`, or past `postgres_ical.expansion_horizon`
+/// fails.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_occurrences(
+    calendar: String,
+    range_start: Option<default!(TimestampWithTimeZone, NULL)>,
+    range_end: Option<default!(TimestampWithTimeZone, NULL)>,
+) -> impl Iterator<Item = Occurrence> {
+    let range = std::rc::Rc::new(expand::Range::new(range_start, range_end));
+
+    // Overrides may follow the events they override
+    let events = parser_options()
+        .build(BufReader::new(Cursor::new(calendar.into_bytes())))
+        .map_while(|event| {
+            check_for_interrupts!();
+            tolerate_truncation(event)
+        })
+        .map(|event| event.unwrap_or_else(|err| error!("invalid calendar: {}", err)))
+        .collect::<Vec<_>>();
+    let overrides = expand::overrides(&events);
+
+    events.into_iter().flat_map(move |event| {
+        let event_overrides = match event.recurrence_id {
+            Some(_) => Vec::new(),
+            None => overrides.get(&event.uid).cloned().unwrap_or_default(),
+        };
+        expand::expand(event, event_overrides, std::rc::Rc::clone(&range))
+    })
+}
+
+// The occurrences of `pg_ical_occurrences`, with the range from their start to their end, that
+// floating date-times and dates have in the time zone of the session. Occurrences that end when
+// they start are single instants rather than empty ranges, so that they still overlap others.
+extension_sql!(
+    r#"
+CREATE FUNCTION pg_ical_expand(
+    calendar text,
+    range_start timestamptz DEFAULT NULL,
+    range_end timestamptz DEFAULT NULL
+) RETURNS TABLE (
+    uid text,
+    recurrence_id timestamptz,
+    recurrence_id_naive timestamp,
+    dt_start timestamptz,
+    dt_start_naive timestamp,
+    dt_end timestamptz,
+    dt_end_naive timestamp,
+    transparent boolean,
+    occurrence tstzrange
+) LANGUAGE sql AS $$
+    SELECT o.uid, o.recurrence_id, o.recurrence_id_naive, o.dt_start, o.dt_start_naive,
+        o.dt_end, o.dt_end_naive, o.transparent,
+        tstzrange(b.start_at, b.end_at, CASE WHEN b.start_at = b.end_at THEN '[]' ELSE '[)' END)
+    FROM pg_ical_occurrences(calendar, range_start, range_end) AS o,
+        LATERAL (
+            SELECT coalesce(o.dt_start, o.dt_start_naive::timestamptz) AS start_at,
+                coalesce(o.dt_end, o.dt_end_naive::timestamptz) AS end_at
+        ) AS b
+$$;
+"#,
+    name = "pg_ical_expand",
+    requires = [pg_ical_occurrences],
+);
+
+// The slots of `duration` that start every `granularity` from the start of `within`, and end in it,
+// that no opaque occurrence of `calendars` overlaps. Every occurrence that starts before the end of
+// `within` is expanded once, rather than once per slot.
+extension_sql!(
+    r#"
+CREATE FUNCTION pg_ical_free_slots(
+    calendars text[],
+    duration interval,
+    within tstzrange,
+    granularity interval
+) RETURNS SETOF tstzrange LANGUAGE plpgsql STRICT AS $$
+DECLARE
+    busy tstzrange[];
+BEGIN
+    IF lower_inf(within) OR upper_inf(within) THEN
+        RAISE EXCEPTION 'pg_ical_free_slots requires a bounded range, got %', within;
+    END IF;
+
+    busy := ARRAY(
+        SELECT o.occurrence
+        FROM unnest(calendars) AS calendar,
+            pg_ical_expand(calendar, NULL, upper(within)) AS o
+        WHERE NOT o.transparent AND o.occurrence && within
+    );
+
+    RETURN QUERY
+    SELECT s.slot
+    FROM generate_series(lower(within), upper(within) - duration, granularity) AS slot_start,
+        LATERAL (SELECT tstzrange(slot_start, slot_start + duration) AS slot) AS s
+    WHERE s.slot <@ within
+        AND NOT EXISTS (SELECT FROM unnest(busy) AS occurrence WHERE occurrence && s.slot)
+    ORDER BY s.slot;
+END
+$$;
+"#,
+    name = "pg_ical_free_slots",
+    requires = ["pg_ical_expand"],
+);
+
+/// Query the events of a [CalDAV][caldav] calendar collection that overlap a time range
+///
+/// A `calendar-query` REPORT is sent to the collection at `url`, so that the server only returns
+/// the relevant events instead of the entire collection. `range_start` and `range_end` are the
+/// bounds of the time range, and either may be `NULL` for an unbounded range. `options` and
+/// `headers` are the same as [`pg_ical_curl`]'s, which is where the credentials go. The
+/// `source_url` of each component is the URL of its calendar object resource.
+///
+/// The number of columns may increase at any moment without it being considered a breaking change.
+/// For forward-compatibility, when consuming this function's output, always do an explicit select.
+/// Column deletion or altering is — however, and obviously — considered breaking.
+///
+/// [caldav]: https://datatracker.ietf.org/doc/html/rfc4791
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_caldav_query(
+    url: &str,
+    range_start: Option<default!(TimestampWithTimeZone, NULL)>,
+    range_end: Option<default!(TimestampWithTimeZone, NULL)>,
+    options: Option<default!(JsonB, NULL)>,
+    headers: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = Component> {
+    let options = fetch::FetchOptions::from_jsonb(options).with_headers(headers);
+
+    let query = caldav::calendar_query(range_start.map(|t| *t), range_end.map(|t| *t), None);
+    caldav_components(url, &options, query)
+}
+
+/// Sends a `calendar-query` REPORT, and parses the calendar objects it returns
+fn caldav_components(
+    url: &str,
+    options: &fetch::FetchOptions,
+    query: String,
+) -> impl Iterator<Item = Component> {
+    let multistatus = caldav::request(url, options, "REPORT", 1, query);
+
+    caldav::calendar_objects(&multistatus, url)
+        .into_iter()
+        .flat_map(|object| {
+            let source_url = object.url;
+            pg_ical_internal(BufReader::new(Cursor::new(
+                object.calendar_data.into_bytes(),
+            )))
+            .map(move |component| Component {
+                source_url: Some(source_url.clone()),
+                ..component
+            })
+        })
+}
+
+/// Load the events of a calendar from the [Google Calendar API][api]
+///
+/// This is meant for calendars that have no iCalendar export. `calendar_id` is the identifier
+/// found in the calendar's settings, or `primary`. Public calendars only need an `api_key`, while
+/// private ones need an OAuth 2.0 `access_token` with a calendar scope. `options` are the same as
+/// [`pg_ical_curl`]'s. Recurring events are returned once, with their first occurrence, rather than
+/// expanded.
+///
+/// The number of columns may increase at any moment without it being considered a breaking change.
+/// For forward-compatibility, when consuming this function's output, always do an explicit select.
+/// Column deletion or altering is — however, and obviously — considered breaking.
+///
+/// [api]: https://developers.google.com/calendar/api/v3/reference/events/list
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_google(
+    calendar_id: &str,
+    api_key: Option<default!(&str, NULL)>,
+    access_token: Option<default!(&str, NULL)>,
+    options: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = Component> {
+    let mut options = fetch::FetchOptions::from_jsonb(options);
+
+    if let Some(access_token) = access_token {
+        if options.bearer_token.is_some() {
+            error!("access_token and the bearer_token fetch option are mutually exclusive");
+        }
+        options.bearer_token = Some(access_token.to_string());
+    }
+
+    google::events(calendar_id, api_key, &options).into_iter()
+}
+
+/// Represents the row returned by [pg_caldav_discover]
+pub struct CalendarCollection {
+    /// Absolute URL of the collection, that can be given to [pg_caldav_query]
+    pub href: String,
+    pub display_name: Option<String>,
+    /// Opaque tag that changes whenever the collection does, if the server supports it
+    pub ctag: Option<String>,
+    /// Color chosen by the owner of the calendar, as in `#FF2968FF`
+    pub color: Option<String>,
+}
+
+/// List the [CalDAV][caldav] calendar collections of the current user
+///
+/// `url` may be the root of the CalDAV server, the user's principal, or their calendar home. The
+/// `current-user-principal` and `calendar-home-set` properties are followed with PROPFIND requests,
+/// then the calendar collections of the home are listed. `options` and `headers` are the same as
+/// [`pg_ical_curl`]'s, which is where the credentials go.
+///
+/// [caldav]: https://datatracker.ietf.org/doc/html/rfc4791
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_caldav_discover(
+    url: &str,
+    options: Option<default!(JsonB, NULL)>,
+    headers: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = CalendarCollection> {
+    let options = fetch::FetchOptions::from_jsonb(options).with_headers(headers);
+
+    caldav::discover(url, &options)
+        .into_iter()
+        .map(|collection| CalendarCollection {
+            href: collection.url,
+            display_name: collection.display_name,
+            ctag: collection.ctag,
+            color: collection.color,
+        })
+}
+
+/// Represents a row returned by [pg_ical_diff]
+pub struct ComponentChange {
+    pub uid: String,
+    pub recurrence_id: Option<TimestampWithTimeZone>,
+    pub recurrence_id_naive: Option<Timestamp>,
+    /// `added`, `removed` or `modified`
+    pub change_type: String,
+    /// Columns of [`Component`] whose values differ, for modified components
+    pub changed_fields: Vec<String>,
+}
+
+/// Compare two [`ical`][ical] calendars
+///
+/// Components are matched by UID and `RECURRENCE-ID`, and one row is returned for each component
+/// that was added, removed or modified. `DTSTAMP` is ignored, as it's usually the time at which
+/// the calendar was exported.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_diff(old: String, new: String) -> impl Iterator<Item = ComponentChange> {
+    let parse = |calendar: String| {
+        pg_ical_internal(BufReader::new(Cursor::new(calendar.into_bytes()))).collect()
+    };
+
+    diff::diff(parse(old), parse(new)).into_iter()
+}
+
+/// Represents the row returned by [pg_ical_sync]
+pub struct SyncCounts {
+    pub inserted: i64,
+    pub updated: i64,
+    pub deleted: i64,
+}
+
+/// Synchronize a table with a remote calendar
+///
+/// `target` may have any subset of the columns returned by [`pg_ical_curl`], including `uid`. Its
+/// rows are matched with the fetched components by `uid`, and `recurrence_id` if it has it. New
+/// components are inserted, the rows whose `sequence` or `last_modified` changed are updated, and
+/// the rows whose component vanished are deleted. `options` and `headers` are the same as
+/// [`pg_ical_curl`]'s.
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_sync(
+    url: &str,
+    target: PgRelation,
+    options: Option<default!(JsonB, NULL)>,
+    headers: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = SyncCounts> {
+    std::iter::once(sync::sync(url, &target, options, headers))
+}
+
+/// Represents the row returned by [pg_ical_curl_meta] and [pg_ical_head]
+pub struct ResponseMeta {
+    pub status: i32,
+    /// URL after following redirections
+    pub url: String,
+    pub content_type: Option<String>,
+    pub content_length: Option<i64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<TimestampWithTimeZone>,
+}
+
+impl From<fetch::Response> for ResponseMeta {
+    fn from(response: fetch::Response) -> Self {
+        let last_modified = response
+            .header("Last-Modified")
+            .and_then(|date| chrono::DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| TimestampWithTimeZone::new(to_time(date.naive_utc()), UtcOffset::UTC));
+
+        ResponseMeta {
+            status: response.status as i32,
+            content_type: response.header("Content-Type").map(ToString::to_string),
+            content_length: response
+                .header("Content-Length")
+                .and_then(|length| length.parse().ok()),
+            etag: response.header("ETag").map(ToString::to_string),
+            last_modified,
+            url: response.url,
+        }
+    }
+}
+
+/// Fetch the metadata of a remote [`ical`][ical] file, without downloading it
+///
+/// Redirections are followed, and a single row describing the final response is returned. Sync jobs
+/// can compare its `etag` or `last_modified` to the ones of their previous run, in order to decide
+/// whether the calendar needs to be ingested again. `options` and `headers` are the same as
+/// [`pg_ical_curl`]'s.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_curl_meta(
+    url: &str,
+    options: Option<default!(JsonB, NULL)>,
+    headers: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = ResponseMeta> {
+    let options = fetch::FetchOptions::from_jsonb(options).with_headers(headers);
+    std::iter::once(fetch::fetch_headers(url, &options, false).into())
+}
+
+/// Fetch the metadata of a remote [`ical`][ical] file with a `HEAD` request
+///
+/// Returns the same row as [`pg_ical_curl_meta`], for schedulers that skip syncs when the calendar
+/// didn't change. Servers that don't support `HEAD` requests answer with another status, such as
+/// 405, which is returned as well.
+///
+/// [ical]: https://datatracker.ietf.org/doc/html/rfc5545
+#[pg_extern_columns("src/lib.rs")]
+pub fn pg_ical_head(
+    url: &str,
+    options: Option<default!(JsonB, NULL)>,
+    headers: Option<default!(JsonB, NULL)>,
+) -> impl Iterator<Item = ResponseMeta> {
+    let options = fetch::FetchOptions::from_jsonb(options).with_headers(headers);
+    std::iter::once(fetch::fetch_headers(url, &options, true).into())
+}
@@ -0,0 +1,179 @@
+//! OAuth 2 access tokens obtained from refresh tokens
+//!
+//! Access tokens of Google and Microsoft expire after an hour, while their refresh tokens last
+//! until they are revoked, or unused for months. Given the latter, fetches exchange it for a fresh
+//! access token when needed, so that feeds keep syncing unattended.
+
+use crate::fetch::{FetchOptions, PreparedRequest, RequestBody};
+use pgx::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
+
+/// Access tokens are refreshed this long before they expire, so that they don't expire in the
+/// middle of a request
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Access tokens of the backend, by token endpoint, client ID and refresh token
+static TOKENS: Mutex<Option<HashMap<(String, String, String), AccessToken>>> = Mutex::new(None);
+
+/// The `oauth2` fetch option
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OAuth2 {
+    /// Provider whose token endpoint is used, unless [`token_url`][Self::token_url] is given
+    pub provider: Option<Provider>,
+
+    /// URL of the token endpoint of another provider
+    pub token_url: Option<String>,
+
+    /// Microsoft tenant, `common` by default
+    pub tenant: Option<String>,
+
+    pub client_id: String,
+
+    /// Omitted for public clients
+    pub client_secret: Option<String>,
+
+    /// Usually given as an `oauth2_refresh_token` credential
+    pub refresh_token: Option<String>,
+
+    /// Space-separated scopes, which Microsoft requires
+    pub scope: Option<String>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Google,
+    Microsoft,
+}
+
+#[derive(Clone)]
+struct AccessToken {
+    token: String,
+    expires: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// In seconds, an hour if missing
+    expires_in: Option<u64>,
+}
+
+impl OAuth2 {
+    fn token_url(&self) -> String {
+        match (&self.token_url, self.provider) {
+            (Some(token_url), None) => token_url.clone(),
+            (None, Some(Provider::Google)) => "https://oauth2.googleapis.com/token".to_string(),
+            (None, Some(Provider::Microsoft)) => format!(
+                "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                self.tenant.as_deref().unwrap_or("common"),
+            ),
+            _ => error!("the oauth2 fetch option requires either a provider or a token_url"),
+        }
+    }
+
+    /// Returns an access token, refreshing it if the cached one is missing or about to expire
+    ///
+    /// `options` are the options of the request that needs the token, whose proxy and TLS options
+    /// also apply to the token endpoint. Must be called from the backend's thread.
+    pub fn access_token(&self, options: &FetchOptions) -> String {
+        let token_url = self.token_url();
+        let refresh_token = self
+            .refresh_token
+            .as_deref()
+            .unwrap_or_else(|| error!("the oauth2 fetch option requires a refresh_token"));
+        let key = (
+            token_url.clone(),
+            self.client_id.clone(),
+            refresh_token.to_string(),
+        );
+
+        let cached = TOKENS
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|tokens| tokens.get(&key).cloned());
+        if let Some(cached) = cached {
+            if cached.expires > Instant::now() + EXPIRY_MARGIN {
+                return cached.token;
+            }
+        }
+
+        let requested = Instant::now();
+        let response = self.refresh(&token_url, refresh_token, options);
+        let expires_in = Duration::from_secs(response.expires_in.unwrap_or(3600));
+
+        TOKENS
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(
+                key,
+                AccessToken {
+                    token: response.access_token.clone(),
+                    expires: requested + expires_in,
+                },
+            );
+
+        response.access_token
+    }
+
+    fn refresh(
+        &self,
+        token_url: &str,
+        refresh_token: &str,
+        options: &FetchOptions,
+    ) -> TokenResponse {
+        let mut form = form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", "refresh_token")
+            .append_pair("refresh_token", refresh_token)
+            .append_pair("client_id", &self.client_id);
+        if let Some(client_secret) = &self.client_secret {
+            form.append_pair("client_secret", client_secret);
+        }
+        if let Some(scope) = &self.scope {
+            form.append_pair("scope", scope);
+        }
+
+        // Only the connection options of the request apply
+        let token_options = FetchOptions {
+            username: None,
+            password: None,
+            auth_method: None,
+            bearer_token: None,
+            oauth2: None,
+            headers: vec![
+                (
+                    "Content-Type".into(),
+                    "application/x-www-form-urlencoded".into(),
+                ),
+                ("Accept".into(), "application/json".into()),
+            ],
+            ..options.clone()
+        };
+        let body = RequestBody {
+            method: "POST",
+            data: form.finish().into_bytes(),
+        };
+
+        let mut response = Vec::new();
+        PreparedRequest::with_body(token_url, &token_options, Some(body))
+            .perform(&mut response)
+            .into_result()
+            .unwrap_or_else(|err| {
+                error!(
+                    "couldn't refresh the OAuth 2 access token at {}: {}",
+                    token_url, err,
+                )
+            });
+
+        serde_json::from_slice(&response).unwrap_or_else(|err| {
+            error!("invalid OAuth 2 token response from {}: {}", token_url, err)
+        })
+    }
+}
@@ -0,0 +1,25 @@
+//! PostGIS geometries of the `GEO` of events
+//!
+//! `geom(ical_event)` is a `geometry(Point, 4326)`, so it can be written `e.geom` for the records
+//! of `pg_ical_records`. It's only created if PostGIS is installed in the `search_path` when
+//! the extension is created, as PostGIS isn't required otherwise.
+
+use pgx::*;
+
+extension_sql!(
+    r#"
+DO $$
+BEGIN
+    IF to_regtype('geometry') IS NOT NULL THEN
+        CREATE FUNCTION geom(event ical_event) RETURNS geometry
+            LANGUAGE sql IMMUTABLE PARALLEL SAFE
+            AS $geom$
+                SELECT ST_SetSRID(ST_MakePoint(event.geo_lng, event.geo_lat), 4326)::geometry(Point, 4326)
+            $geom$;
+    END IF;
+END
+$$;
+"#,
+    name = "geom",
+    requires = ["ical_event"],
+);
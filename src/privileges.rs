@@ -0,0 +1,60 @@
+//! Role checks for the features that reach outside of the database
+
+use pgx::*;
+
+// Role names starting with `pg_` are reserved for the predefined roles, hence the prefix. Roles are
+// shared by the databases of a cluster, so the role may already exist.
+extension_sql!(
+    r#"
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT FROM pg_catalog.pg_roles WHERE rolname = 'postgres_ical_fetch') THEN
+        CREATE ROLE postgres_ical_fetch NOLOGIN;
+    END IF;
+END
+$$;
+"#,
+    name = "postgres_ical_fetch",
+);
+
+/// Raises an SQL error unless the current user has the privileges of the `pg_read_server_files`
+/// role, which superusers implicitly have
+///
+/// The role was added in PostgreSQL 11, before which only superusers have these privileges.
+pub fn require_read_server_files(feature: &str) {
+    require_role("pg_read_server_files", feature);
+}
+
+/// Raises an SQL error unless the current user has the privileges of the `postgres_ical_fetch`
+/// role, which superusers implicitly have
+///
+/// Every network request goes through this check, so that only the members of the role can make
+/// the database host reach other hosts, whoever may execute the functions.
+pub fn require_fetch(feature: &str) {
+    require_role("postgres_ical_fetch", feature);
+}
+
+fn require_role(role: &str, feature: &str) {
+    let exists = Spi::get_one::<bool>(&format!(
+        "SELECT EXISTS (SELECT FROM pg_catalog.pg_roles WHERE rolname = '{}')",
+        role,
+    ))
+    .unwrap_or(false);
+
+    if !exists {
+        if !unsafe { pg_sys::superuser() } {
+            error!(
+                "{} requires superuser privileges, as the {} role doesn't exist",
+                feature, role,
+            );
+        }
+        return;
+    }
+
+    let allowed =
+        Spi::get_one::<bool>(&format!("SELECT pg_has_role('{}', 'USAGE')", role)).unwrap_or(false);
+
+    if !allowed {
+        error!("{} requires the privileges of the {} role", feature, role);
+    }
+}
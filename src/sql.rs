@@ -0,0 +1,18 @@
+//! Quoting of the identifiers and literals of generated SQL statements
+
+use pgx::*;
+use std::ffi::{CStr, CString};
+
+pub fn quote_identifier(identifier: &str) -> String {
+    let identifier = CString::new(identifier).unwrap();
+    unsafe { CStr::from_ptr(pg_sys::quote_identifier(identifier.as_ptr())) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+pub fn quote_literal(literal: &str) -> String {
+    let literal = CString::new(literal).unwrap();
+    unsafe { CStr::from_ptr(pg_sys::quote_literal_cstr(literal.as_ptr())) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,214 @@
+//! Reconciliation of a table with a remote calendar
+//!
+//! Rows are matched by `uid`, and by `recurrence_id` if the table has it, so that the overrides of
+//! a recurring event are kept apart. A matched row is only updated when its `sequence` or
+//! `last_modified` changed, or any of its columns when the table has neither. Components of the
+//! feed that share a key are synchronized once, as the one without a `recurrence_id`, or as the
+//! first one otherwise, so that tables without `recurrence_id` hold recurring events once.
+//!
+//! When `postgres_ical.notify_channel` is set, the UIDs of the modified rows are sent on that
+//! channel once the transaction commits, with the URL of the feed, whose credentials are redacted
+//! as any session may listen to the channel.
+
+use crate::columns::COLUMNS;
+use crate::fetch::audit::redact;
+use crate::guc;
+use crate::sql::quote_identifier;
+use crate::SyncCounts;
+use pgx::*;
+use serde_json::json;
+
+const KEY_COLUMNS: &[&str] = &["uid", "recurrence_id", "recurrence_id_naive"];
+const VERSION_COLUMNS: &[&str] = &["sequence", "last_modified", "last_modified_naive"];
+
+/// Fetches `url` with `pg_ical_curl`, and applies the differences to `target` in a single
+/// statement
+pub fn sync(
+    url: &str,
+    target: &PgRelation,
+    options: Option<JsonB>,
+    headers: Option<JsonB>,
+) -> SyncCounts {
+    let table = format!(
+        "{}.{}",
+        quote_identifier(target.namespace()),
+        quote_identifier(target.name()),
+    );
+
+    let tuple_desc = target.tuple_desc();
+    let columns: Vec<&str> = tuple_desc
+        .iter()
+        .filter(|attribute| !attribute.is_dropped())
+        .filter_map(|attribute| {
+            let name = attribute.name();
+            COLUMNS.iter().copied().find(|column| *column == name)
+        })
+        .collect();
+
+    if !columns.contains(&"uid") {
+        error!("{} must have a uid column to be synchronized", table);
+    }
+
+    let subset = |candidates: &[&'static str]| -> Vec<&str> {
+        candidates
+            .iter()
+            .copied()
+            .filter(|column| columns.contains(column))
+            .collect()
+    };
+
+    let key_columns = subset(KEY_COLUMNS)
+        .iter()
+        .map(|column| format!("c.{}", quote_identifier(column)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let key = subset(KEY_COLUMNS)
+        .iter()
+        .map(|column| {
+            let column = quote_identifier(column);
+            format!("t.{0} IS NOT DISTINCT FROM f.{0}", column)
+        })
+        .collect::<Vec<_>>()
+        .join(" AND ");
+
+    let mut compared = subset(VERSION_COLUMNS);
+    if compared.is_empty() {
+        compared = columns.clone();
+    }
+    let prefixed = |prefix: &str, columns: &[&str]| {
+        columns
+            .iter()
+            .map(|column| format!("{}.{}", prefix, quote_identifier(column)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let changed = format!(
+        "ROW({}) IS DISTINCT FROM ROW({})",
+        prefixed("t", &compared),
+        prefixed("f", &compared),
+    );
+
+    let column_list = columns
+        .iter()
+        .map(|column| quote_identifier(column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let assignments = columns
+        .iter()
+        .map(|column| {
+            let column = quote_identifier(column);
+            format!("{0} = f.{0}", column)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    // The modifications of the CTEs see the same snapshot of the table, and touch different rows.
+    // `fetched` is referenced more than once, so it is fetched once.
+    let query = format!(
+        "WITH fetched AS (SELECT DISTINCT ON ({key_columns}) {columns} \
+             FROM {schema}.pg_ical_curl($1, $2, $3) AS c \
+             ORDER BY {key_columns}, (c.recurrence_id IS NULL AND c.recurrence_id_naive IS NULL) \
+                 DESC, c.calendar_seq, c.event_ordinality), \
+         deleted AS (DELETE FROM {table} AS t \
+             WHERE NOT EXISTS (SELECT FROM fetched AS f WHERE {key}) RETURNING t.uid), \
+         updated AS (UPDATE {table} AS t SET {assignments} FROM fetched AS f \
+             WHERE {key} AND {changed} RETURNING t.uid), \
+         inserted AS (INSERT INTO {table} ({columns}) SELECT {columns} FROM fetched AS f \
+             WHERE NOT EXISTS (SELECT FROM {table} AS t WHERE {key}) RETURNING uid) \
+         SELECT array(SELECT uid FROM inserted), array(SELECT uid FROM updated), \
+             array(SELECT uid FROM deleted)",
+        columns = column_list,
+        key_columns = key_columns,
+        schema = extension_schema(),
+        table = table,
+        key = key,
+        assignments = assignments,
+        changed = changed,
+    );
+
+    let (inserted, updated, deleted) =
+        Spi::get_three_with_args::<Vec<String>, Vec<String>, Vec<String>>(
+            &query,
+            vec![
+                (PgBuiltInOids::TEXTOID.oid(), url.into_datum()),
+                (PgBuiltInOids::JSONBOID.oid(), options.into_datum()),
+                (PgBuiltInOids::JSONBOID.oid(), headers.into_datum()),
+            ],
+        );
+
+    let (inserted, updated, deleted) = (
+        inserted.unwrap_or_default(),
+        updated.unwrap_or_default(),
+        deleted.unwrap_or_default(),
+    );
+
+    if let Some(channel) = guc::NOTIFY_CHANNEL
+        .get()
+        .filter(|channel| !channel.is_empty())
+    {
+        if !(inserted.is_empty() && updated.is_empty() && deleted.is_empty()) {
+            notify(&channel, url, &table, &inserted, &updated, &deleted);
+        }
+    }
+
+    SyncCounts {
+        inserted: inserted.len() as i64,
+        updated: updated.len() as i64,
+        deleted: deleted.len() as i64,
+    }
+}
+
+/// Schema of the extension, quoted if needed, as `search_path` may not include it
+fn extension_schema() -> String {
+    Spi::get_one::<String>(
+        "SELECT extnamespace::regnamespace::text FROM pg_extension WHERE extname = 'postgres_ical'",
+    )
+    .unwrap_or_else(|| error!("the postgres_ical extension isn't installed"))
+}
+
+/// Largest payload accepted by `pg_notify`
+const MAX_PAYLOAD_LENGTH: usize = 7999;
+
+/// Sends the UIDs of the modified rows, or only their numbers if they don't fit in a notification
+fn notify(
+    channel: &str,
+    url: &str,
+    table: &str,
+    inserted: &[String],
+    updated: &[String],
+    deleted: &[String],
+) {
+    let url = redact(url);
+    let mut payload = json!({
+        "url": url,
+        "table": table,
+        "added": inserted,
+        "updated": updated,
+        "deleted": deleted,
+    })
+    .to_string();
+
+    if payload.len() > MAX_PAYLOAD_LENGTH {
+        payload = json!({
+            "url": url,
+            "table": table,
+            "truncated": true,
+            "added_count": inserted.len(),
+            "updated_count": updated.len(),
+            "deleted_count": deleted.len(),
+        })
+        .to_string();
+    }
+
+    Spi::connect(|mut client| {
+        client.update(
+            "SELECT pg_notify($1, $2)",
+            None,
+            Some(vec![
+                (PgBuiltInOids::TEXTOID.oid(), channel.into_datum()),
+                (PgBuiltInOids::TEXTOID.oid(), payload.into_datum()),
+            ]),
+        );
+        Ok(Some(()))
+    });
+}
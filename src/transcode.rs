@@ -0,0 +1,71 @@
+//! Conversion of calendars to UTF-8 before parsing
+//!
+//! RFC 5545 mandates UTF-8, but quite a few generators still produce ISO-8859-1 or Windows-1252,
+//! which they usually (but not always) advertise in the `Content-Type` HTTP header.
+
+use encoding_rs::{Decoder, Encoding};
+use std::io::{self, Write};
+
+/// Reads the encoding from the value of a `Content-Type` header, if it has a known `charset`
+pub fn encoding_from_content_type(value: &str) -> Option<&'static Encoding> {
+    value.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Encoding::for_label(value.trim().trim_matches('"').as_bytes())
+        } else {
+            None
+        }
+    })
+}
+
+/// Writes data of a given encoding to an inner UTF-8 writer
+///
+/// A byte order mark at the start of the data takes precedence over the given encoding, and is
+/// removed. Invalid sequences are replaced by U+FFFD.
+pub struct Utf8Writer<W: Write> {
+    decoder: Decoder,
+    inner: W,
+    buffer: String,
+}
+
+impl<W: Write> Utf8Writer<W> {
+    pub fn new(encoding: &'static Encoding, inner: W) -> Self {
+        Self {
+            decoder: encoding.new_decoder(),
+            inner,
+            buffer: String::new(),
+        }
+    }
+
+    fn decode(&mut self, data: &[u8], last: bool) -> io::Result<()> {
+        self.buffer.clear();
+
+        // With enough room, the decoder always consumes its whole input
+        let needed = self
+            .decoder
+            .max_utf8_buffer_length(data.len())
+            .ok_or_else(|| io::Error::other("chunk too large"))?;
+        self.buffer.reserve(needed);
+
+        let _ = self.decoder.decode_to_string(data, &mut self.buffer, last);
+        self.inner.write_all(self.buffer.as_bytes())
+    }
+
+    /// Flushes the end of the data, which might be an incomplete sequence, and returns the inner
+    /// writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.decode(&[], true)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Utf8Writer<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.decode(data, false)?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,286 @@
+//! Server-side request forgery protection for the functions that fetch remote calendars
+//!
+//! Any member of the `postgres_ical_fetch` role can make the database host issue HTTP requests,
+//! through [`pg_ical_curl`][crate::pg_ical_curl] and the like. The `postgres_ical.*` settings
+//! checked here are superuser-only, so that administrators can restrict where those requests may
+//! go.
+
+use crate::guc;
+use pgx::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use url::{Host, Url};
+
+/// Checks `url` against the URL patterns and private address settings, raising an SQL error if it
+/// may not be fetched
+///
+/// The returned host, if any, must be used to pin the connection to the addresses that were
+/// checked, so that a second DNS resolution done by the HTTP client can't point elsewhere. Hosts
+/// in `overrides` aren't resolved, their addresses must be checked with [`check_override`].
+pub fn check_url(url: &str, overrides: &[ResolvedHost]) -> Option<ResolvedHost> {
+    let parsed = check_patterns(url);
+
+    if !guc::BLOCK_PRIVATE_ADDRESSES.get() {
+        return None;
+    }
+
+    let port = parsed.port_or_known_default().unwrap_or(80);
+    if let Some(Host::Domain(domain)) = parsed.host() {
+        if overrides.iter().any(|host| host.matches(domain, port)) {
+            return None;
+        }
+    }
+    let addresses = resolve_public(&parsed, port);
+
+    match parsed.host() {
+        Some(Host::Domain(domain)) => Some(ResolvedHost {
+            domain: domain.to_string(),
+            port,
+            addresses,
+        }),
+        // IP addresses aren't resolved again
+        _ => None,
+    }
+}
+
+/// Checks `url` against the URL patterns only, for the requests that don't connect to its host,
+/// such as the ones sent through a Unix socket
+pub fn check_patterns(url: &str) -> Url {
+    let parsed = Url::parse(url).unwrap_or_else(|err| error!("invalid URL {:?}: {}", url, err));
+
+    match parsed.scheme() {
+        "http" | "https" => {}
+        scheme => error!(
+            "unsupported URL scheme {:?}, expected http or https",
+            scheme
+        ),
+    }
+
+    let denied = guc::DENIED_URL_PATTERNS.get().unwrap_or_default();
+    if let Some(pattern) = patterns(&denied).find(|pattern| glob_match(pattern, parsed.as_str())) {
+        error!(
+            "URL {} is denied by postgres_ical.denied_url_patterns ({})",
+            parsed, pattern,
+        );
+    }
+
+    let allowed = guc::ALLOWED_URL_PATTERNS.get().unwrap_or_default();
+    if patterns(&allowed).next().is_some()
+        && !patterns(&allowed).any(|pattern| glob_match(pattern, parsed.as_str()))
+    {
+        error!(
+            "URL {} doesn't match postgres_ical.allowed_url_patterns",
+            parsed,
+        );
+    }
+
+    parsed
+}
+
+/// Addresses that a host name was resolved to, and that were checked, or that were given instead
+/// of resolving it
+pub struct ResolvedHost {
+    pub domain: String,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+}
+
+impl ResolvedHost {
+    pub fn matches(&self, domain: &str, port: u16) -> bool {
+        self.domain.eq_ignore_ascii_case(domain) && self.port == port
+    }
+}
+
+/// Checks the addresses that a function caller gave for a host, raising an SQL error if they may
+/// not be connected to
+pub fn check_override(host: &ResolvedHost) {
+    if !guc::BLOCK_PRIVATE_ADDRESSES.get() {
+        return;
+    }
+
+    if let Some(address) = host.addresses.iter().find(|address| is_private(**address)) {
+        error!(
+            "host {} is resolved to the non-public address {}, which is refused by \
+             postgres_ical.block_private_addresses",
+            host.domain, address,
+        );
+    }
+}
+
+/// Checks a proxy URL given by a function caller, raising an SQL error if it may not be used
+///
+/// Proxies configured by administrators through `postgres_ical.proxy` aren't checked, as they
+/// frequently live on private networks.
+pub fn check_proxy(proxy: &str) {
+    let parsed =
+        Url::parse(proxy).unwrap_or_else(|err| error!("invalid proxy URL {:?}: {}", proxy, err));
+
+    let default_port = match parsed.scheme() {
+        "http" => 80,
+        "https" => 443,
+        "socks4" | "socks4a" | "socks5" | "socks5h" => 1080,
+        scheme => error!(
+            "unsupported proxy URL scheme {:?}, expected http, https, socks4, socks4a, socks5 or socks5h",
+            scheme,
+        ),
+    };
+
+    if guc::BLOCK_PRIVATE_ADDRESSES.get() {
+        resolve_public(&parsed, parsed.port().unwrap_or(default_port));
+    }
+}
+
+/// Resolves the host of `url`, raising an SQL error if any of its addresses isn't public
+fn resolve_public(url: &Url, port: u16) -> Vec<IpAddr> {
+    let addresses: Vec<IpAddr> = match url.host() {
+        Some(Host::Domain(domain)) => (domain, port)
+            .to_socket_addrs()
+            .unwrap_or_else(|err| error!("couldn't resolve host {}: {}", domain, err))
+            .map(|address| address.ip())
+            .collect(),
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        None => error!("URL {} has no host", url),
+    };
+
+    if let Some(address) = addresses.iter().find(|address| is_private(**address)) {
+        error!(
+            "URL {} resolves to the non-public address {}, which is refused by \
+             postgres_ical.block_private_addresses",
+            url, address,
+        );
+    }
+
+    addresses
+}
+
+fn patterns(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+}
+
+/// Matches `text` against `pattern`, in which `*` stands for any (possibly empty) sequence of
+/// characters
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    // There's always a first part, possibly empty
+    let first = parts.next().unwrap();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+
+    // No wildcard at all
+    rest.is_empty()
+}
+
+fn is_private(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => is_private_v6(ip),
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || a == 0
+        || a >= 240 // Reserved, 240.0.0.0/4
+        || (a == 100 && (b & 0b1100_0000) == 64) // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 198 && (b & 0b1111_1110) == 18) // Benchmarking, 198.18.0.0/15
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    let first_segment = ip.segments()[0];
+    let [.., a, b, c, d] = ip.octets();
+
+    match ip.segments() {
+        // IPv4-mapped, ::ffff:0:0/96, NAT64, 64:ff9b::/96, and IPv4-compatible, ::/96
+        [0, 0, 0, 0, 0, 0xffff, _, _]
+        | [0x64, 0xff9b, 0, 0, 0, 0, _, _]
+        | [0, 0, 0, 0, 0, 0, _, _] => {
+            return is_private_v4(Ipv4Addr::new(a, b, c, d));
+        }
+        // 6to4, 2002::/16, whose IPv4 address follows the prefix
+        [0x2002, high, low, ..] => {
+            let [a, b] = high.to_be_bytes();
+            let [c, d] = low.to_be_bytes();
+            return is_private_v4(Ipv4Addr::new(a, b, c, d));
+        }
+        _ => {}
+    }
+
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || (first_segment & 0xfe00) == 0xfc00 // Unique local, fc00::/7
+        || (first_segment & 0xffc0) == 0xfe80 // Link-local, fe80::/10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_addresses() {
+        let private = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "100.64.0.1",
+            "169.254.169.254",
+            "198.18.0.1",
+            "198.19.255.255",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "::10.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::127.0.0.1",
+            "2002:7f00:1::",
+            "2002:a00:1::1",
+        ];
+        let public = [
+            "93.184.216.34",
+            "198.17.255.255",
+            "198.20.0.1",
+            "223.255.255.255",
+            "2606:2800:220:1::",
+            "::ffff:93.184.216.34",
+            "64:ff9b::5db8:d822",
+            "2002:5db8:d822::1",
+        ];
+
+        for address in private {
+            assert!(is_private(address.parse().unwrap()), "{}", address);
+        }
+        for address in public {
+            assert!(!is_private(address.parse().unwrap()), "{}", address);
+        }
+    }
+}